@@ -0,0 +1,439 @@
+//! Archive unpacking for recursive scans.
+//!
+//! ZIP (and the formats built on it), TAR, gzip, bzip2 and xz are unpacked in
+//! memory, one member at a time. Every read is bounded by [`Budget`], which
+//! is shared by the whole tree below one scanned file, so a small archive
+//! can't expand into gigabytes however deeply it nests. Single-stream
+//! compressors yield one member named after the archive without its
+//! extension, which is how `x.tar.gz` ends up as `x.tar.gz!/x.tar!/...`.
+//! PDF and email attachments are unpacked as members too, and every message
+//! of an mbox file is a member of its own.
+
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::io::{Cursor, Read};
+
+const DEFAULT_MAX_DEPTH: usize = 5;
+const DEFAULT_MAX_MEMBERS: usize = 10_000;
+const DEFAULT_MAX_MEMBER_SIZE: u64 = 256 * 1024 * 1024;
+const DEFAULT_MAX_TOTAL_SIZE: u64 = 1024 * 1024 * 1024;
+/// Members that hit a size limit while expanding more than this are treated
+/// as decompression bombs rather than just big files.
+const BOMB_RATIO: u64 = 100;
+
+/// Separates an archive's path from the path of a member inside it.
+pub const MEMBER_SEPARATOR: &str = "!/";
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ArchiveLimits {
+    /// Nesting levels unpacked below the scanned file, `0` disables unpacking.
+    pub max_depth: usize,
+    /// Members scanned per top-level file, across all nesting levels.
+    pub max_members: usize,
+    pub max_member_size: u64,
+    /// Decompressed bytes per top-level file, across all nesting levels.
+    pub max_total_size: u64,
+}
+
+impl Default for ArchiveLimits {
+    fn default() -> Self {
+        ArchiveLimits {
+            max_depth: DEFAULT_MAX_DEPTH,
+            max_members: DEFAULT_MAX_MEMBERS,
+            max_member_size: DEFAULT_MAX_MEMBER_SIZE,
+            max_total_size: DEFAULT_MAX_TOTAL_SIZE,
+        }
+    }
+}
+
+/// What is left of the limits while one top-level file is unpacked.
+pub struct Budget {
+    members: usize,
+    bytes: u64,
+    max_member_size: u64,
+}
+
+impl Budget {
+    pub fn new(limits: &ArchiveLimits) -> Self {
+        Budget { members: limits.max_members, bytes: limits.max_total_size, max_member_size: limits.max_member_size }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArchiveKind {
+    Zip,
+    Tar,
+    Gzip,
+    Bzip2,
+    Xz,
+    Pdf,
+    Email,
+    Mbox,
+}
+
+impl ArchiveKind {
+    /// Maps a `filetype` kind to the unpacker for it. Office documents, JARs
+    /// and APKs are ZIP files and are unpacked as such.
+    pub fn from_file_kind(kind: &str) -> Option<Self> {
+        match kind {
+            "zip" | "jar" | "apk" | "epub" | "docx" | "docm" | "xlsx" | "xlsm" | "pptx" | "pptm" | "ooxml" | "odt"
+            | "ods" | "odp" => Some(ArchiveKind::Zip),
+            "tar" => Some(ArchiveKind::Tar),
+            "gzip" => Some(ArchiveKind::Gzip),
+            "bzip2" => Some(ArchiveKind::Bzip2),
+            "xz" => Some(ArchiveKind::Xz),
+            "pdf" => Some(ArchiveKind::Pdf),
+            "email" | "msg" => Some(ArchiveKind::Email),
+            "mbox" => Some(ArchiveKind::Mbox),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SkipReason {
+    MemberTooLarge,
+    TotalSizeExceeded,
+    TooManyMembers,
+    /// Hit a size limit while expanding far beyond its compressed size.
+    DecompressionBomb { compressed: u64 },
+    Unreadable(String),
+}
+
+impl fmt::Display for SkipReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SkipReason::MemberTooLarge => write!(f, "member exceeds the size limit"),
+            SkipReason::TotalSizeExceeded => write!(f, "archive exceeds the total decompressed size limit"),
+            SkipReason::TooManyMembers => write!(f, "archive exceeds the member limit"),
+            SkipReason::DecompressionBomb { compressed } => {
+                write!(f, "expands beyond the size limits from {} compressed bytes", compressed)
+            }
+            SkipReason::Unreadable(e) => write!(f, "cannot unpack: {}", e),
+        }
+    }
+}
+
+pub enum Member {
+    File { name: String, data: Vec<u8> },
+    /// Password protected, its content can't be scanned.
+    Encrypted { name: String, size: u64 },
+    Skipped { name: String, reason: SkipReason },
+}
+
+/// Unpacks `data` and hands every member to `visit`, along with the budget
+/// so nested archives can be unpacked from the same one. Returns why
+/// extraction stopped early, if it did; problems with single members are
+/// reported through `visit` instead.
+pub fn extract(
+    kind: ArchiveKind,
+    name: &str,
+    data: &[u8],
+    budget: &mut Budget,
+    visit: &mut dyn FnMut(Member, &mut Budget),
+) -> Result<Option<SkipReason>, String> {
+    match kind {
+        ArchiveKind::Zip => extract_zip(data, budget, visit),
+        ArchiveKind::Tar => extract_tar(data, budget, visit),
+        ArchiveKind::Gzip => {
+            extract_stream(flate2::read::MultiGzDecoder::new(data), stream_member_name(name, &["tgz", "gz"]), data, budget, visit)
+        }
+        ArchiveKind::Bzip2 => {
+            extract_stream(bzip2::read::MultiBzDecoder::new(data), stream_member_name(name, &["tbz2", "tbz", "bz2"]), data, budget, visit)
+        }
+        ArchiveKind::Xz => {
+            extract_stream(xz2::read::XzDecoder::new_multi_decoder(data), stream_member_name(name, &["txz", "xz"]), data, budget, visit)
+        }
+        ArchiveKind::Pdf => extract_decoded(crate::pdf::parse(data)?.embedded_files(), budget, visit),
+        ArchiveKind::Email => extract_decoded(crate::email::attachments(data)?, budget, visit),
+        ArchiveKind::Mbox => {
+            let messages = crate::email::mbox_messages(data).into_iter().enumerate();
+            extract_decoded(messages.map(|(i, m)| (format!("message-{}.eml", i + 1), m)).collect(), budget, visit)
+        }
+    }
+}
+
+/// `x.tar.gz` holds `x.tar`, `x.tgz` holds `x.tar` too.
+fn stream_member_name(name: &str, extensions: &[&str]) -> String {
+    let lower = name.to_ascii_lowercase();
+    for ext in extensions {
+        if lower.ends_with(&format!(".{}", ext)) {
+            let stem = &name[..name.len() - ext.len() - 1];
+            return if ext.starts_with('t') { format!("{}.tar", stem) } else { stem.to_string() };
+        }
+    }
+    if name.is_empty() { "data".to_string() } else { format!("{}.out", name) }
+}
+
+/// Takes one member slot from the budget, `false` once none are left.
+fn take_member(budget: &mut Budget) -> bool {
+    if budget.members == 0 {
+        return false;
+    }
+    budget.members -= 1;
+    true
+}
+
+/// Reads at most what the budget allows; one byte more tells a member that
+/// fits exactly apart from one that is too big.
+fn read_bounded(reader: &mut dyn Read, compressed: u64, budget: &mut Budget) -> Result<Vec<u8>, SkipReason> {
+    let limit = budget.max_member_size.min(budget.bytes);
+    let mut data = Vec::new();
+    reader
+        .take(limit.saturating_add(1))
+        .read_to_end(&mut data)
+        .map_err(|e| SkipReason::Unreadable(e.to_string()))?;
+
+    let read = data.len() as u64;
+    if read > limit {
+        budget.bytes = budget.bytes.saturating_sub(limit);
+        return Err(if compressed > 0 && read / compressed >= BOMB_RATIO {
+            SkipReason::DecompressionBomb { compressed }
+        } else if limit == budget.max_member_size {
+            SkipReason::MemberTooLarge
+        } else {
+            SkipReason::TotalSizeExceeded
+        });
+    }
+    budget.bytes -= read;
+    Ok(data)
+}
+
+fn extract_zip(data: &[u8], budget: &mut Budget, visit: &mut dyn FnMut(Member, &mut Budget)) -> Result<Option<SkipReason>, String> {
+    let mut zip = zip::ZipArchive::new(Cursor::new(data)).map_err(|e| e.to_string())?;
+    for index in 0..zip.len() {
+        let (name, encrypted, size, compressed) = match zip.by_index_raw(index) {
+            Ok(entry) if !entry.is_file() => continue,
+            Ok(entry) => (entry.name().to_string(), entry.encrypted(), entry.size(), entry.compressed_size()),
+            Err(e) => return Err(e.to_string()),
+        };
+        if !take_member(budget) {
+            return Ok(Some(SkipReason::TooManyMembers));
+        }
+        if encrypted {
+            visit(Member::Encrypted { name, size }, budget);
+            continue;
+        }
+
+        let member = match zip.by_index(index) {
+            Ok(mut entry) => match read_bounded(&mut entry, compressed, budget) {
+                Ok(data) => Member::File { name, data },
+                Err(reason) => Member::Skipped { name, reason },
+            },
+            Err(e) => Member::Skipped { name, reason: SkipReason::Unreadable(e.to_string()) },
+        };
+        let out_of_bytes = matches!(&member, Member::Skipped { reason: SkipReason::TotalSizeExceeded, .. });
+        visit(member, budget);
+        if out_of_bytes {
+            return Ok(Some(SkipReason::TotalSizeExceeded));
+        }
+    }
+    Ok(None)
+}
+
+fn extract_tar(data: &[u8], budget: &mut Budget, visit: &mut dyn FnMut(Member, &mut Budget)) -> Result<Option<SkipReason>, String> {
+    let mut archive = tar::Archive::new(data);
+    for entry in archive.entries().map_err(|e| e.to_string())? {
+        let mut entry = entry.map_err(|e| e.to_string())?;
+        if !entry.header().entry_type().is_file() {
+            continue;
+        }
+        let path = entry.path().map(|p| p.to_string_lossy().to_string()).unwrap_or_default();
+        let name = path.trim_start_matches("./").to_string();
+        if !take_member(budget) {
+            return Ok(Some(SkipReason::TooManyMembers));
+        }
+        match read_bounded(&mut entry, 0, budget) {
+            Ok(data) => visit(Member::File { name, data }, budget),
+            Err(SkipReason::TotalSizeExceeded) => {
+                visit(Member::Skipped { name, reason: SkipReason::TotalSizeExceeded }, budget);
+                return Ok(Some(SkipReason::TotalSizeExceeded));
+            }
+            Err(reason) => visit(Member::Skipped { name, reason }, budget),
+        }
+    }
+    Ok(None)
+}
+
+fn extract_stream(
+    mut reader: impl Read,
+    name: String,
+    data: &[u8],
+    budget: &mut Budget,
+    visit: &mut dyn FnMut(Member, &mut Budget),
+) -> Result<Option<SkipReason>, String> {
+    if !take_member(budget) {
+        return Ok(Some(SkipReason::TooManyMembers));
+    }
+    match read_bounded(&mut reader, data.len() as u64, budget) {
+        Ok(data) => visit(Member::File { name, data }, budget),
+        // A stream that doesn't even start to decode isn't what its magic claims
+        Err(SkipReason::Unreadable(e)) => return Err(e),
+        Err(reason) => visit(Member::Skipped { name, reason }, budget),
+    }
+    Ok(None)
+}
+
+/// Members another module already decoded, still held to the budget.
+pub fn extract_decoded(
+    members: Vec<(String, impl AsRef<[u8]>)>,
+    budget: &mut Budget,
+    visit: &mut dyn FnMut(Member, &mut Budget),
+) -> Result<Option<SkipReason>, String> {
+    for (name, content) in members {
+        if !take_member(budget) {
+            return Ok(Some(SkipReason::TooManyMembers));
+        }
+        match read_bounded(&mut content.as_ref(), 0, budget) {
+            Ok(data) => visit(Member::File { name, data }, budget),
+            Err(SkipReason::TotalSizeExceeded) => {
+                visit(Member::Skipped { name, reason: SkipReason::TotalSizeExceeded }, budget);
+                return Ok(Some(SkipReason::TotalSizeExceeded));
+            }
+            Err(reason) => visit(Member::Skipped { name, reason }, budget),
+        }
+    }
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use zip::write::SimpleFileOptions;
+
+    fn zip(members: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut writer = zip::ZipWriter::new(Cursor::new(Vec::new()));
+        let options = SimpleFileOptions::default().compression_method(zip::CompressionMethod::Stored);
+        for (name, data) in members {
+            writer.start_file(*name, options).unwrap();
+            writer.write_all(data).unwrap();
+        }
+        writer.finish().unwrap().into_inner()
+    }
+
+    fn gzip(data: &[u8]) -> Vec<u8> {
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::best());
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    fn limits(max_members: usize, max_member_size: u64, max_total_size: u64) -> ArchiveLimits {
+        ArchiveLimits { max_members, max_member_size, max_total_size, ..Default::default() }
+    }
+
+    /// Member names, with the reason for the ones that were skipped.
+    fn unpack(kind: ArchiveKind, name: &str, data: &[u8], budget: &mut Budget) -> (Vec<String>, Option<SkipReason>) {
+        let mut seen = Vec::new();
+        let stopped = extract(kind, name, data, budget, &mut |member, _| {
+            seen.push(match member {
+                Member::File { name, data } => format!("{} ({})", name, data.len()),
+                Member::Encrypted { name, .. } => format!("{} (encrypted)", name),
+                Member::Skipped { name, reason } => format!("{}: {}", name, reason),
+            })
+        })
+        .unwrap();
+        (seen, stopped)
+    }
+
+    #[test]
+    fn members_are_held_to_the_limits() {
+        let data = zip(&[("a.txt", &[b'a'; 10]), ("b.bin", &[b'b'; 500]), ("c.txt", &[b'c'; 10])]);
+
+        let mut budget = Budget::new(&limits(2, 1000, 1000));
+        let (seen, stopped) = unpack(ArchiveKind::Zip, "x.zip", &data, &mut budget);
+        assert_eq!(seen, ["a.txt (10)", "b.bin (500)"]);
+        assert_eq!(stopped, Some(SkipReason::TooManyMembers));
+
+        // Too large members are skipped, the rest is still unpacked
+        let mut budget = Budget::new(&limits(10, 100, 1000));
+        let (seen, stopped) = unpack(ArchiveKind::Zip, "x.zip", &data, &mut budget);
+        assert_eq!(seen, ["a.txt (10)", "b.bin: member exceeds the size limit", "c.txt (10)"]);
+        assert_eq!(stopped, None);
+
+        // A member that fits exactly is not too large
+        let mut budget = Budget::new(&limits(10, 500, 520));
+        let (seen, stopped) = unpack(ArchiveKind::Zip, "x.zip", &data, &mut budget);
+        assert_eq!(seen, ["a.txt (10)", "b.bin (500)", "c.txt (10)"]);
+        assert_eq!((stopped, budget.bytes, budget.members), (None, 0, 7));
+    }
+
+    #[test]
+    fn the_budget_is_shared_across_nesting_levels() {
+        let inner = zip(&[("payload.exe", &[b'p'; 300])]);
+        let outer = zip(&[("first.txt", &[b'f'; 300]), ("inner.zip", &inner), ("last.txt", &[b'l'; 10])]);
+
+        // Unpack nested archives from the same budget, as the scanner does
+        fn walk(name: &str, data: &[u8], budget: &mut Budget, seen: &mut Vec<String>) -> Option<SkipReason> {
+            let mut nested = Vec::new();
+            let stopped = extract(ArchiveKind::Zip, name, data, budget, &mut |member, budget| match member {
+                Member::File { name, data } if name.ends_with(".zip") => {
+                    seen.push(name.clone());
+                    nested.push(walk(&name, &data, budget, seen));
+                }
+                Member::File { name, .. } => seen.push(name),
+                Member::Encrypted { name, .. } => seen.push(name),
+                Member::Skipped { name, reason } => seen.push(format!("{}: {}", name, reason)),
+            })
+            .unwrap();
+            nested.into_iter().flatten().next().or(stopped)
+        }
+
+        let mut seen = Vec::new();
+        let mut budget = Budget::new(&limits(10, 1000, 2000));
+        assert_eq!(walk("outer.zip", &outer, &mut budget, &mut seen), None);
+        assert_eq!(seen, ["first.txt", "inner.zip", "payload.exe", "last.txt"]);
+        assert_eq!((budget.members, budget.bytes), (6, 2000 - 300 - inner.len() as u64 - 300 - 10));
+
+        // The inner member no longer fits in what the outer ones left over
+        let mut seen = Vec::new();
+        let mut budget = Budget::new(&limits(10, 1000, 300 + inner.len() as u64 + 100));
+        assert_eq!(walk("outer.zip", &outer, &mut budget, &mut seen), Some(SkipReason::TotalSizeExceeded));
+        let exceeded = "archive exceeds the total decompressed size limit";
+        // Nothing is left for the outer archive's remaining members either
+        let expected = ["first.txt".to_string(), "inner.zip".to_string(), format!("payload.exe: {}", exceeded)];
+        assert_eq!(seen[..3], expected);
+        assert_eq!(seen[3], format!("last.txt: {}", exceeded));
+
+        // Members are counted across levels too
+        let mut seen = Vec::new();
+        let mut budget = Budget::new(&limits(3, 1000, 10_000));
+        assert_eq!(walk("outer.zip", &outer, &mut budget, &mut seen), Some(SkipReason::TooManyMembers));
+        assert_eq!(seen, ["first.txt", "inner.zip", "payload.exe"]);
+    }
+
+    #[test]
+    fn streams_that_expand_too_far_are_bombs() {
+        let data = gzip(&vec![0u8; 4 * 1024 * 1024]);
+        let mut budget = Budget::new(&limits(10, 1024 * 1024, u64::MAX));
+        let mut skipped = None;
+        extract(ArchiveKind::Gzip, "zeros.bin.gz", &data, &mut budget, &mut |member, _| {
+            if let Member::Skipped { name, reason } = member {
+                skipped = Some((name, reason));
+            }
+        })
+        .unwrap();
+        let bomb = SkipReason::DecompressionBomb { compressed: data.len() as u64 };
+        assert_eq!(skipped, Some(("zeros.bin".to_string(), bomb)));
+
+        // Plain large content that doesn't compress well is only too large
+        let noise: Vec<u8> = (0..4096u32).map(|i| (i.wrapping_mul(2_654_435_761) >> 13) as u8).collect();
+        let mut budget = Budget::new(&limits(10, 1024, u64::MAX));
+        let (seen, _) = unpack(ArchiveKind::Gzip, "noise.gz", &gzip(&noise), &mut budget);
+        assert_eq!(seen, ["noise: member exceeds the size limit"]);
+
+        // Not gzip past the magic
+        let mut budget = Budget::new(&ArchiveLimits::default());
+        assert!(extract(ArchiveKind::Gzip, "bad.gz", b"\x1f\x8bnot really", &mut budget, &mut |_, _| {}).is_err());
+    }
+
+    #[test]
+    fn streams_are_named_after_the_archive() {
+        assert_eq!(stream_member_name("logs.tar.gz", &["tgz", "gz"]), "logs.tar");
+        assert_eq!(stream_member_name("logs.TGZ", &["tgz", "gz"]), "logs.tar");
+        assert_eq!(stream_member_name("report.pdf.xz", &["txz", "xz"]), "report.pdf");
+        assert_eq!(stream_member_name("download", &["tgz", "gz"]), "download.out");
+        assert_eq!(stream_member_name("", &["gz"]), "data");
+    }
+}
@@ -0,0 +1,517 @@
+//! ClamAV signature database import.
+//!
+//! Reads `.cvd`/`.cld` containers (a 512-byte text header followed by a
+//! tar archive, gzip-compressed for `.cvd`) as well as loose database files,
+//! and converts the supported signature formats into [`SignatureDb`] entries:
+//!
+//! * `.hdb`/`.hsb` (and PUA `.hdu`/`.hsu`): MD5, SHA-1 and SHA-256 hashes
+//! * `.ndb`/`.ndu`: extended body signatures with target type and offset
+//! * `.ldb`/`.ldu`: logical signatures whose sub-signatures are plain body
+//!   patterns and whose target block only uses `Target` and `Engine`
+//!
+//! The MD5 recorded in a `.cvd` header is checked against the archive body;
+//! the RSA digital signature is not verified.
+//!
+//! Anything else (PE section hashes, PCRE sub-signatures, entry-point relative
+//! offsets, bytecode, phishing lists, ...) is counted in the load summary
+//! under a descriptive reason instead of being dropped silently.
+
+use flate2::read::GzDecoder;
+use md5::{Digest, Md5};
+use std::fs;
+use std::io::Read;
+use std::path::Path;
+
+use crate::signatures::{CountOp, LoadSummary, LogicalExpr, PatternSignature, Severity, SignatureDb, Target};
+
+const CVD_HEADER_LEN: usize = 512;
+
+/// Per-file cap on reported line errors, large databases can otherwise
+/// flood the summary.
+const MAX_LINE_ERRORS: usize = 20;
+
+/// Parenthesis nesting allowed in a logical expression. Real signatures
+/// stay in single digits; the limit keeps a crafted one off the stack.
+const MAX_EXPR_DEPTH: usize = 32;
+
+const DATABASE_EXTENSIONS: [&str; 30] = [
+    "cvd", "cld", "cud", "hdb", "hsb", "hdu", "hsu", "mdb", "msb", "mdu", "msu", "ndb", "ndu", "ldb", "ldu", "idb",
+    "cdb", "crb", "cbc", "pdb", "gdb", "wdb", "ftm", "fp", "sfp", "ign", "ign2", "info", "cfg", "imp",
+];
+
+pub fn is_database(path: &Path) -> bool {
+    path.extension()
+        .and_then(|e| e.to_str())
+        .is_some_and(|e| DATABASE_EXTENSIONS.contains(&e.to_ascii_lowercase().as_str()))
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CvdHeader {
+    pub build_time: String,
+    pub version: u32,
+    pub signatures: u64,
+    pub functionality_level: u32,
+    pub md5: String,
+    pub builder: String,
+}
+
+pub fn parse_cvd_header(data: &[u8]) -> Result<CvdHeader, String> {
+    let header = data.get(..CVD_HEADER_LEN).ok_or("file too short for a CVD header")?;
+    let header = String::from_utf8_lossy(header);
+    let fields: Vec<&str> = header.trim_end_matches(['\0', ' ']).split(':').collect();
+
+    if fields.first() != Some(&"ClamAV-VDB") || fields.len() < 8 {
+        return Err("missing ClamAV-VDB header".to_string());
+    }
+
+    let number = |i: usize, what: &str| -> Result<u64, String> {
+        fields[i].trim().parse::<u64>().map_err(|_| format!("invalid {} in CVD header", what))
+    };
+
+    Ok(CvdHeader {
+        build_time: fields[1].trim().to_string(),
+        version: number(2, "version")? as u32,
+        signatures: number(3, "signature count")?,
+        functionality_level: number(4, "functionality level")? as u32,
+        md5: fields[5].trim().to_ascii_lowercase(),
+        builder: fields[7].trim().to_string(),
+    })
+}
+
+pub fn load_file(db: &mut SignatureDb, path: &Path, summary: &mut LoadSummary) -> Result<(), String> {
+    let name = path.file_name().and_then(|n| n.to_str()).unwrap_or("").to_string();
+    let extension = path.extension().and_then(|e| e.to_str()).unwrap_or("").to_ascii_lowercase();
+    let data = fs::read(path).map_err(|e| e.to_string())?;
+
+    match extension.as_str() {
+        "cvd" | "cld" => load_container(db, &data, extension == "cvd", summary),
+        "cud" => {
+            summary.skip("incremental .cud update");
+            Ok(())
+        }
+        _ => {
+            load_database(db, &name, &data, summary);
+            Ok(())
+        }
+    }
+}
+
+/// Unpacks a CVD/CLD container and loads every database file inside it.
+pub fn load_container(db: &mut SignatureDb, data: &[u8], verify_md5: bool, summary: &mut LoadSummary) -> Result<(), String> {
+    let header = parse_cvd_header(data)?;
+    let body = &data[CVD_HEADER_LEN..];
+
+    if verify_md5 {
+        let actual = hex::encode(Md5::digest(body));
+        if actual != header.md5 {
+            return Err(format!("MD5 mismatch in CVD body (expected {}, got {})", header.md5, actual));
+        }
+    }
+
+    let reader: Box<dyn Read + '_> = if body.starts_with(&[0x1f, 0x8b]) {
+        Box::new(GzDecoder::new(body))
+    } else {
+        Box::new(body)
+    };
+
+    let mut archive = tar::Archive::new(reader);
+    let entries = archive.entries().map_err(|e| format!("invalid tar archive: {}", e))?;
+    for entry in entries {
+        let mut entry = entry.map_err(|e| format!("invalid tar entry: {}", e))?;
+        if !entry.header().entry_type().is_file() {
+            continue;
+        }
+        let name = entry
+            .path()
+            .map(|p| p.to_string_lossy().to_string())
+            .map_err(|e| format!("invalid tar entry name: {}", e))?;
+        let mut contents = Vec::new();
+        entry
+            .read_to_end(&mut contents)
+            .map_err(|e| format!("failed to read {}: {}", name, e))?;
+        load_database(db, &name, &contents, summary);
+    }
+
+    Ok(())
+}
+
+type LineLoader = fn(&mut SignatureDb, &str, Severity, &mut LoadSummary) -> Result<(), String>;
+
+/// Loads one text database, dispatching on its extension.
+pub fn load_database(db: &mut SignatureDb, name: &str, data: &[u8], summary: &mut LoadSummary) {
+    let extension = Path::new(name)
+        .extension()
+        .and_then(|e| e.to_str())
+        .unwrap_or("")
+        .to_ascii_lowercase();
+
+    let (loader, severity): (LineLoader, Severity) =
+        match extension.as_str() {
+            "hdb" | "hsb" => (load_hash_line, Severity::Threat),
+            "hdu" | "hsu" => (load_hash_line, Severity::Suspicious),
+            "ndb" => (load_body_line, Severity::Threat),
+            "ndu" => (load_body_line, Severity::Suspicious),
+            "ldb" => (load_logical_line, Severity::Threat),
+            "ldu" => (load_logical_line, Severity::Suspicious),
+            // Metadata shipped alongside the signatures, nothing to report
+            "info" | "cfg" | "" => return,
+            "mdb" | "msb" | "mdu" | "msu" | "imp" => {
+                summary.skip(&format!("PE section/import hash database (.{})", extension));
+                return;
+            }
+            "fp" | "sfp" | "ign" | "ign2" => {
+                summary.skip(&format!("allow/ignore list (.{})", extension));
+                return;
+            }
+            other => {
+                summary.skip(&format!("unsupported database type (.{})", other));
+                return;
+            }
+        };
+
+    let text = String::from_utf8_lossy(data);
+    let mut line_errors = 0;
+    for (number, line) in text.lines().enumerate() {
+        let line = line.trim_end_matches('\r');
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        if let Err(e) = loader(db, line, severity, summary) {
+            line_errors += 1;
+            if line_errors <= MAX_LINE_ERRORS {
+                summary.errors.push(format!("{}:{}: {}", name, number + 1, e));
+            }
+        }
+    }
+    if line_errors > MAX_LINE_ERRORS {
+        summary.errors.push(format!("{}: {} more invalid lines", name, line_errors - MAX_LINE_ERRORS));
+    }
+}
+
+/// `HashString:FileSize:MalwareName[:MinFL]`, the size may be `*`.
+fn load_hash_line(db: &mut SignatureDb, line: &str, severity: Severity, summary: &mut LoadSummary) -> Result<(), String> {
+    let fields: Vec<&str> = line.split(':').collect();
+    if fields.len() < 3 {
+        return Err("expected hash:size:name".to_string());
+    }
+    let size = match fields[1] {
+        "*" => None,
+        size => Some(size.parse::<u64>().map_err(|_| format!("invalid file size '{}'", size))?),
+    };
+    db.add_hash(fields[0], fields[2], size, severity)?;
+    summary.hash_signatures += 1;
+    Ok(())
+}
+
+/// ClamAV offsets other than absolute/`EOF-n` depend on executable parsing
+/// (`EP+n`, `Sx+n`, `SL+n`, `SEx`, `VI`) and aren't supported.
+fn is_supported_offset(offset: &str) -> bool {
+    let base = offset.split(',').next().unwrap_or("");
+    base == "*" || base.starts_with("EOF-") || base.bytes().all(|b| b.is_ascii_digit())
+}
+
+/// Alternatives, negations, anchored byte ranges and boundary markers are
+/// valid ClamAV syntax the pattern matcher doesn't implement.
+fn is_supported_hex(hex: &str) -> bool {
+    !hex.contains(['(', ')', '[', ']', '!', '|'])
+}
+
+/// `MalwareName:TargetType:Offset:HexSignature[:MinFL[:MaxFL]]`
+fn load_body_line(db: &mut SignatureDb, line: &str, severity: Severity, summary: &mut LoadSummary) -> Result<(), String> {
+    let fields: Vec<&str> = line.split(':').collect();
+    if fields.len() < 4 {
+        return Err("expected name:target:offset:hex".to_string());
+    }
+    let (name, target, offset, hex) = (fields[0], fields[1], fields[2], fields[3]);
+
+    let target = match target.parse::<u32>().ok().map(Target::from_clamav) {
+        Some(Some(target)) => target,
+        Some(None) => {
+            summary.skip("body signature with unsupported target type");
+            return Ok(());
+        }
+        None => return Err(format!("invalid target type '{}'", target)),
+    };
+    if !is_supported_offset(offset) {
+        summary.skip("body signature with executable-relative offset");
+        return Ok(());
+    }
+    if !is_supported_hex(hex) {
+        summary.skip("body signature with alternatives/negation");
+        return Ok(());
+    }
+
+    let mut signature = PatternSignature::parse(name, hex, offset, severity)?;
+    if !signature.is_anchored() {
+        summary.skip("body signature without a literal to anchor on");
+        return Ok(());
+    }
+    signature.target = target;
+    db.add_pattern(signature);
+    summary.pattern_signatures += 1;
+    Ok(())
+}
+
+/// `SignatureName;TargetDescriptionBlock;LogicalExpression;Subsig0;Subsig1;...`
+fn load_logical_line(db: &mut SignatureDb, line: &str, severity: Severity, summary: &mut LoadSummary) -> Result<(), String> {
+    let fields: Vec<&str> = line.split(';').collect();
+    if fields.len() < 4 {
+        return Err("expected name;target block;expression;subsignatures".to_string());
+    }
+    let name = fields[0];
+
+    let mut target = Target::Any;
+    for item in fields[1].split(',') {
+        let (key, value) = item.split_once(':').ok_or_else(|| format!("invalid target block entry '{}'", item))?;
+        match key {
+            "Target" => {
+                target = match value.parse::<u32>().ok().and_then(Target::from_clamav) {
+                    Some(target) => target,
+                    None => {
+                        summary.skip("logical signature with unsupported target type");
+                        return Ok(());
+                    }
+                }
+            }
+            // Engine functionality levels only gate old ClamAV versions
+            "Engine" => {}
+            _ => {
+                summary.skip(&format!("logical signature with '{}' condition", key));
+                return Ok(());
+            }
+        }
+    }
+
+    let expr = match parse_logical_expr(fields[2]) {
+        Ok(expr) => expr,
+        Err(e) => return Err(format!("invalid logical expression '{}': {}", fields[2], e)),
+    };
+
+    let mut subsigs = Vec::new();
+    for (i, raw) in fields[3..].iter().enumerate() {
+        // PCRE, byte-compare, fuzzy-image and modifier-carrying sub-signatures
+        if raw.contains(['/', '#']) || raw.contains("::") {
+            summary.skip("logical signature with PCRE/byte-compare/modified sub-signature");
+            return Ok(());
+        }
+        let (offset, hex) = match raw.split_once(':') {
+            Some((offset, hex)) => (offset, hex),
+            None => ("*", *raw),
+        };
+        if !is_supported_offset(offset) {
+            summary.skip("logical signature with executable-relative offset");
+            return Ok(());
+        }
+        if !is_supported_hex(hex) {
+            summary.skip("logical signature with alternatives/negation");
+            return Ok(());
+        }
+        let signature = PatternSignature::parse(&format!("{}/{}", name, i), hex, offset, severity)
+            .map_err(|e| format!("sub-signature {}: {}", i, e))?;
+        if !signature.is_anchored() {
+            summary.skip("logical signature with a sub-signature without a literal to anchor on");
+            return Ok(());
+        }
+        subsigs.push(signature);
+    }
+
+    if expr.max_subsig().is_some_and(|max| max >= subsigs.len()) {
+        return Err("logical expression references a missing sub-signature".to_string());
+    }
+
+    db.add_logical(name, severity, target, expr, subsigs);
+    summary.logical_signatures += 1;
+    Ok(())
+}
+
+/// Parses logical expressions such as `0&1`, `(0|1|2)>1,2` or `0&(1|2)=3`.
+/// `&` binds tighter than `|`.
+pub fn parse_logical_expr(src: &str) -> Result<LogicalExpr, String> {
+    let chars: Vec<char> = src.chars().filter(|c| !c.is_whitespace()).collect();
+    let mut pos = 0;
+    let expr = parse_or(&chars, &mut pos, 0)?;
+    if pos != chars.len() {
+        return Err(format!("unexpected '{}' at position {}", chars[pos], pos));
+    }
+    Ok(expr)
+}
+
+fn parse_or(chars: &[char], pos: &mut usize, depth: usize) -> Result<LogicalExpr, String> {
+    let mut items = vec![parse_and(chars, pos, depth)?];
+    while chars.get(*pos) == Some(&'|') {
+        *pos += 1;
+        items.push(parse_and(chars, pos, depth)?);
+    }
+    Ok(if items.len() == 1 { items.remove(0) } else { LogicalExpr::Or(items) })
+}
+
+fn parse_and(chars: &[char], pos: &mut usize, depth: usize) -> Result<LogicalExpr, String> {
+    let mut items = vec![parse_term(chars, pos, depth)?];
+    while chars.get(*pos) == Some(&'&') {
+        *pos += 1;
+        items.push(parse_term(chars, pos, depth)?);
+    }
+    Ok(if items.len() == 1 { items.remove(0) } else { LogicalExpr::And(items) })
+}
+
+fn parse_number(chars: &[char], pos: &mut usize) -> Result<usize, String> {
+    let start = *pos;
+    while chars.get(*pos).is_some_and(|c| c.is_ascii_digit()) {
+        *pos += 1;
+    }
+    if start == *pos {
+        return Err(format!("expected a number at position {}", start));
+    }
+    chars[start..*pos].iter().collect::<String>().parse().map_err(|_| "number out of range".to_string())
+}
+
+fn parse_term(chars: &[char], pos: &mut usize, depth: usize) -> Result<LogicalExpr, String> {
+    let atom = if chars.get(*pos) == Some(&'(') {
+        if depth == MAX_EXPR_DEPTH {
+            return Err(format!("parentheses nested deeper than {}", MAX_EXPR_DEPTH));
+        }
+        *pos += 1;
+        let inner = parse_or(chars, pos, depth + 1)?;
+        if chars.get(*pos) != Some(&')') {
+            return Err("missing ')'".to_string());
+        }
+        *pos += 1;
+        inner
+    } else {
+        LogicalExpr::Sub(parse_number(chars, pos)?)
+    };
+
+    let op = match chars.get(*pos) {
+        Some('=') => CountOp::Eq,
+        Some('>') => CountOp::Gt,
+        Some('<') => CountOp::Lt,
+        _ => return Ok(atom),
+    };
+    *pos += 1;
+    let value = parse_number(chars, pos)?;
+    let distinct = if chars.get(*pos) == Some(&',') {
+        *pos += 1;
+        Some(parse_number(chars, pos)?)
+    } else {
+        None
+    };
+
+    Ok(LogicalExpr::Count { expr: Box::new(atom), op, value, distinct })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hashing;
+
+    const EICAR: &[u8] = br"X5O!P%@AP[4\PZX54(P^)7CC)7}$EICAR-STANDARD-ANTIVIRUS-TEST-FILE!$H+H*";
+
+    fn load(name: &str, text: &str) -> (SignatureDb, LoadSummary) {
+        let mut db = SignatureDb::new();
+        let mut summary = LoadSummary::default();
+        load_database(&mut db, name, text.as_bytes(), &mut summary);
+        db.build();
+        (db, summary)
+    }
+
+    fn names(db: &SignatureDb, data: &[u8]) -> Vec<String> {
+        db.match_patterns(data).into_iter().map(|d| d.name).collect()
+    }
+
+    #[test]
+    fn hash_databases() {
+        let (db, summary) = load(
+            "test.hdb",
+            "# comment\n\
+             44d88612fea8a8f36de82e1278abb02f:68:Eicar-Test-Signature\n\
+             275a021bbfb6489e54d471899f7db9d1663fc695ec2fe2a2c4538aabf651fd0f:*:Eicar-SHA256\n\
+             not-a-hash:68:Broken\n\
+             44d88612fea8a8f36de82e1278abb02f:many:Broken",
+        );
+        assert_eq!(summary.hash_signatures, 2);
+        assert_eq!(summary.errors.len(), 2);
+        assert!(summary.errors[0].starts_with("test.hdb:4: "));
+        assert_eq!(summary.errors[1], "test.hdb:5: invalid file size 'many'");
+
+        let digests = hashing::digest_bytes(EICAR);
+        let found: Vec<String> = db.match_hashes(&digests, 68).into_iter().map(|d| d.name).collect();
+        assert_eq!(found, ["Eicar-Test-Signature", "Eicar-SHA256"]);
+        // `*` matches any size, a recorded size has to agree
+        let found: Vec<String> = db.match_hashes(&digests, 69).into_iter().map(|d| d.name).collect();
+        assert_eq!(found, ["Eicar-SHA256"]);
+
+        let (db, _) = load("test.hdu", "44d88612fea8a8f36de82e1278abb02f:68:Eicar-PUA");
+        assert_eq!(db.match_hashes(&digests, 68)[0].severity, Severity::Suspicious);
+    }
+
+    #[test]
+    fn body_databases() {
+        let (db, summary) = load(
+            "test.ndb",
+            "Test.Anywhere:0:*:4d5a{2}0000\n\
+             Test.AtStart:0:0:cafebabe\n\
+             Test.AtEnd:0:EOF-4:deadbeef\n\
+             Test.Elf:6:*:4d5a\n\
+             Test.EntryPoint:1:EP+0:4d5a\n\
+             Test.Alternative:0:*:4d5a(90|91)\n\
+             Test.Gaps:0:*:4d5a*0000\n\
+             Test.Unanchored:0:*:4d*5a\n\
+             Test.Broken:x:*:4d5a",
+        );
+        assert_eq!(summary.pattern_signatures, 5);
+        assert_eq!(summary.unsupported.get("body signature with executable-relative offset"), Some(&1));
+        assert_eq!(summary.unsupported.get("body signature with alternatives/negation"), Some(&1));
+        assert_eq!(summary.unsupported.get("body signature without a literal to anchor on"), Some(&1));
+        assert_eq!(summary.errors, ["test.ndb:9: invalid target type 'x'"]);
+
+        assert_eq!(names(&db, b"\xca\xfe\xba\xbe..MZ\x90\x00\x00\x00..\xde\xad\xbe\xef"), [
+            "Test.Anywhere",
+            "Test.AtStart",
+            "Test.AtEnd",
+            "Test.Gaps",
+        ]);
+        assert!(names(&db, b"..\xca\xfe\xba\xbe\xde\xad\xbe\xef..").is_empty());
+        assert_eq!(names(&db, b"\x7fELF MZ"), ["Test.Elf"]);
+    }
+
+    #[test]
+    fn logical_databases() {
+        let (db, summary) = load(
+            "test.ldb",
+            "Test.Both;Engine:51-255,Target:0;0&1;414243;444546\n\
+             Test.Either;Target:0;0|1;0:474849;4a4b4c\n\
+             Test.Twice;Target:0;0>1;4d4e4f\n\
+             Test.Distinct;Target:0;(0|1|2)>1,2;505152;535455;565758\n\
+             Test.Icon;Target:1,IconGroup1:x;0;414243\n\
+             Test.Pcre;Target:0;0&1;414243;0/abc/\n\
+             Test.Missing;Target:0;0&2;414243;444546",
+        );
+        assert_eq!(summary.logical_signatures, 4);
+        assert_eq!(summary.unsupported.get("logical signature with 'IconGroup1' condition"), Some(&1));
+        assert_eq!(summary.unsupported.get("logical signature with PCRE/byte-compare/modified sub-signature"), Some(&1));
+        assert_eq!(summary.errors, ["test.ldb:7: logical expression references a missing sub-signature"]);
+
+        assert_eq!(names(&db, b"ABC DEF"), ["Test.Both"]);
+        assert_eq!(names(&db, b"GHI JKL"), ["Test.Either"]);
+        // Sub-signature 0 of Test.Either is anchored at offset 0
+        assert!(names(&db, b" GHI").is_empty());
+        assert_eq!(names(&db, b"MNO MNO"), ["Test.Twice"]);
+        assert!(names(&db, b"PQR PQR").is_empty());
+        assert_eq!(names(&db, b"PQR STU"), ["Test.Distinct"]);
+    }
+
+    #[test]
+    fn logical_expressions() {
+        assert_eq!(parse_logical_expr("0&1)").unwrap_err(), "unexpected ')' at position 3");
+        assert_eq!(parse_logical_expr("0&(1|2)=3|3").unwrap().max_subsig(), Some(3));
+        assert_eq!(parse_logical_expr("(0|1").unwrap_err(), "missing ')'");
+        assert_eq!(parse_logical_expr("0&").unwrap_err(), "expected a number at position 2");
+
+        let nested = |depth: usize| format!("{}0{}", "(".repeat(depth), ")".repeat(depth));
+        assert!(parse_logical_expr(&nested(MAX_EXPR_DEPTH)).is_ok());
+        assert_eq!(
+            parse_logical_expr(&nested(100_000)).unwrap_err(),
+            format!("parentheses nested deeper than {}", MAX_EXPR_DEPTH)
+        );
+    }
+}
@@ -0,0 +1,435 @@
+//! Static analysis of ELF binaries.
+//!
+//! Headers, segments, sections, dynamic symbols and the dynamic section come
+//! from `goblin`. On top of that every loadable segment and section is
+//! measured for entropy, UPX is recognised from its loader stub, and the
+//! layout is cross-checked: the entry point must lie in an executable
+//! segment and allocated sections must be covered by a `PT_LOAD` segment.
+//! Hand-crafted and packed binaries routinely break these rules while
+//! linker output never does.
+
+use goblin::elf::dynamic::{DF_1_NOW, DF_1_PIE, DF_BIND_NOW, DT_BIND_NOW, DT_FLAGS, DT_FLAGS_1, DT_TEXTREL};
+use goblin::elf::header::{et_to_str, machine_to_str, ET_DYN, ET_EXEC};
+use goblin::elf::program_header::{pt_to_str, ProgramHeader, PF_R, PF_W, PF_X, PT_GNU_RELRO, PT_GNU_STACK, PT_LOAD};
+use goblin::elf::section_header::{sht_to_str, SectionHeader, SHF_ALLOC, SHF_EXECINSTR, SHF_WRITE, SHT_NOBITS};
+use goblin::elf::sym::{STB_GLOBAL, STB_WEAK};
+use goblin::elf::Elf;
+use serde::{Deserialize, Serialize};
+
+use crate::entropy;
+use crate::signatures::Detection;
+
+const MIN_PACKED_SEGMENT_SIZE: u64 = 1024;
+/// Cap on the symbols listed, stripped binaries can still export thousands.
+const MAX_SYMBOLS: usize = 4096;
+/// Directories a legitimate program interpreter is installed in.
+const INTERPRETER_DIRS: [&str; 5] = ["/lib/", "/lib64/", "/usr/lib/", "/usr/lib64/", "/system/bin/"];
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ElfSegment {
+    pub kind: String,
+    pub offset: u64,
+    pub virtual_address: u64,
+    pub file_size: u64,
+    pub memory_size: u64,
+    /// `rwx` style summary of `p_flags`.
+    pub permissions: String,
+    pub entropy: f64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ElfSection {
+    pub name: String,
+    pub kind: String,
+    pub address: u64,
+    pub offset: u64,
+    pub size: u64,
+    /// `wax` style summary of the write/alloc/exec flags.
+    pub flags: String,
+    pub entropy: f64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ElfAnalysis {
+    pub class: String,
+    pub endianness: String,
+    pub file_type: String,
+    pub machine: String,
+    pub entry_point: u64,
+    pub interpreter: Option<String>,
+    pub soname: Option<String>,
+    pub needed_libraries: Vec<String>,
+    pub rpath: Vec<String>,
+    pub runpath: Vec<String>,
+    /// No `.symtab`, only the dynamic symbols survive.
+    pub stripped: bool,
+    pub pie: bool,
+    /// `none`, `partial` or `full`.
+    pub relro: String,
+    pub executable_stack: bool,
+    pub segments: Vec<ElfSegment>,
+    pub sections: Vec<ElfSection>,
+    pub imported_symbols: Vec<String>,
+    pub exported_symbols: Vec<String>,
+    pub packer: Option<String>,
+    pub findings: Vec<Detection>,
+}
+
+pub fn analyze(data: &[u8]) -> Result<ElfAnalysis, String> {
+    let elf = Elf::parse(data).map_err(|e| format!("Not a valid ELF file: {}", e))?;
+
+    let segments: Vec<ElfSegment> = elf.program_headers.iter().map(|ph| segment_info(ph, data)).collect();
+    let sections: Vec<ElfSection> = elf
+        .section_headers
+        .iter()
+        .filter(|sh| sh.sh_type != 0)
+        .map(|sh| section_info(&elf, sh, data))
+        .collect();
+
+    let mut imported_symbols = Vec::new();
+    let mut exported_symbols = Vec::new();
+    for sym in elf.dynsyms.iter() {
+        let bind = sym.st_bind();
+        if bind != STB_GLOBAL && bind != STB_WEAK {
+            continue;
+        }
+        let Some(name) = elf.dynstrtab.get_at(sym.st_name).filter(|n| !n.is_empty()) else { continue };
+        let list = if sym.st_shndx == 0 { &mut imported_symbols } else { &mut exported_symbols };
+        if list.len() < MAX_SYMBOLS {
+            list.push(name.to_string());
+        }
+    }
+
+    let dynamic_value = |tag: u64| {
+        elf.dynamic.as_ref().and_then(|d| d.dyns.iter().find(|dyn_| dyn_.d_tag == tag)).map(|dyn_| dyn_.d_val)
+    };
+    let bind_now = dynamic_value(DT_BIND_NOW).is_some()
+        || dynamic_value(DT_FLAGS).is_some_and(|f| f & DF_BIND_NOW != 0)
+        || dynamic_value(DT_FLAGS_1).is_some_and(|f| f & DF_1_NOW != 0);
+    let has_relro = elf.program_headers.iter().any(|ph| ph.p_type == PT_GNU_RELRO);
+    let relro = match (has_relro, bind_now) {
+        (false, _) => "none",
+        (true, false) => "partial",
+        (true, true) => "full",
+    };
+
+    let mut analysis = ElfAnalysis {
+        class: if elf.is_64 { "ELF64" } else { "ELF32" }.to_string(),
+        endianness: if elf.little_endian { "little" } else { "big" }.to_string(),
+        file_type: et_to_str(elf.header.e_type).to_string(),
+        machine: machine_to_str(elf.header.e_machine).to_string(),
+        entry_point: elf.entry,
+        interpreter: elf.interpreter.map(str::to_string),
+        soname: elf.soname.map(str::to_string),
+        needed_libraries: elf.libraries.iter().map(|l| l.to_string()).collect(),
+        rpath: elf.rpaths.iter().map(|p| p.to_string()).collect(),
+        runpath: elf.runpaths.iter().map(|p| p.to_string()).collect(),
+        stripped: elf.syms.is_empty(),
+        pie: elf.header.e_type == ET_DYN
+            && (elf.interpreter.is_some() || dynamic_value(DT_FLAGS_1).is_some_and(|f| f & DF_1_PIE != 0)),
+        relro: relro.to_string(),
+        executable_stack: elf.program_headers.iter().any(|ph| ph.p_type == PT_GNU_STACK && ph.p_flags & PF_X != 0),
+        segments,
+        sections,
+        imported_symbols,
+        exported_symbols,
+        packer: detect_packer(data),
+        findings: Vec::new(),
+    };
+    analysis.findings = findings(&elf, &analysis, data, dynamic_value(DT_TEXTREL).is_some());
+    Ok(analysis)
+}
+
+fn file_slice(data: &[u8], offset: u64, size: u64) -> &[u8] {
+    let start = (offset as usize).min(data.len());
+    let end = start.saturating_add(size as usize).min(data.len());
+    &data[start..end]
+}
+
+fn segment_info(ph: &ProgramHeader, data: &[u8]) -> ElfSegment {
+    let flag = |bit: u32, ch: char| if ph.p_flags & bit != 0 { ch } else { '-' };
+    ElfSegment {
+        kind: pt_to_str(ph.p_type).trim_start_matches("PT_").to_string(),
+        offset: ph.p_offset,
+        virtual_address: ph.p_vaddr,
+        file_size: ph.p_filesz,
+        memory_size: ph.p_memsz,
+        permissions: [flag(PF_R, 'r'), flag(PF_W, 'w'), flag(PF_X, 'x')].iter().collect(),
+        entropy: entropy::shannon(file_slice(data, ph.p_offset, ph.p_filesz)),
+    }
+}
+
+fn section_info(elf: &Elf, sh: &SectionHeader, data: &[u8]) -> ElfSection {
+    let flag = |bit: u32, ch: char| if sh.sh_flags & bit as u64 != 0 { ch } else { '-' };
+    let bytes = if sh.sh_type == SHT_NOBITS { &[][..] } else { file_slice(data, sh.sh_offset, sh.sh_size) };
+    ElfSection {
+        name: elf.shdr_strtab.get_at(sh.sh_name).unwrap_or("").to_string(),
+        kind: sht_to_str(sh.sh_type).trim_start_matches("SHT_").to_string(),
+        address: sh.sh_addr,
+        offset: sh.sh_offset,
+        size: sh.sh_size,
+        flags: [flag(SHF_WRITE, 'w'), flag(SHF_ALLOC, 'a'), flag(SHF_EXECINSTR, 'x')].iter().collect(),
+        entropy: entropy::shannon(bytes),
+    }
+}
+
+/// UPX leaves its `UPX!` header behind the program headers and an info
+/// string in the stub; other packers are recognised by their stub strings.
+fn detect_packer(data: &[u8]) -> Option<String> {
+    let head = &data[..data.len().min(4096)];
+    let contains = |haystack: &[u8], needle: &[u8]| haystack.windows(needle.len()).any(|w| w == needle);
+    if contains(head, b"UPX!") || contains(data, b"This file is packed with the UPX") {
+        return Some("UPX".to_string());
+    }
+    [(&b"MPRESS"[..], "MPRESS"), (b"Ezuri", "Ezuri"), (b"$Id: burneye", "Burneye")]
+        .iter()
+        .find(|(marker, _)| contains(head, marker))
+        .map(|(_, name)| name.to_string())
+}
+
+fn findings(elf: &Elf, analysis: &ElfAnalysis, data: &[u8], textrel: bool) -> Vec<Detection> {
+    let mut findings = Vec::new();
+    let loads: Vec<&ProgramHeader> = elf.program_headers.iter().filter(|ph| ph.p_type == PT_LOAD).collect();
+
+    if let Some(packer) = &analysis.packer {
+        findings.push(Detection::heuristic(
+            format!("Heuristic.ELF.Packer.{}", packer),
+            "elf",
+            format!("Binary carries the {} packer stub", packer),
+        ));
+    }
+
+    if analysis.executable_stack {
+        findings.push(Detection::heuristic(
+            "Heuristic.ELF.ExecutableStack",
+            "elf",
+            "PT_GNU_STACK requests an executable stack",
+        ));
+    }
+
+    if loads.iter().any(|ph| ph.p_flags & PF_W != 0 && ph.p_flags & PF_X != 0) {
+        findings.push(Detection::heuristic(
+            "Heuristic.ELF.WritableExecutableSegment",
+            "elf",
+            "A loadable segment is both writable and executable",
+        ));
+    }
+
+    if let Some(segment) = loads.iter().find(|ph| {
+        ph.p_flags & PF_X != 0
+            && ph.p_filesz >= MIN_PACKED_SEGMENT_SIZE
+            && entropy::shannon(file_slice(data, ph.p_offset, ph.p_filesz)) >= entropy::PACKED
+    }) {
+        findings.push(Detection::heuristic(
+            "Heuristic.ELF.PackedSegment",
+            "elf",
+            format!(
+                "Executable segment at 0x{:x} has entropy {:.2}",
+                segment.p_vaddr,
+                entropy::shannon(file_slice(data, segment.p_offset, segment.p_filesz))
+            ),
+        ));
+    }
+
+    let is_program = matches!(elf.header.e_type, ET_EXEC | ET_DYN);
+    if is_program && elf.entry != 0 && !loads.iter().any(|ph| ph.p_flags & PF_X != 0 && ph.vm_range().contains(&(elf.entry as usize))) {
+        findings.push(Detection::heuristic(
+            "Heuristic.ELF.EntryPointAnomaly",
+            "elf",
+            format!("Entry point 0x{:x} lies outside every executable segment", elf.entry),
+        ));
+    }
+
+    if is_program && !loads.is_empty() {
+        if elf.section_headers.is_empty() {
+            findings.push(Detection::heuristic(
+                "Heuristic.ELF.NoSectionHeaders",
+                "elf",
+                "Section header table removed, common for packed or hand-crafted binaries",
+            ));
+        }
+        let mismatched: Vec<&str> = elf
+            .section_headers
+            .iter()
+            .filter(|sh| sh.sh_flags & SHF_ALLOC as u64 != 0 && sh.sh_size > 0 && sh.sh_addr != 0)
+            .filter(|sh| {
+                let end = sh.sh_addr.saturating_add(sh.sh_size);
+                !loads.iter().any(|ph| sh.sh_addr >= ph.p_vaddr && end <= ph.p_vaddr.saturating_add(ph.p_memsz))
+            })
+            .map(|sh| elf.shdr_strtab.get_at(sh.sh_name).unwrap_or("?"))
+            .collect();
+        if !mismatched.is_empty() {
+            findings.push(Detection::heuristic(
+                "Heuristic.ELF.SectionSegmentMismatch",
+                "elf",
+                format!("Allocated sections outside every loadable segment: {}", mismatched.join(", ")),
+            ));
+        }
+    }
+
+    if textrel {
+        findings.push(Detection::heuristic(
+            "Heuristic.ELF.TextRelocations",
+            "elf",
+            "Dynamic section requests relocations in read-only code",
+        ));
+    }
+
+    if let Some(interpreter) = &analysis.interpreter {
+        if !INTERPRETER_DIRS.iter().any(|dir| interpreter.starts_with(dir)) {
+            findings.push(Detection::heuristic(
+                "Heuristic.ELF.UnusualInterpreter",
+                "elf",
+                format!("Program interpreter {} is outside the system library directories", interpreter),
+            ));
+        }
+    }
+
+    // Writable or relative search paths let anyone plant a library
+    let risky: Vec<&str> = analysis
+        .rpath
+        .iter()
+        .chain(&analysis.runpath)
+        .flat_map(|p| p.split(':'))
+        .filter(|p| {
+            p.is_empty() || (!p.starts_with('/') && !p.starts_with("$ORIGIN")) || p.starts_with("/tmp") || p.starts_with("/dev/shm")
+        })
+        .collect();
+    if !risky.is_empty() {
+        findings.push(Detection::heuristic(
+            "Heuristic.ELF.InsecureRunpath",
+            "elf",
+            format!("Library search path includes {}", risky.join(", ")),
+        ));
+    }
+
+    findings
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use goblin::elf::program_header::PT_INTERP;
+
+    const BASE: u64 = 0x40_0000;
+    const INTERP_OFFSET: usize = 0x180;
+
+    struct Segment {
+        kind: u32,
+        flags: u32,
+        offset: u64,
+        size: u64,
+    }
+
+    /// Little-endian x86-64 executable without section headers, 0x1000
+    /// bytes long and loaded at `BASE`.
+    fn elf(entry: u64, segments: &[Segment], interpreter: &str) -> Vec<u8> {
+        let mut data = vec![0u8; 0x1000];
+        data[..8].copy_from_slice(b"\x7fELF\x02\x01\x01\x00");
+        data[16..18].copy_from_slice(&ET_EXEC.to_le_bytes());
+        data[18..20].copy_from_slice(&62u16.to_le_bytes());
+        data[20..24].copy_from_slice(&1u32.to_le_bytes());
+        data[24..32].copy_from_slice(&entry.to_le_bytes());
+        data[32..40].copy_from_slice(&64u64.to_le_bytes());
+        data[52..54].copy_from_slice(&64u16.to_le_bytes());
+        data[54..56].copy_from_slice(&56u16.to_le_bytes());
+        data[56..58].copy_from_slice(&(segments.len() as u16 + 1).to_le_bytes());
+        data[58..60].copy_from_slice(&64u16.to_le_bytes());
+
+        let interp = Segment {
+            kind: PT_INTERP,
+            flags: PF_R,
+            offset: INTERP_OFFSET as u64,
+            size: interpreter.len() as u64 + 1,
+        };
+        for (i, segment) in std::iter::once(&interp).chain(segments).enumerate() {
+            let header = 64 + i * 56;
+            let vaddr = if segment.kind == PT_GNU_STACK { 0 } else { BASE + segment.offset };
+            for (at, value) in [(8, segment.offset), (16, vaddr), (24, vaddr), (32, segment.size), (40, segment.size)] {
+                data[header + at..header + at + 8].copy_from_slice(&value.to_le_bytes());
+            }
+            data[header..header + 4].copy_from_slice(&segment.kind.to_le_bytes());
+            data[header + 4..header + 8].copy_from_slice(&segment.flags.to_le_bytes());
+        }
+        data[INTERP_OFFSET..INTERP_OFFSET + interpreter.len()].copy_from_slice(interpreter.as_bytes());
+        data
+    }
+
+    fn names(analysis: &ElfAnalysis) -> Vec<&str> {
+        analysis.findings.iter().map(|f| f.name.as_str()).collect()
+    }
+
+    #[test]
+    fn plain_executables_only_lack_sections() {
+        let code = Segment { kind: PT_LOAD, flags: PF_R | PF_X, offset: 0, size: 0x1000 };
+        let stack = Segment { kind: PT_GNU_STACK, flags: PF_R | PF_W, offset: 0, size: 0 };
+        let analysis = analyze(&elf(BASE + 0x400, &[code, stack], "/lib64/ld-linux-x86-64.so.2")).unwrap();
+
+        assert_eq!((analysis.class.as_str(), analysis.endianness.as_str()), ("ELF64", "little"));
+        assert_eq!((analysis.file_type.as_str(), analysis.machine.as_str()), ("EXEC", "X86_64"));
+        assert_eq!(analysis.interpreter.as_deref(), Some("/lib64/ld-linux-x86-64.so.2"));
+        assert_eq!((analysis.pie, analysis.relro.as_str(), analysis.executable_stack), (false, "none", false));
+        assert_eq!(analysis.segments[1].permissions, "r-x");
+        assert!(analysis.stripped);
+        assert_eq!(names(&analysis), ["Heuristic.ELF.NoSectionHeaders"]);
+    }
+
+    #[test]
+    fn flags_layout_anomalies() {
+        let mut data = elf(
+            BASE + 0x10_0000,
+            &[
+                Segment { kind: PT_LOAD, flags: PF_R | PF_W | PF_X, offset: 0, size: 0x400 },
+                Segment { kind: PT_LOAD, flags: PF_R | PF_X, offset: 0x400, size: 0xc00 },
+                Segment { kind: PT_GNU_STACK, flags: PF_R | PF_W | PF_X, offset: 0, size: 0 },
+            ],
+            "/tmp/ld.so",
+        );
+        data[0x300..0x304].copy_from_slice(b"UPX!");
+        // xorshift noise for a packed-looking segment
+        let mut state = 0x2545_f491_4f6c_dd1du64;
+        for byte in &mut data[0x400..] {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            *byte = state as u8;
+        }
+
+        let analysis = analyze(&data).unwrap();
+        assert_eq!(analysis.packer.as_deref(), Some("UPX"));
+        assert!(analysis.executable_stack);
+        assert_eq!(
+            names(&analysis),
+            [
+                "Heuristic.ELF.Packer.UPX",
+                "Heuristic.ELF.ExecutableStack",
+                "Heuristic.ELF.WritableExecutableSegment",
+                "Heuristic.ELF.PackedSegment",
+                "Heuristic.ELF.EntryPointAnomaly",
+                "Heuristic.ELF.NoSectionHeaders",
+                "Heuristic.ELF.UnusualInterpreter",
+            ]
+        );
+    }
+
+    #[test]
+    fn rejects_broken_headers() {
+        assert!(analyze(b"\x7fELF").is_err());
+        assert!(analyze(b"MZ\x90\x00").is_err());
+    }
+
+    /// The test binary itself is ordinary linker output.
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn linker_output_is_clean() {
+        let data = std::fs::read(std::env::current_exe().unwrap()).unwrap();
+        let analysis = analyze(&data).unwrap();
+        assert!(!analysis.segments.is_empty() && !analysis.sections.is_empty());
+        assert!(analysis.needed_libraries.iter().any(|l| l.starts_with("libc.so")));
+        assert!(analysis.imported_symbols.len() <= MAX_SYMBOLS);
+        // The binary carries this module, and with it the UPX marker strings
+        let mut findings = names(&analysis);
+        findings.retain(|n| !n.starts_with("Heuristic.ELF.Packer"));
+        assert!(findings.is_empty(), "{:?}", findings);
+    }
+}
@@ -0,0 +1,917 @@
+//! Email parsing for `.eml`, mbox and Outlook `.msg` files.
+//!
+//! RFC 5322 messages are taken apart by hand: headers are unfolded and their
+//! RFC 2047 words decoded, multipart bodies are split on their boundaries,
+//! and base64 and quoted-printable parts are decoded. A `.msg` is an OLE2
+//! file whose MAPI properties carry the same information, including the
+//! original transport headers for mail that came from outside.
+//!
+//! Attachments are returned by [`attachments`] and scanned as archive
+//! members, mbox files yield one member per message. The report covers the
+//! headers, links and attachments of a single message.
+
+use base64::engine::general_purpose::{GeneralPurpose, GeneralPurposeConfig};
+use base64::engine::DecodePaddingMode;
+use base64::Engine;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::io::{Cursor, Read};
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+use crate::filetype;
+use crate::signatures::Detection;
+
+/// Bound on a single `.msg` property stream.
+const MAX_STREAM_SIZE: u64 = 256 * 1024 * 1024;
+const MAX_PARTS: usize = 1000;
+const MAX_NESTING: usize = 20;
+const MAX_URLS: usize = 1000;
+/// Clock skew tolerated between two relays before the chain looks forged;
+/// misconfigured time zones put honest relays hours apart.
+const MAX_HOP_SKEW_SECS: i64 = 24 * 60 * 60;
+const OLE2_MAGIC: [u8; 8] = [0xd0, 0xcf, 0x11, 0xe0, 0xa1, 0xb1, 0x1a, 0xe1];
+
+/// Attachment extensions mail clients run, or mount, on a double click.
+const DANGEROUS_EXTENSIONS: &[&str] = &[
+    "exe", "scr", "com", "pif", "cpl", "msi", "dll", "bat", "cmd", "ps1", "js", "jse", "vbs", "vbe", "wsf", "hta",
+    "lnk", "jar", "reg", "iso", "img", "vhd", "vhdx", "one",
+];
+
+/// Lenient base64: mail bodies are wrapped and often badly padded.
+const BASE64: GeneralPurpose = GeneralPurpose::new(
+    &base64::alphabet::STANDARD,
+    GeneralPurposeConfig::new().with_decode_padding_mode(DecodePaddingMode::Indifferent),
+);
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReceivedHop {
+    pub from: Option<String>,
+    pub by: Option<String>,
+    pub date: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EmailAttachment {
+    pub name: String,
+    pub content_type: String,
+    pub size: usize,
+    pub file_type: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EmailReport {
+    /// `eml` or `msg`.
+    pub format: String,
+    pub subject: String,
+    pub from: String,
+    pub reply_to: Option<String>,
+    pub return_path: Option<String>,
+    pub to: Vec<String>,
+    pub date: Option<String>,
+    pub message_id: Option<String>,
+    /// Relays, newest first as they appear in the headers.
+    pub received: Vec<ReceivedHop>,
+    /// SPF, DKIM and DMARC results from `Authentication-Results`.
+    pub authentication: BTreeMap<String, String>,
+    pub attachments: Vec<EmailAttachment>,
+    pub urls: Vec<String>,
+    pub findings: Vec<Detection>,
+}
+
+struct Attachment {
+    name: String,
+    content_type: String,
+    data: Vec<u8>,
+}
+
+/// A parsed message, the same for both formats.
+#[derive(Default)]
+struct Message {
+    headers: Vec<(String, String)>,
+    text: Vec<String>,
+    html: Vec<String>,
+    attachments: Vec<Attachment>,
+}
+
+impl Message {
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers.iter().find(|(n, _)| n.eq_ignore_ascii_case(name)).map(|(_, v)| v.as_str())
+    }
+
+    fn headers_named<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+        self.headers.iter().filter(move |(n, _)| n.eq_ignore_ascii_case(name)).map(|(_, v)| v.as_str())
+    }
+}
+
+pub fn analyze(data: &[u8]) -> Result<EmailReport, String> {
+    let (format, message) = if data.starts_with(&OLE2_MAGIC) { ("msg", parse_msg(data)?) } else { ("eml", parse_eml(data)) };
+    if message.headers.is_empty() {
+        return Err("No message headers".to_string());
+    }
+
+    let mut report = EmailReport {
+        format: format.to_string(),
+        subject: message.header("Subject").unwrap_or_default().to_string(),
+        from: message.header("From").unwrap_or_default().to_string(),
+        reply_to: message.header("Reply-To").map(str::to_string),
+        return_path: message.header("Return-Path").map(str::to_string),
+        to: message.header("To").map(split_addresses).unwrap_or_default(),
+        date: message.header("Date").map(str::to_string),
+        message_id: message.header("Message-ID").map(str::to_string),
+        received: message.headers_named("Received").map(parse_received).collect(),
+        authentication: authentication_results(&message),
+        attachments: message
+            .attachments
+            .iter()
+            .map(|a| EmailAttachment {
+                name: a.name.clone(),
+                content_type: a.content_type.clone(),
+                size: a.data.len(),
+                file_type: filetype::identify(&a.data).kind,
+            })
+            .collect(),
+        urls: extract_urls(&message),
+        findings: Vec::new(),
+    };
+    report.findings = findings(&report, &message);
+    Ok(report)
+}
+
+/// Decoded attachments with their file names, for scanning as members.
+pub fn attachments(data: &[u8]) -> Result<Vec<(String, Vec<u8>)>, String> {
+    let message = if data.starts_with(&OLE2_MAGIC) { parse_msg(data)? } else { parse_eml(data) };
+    Ok(message
+        .attachments
+        .into_iter()
+        // Names come from the sender, keep them from escaping the member path
+        .map(|a| (a.name.replace(['/', '\\'], "_"), a.data))
+        .collect())
+}
+
+/// The messages of an mbox file. `From ` lines start a message after a blank
+/// line, and one level of `>From ` quoting is undone (mboxrd).
+pub fn mbox_messages(data: &[u8]) -> Vec<Vec<u8>> {
+    let mut messages = Vec::new();
+    let mut current: Option<Vec<u8>> = None;
+    let mut previous_blank = true;
+    for line in data.split_inclusive(|&b| b == b'\n') {
+        if previous_blank && line.starts_with(b"From ") {
+            messages.extend(current.take());
+            current = Some(Vec::new());
+        } else if let Some(message) = current.as_mut() {
+            let quoted = line.iter().position(|&b| b != b'>').is_some_and(|p| p > 0 && line[p..].starts_with(b"From "));
+            message.extend_from_slice(if quoted { &line[1..] } else { line });
+        }
+        previous_blank = trim_eol(line).is_empty();
+    }
+    messages.extend(current);
+    messages
+}
+
+fn findings(report: &EmailReport, message: &Message) -> Vec<Detection> {
+    let mut findings = Vec::new();
+    let (display, address) = parse_address(&report.from);
+    let from_domain = domain(&address);
+
+    if let (Some(from_domain), Some(reply_domain)) =
+        (from_domain.as_deref(), report.reply_to.as_deref().and_then(|r| domain(&parse_address(r).1)))
+    {
+        if !same_domain(from_domain, &reply_domain) {
+            findings.push(Detection::heuristic(
+                "Heuristic.Email.ReplyToMismatch",
+                "email",
+                format!("Replies go to {}, not to the sender's domain {}", reply_domain, from_domain),
+            ));
+        }
+    }
+
+    // "PayPal <service@paypal.com>" <x@evil.example>
+    if let (Some(from_domain), Some(shown)) = (from_domain.as_deref(), domain(&display)) {
+        if display.contains('@') && !same_domain(from_domain, &shown) {
+            findings.push(Detection::heuristic(
+                "Heuristic.Email.DisplayNameSpoof",
+                "email",
+                format!("Sender name shows an address at {} but the mail is from {}", shown, from_domain),
+            ));
+        }
+    }
+
+    if let Some(description) = received_chain_problem(&report.received) {
+        findings.push(Detection::heuristic("Heuristic.Email.ReceivedChain", "email", description));
+    }
+
+    let failed: Vec<String> = report
+        .authentication
+        .iter()
+        .filter(|(_, result)| matches!(result.as_str(), "fail" | "softfail" | "permerror"))
+        .map(|(method, result)| format!("{} {}", method.to_uppercase(), result))
+        .collect();
+    if !failed.is_empty() {
+        findings.push(Detection::heuristic(
+            "Heuristic.Email.AuthenticationFailed",
+            "email",
+            format!("Sender authentication failed: {}", failed.join(", ")),
+        ));
+    }
+
+    if let Some((name, extension)) = report.attachments.iter().find_map(|a| {
+        let extension = Path::new(&a.name).extension()?.to_str()?.to_ascii_lowercase();
+        DANGEROUS_EXTENSIONS.contains(&extension.as_str()).then(|| (a.name.clone(), extension))
+    }) {
+        findings.push(Detection::heuristic(
+            "Heuristic.Email.DangerousAttachment",
+            "email",
+            format!("Attachment {} has a .{} extension", name, extension),
+        ));
+    }
+
+    if let Some((shown, target)) = deceptive_link(&message.html) {
+        findings.push(Detection::heuristic(
+            "Heuristic.Email.DeceptiveLink",
+            "email",
+            format!("Link text shows {} but points to {}", shown, target),
+        ));
+    }
+    findings
+}
+
+/// Relays add `Received` on top, so each header should be no older than the
+/// one below it. An older hop dated well after a newer one was written by
+/// the sender rather than a relay.
+fn received_chain_problem(hops: &[ReceivedHop]) -> Option<String> {
+    let dates: Vec<(usize, chrono::DateTime<chrono::FixedOffset>)> =
+        hops.iter().enumerate().filter_map(|(i, hop)| Some((i, parse_date(hop.date.as_deref()?)?))).collect();
+    for pair in dates.windows(2) {
+        let ((newer_index, newer), (older_index, older)) = (pair[0], pair[1]);
+        if (older - newer).num_seconds() > MAX_HOP_SKEW_SECS {
+            return Some(format!(
+                "Received header {} is dated after header {}, the chain may be forged",
+                older_index + 1,
+                newer_index + 1
+            ));
+        }
+    }
+    None
+}
+
+fn parse_date(value: &str) -> Option<chrono::DateTime<chrono::FixedOffset>> {
+    static COMMENT: OnceLock<Regex> = OnceLock::new();
+    let comment = COMMENT.get_or_init(|| Regex::new(r"\([^)]*\)").expect("comment pattern is valid"));
+    let cleaned = comment.replace_all(value, " ");
+    let cleaned = cleaned.split_whitespace().collect::<Vec<_>>().join(" ");
+    chrono::DateTime::parse_from_rfc2822(&cleaned).ok()
+}
+
+fn parse_received(value: &str) -> ReceivedHop {
+    static PATTERNS: OnceLock<[Regex; 2]> = OnceLock::new();
+    let [from, by] = PATTERNS.get_or_init(|| {
+        [
+            Regex::new(r"(?i)^\s*from\s+([^\s;()]+)").expect("received pattern is valid"),
+            Regex::new(r"(?i)\bby\s+([^\s;()]+)").expect("received pattern is valid"),
+        ]
+    });
+    let (route, date) = match value.rfind(';') {
+        Some(semicolon) => (&value[..semicolon], Some(value[semicolon + 1..].trim().to_string())),
+        None => (value, None),
+    };
+    ReceivedHop {
+        from: from.captures(route).map(|c| c[1].to_string()),
+        by: by.captures(route).map(|c| c[1].to_string()),
+        date,
+    }
+}
+
+/// The verdicts in `Authentication-Results` and `Received-SPF` added by the
+/// receiving server, the topmost one winning.
+fn authentication_results(message: &Message) -> BTreeMap<String, String> {
+    static PATTERN: OnceLock<Regex> = OnceLock::new();
+    let pattern = PATTERN.get_or_init(|| Regex::new(r"(?i)\b(spf|dkim|dmarc)\s*=\s*([a-z]+)").expect("auth pattern is valid"));
+    let mut results = BTreeMap::new();
+    for value in message.headers_named("Authentication-Results") {
+        for c in pattern.captures_iter(value) {
+            results.entry(c[1].to_ascii_lowercase()).or_insert_with(|| c[2].to_ascii_lowercase());
+        }
+    }
+    if let Some(spf) = message.header("Received-SPF").and_then(|v| v.split_whitespace().next()) {
+        results.entry("spf".to_string()).or_insert_with(|| spf.to_ascii_lowercase());
+    }
+    results
+}
+
+/// `<a href>` whose text is itself a URL or host name, for another host.
+fn deceptive_link(html: &[String]) -> Option<(String, String)> {
+    static PATTERNS: OnceLock<[Regex; 2]> = OnceLock::new();
+    let [anchor, tag] = PATTERNS.get_or_init(|| {
+        [
+            Regex::new(r#"(?is)<a\s[^>]*href\s*=\s*["']?(https?://[^"'\s>]+)[^>]*>(.*?)</a>"#).expect("anchor pattern is valid"),
+            Regex::new(r"<[^>]*>").expect("tag pattern is valid"),
+        ]
+    });
+    for body in html {
+        for c in anchor.captures_iter(body) {
+            let text = tag.replace_all(&c[2], "").trim().to_string();
+            let looks_like_url = text.starts_with("http://") || text.starts_with("https://") || text.starts_with("www.");
+            if !looks_like_url {
+                continue;
+            }
+            let (Some(shown), Some(target)) = (host(&text), host(&c[1])) else { continue };
+            if !same_domain(&shown, &target) {
+                return Some((shown, target));
+            }
+        }
+    }
+    None
+}
+
+fn host(url: &str) -> Option<String> {
+    let rest = url.split_once("://").map_or(url, |(_, rest)| rest);
+    let host = rest.split(['/', '?', '#', ':']).next()?.rsplit('@').next()?.trim_end_matches('.');
+    (!host.is_empty()).then(|| host.to_ascii_lowercase())
+}
+
+fn extract_urls(message: &Message) -> Vec<String> {
+    static PATTERN: OnceLock<Regex> = OnceLock::new();
+    let pattern = PATTERN.get_or_init(|| Regex::new(r#"(?i)\bhttps?://[^\s<>"'()\[\]{}]+"#).expect("url pattern is valid"));
+    let mut urls = Vec::new();
+    for body in message.text.iter().chain(&message.html) {
+        for m in pattern.find_iter(body) {
+            let url = m.as_str().trim_end_matches(['.', ',', ';', ':', '!', '?']).replace("&amp;", "&");
+            if urls.len() < MAX_URLS && !urls.contains(&url) {
+                urls.push(url);
+            }
+        }
+    }
+    urls
+}
+
+/// Splits `"Name" <a@b>` into the display name and the address.
+fn parse_address(value: &str) -> (String, String) {
+    match (value.rfind('<'), value.rfind('>')) {
+        (Some(open), Some(close)) if open < close => {
+            (value[..open].trim().trim_matches('"').trim().to_string(), value[open + 1..close].trim().to_string())
+        }
+        _ => (String::new(), value.trim().to_string()),
+    }
+}
+
+fn split_addresses(value: &str) -> Vec<String> {
+    let mut addresses = Vec::new();
+    let mut current = String::new();
+    let mut quoted = false;
+    for c in value.chars() {
+        match c {
+            '"' => {
+                quoted = !quoted;
+                current.push(c);
+            }
+            ',' if !quoted => addresses.push(std::mem::take(&mut current)),
+            _ => current.push(c),
+        }
+    }
+    addresses.push(current);
+    addresses.into_iter().map(|a| a.trim().to_string()).filter(|a| !a.is_empty()).collect()
+}
+
+fn domain(address: &str) -> Option<String> {
+    let (_, domain) = address.rsplit_once('@')?;
+    let domain = domain.trim().trim_end_matches(['>', '.', '"', ')']).to_ascii_lowercase();
+    (domain.contains('.') && !domain.contains(char::is_whitespace)).then_some(domain)
+}
+
+/// Equal, or one a subdomain of the other; `mail.example.com` sends for
+/// `example.com` all the time.
+fn same_domain(a: &str, b: &str) -> bool {
+    a == b || a.ends_with(&format!(".{}", b)) || b.ends_with(&format!(".{}", a))
+}
+
+fn parse_eml(data: &[u8]) -> Message {
+    let mut message = Message::default();
+    let (headers, body) = split_headers(data);
+    parse_part(&headers, body, 0, &mut message);
+    message.headers = headers;
+    message
+}
+
+/// Unfolded headers with encoded words decoded, and the body after them.
+fn split_headers(data: &[u8]) -> (Vec<(String, String)>, &[u8]) {
+    let mut headers: Vec<(String, String)> = Vec::new();
+    let mut offset = 0;
+    for line in data.split_inclusive(|&b| b == b'\n') {
+        let content = trim_eol(line);
+        if content.is_empty() {
+            offset += line.len();
+            break;
+        }
+        let text = String::from_utf8_lossy(content);
+        if content[0] == b' ' || content[0] == b'\t' {
+            if let Some((_, value)) = headers.last_mut() {
+                value.push(' ');
+                value.push_str(text.trim());
+            }
+        } else if let Some((name, value)) = text.split_once(':') {
+            if name.is_empty() || name.contains(char::is_whitespace) {
+                break;
+            }
+            headers.push((name.to_string(), value.trim().to_string()));
+        } else {
+            break;
+        }
+        offset += line.len();
+    }
+    for (_, value) in headers.iter_mut() {
+        *value = decode_words(value);
+    }
+    (headers, &data[offset.min(data.len())..])
+}
+
+fn parse_part(headers: &[(String, String)], body: &[u8], depth: usize, message: &mut Message) {
+    if depth > MAX_NESTING || message.attachments.len() + message.text.len() + message.html.len() >= MAX_PARTS {
+        return;
+    }
+    let header = |name: &str| headers.iter().find(|(n, _)| n.eq_ignore_ascii_case(name)).map(|(_, v)| v.as_str());
+    let (content_type, type_params) = parse_parameters(header("Content-Type").unwrap_or("text/plain"));
+    let (disposition, disposition_params) = parse_parameters(header("Content-Disposition").unwrap_or(""));
+
+    if content_type.starts_with("multipart/") {
+        if let Some(boundary) = type_params.get("boundary") {
+            for part in split_multipart(body, boundary) {
+                let (part_headers, part_body) = split_headers(part);
+                parse_part(&part_headers, part_body, depth + 1, message);
+            }
+            return;
+        }
+    }
+
+    let data = decode_transfer(body, header("Content-Transfer-Encoding").unwrap_or(""));
+    let name = disposition_params.get("filename").or_else(|| type_params.get("name")).cloned();
+    let is_text = content_type.starts_with("text/") || content_type.is_empty();
+    if name.is_some() || disposition == "attachment" || !is_text {
+        let name = name.filter(|n| !n.trim().is_empty()).unwrap_or_else(|| {
+            let extension = if content_type == "message/rfc822" { "eml" } else { "bin" };
+            format!("attachment-{}.{}", message.attachments.len() + 1, extension)
+        });
+        message.attachments.push(Attachment { name, content_type, data });
+        return;
+    }
+
+    let text = decode_charset(&data, type_params.get("charset").map_or("", String::as_str));
+    if content_type == "text/html" {
+        message.html.push(text);
+    } else {
+        message.text.push(text);
+    }
+}
+
+/// Parts between `--boundary` lines, up to the closing `--boundary--`.
+fn split_multipart<'a>(body: &'a [u8], boundary: &str) -> Vec<&'a [u8]> {
+    let delimiter = format!("--{}", boundary);
+    let mut parts = Vec::new();
+    let mut start: Option<usize> = None;
+    let mut offset = 0;
+    for line in body.split_inclusive(|&b| b == b'\n') {
+        if let Some(rest) = line.strip_prefix(delimiter.as_bytes()) {
+            let closing = rest.starts_with(b"--");
+            if closing || rest.trim_ascii().is_empty() {
+                if let Some(start) = start {
+                    // The line break before a delimiter belongs to it
+                    parts.push(trim_one_eol(&body[start..offset]));
+                }
+                if closing || parts.len() >= MAX_PARTS {
+                    return parts;
+                }
+                start = Some(offset + line.len());
+            }
+        }
+        offset += line.len();
+    }
+    if let Some(start) = start {
+        parts.push(&body[start.min(body.len())..]);
+    }
+    parts
+}
+
+/// The value before the first `;` in lower case and the parameters after it,
+/// with RFC 2231 continuations joined and decoded.
+fn parse_parameters(value: &str) -> (String, BTreeMap<String, String>) {
+    let mut pieces = Vec::new();
+    let mut current = String::new();
+    let mut quoted = false;
+    for c in value.chars() {
+        match c {
+            '"' => quoted = !quoted,
+            ';' if !quoted => pieces.push(std::mem::take(&mut current)),
+            _ => current.push(c),
+        }
+    }
+    pieces.push(current);
+
+    let main = pieces[0].trim().to_ascii_lowercase();
+    let mut plain = BTreeMap::new();
+    let mut extended: BTreeMap<String, Vec<(usize, bool, String)>> = BTreeMap::new();
+    for piece in &pieces[1..] {
+        let Some((key, value)) = piece.split_once('=') else { continue };
+        let key = key.trim().to_ascii_lowercase();
+        let value = value.trim().to_string();
+        // name*=utf-8''x, name*0=a, name*1*=%62
+        let (base, encoded) = match key.strip_suffix('*') {
+            Some(base) => (base.to_string(), true),
+            None => (key.clone(), false),
+        };
+        match base.split_once('*') {
+            Some((name, index)) => {
+                let index = index.parse().unwrap_or(0);
+                extended.entry(name.to_string()).or_default().push((index, encoded, value));
+            }
+            None if encoded => extended.entry(base).or_default().push((0, true, value)),
+            None => {
+                plain.insert(base, value);
+            }
+        }
+    }
+    for (name, mut sections) in extended {
+        sections.sort_by_key(|s| s.0);
+        let mut charset = String::new();
+        let mut bytes = Vec::new();
+        for (index, encoded, value) in sections {
+            let mut value = value.as_str();
+            if encoded && index == 0 {
+                if let Some((set, rest)) = value.split_once('\'').and_then(|(set, rest)| Some((set, rest.split_once('\'')?.1))) {
+                    charset = set.to_string();
+                    value = rest;
+                }
+            }
+            if encoded {
+                bytes.extend(percent_decode(value));
+            } else {
+                bytes.extend_from_slice(value.as_bytes());
+            }
+        }
+        plain.insert(name, decode_charset(&bytes, &charset));
+    }
+    (main, plain)
+}
+
+fn percent_decode(value: &str) -> Vec<u8> {
+    let bytes = value.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes.get(i + 1..i + 3).and_then(|h| std::str::from_utf8(h).ok()).and_then(|h| u8::from_str_radix(h, 16).ok());
+        match (bytes[i], hex) {
+            (b'%', Some(byte)) => {
+                out.push(byte);
+                i += 3;
+            }
+            (b, _) => {
+                out.push(b);
+                i += 1;
+            }
+        }
+    }
+    out
+}
+
+fn decode_transfer(body: &[u8], encoding: &str) -> Vec<u8> {
+    match encoding.trim().to_ascii_lowercase().as_str() {
+        "base64" => {
+            let clean: Vec<u8> = body.iter().copied().filter(|b| b.is_ascii_alphanumeric() || matches!(b, b'+' | b'/')).collect();
+            // A stray trailing character leaves a length no decoder accepts
+            let usable = if clean.len() % 4 == 1 { clean.len() - 1 } else { clean.len() };
+            BASE64.decode(&clean[..usable]).unwrap_or_default()
+        }
+        "quoted-printable" => quoted_printable(body, false),
+        _ => body.to_vec(),
+    }
+}
+
+/// `=XX` escapes and `=` soft line breaks; in encoded words `_` is a space.
+fn quoted_printable(data: &[u8], header: bool) -> Vec<u8> {
+    let mut out = Vec::with_capacity(data.len());
+    let mut i = 0;
+    while i < data.len() {
+        match data[i] {
+            b'=' if data[i + 1..].starts_with(b"\r\n") => i += 3,
+            b'=' if data[i + 1..].starts_with(b"\n") => i += 2,
+            b'=' => {
+                let hex = data.get(i + 1..i + 3).and_then(|h| std::str::from_utf8(h).ok());
+                match hex.and_then(|h| u8::from_str_radix(h, 16).ok()) {
+                    Some(byte) => {
+                        out.push(byte);
+                        i += 3;
+                    }
+                    None => {
+                        out.push(b'=');
+                        i += 1;
+                    }
+                }
+            }
+            b'_' if header => {
+                out.push(b' ');
+                i += 1;
+            }
+            b => {
+                out.push(b);
+                i += 1;
+            }
+        }
+    }
+    out
+}
+
+/// RFC 2047 `=?charset?B|Q?...?=` words; the space between two of them is
+/// not part of the text.
+fn decode_words(value: &str) -> String {
+    static PATTERN: OnceLock<Regex> = OnceLock::new();
+    let pattern = PATTERN.get_or_init(|| Regex::new(r"=\?([^?\s]+)\?([BbQq])\?([^?\s]*)\?=").expect("encoded word pattern is valid"));
+    if !value.contains("=?") {
+        return value.to_string();
+    }
+    let mut out = String::new();
+    let mut last = 0;
+    for c in pattern.captures_iter(value) {
+        let whole = c.get(0).expect("capture has a match");
+        let between = &value[last..whole.start()];
+        if last == 0 || !between.trim().is_empty() {
+            out.push_str(between);
+        }
+        let bytes = if c[2].eq_ignore_ascii_case("b") {
+            BASE64.decode(&c[3]).unwrap_or_default()
+        } else {
+            quoted_printable(c[3].as_bytes(), true)
+        };
+        out.push_str(&decode_charset(&bytes, &c[1]));
+        last = whole.end();
+    }
+    out.push_str(&value[last..]);
+    out
+}
+
+/// UTF-8 and the Latin-1 family; anything else is read as UTF-8.
+fn decode_charset(bytes: &[u8], charset: &str) -> String {
+    match charset.trim().to_ascii_lowercase().as_str() {
+        "iso-8859-1" | "iso-8859-15" | "latin1" | "windows-1252" | "cp1252" => bytes.iter().map(|&b| b as char).collect(),
+        _ => String::from_utf8_lossy(bytes).to_string(),
+    }
+}
+
+fn trim_eol(line: &[u8]) -> &[u8] {
+    let line = line.strip_suffix(b"\n").unwrap_or(line);
+    line.strip_suffix(b"\r").unwrap_or(line)
+}
+
+fn trim_one_eol(data: &[u8]) -> &[u8] {
+    data.strip_suffix(b"\r\n").or_else(|| data.strip_suffix(b"\n")).unwrap_or(data)
+}
+
+/// Reads an Outlook message from its MAPI property streams (MS-OXMSG).
+fn parse_msg(data: &[u8]) -> Result<Message, String> {
+    let mut file = cfb::CompoundFile::open(Cursor::new(data)).map_err(|e| format!("Invalid OLE2 file: {}", e))?;
+    let root = PathBuf::from("/");
+    let mut message = Message::default();
+
+    // Mail from outside keeps its internet headers, which is all the header
+    // checks need
+    if let Some(headers) = string_property(&mut file, &root, "007D") {
+        message.headers = split_headers(headers.as_bytes()).0;
+    }
+    if message.headers.is_empty() {
+        let sender = string_property(&mut file, &root, "5D01").or_else(|| string_property(&mut file, &root, "0C1F"));
+        let name = string_property(&mut file, &root, "0C1A");
+        let from = match (name, sender) {
+            (Some(name), Some(sender)) => format!("{} <{}>", name, sender),
+            (name, sender) => sender.or(name).unwrap_or_default(),
+        };
+        message.headers.push(("From".to_string(), from));
+        for (header, id) in [("Subject", "0037"), ("To", "0E04"), ("Message-ID", "1035")] {
+            if let Some(value) = string_property(&mut file, &root, id) {
+                message.headers.push((header.to_string(), value));
+            }
+        }
+    }
+    if !message.headers.iter().any(|(n, _)| n.eq_ignore_ascii_case("Subject")) {
+        if let Some(subject) = string_property(&mut file, &root, "0037") {
+            message.headers.push(("Subject".to_string(), subject));
+        }
+    }
+
+    message.text.extend(string_property(&mut file, &root, "1000"));
+    if let Some(html) = read_property(&mut file, &root.join("__substg1.0_10130102")) {
+        message.html.push(String::from_utf8_lossy(&html).to_string());
+    }
+
+    let storages: Vec<PathBuf> = file
+        .read_root_storage()
+        .filter(|e| e.is_storage() && e.name().starts_with("__attach_version1.0_"))
+        .map(|e| e.path().to_path_buf())
+        .collect();
+    for storage in storages.into_iter().take(MAX_PARTS) {
+        let Some(data) = read_property(&mut file, &storage.join("__substg1.0_37010102")) else { continue };
+        let name = ["3707", "3704", "3001"]
+            .iter()
+            .find_map(|id| string_property(&mut file, &storage, id))
+            .filter(|n| !n.trim().is_empty())
+            .unwrap_or_else(|| format!("attachment-{}.bin", message.attachments.len() + 1));
+        let content_type = string_property(&mut file, &storage, "370E").unwrap_or_default();
+        message.attachments.push(Attachment { name, content_type, data });
+    }
+    Ok(message)
+}
+
+/// A string property, stored as UTF-16 (`001F`) or 8-bit (`001E`).
+fn string_property(file: &mut cfb::CompoundFile<Cursor<&[u8]>>, storage: &Path, id: &str) -> Option<String> {
+    if let Some(data) = read_property(file, &storage.join(format!("__substg1.0_{}001F", id))) {
+        let units: Vec<u16> = data.chunks_exact(2).map(|c| u16::from_le_bytes([c[0], c[1]])).collect();
+        return Some(String::from_utf16_lossy(&units).trim_end_matches('\0').to_string());
+    }
+    let data = read_property(file, &storage.join(format!("__substg1.0_{}001E", id)))?;
+    Some(decode_charset(&data, "windows-1252").trim_end_matches('\0').to_string())
+}
+
+fn read_property(file: &mut cfb::CompoundFile<Cursor<&[u8]>>, path: &Path) -> Option<Vec<u8>> {
+    let mut data = Vec::new();
+    file.open_stream(path).ok()?.take(MAX_STREAM_SIZE).read_to_end(&mut data).ok()?;
+    Some(data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    const PHISH: &str = "\
+Received: from relay.example.net (relay.example.net [192.0.2.1])\r
+\tby mx.example.com; Tue, 1 Oct 2024 10:00:05 +0000\r
+Received: from sender.invalid by relay.example.net; Fri, 4 Oct 2024 10:00:00 +0000\r
+Authentication-Results: mx.example.com; spf=softfail smtp.mailfrom=evil.example;\r
+ dkim=none; dmarc=fail header.from=paypal.com\r
+From: \"service@paypal.com\" <billing@evil.example>\r
+Reply-To: <collect@other.example>\r
+To: alice@example.com, \"Bob, Jr.\" <bob@example.com>\r
+Subject: =?UTF-8?B?WW91ciBhY2NvdW50?= =?ISO-8859-1?Q?_is_gesperrt_=FCberpr=FCfen?=\r
+Message-ID: <1@evil.example>\r
+MIME-Version: 1.0\r
+Content-Type: multipart/mixed; boundary=\"outer\"\r
+\r
+This is a multi-part message in MIME format.\r
+--outer\r
+Content-Type: multipart/alternative; boundary=inner\r
+\r
+--inner\r
+Content-Type: text/plain; charset=utf-8\r
+Content-Transfer-Encoding: quoted-printable\r
+\r
+Verify at https://login.evil.example/verify?id=3D1&amp;x=3D2. Thanks=\r
+ again.\r
+--inner\r
+Content-Type: text/html\r
+\r
+<p><a href=\"https://login.evil.example/verify\">https://www.paypal.com/signin</a></p>\r
+<p><a href=\"https://help.paypal.com/\">www.paypal.com</a></p>\r
+--inner--\r
+--outer\r
+Content-Type: application/octet-stream\r
+Content-Disposition: attachment; filename*0*=utf-8''Rechnung%20; filename*1=\"2024.pdf.exe\"\r
+Content-Transfer-Encoding: base64\r
+\r
+TVqQAAMAAAAEAAAA\r
+//8AALgAAAA=\r
+--outer--\r
+epilogue\r
+";
+
+    fn names(report: &EmailReport) -> Vec<&str> {
+        report.findings.iter().map(|f| f.name.as_str()).collect()
+    }
+
+    #[test]
+    fn parses_headers_and_mime_parts() {
+        let report = analyze(PHISH.as_bytes()).unwrap();
+        assert_eq!(report.format, "eml");
+        assert_eq!(report.subject, "Your account is gesperrt überprüfen");
+        assert_eq!(report.to, ["alice@example.com", "\"Bob, Jr.\" <bob@example.com>"]);
+        assert_eq!(report.message_id.as_deref(), Some("<1@evil.example>"));
+        assert_eq!(report.received.len(), 2);
+        assert_eq!(report.received[0].from.as_deref(), Some("relay.example.net"));
+        assert_eq!(report.received[0].by.as_deref(), Some("mx.example.com"));
+        assert_eq!(report.received[1].date.as_deref(), Some("Fri, 4 Oct 2024 10:00:00 +0000"));
+        let authentication: Vec<(&str, &str)> =
+            report.authentication.iter().map(|(k, v)| (k.as_str(), v.as_str())).collect();
+        assert_eq!(authentication, [("dkim", "none"), ("dmarc", "fail"), ("spf", "softfail")]);
+
+        assert_eq!(report.attachments.len(), 1);
+        let attachment = &report.attachments[0];
+        assert_eq!(attachment.name, "Rechnung 2024.pdf.exe");
+        assert_eq!(attachment.content_type, "application/octet-stream");
+        assert_eq!(attachment.size, 20);
+
+        let members = attachments(PHISH.as_bytes()).unwrap();
+        assert_eq!(members.len(), 1);
+        assert!(members[0].1.starts_with(b"MZ\x90\0"));
+    }
+
+    #[test]
+    fn extracts_links_from_text_and_html() {
+        let report = analyze(PHISH.as_bytes()).unwrap();
+        assert_eq!(
+            report.urls,
+            [
+                "https://login.evil.example/verify?id=1&x=2",
+                "https://login.evil.example/verify",
+                "https://www.paypal.com/signin",
+                "https://help.paypal.com/",
+            ]
+        );
+    }
+
+    #[test]
+    fn flags_phishing_traits() {
+        let report = analyze(PHISH.as_bytes()).unwrap();
+        assert_eq!(
+            names(&report),
+            [
+                "Heuristic.Email.ReplyToMismatch",
+                "Heuristic.Email.DisplayNameSpoof",
+                "Heuristic.Email.ReceivedChain",
+                "Heuristic.Email.AuthenticationFailed",
+                "Heuristic.Email.DangerousAttachment",
+                "Heuristic.Email.DeceptiveLink",
+            ]
+        );
+        let deceptive = report.findings.last().unwrap();
+        assert_eq!(
+            deceptive.description.as_deref(),
+            Some("Link text shows www.paypal.com but points to login.evil.example")
+        );
+
+        let honest = "From: News <news@mail.example.com>\r\nReply-To: desk@example.com\r\nSubject: Hi\r\n\r\n\
+            <a href=\"https://www.example.com/a\">www.example.com</a>\r\n";
+        assert!(analyze(honest.as_bytes()).unwrap().findings.is_empty());
+    }
+
+    #[test]
+    fn decodes_encoded_words_and_parameters() {
+        assert_eq!(decode_words("=?utf-8?q?caf=C3=A9_au_lait?="), "café au lait");
+        assert_eq!(decode_words("=?utf-8?B?SGVs?=  =?utf-8?B?bG8=?= world"), "Hello world");
+        assert_eq!(decode_words("plain =?bogus"), "plain =?bogus");
+
+        let (value, parameters) = parse_parameters("Attachment; filename=\"a;b.txt\"; size=3");
+        assert_eq!(value, "attachment");
+        assert_eq!(parameters["filename"], "a;b.txt");
+        assert_eq!(parameters["size"], "3");
+        let (_, parameters) = parse_parameters("attachment; filename*=iso-8859-1'de'%FCber.doc");
+        assert_eq!(parameters["filename"], "über.doc");
+    }
+
+    #[test]
+    fn unnamed_parts_and_nested_messages_become_attachments() {
+        let mail = "From: a@example.com\nContent-Type: multipart/mixed; boundary=b\n\n--b\n\
+            Content-Type: image/png\n\nPNG\n--b\nContent-Type: message/rfc822\n\nFrom: c@example.com\n\nhi\n--b--\n";
+        let members = attachments(mail.as_bytes()).unwrap();
+        let names: Vec<&str> = members.iter().map(|(n, _)| n.as_str()).collect();
+        assert_eq!(names, ["attachment-1.bin", "attachment-2.eml"]);
+        assert_eq!(members[1].1, b"From: c@example.com\n\nhi");
+    }
+
+    #[test]
+    fn rejects_data_without_headers() {
+        assert!(analyze(b"just some text\nwithout headers\n").is_err());
+    }
+
+    #[test]
+    fn splits_mbox_files() {
+        let mbox = b"From alice Mon Jan  1 00:00:00 2024\nSubject: one\n\nHello\nFrom here on\n>From the quote\n\n\
+            From bob Mon Jan  1 00:00:01 2024\nSubject: two\n\nbody\n";
+        let messages = mbox_messages(mbox);
+        assert_eq!(messages.len(), 2);
+        assert_eq!(messages[0], b"Subject: one\n\nHello\nFrom here on\nFrom the quote\n\n");
+        assert_eq!(messages[1], b"Subject: two\n\nbody\n");
+    }
+
+    #[test]
+    fn reads_outlook_messages() {
+        let utf16 = |text: &str| -> Vec<u8> { text.encode_utf16().flat_map(u16::to_le_bytes).collect() };
+        let mut file = cfb::CompoundFile::create(Cursor::new(Vec::new())).unwrap();
+        let stream = |file: &mut cfb::CompoundFile<Cursor<Vec<u8>>>, path: &str, data: &[u8]| {
+            file.create_stream(path).unwrap().write_all(data).unwrap();
+        };
+        stream(&mut file, "/__substg1.0_0037001F", &utf16("Invoice"));
+        stream(&mut file, "/__substg1.0_0C1A001F", &utf16("Billing"));
+        stream(&mut file, "/__substg1.0_5D01001F", &utf16("billing@example.com"));
+        stream(&mut file, "/__substg1.0_1000001F", &utf16("Pay at https://pay.example.com/1 today"));
+        file.create_storage("/__attach_version1.0_#00000000").unwrap();
+        stream(&mut file, "/__attach_version1.0_#00000000/__substg1.0_3707001E", b"invoice.js");
+        stream(&mut file, "/__attach_version1.0_#00000000/__substg1.0_37010102", b"WScript.Echo(1)");
+        let data = file.into_inner().into_inner();
+
+        let report = analyze(&data).unwrap();
+        assert_eq!(report.format, "msg");
+        assert_eq!(report.from, "Billing <billing@example.com>");
+        assert_eq!(report.subject, "Invoice");
+        assert_eq!(report.urls, ["https://pay.example.com/1"]);
+        assert_eq!(report.attachments[0].name, "invoice.js");
+        assert_eq!(names(&report), ["Heuristic.Email.DangerousAttachment"]);
+        assert_eq!(attachments(&data).unwrap(), [("invoice.js".to_string(), b"WScript.Echo(1)".to_vec())]);
+    }
+}
@@ -0,0 +1,116 @@
+//! Shared scan engine state.
+//!
+//! A single `ScanEngine` is created at startup and handed to every command
+//! through Tauri's managed state. Databases and the scoring config sit behind
+//! `RwLock`s so they can be replaced while no scan holds them.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{RwLock, RwLockReadGuard};
+
+use crate::heuristics::ScoringConfig;
+use crate::ioc::IocIndex;
+use crate::signatures::{LoadSummary, SignatureDb, SignatureFile, SimilarSignatureDef};
+use crate::update;
+use crate::yara::{RuleLoadReport, RuleSet};
+
+/// Signature file in the signature directory that samples added through
+/// [`ScanEngine::add_similar_sample`] are written to.
+const SIMILARITY_FILE: &str = "similarity.json";
+
+pub struct ScanEngine {
+    signature_dir: PathBuf,
+    rules_dir: PathBuf,
+    signatures: RwLock<SignatureDb>,
+    rules: RwLock<RuleSet>,
+    scoring: RwLock<ScoringConfig>,
+    iocs: RwLock<IocIndex>,
+}
+
+impl ScanEngine {
+    pub fn new(signature_dir: PathBuf, rules_dir: PathBuf) -> Self {
+        ScanEngine {
+            signature_dir,
+            rules_dir,
+            signatures: RwLock::new(SignatureDb::builtin()),
+            rules: RwLock::new(RuleSet::new()),
+            scoring: RwLock::new(ScoringConfig::default()),
+            iocs: RwLock::new(IocIndex::default()),
+        }
+    }
+
+    pub fn signature_dir(&self) -> &Path {
+        &self.signature_dir
+    }
+
+    /// Rebuilds the signature database from the builtin set plus every file in
+    /// the signature directory and swaps it in.
+    pub fn reload_signatures(&self) -> LoadSummary {
+        let mut db = SignatureDb::builtin();
+        let mut summary = if self.signature_dir.is_dir() {
+            db.load_dir(&self.signature_dir)
+        } else {
+            LoadSummary::default()
+        };
+        let updates = self.signature_dir.join(update::DATABASE_DIR);
+        if updates.is_dir() {
+            summary.merge(db.load_dir(&updates));
+        }
+        db.build();
+
+        *self.signatures.write().unwrap_or_else(|e| e.into_inner()) = db;
+        summary
+    }
+
+    /// Adds a known sample's fuzzy hashes to the local similarity database
+    /// and reloads the signatures so later scans report files close to it.
+    pub fn add_similar_sample(&self, sample: SimilarSignatureDef) -> Result<LoadSummary, String> {
+        SignatureDb::new().add_similar(&sample)?;
+        fs::create_dir_all(&self.signature_dir)
+            .map_err(|e| format!("Cannot create {}: {}", self.signature_dir.display(), e))?;
+        let path = self.signature_dir.join(SIMILARITY_FILE);
+        let mut file = if path.exists() {
+            let json = fs::read_to_string(&path).map_err(|e| format!("Cannot read {}: {}", path.display(), e))?;
+            serde_json::from_str(&json).map_err(|e| format!("Invalid signature file {}: {}", path.display(), e))?
+        } else {
+            SignatureFile::default()
+        };
+        file.similar.push(sample);
+        let json = serde_json::to_string_pretty(&file).map_err(|e| format!("Cannot encode signatures: {}", e))?;
+        fs::write(&path, json).map_err(|e| format!("Cannot write {}: {}", path.display(), e))?;
+        Ok(self.reload_signatures())
+    }
+
+    pub fn signatures(&self) -> RwLockReadGuard<'_, SignatureDb> {
+        self.signatures.read().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Compiles the YARA rules in `dir` (the configured rules directory when
+    /// `None`) and makes them the active rule set.
+    pub fn load_rules(&self, dir: Option<&Path>) -> RuleLoadReport {
+        let (set, report) = RuleSet::load_dir(dir.unwrap_or(&self.rules_dir));
+        *self.rules.write().unwrap_or_else(|e| e.into_inner()) = set;
+        report
+    }
+
+    pub fn rules(&self) -> RwLockReadGuard<'_, RuleSet> {
+        self.rules.read().unwrap_or_else(|e| e.into_inner())
+    }
+
+    pub fn scoring(&self) -> ScoringConfig {
+        self.scoring.read().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    pub fn set_scoring(&self, config: ScoringConfig) {
+        *self.scoring.write().unwrap_or_else(|e| e.into_inner()) = config;
+    }
+
+    pub fn iocs(&self) -> RwLockReadGuard<'_, IocIndex> {
+        self.iocs.read().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Swaps in an index rebuilt from the IOC store after it changed.
+    pub fn set_iocs(&self, index: IocIndex) {
+        *self.iocs.write().unwrap_or_else(|e| e.into_inner()) = index;
+    }
+}
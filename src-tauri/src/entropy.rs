@@ -0,0 +1,56 @@
+//! Byte entropy, shared by the executable and document analyzers.
+
+/// Code above this entropy is most likely compressed or encrypted.
+pub const PACKED: f64 = 7.0;
+
+/// Shannon entropy in bits per byte, from 0.0 (constant) to 8.0 (random).
+pub fn shannon(data: &[u8]) -> f64 {
+    if data.is_empty() {
+        return 0.0;
+    }
+    let mut counts = [0usize; 256];
+    for &b in data {
+        counts[b as usize] += 1;
+    }
+    let len = data.len() as f64;
+    counts
+        .iter()
+        .filter(|&&c| c > 0)
+        .map(|&c| {
+            let p = c as f64 / len;
+            -p * p.log2()
+        })
+        .sum()
+}
+
+/// Entropy of each consecutive `window`-byte block. A shorter trailing block
+/// is only included when it is the whole input.
+pub fn windowed(data: &[u8], window: usize) -> Vec<f64> {
+    let window = window.max(1);
+    if data.len() < window {
+        return vec![shannon(data)];
+    }
+    data.chunks_exact(window).map(shannon).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn measures_bits_per_byte() {
+        assert_eq!(shannon(b""), 0.0);
+        assert_eq!(shannon(&[7; 100]), 0.0);
+        assert_eq!(shannon(b"abababab"), 1.0);
+        assert_eq!(shannon(b"abcdabcd"), 2.0);
+        let every_byte: Vec<u8> = (0..=255).collect();
+        assert_eq!(shannon(&every_byte), 8.0);
+    }
+
+    #[test]
+    fn windows_drop_the_short_tail() {
+        assert_eq!(windowed(b"abab", 8), [1.0]);
+        assert_eq!(windowed(b"aaaaababx", 4), [0.0, 1.0]);
+        assert_eq!(windowed(b"ab", 0), [0.0, 0.0]);
+    }
+}
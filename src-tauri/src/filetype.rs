@@ -0,0 +1,511 @@
+//! Content-based file type identification.
+//!
+//! Types are derived from magic numbers, the member names of ZIP containers
+//! (to tell Office documents, JARs and APKs apart from plain archives) and,
+//! for everything else, a text/encoding check of the first few kilobytes.
+//! The result is compared against the file extension so executables dressed
+//! up as documents or pictures can be flagged.
+
+use serde::{Deserialize, Serialize};
+
+use crate::signatures::Detection;
+
+/// Bytes inspected when deciding whether content is text.
+const TEXT_SNIFF_LEN: usize = 8 * 1024;
+/// The ZIP end-of-central-directory record sits within this many bytes of
+/// the end (22 byte record plus a comment of up to 64 KiB).
+const EOCD_SEARCH_LEN: usize = 22 + 0xffff;
+/// Central directory entries examined when sniffing a ZIP container.
+const MAX_ZIP_ENTRIES: usize = 4096;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FileType {
+    /// Short identifier such as `pe`, `pdf`, `docx` or `text`.
+    pub kind: String,
+    pub mime: String,
+    /// Character encoding, for text only.
+    pub encoding: Option<String>,
+}
+
+impl FileType {
+    fn new(kind: &str, mime: &str) -> Self {
+        FileType { kind: kind.to_string(), mime: mime.to_string(), encoding: None }
+    }
+
+    fn text(kind: &str, mime: &str, encoding: &str) -> Self {
+        FileType { kind: kind.to_string(), mime: mime.to_string(), encoding: Some(encoding.to_string()) }
+    }
+
+    /// Native executables, bytecode and other content that runs when opened.
+    pub fn is_executable(&self) -> bool {
+        matches!(self.kind.as_str(), "pe" | "elf" | "macho" | "java-class" | "dex" | "script" | "lnk")
+    }
+}
+
+pub fn identify(data: &[u8]) -> FileType {
+    if let Some(file_type) = identify_binary(data) {
+        return file_type;
+    }
+    identify_text(data).unwrap_or_else(|| {
+        if data.is_empty() {
+            FileType::new("empty", "application/x-empty")
+        } else {
+            FileType::new("unknown", "application/octet-stream")
+        }
+    })
+}
+
+fn identify_binary(data: &[u8]) -> Option<FileType> {
+    let at = |offset: usize, magic: &[u8]| data.get(offset..offset + magic.len()) == Some(magic);
+
+    let file_type = if at(0, b"MZ") {
+        FileType::new("pe", "application/vnd.microsoft.portable-executable")
+    } else if at(0, b"\x7fELF") {
+        FileType::new("elf", "application/x-elf")
+    } else if [[0xfe, 0xed, 0xfa, 0xce], [0xfe, 0xed, 0xfa, 0xcf], [0xce, 0xfa, 0xed, 0xfe], [0xcf, 0xfa, 0xed, 0xfe]]
+        .iter()
+        .any(|magic| at(0, magic))
+    {
+        FileType::new("macho", "application/x-mach-binary")
+    } else if at(0, &[0xca, 0xfe, 0xba, 0xbe]) {
+        // Shared by Java classes and universal Mach-O binaries; the latter
+        // store a small architecture count where classes keep their version
+        let second = u32::from_be_bytes(data.get(4..8)?.try_into().ok()?);
+        if second < 45 {
+            FileType::new("macho", "application/x-mach-binary")
+        } else {
+            FileType::new("java-class", "application/java-vm")
+        }
+    } else if at(0, b"dex\n") {
+        FileType::new("dex", "application/vnd.android.dex")
+    } else if at(0, b"#!") {
+        FileType::text("script", "text/x-shellscript", "ascii")
+    } else if at(0, &[0x4c, 0x00, 0x00, 0x00, 0x01, 0x14, 0x02, 0x00]) {
+        FileType::new("lnk", "application/x-ms-shortcut")
+    } else if data[..data.len().min(1024)].windows(5).any(|w| w == b"%PDF-") {
+        FileType::new("pdf", "application/pdf")
+    } else if at(0, b"PK\x03\x04") || at(0, b"PK\x05\x06") {
+        identify_zip(data)
+    } else if at(0, &[0xd0, 0xcf, 0x11, 0xe0, 0xa1, 0xb1, 0x1a, 0xe1]) {
+        // Outlook messages keep their properties in `__substg1.0_` streams,
+        // directory entry names are UTF-16
+        let substg: Vec<u8> = "__substg1.0_".bytes().flat_map(|b| [b, 0]).collect();
+        if contains(data, &substg) {
+            FileType::new("msg", "application/vnd.ms-outlook")
+        } else {
+            FileType::new("ole2", "application/x-ole-storage")
+        }
+    } else if at(0, b"{\\rtf") {
+        FileType::new("rtf", "application/rtf")
+    } else if at(0, b"\x89PNG\r\n\x1a\n") {
+        FileType::new("png", "image/png")
+    } else if at(0, &[0xff, 0xd8, 0xff]) {
+        FileType::new("jpeg", "image/jpeg")
+    } else if at(0, b"GIF87a") || at(0, b"GIF89a") {
+        FileType::new("gif", "image/gif")
+    } else if at(0, b"BM") && data.len() >= 26 && at(14, &[0x28, 0x00, 0x00, 0x00]) {
+        FileType::new("bmp", "image/bmp")
+    } else if at(0, b"RIFF") && at(8, b"WEBP") {
+        FileType::new("webp", "image/webp")
+    } else if at(0, b"RIFF") && at(8, b"WAVE") {
+        FileType::new("wav", "audio/wav")
+    } else if at(0, b"RIFF") && at(8, b"AVI ") {
+        FileType::new("avi", "video/x-msvideo")
+    } else if at(0, b"II*\x00") || at(0, b"MM\x00*") {
+        FileType::new("tiff", "image/tiff")
+    } else if at(0, &[0x00, 0x00, 0x01, 0x00]) && data.len() >= 6 && data[4..6] != [0, 0] {
+        FileType::new("ico", "image/vnd.microsoft.icon")
+    } else if at(4, b"ftyp") {
+        FileType::new("mp4", "video/mp4")
+    } else if at(0, b"ID3") || at(0, &[0xff, 0xfb]) || at(0, &[0xff, 0xf3]) {
+        FileType::new("mp3", "audio/mpeg")
+    } else if at(0, b"OggS") {
+        FileType::new("ogg", "audio/ogg")
+    } else if at(0, b"fLaC") {
+        FileType::new("flac", "audio/flac")
+    } else if at(0, &[0x1f, 0x8b]) {
+        FileType::new("gzip", "application/gzip")
+    } else if at(0, b"BZh") {
+        FileType::new("bzip2", "application/x-bzip2")
+    } else if at(0, &[0xfd, b'7', b'z', b'X', b'Z', 0x00]) {
+        FileType::new("xz", "application/x-xz")
+    } else if at(0, &[b'7', b'z', 0xbc, 0xaf, 0x27, 0x1c]) {
+        FileType::new("7z", "application/x-7z-compressed")
+    } else if at(0, b"Rar!\x1a\x07") {
+        FileType::new("rar", "application/vnd.rar")
+    } else if at(0, &[0x28, 0xb5, 0x2f, 0xfd]) {
+        FileType::new("zstd", "application/zstd")
+    } else if at(0, b"MSCF") {
+        FileType::new("cab", "application/vnd.ms-cab-compressed")
+    } else if at(257, b"ustar") {
+        FileType::new("tar", "application/x-tar")
+    } else if at(0, b"FWS") || at(0, b"CWS") || at(0, b"ZWS") {
+        FileType::new("swf", "application/x-shockwave-flash")
+    } else if at(0, b"\x00asm") {
+        FileType::new("wasm", "application/wasm")
+    } else if at(0, b"SQLite format 3\x00") {
+        FileType::new("sqlite", "application/vnd.sqlite3")
+    } else {
+        return None;
+    };
+    Some(file_type)
+}
+
+/// Names of the members of a ZIP archive, read from the central directory
+/// and falling back to the local headers for truncated archives.
+pub fn zip_entry_names(data: &[u8]) -> Vec<String> {
+    let u16_at = |o: usize| data.get(o..o + 2).map(|b| u16::from_le_bytes([b[0], b[1]]) as usize);
+    let u32_at = |o: usize| data.get(o..o + 4).map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]) as usize);
+    let mut names = Vec::new();
+
+    let tail_start = data.len().saturating_sub(EOCD_SEARCH_LEN);
+    let eocd = data[tail_start..].windows(4).rposition(|w| w == b"PK\x05\x06").map(|p| tail_start + p);
+    if let Some(mut offset) = eocd.and_then(|eocd| u32_at(eocd + 16)) {
+        while names.len() < MAX_ZIP_ENTRIES && data.get(offset..offset + 4) == Some(b"PK\x01\x02") {
+            let (Some(name_len), Some(extra_len), Some(comment_len)) =
+                (u16_at(offset + 28), u16_at(offset + 30), u16_at(offset + 32))
+            else {
+                break;
+            };
+            let Some(name) = data.get(offset + 46..offset + 46 + name_len) else { break };
+            names.push(String::from_utf8_lossy(name).to_string());
+            offset += 46 + name_len + extra_len + comment_len;
+        }
+    }
+
+    if names.is_empty() {
+        let mut offset = 0;
+        while names.len() < MAX_ZIP_ENTRIES && data.get(offset..offset + 4) == Some(b"PK\x03\x04") {
+            let (Some(size), Some(name_len), Some(extra_len)) = (u32_at(offset + 18), u16_at(offset + 26), u16_at(offset + 28))
+            else {
+                break;
+            };
+            let Some(name) = data.get(offset + 30..offset + 30 + name_len) else { break };
+            names.push(String::from_utf8_lossy(name).to_string());
+            offset += 30 + name_len + extra_len + size;
+        }
+    }
+    names
+}
+
+fn identify_zip(data: &[u8]) -> FileType {
+    let names = zip_entry_names(data);
+    let has = |name: &str| names.iter().any(|n| n == name);
+    let has_prefix = |prefix: &str| names.iter().any(|n| n.starts_with(prefix));
+
+    // ODF stores its MIME type uncompressed as the first member
+    if data.get(30..38) == Some(b"mimetype") {
+        let mime = &data[38..data.len().min(38 + 80)];
+        let mime = String::from_utf8_lossy(mime);
+        for (prefix, kind) in [
+            ("application/vnd.oasis.opendocument.text", "odt"),
+            ("application/vnd.oasis.opendocument.spreadsheet", "ods"),
+            ("application/vnd.oasis.opendocument.presentation", "odp"),
+            ("application/epub+zip", "epub"),
+        ] {
+            if mime.starts_with(prefix) {
+                return FileType::new(kind, prefix);
+            }
+        }
+    }
+
+    if has("[Content_Types].xml") {
+        let macros = names.iter().any(|n| n.ends_with("vbaProject.bin"));
+        let (kind, mime) = if has_prefix("word/") {
+            if macros {
+                ("docm", "application/vnd.ms-word.document.macroEnabled.12")
+            } else {
+                ("docx", "application/vnd.openxmlformats-officedocument.wordprocessingml.document")
+            }
+        } else if has_prefix("xl/") {
+            if macros {
+                ("xlsm", "application/vnd.ms-excel.sheet.macroEnabled.12")
+            } else {
+                ("xlsx", "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet")
+            }
+        } else if has_prefix("ppt/") {
+            if macros {
+                ("pptm", "application/vnd.ms-powerpoint.presentation.macroEnabled.12")
+            } else {
+                ("pptx", "application/vnd.openxmlformats-officedocument.presentationml.presentation")
+            }
+        } else {
+            ("ooxml", "application/vnd.openxmlformats-package")
+        };
+        return FileType::new(kind, mime);
+    }
+
+    if has("AndroidManifest.xml") && has("classes.dex") {
+        FileType::new("apk", "application/vnd.android.package-archive")
+    } else if has("META-INF/MANIFEST.MF") || names.iter().any(|n| n.ends_with(".class")) {
+        FileType::new("jar", "application/java-archive")
+    } else {
+        FileType::new("zip", "application/zip")
+    }
+}
+
+fn identify_text(data: &[u8]) -> Option<FileType> {
+    let (encoding, body) = if let Some(rest) = data.strip_prefix(&[0xef, 0xbb, 0xbf]) {
+        ("utf-8", rest)
+    } else if data.starts_with(&[0xff, 0xfe]) {
+        return Some(FileType::text("text", "text/plain", "utf-16le"));
+    } else if data.starts_with(&[0xfe, 0xff]) {
+        return Some(FileType::text("text", "text/plain", "utf-16be"));
+    } else {
+        ("", data)
+    };
+
+    let head = &body[..body.len().min(TEXT_SNIFF_LEN)];
+    if head.is_empty() {
+        return None;
+    }
+    let is_control = |b: u8| b < 0x20 && !matches!(b, b'\t' | b'\n' | b'\r' | 0x0c | 0x1b);
+    if head.iter().any(|&b| is_control(b)) {
+        return None;
+    }
+
+    let encoding = if !encoding.is_empty() {
+        encoding
+    } else if head.is_ascii() {
+        "ascii"
+    } else {
+        match std::str::from_utf8(head) {
+            Ok(_) => "utf-8",
+            // A multi-byte character cut off by the sniff window is still UTF-8
+            Err(e) if e.error_len().is_none() => "utf-8",
+            Err(_) => "iso-8859-1",
+        }
+    };
+
+    let lower: Vec<u8> = head.iter().take(512).map(u8::to_ascii_lowercase).collect();
+    let trimmed = lower.trim_ascii_start();
+    let (kind, mime) = if trimmed.starts_with(b"<!doctype html") || trimmed.starts_with(b"<html") {
+        ("html", "text/html")
+    } else if trimmed.starts_with(b"<svg") || (trimmed.starts_with(b"<?xml") && contains(&lower, b"<svg")) {
+        ("svg", "image/svg+xml")
+    } else if trimmed.starts_with(b"<?xml") {
+        ("xml", "application/xml")
+    } else if trimmed.starts_with(b"{") || trimmed.starts_with(b"[") {
+        ("json", "application/json")
+    } else if lower.starts_with(b"from ") && looks_like_email(lower.splitn(2, |&b| b == b'\n').nth(1).unwrap_or_default()) {
+        ("mbox", "application/mbox")
+    } else if looks_like_email(&lower) {
+        ("email", "message/rfc822")
+    } else {
+        ("text", "text/plain")
+    };
+    Some(FileType::text(kind, mime, encoding))
+}
+
+/// Starts with a header block holding at least two common message headers.
+fn looks_like_email(lower: &[u8]) -> bool {
+    const HEADERS: [&[u8]; 11] = [
+        b"received:",
+        b"from:",
+        b"to:",
+        b"subject:",
+        b"date:",
+        b"message-id:",
+        b"mime-version:",
+        b"return-path:",
+        b"delivered-to:",
+        b"reply-to:",
+        b"content-type:",
+    ];
+    let mut known = 0;
+    for (i, line) in lower.split_inclusive(|&b| b == b'\n').take(32).enumerate() {
+        // The sniff window may end in the middle of a header
+        let Some(line) = line.strip_suffix(b"\n") else { break };
+        let line = line.strip_suffix(b"\r").unwrap_or(line);
+        if line.is_empty() {
+            break;
+        }
+        let folded = line[0] == b' ' || line[0] == b'\t';
+        let is_header = line.iter().position(|&b| b == b':').is_some_and(|colon| {
+            colon > 0 && line[..colon].iter().all(|&b| b.is_ascii_graphic())
+        });
+        if !is_header && (i == 0 || !folded) {
+            return false;
+        }
+        if HEADERS.iter().any(|h| line.starts_with(h)) {
+            known += 1;
+        }
+    }
+    known >= 2
+}
+
+fn contains(haystack: &[u8], needle: &[u8]) -> bool {
+    haystack.windows(needle.len()).any(|w| w == needle)
+}
+
+/// Kinds a file with the given extension may legitimately contain.
+fn expected_kinds(extension: &str) -> Option<&'static [&'static str]> {
+    Some(match extension {
+        "pdf" => &["pdf"],
+        "jpg" | "jpeg" | "jpe" | "jfif" => &["jpeg"],
+        "png" => &["png"],
+        "gif" => &["gif"],
+        "bmp" => &["bmp"],
+        "webp" => &["webp"],
+        "tif" | "tiff" => &["tiff"],
+        "ico" => &["ico", "png"],
+        "svg" => &["svg", "xml"],
+        "mp3" => &["mp3"],
+        "mp4" | "m4a" | "m4v" | "mov" => &["mp4"],
+        "wav" => &["wav"],
+        "avi" => &["avi"],
+        "ogg" => &["ogg"],
+        "flac" => &["flac"],
+        "doc" | "xls" | "ppt" => &["ole2", "rtf"],
+        "msg" => &["msg", "ole2"],
+        "eml" => &["email", "text"],
+        "mbox" => &["mbox", "email", "text"],
+        "docx" | "docm" | "xlsx" | "xlsm" | "pptx" | "pptm" => &["docx", "docm", "xlsx", "xlsm", "pptx", "pptm", "ooxml"],
+        "odt" | "ods" | "odp" => &["odt", "ods", "odp", "zip"],
+        "rtf" => &["rtf"],
+        "txt" | "csv" | "log" | "md" | "ini" => &["text", "script", "json", "xml", "html", "empty"],
+        "html" | "htm" => &["html", "text", "xml"],
+        "zip" => &["zip", "jar", "apk", "docx", "xlsx", "pptx", "odt"],
+        "gz" | "tgz" => &["gzip"],
+        "7z" => &["7z"],
+        "rar" => &["rar"],
+        _ => return None,
+    })
+}
+
+/// Flags content that would run when opened while its extension claims a
+/// harmless document, picture or media type.
+pub fn extension_mismatch(extension: &str, file_type: &FileType) -> Option<Detection> {
+    let expected = expected_kinds(&extension.to_ascii_lowercase())?;
+    if !file_type.is_executable() || expected.contains(&file_type.kind.as_str()) {
+        return None;
+    }
+
+    Some(Detection::heuristic(
+        format!("Heuristic.ExtensionMismatch.{}", file_type.kind.to_ascii_uppercase()),
+        "filetype",
+        format!("File named .{} contains {} content", extension, file_type.kind),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// ZIP archive of empty stored members, with a central directory.
+    fn zip(names: &[&str]) -> Vec<u8> {
+        let mut data = Vec::new();
+        let mut central = Vec::new();
+        for name in names {
+            let offset = data.len() as u32;
+            data.extend_from_slice(b"PK\x03\x04");
+            data.extend_from_slice(&[0; 22]);
+            data.extend_from_slice(&(name.len() as u16).to_le_bytes());
+            data.extend_from_slice(&[0; 2]);
+            data.extend_from_slice(name.as_bytes());
+
+            central.extend_from_slice(b"PK\x01\x02");
+            central.extend_from_slice(&[0; 24]);
+            central.extend_from_slice(&(name.len() as u16).to_le_bytes());
+            central.extend_from_slice(&[0; 12]);
+            central.extend_from_slice(&offset.to_le_bytes());
+            central.extend_from_slice(name.as_bytes());
+        }
+        let central_offset = data.len() as u32;
+        data.extend_from_slice(&central);
+        data.extend_from_slice(b"PK\x05\x06");
+        data.extend_from_slice(&[0; 12]);
+        data.extend_from_slice(&central_offset.to_le_bytes());
+        data.extend_from_slice(&[0; 2]);
+        data
+    }
+
+    fn kind(data: &[u8]) -> String {
+        identify(data).kind
+    }
+
+    #[test]
+    fn identifies_magic_numbers() {
+        assert_eq!(kind(b"MZ\x90\x00\x03\x00"), "pe");
+        assert_eq!(kind(b"\x7fELF\x02\x01\x01"), "elf");
+        assert_eq!(kind(&[0xcf, 0xfa, 0xed, 0xfe, 7, 0, 0, 1]), "macho");
+        // Universal binaries and classes share a magic
+        assert_eq!(kind(&[0xca, 0xfe, 0xba, 0xbe, 0, 0, 0, 2]), "macho");
+        assert_eq!(kind(&[0xca, 0xfe, 0xba, 0xbe, 0, 0, 0, 52]), "java-class");
+        assert_eq!(kind(b"\xff\xd8\xff\xe0\x00\x10JFIF"), "jpeg");
+        assert_eq!(kind(b"\x89PNG\r\n\x1a\n\x00\x00"), "png");
+        assert_eq!(kind(b"junk before %PDF-1.7\n"), "pdf");
+        assert_eq!(kind(b"RIFF\x00\x00\x00\x00WEBPVP8 "), "webp");
+        assert_eq!(kind(b"\x1f\x8b\x08\x00"), "gzip");
+        assert_eq!(kind(b"#!/bin/sh\necho hi\n"), "script");
+        assert_eq!(identify(b"#!/bin/sh\n").mime, "text/x-shellscript");
+        assert_eq!(kind(b""), "empty");
+        assert_eq!(kind(&[0x00, 0x01, 0x02, 0x03]), "unknown");
+    }
+
+    #[test]
+    fn tells_zip_containers_apart() {
+        assert_eq!(kind(&zip(&["[Content_Types].xml", "word/document.xml"])), "docx");
+        assert_eq!(kind(&zip(&["[Content_Types].xml", "xl/workbook.xml", "xl/vbaProject.bin"])), "xlsm");
+        assert_eq!(kind(&zip(&["[Content_Types].xml", "ppt/presentation.xml"])), "pptx");
+        assert_eq!(kind(&zip(&["META-INF/MANIFEST.MF", "a/Main.class"])), "jar");
+        assert_eq!(kind(&zip(&["AndroidManifest.xml", "classes.dex", "META-INF/MANIFEST.MF"])), "apk");
+        assert_eq!(kind(&zip(&["readme.txt"])), "zip");
+
+        // Without the central directory the local headers still name the members
+        let full = zip(&["[Content_Types].xml", "word/document.xml"]);
+        let truncated = &full[..full.windows(4).position(|w| w == b"PK\x01\x02").unwrap()];
+        assert_eq!(zip_entry_names(truncated), ["[Content_Types].xml", "word/document.xml"]);
+        assert_eq!(kind(truncated), "docx");
+
+        let mut odt = b"PK\x03\x04".to_vec();
+        odt.extend_from_slice(&[0; 22]);
+        odt.extend_from_slice(&8u16.to_le_bytes());
+        odt.extend_from_slice(&[0; 2]);
+        odt.extend_from_slice(b"mimetypeapplication/vnd.oasis.opendocument.text");
+        assert_eq!(identify(&odt), FileType::new("odt", "application/vnd.oasis.opendocument.text"));
+    }
+
+    #[test]
+    fn detects_text_encodings() {
+        let encoding = |data: &[u8]| identify(data).encoding;
+        assert_eq!(encoding(b"plain words\n"), Some("ascii".to_string()));
+        assert_eq!(encoding("caf\u{e9}\n".as_bytes()), Some("utf-8".to_string()));
+        assert_eq!(encoding(b"\xef\xbb\xbfbom"), Some("utf-8".to_string()));
+        assert_eq!(encoding(b"\xff\xfeh\x00i\x00"), Some("utf-16le".to_string()));
+        assert_eq!(encoding(b"caf\xe9\n"), Some("iso-8859-1".to_string()));
+        assert_eq!(kind(b"text with a \x00 byte"), "unknown");
+
+        // A character cut in half by the sniff window
+        let mut long = vec![b'a'; TEXT_SNIFF_LEN - 1];
+        long.extend_from_slice("\u{e9}".as_bytes());
+        assert_eq!(encoding(&long), Some("utf-8".to_string()));
+
+        assert_eq!(kind(b"  <!DOCTYPE html><html></html>"), "html");
+        assert_eq!(kind(b"<?xml version=\"1.0\"?>\n<svg xmlns=\"\"/>"), "svg");
+        assert_eq!(kind(b"{\"a\": 1}"), "json");
+        assert_eq!(kind(b"From: a@example.com\nSubject: hi\n\nbody\n"), "email");
+        assert_eq!(kind(b"From a@example.com Mon Jan 1\nFrom: a@example.com\nTo: b@example.com\n\n"), "mbox");
+        assert_eq!(kind(b"Note: one colon is not a message\nsecond line\n"), "text");
+    }
+
+    #[test]
+    fn flags_executables_behind_harmless_extensions() {
+        let pe = identify(b"MZ\x90\x00");
+        let detection = extension_mismatch("pdf", &pe).unwrap();
+        assert_eq!(detection.name, "Heuristic.ExtensionMismatch.PE");
+        assert_eq!(detection.source, "filetype");
+        assert_eq!(detection.description.as_deref(), Some("File named .pdf contains pe content"));
+
+        let elf = identify(b"\x7fELF\x02\x01\x01");
+        assert_eq!(extension_mismatch("JPG", &elf).unwrap().name, "Heuristic.ExtensionMismatch.ELF");
+        assert!(extension_mismatch("docx", &identify(&zip(&["a/B.class"]))).is_none(), "jar is not executable");
+
+        // Matching, unknown and executable extensions are left alone
+        assert!(extension_mismatch("pdf", &identify(b"%PDF-1.4")).is_none());
+        assert!(extension_mismatch("exe", &pe).is_none());
+        assert!(extension_mismatch("", &pe).is_none());
+        assert!(extension_mismatch("txt", &identify(b"#!/bin/sh\n")).is_none());
+        assert!(extension_mismatch("pdf", &identify(b"#!/bin/sh\n")).is_some());
+    }
+}
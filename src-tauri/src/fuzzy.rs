@@ -0,0 +1,628 @@
+//! Fuzzy hashes for finding variants of known files.
+//!
+//! * ssdeep splits the input at content-defined boundaries and hashes each
+//!   piece to one character, so an edit only changes the characters around
+//!   it. Two digests are compared by edit distance, giving a score from 0 to
+//!   100 (identical).
+//! * TLSH summarises the distribution of byte trigrams into quartile codes.
+//!   Two digests are compared by a distance from 0 (identical) upwards; below
+//!   about 50 files are usually closely related.
+//!
+//! Both hashers are streaming so they can ride along with the cryptographic
+//! digests in [`crate::hashing`].
+
+use serde::{Deserialize, Serialize};
+
+const B64: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+const ROLLING_WINDOW: usize = 7;
+const MIN_BLOCKSIZE: u64 = 3;
+const NUM_BLOCKHASHES: usize = 31;
+const SPAMSUM_LENGTH: usize = 64;
+const HASH_PRIME: u32 = 0x0100_0193;
+const HASH_INIT: u32 = 0x2802_1967;
+
+#[derive(Clone, Copy)]
+struct BlockHash {
+    digest: [u8; SPAMSUM_LENGTH],
+    len: usize,
+    half_digest: u8,
+    h: u32,
+    half_h: u32,
+}
+
+impl BlockHash {
+    const EMPTY: BlockHash =
+        BlockHash { digest: [0; SPAMSUM_LENGTH], len: 0, half_digest: 0, h: HASH_INIT, half_h: HASH_INIT };
+}
+
+#[derive(Default)]
+struct RollingHash {
+    window: [u8; ROLLING_WINDOW],
+    h1: u32,
+    h2: u32,
+    h3: u32,
+    n: usize,
+}
+
+impl RollingHash {
+    fn update(&mut self, c: u8) {
+        let c32 = c as u32;
+        self.h2 = self.h2.wrapping_sub(self.h1).wrapping_add(ROLLING_WINDOW as u32 * c32);
+        self.h1 = self.h1.wrapping_add(c32).wrapping_sub(self.window[self.n] as u32);
+        self.window[self.n] = c;
+        self.n = (self.n + 1) % ROLLING_WINDOW;
+        self.h3 = (self.h3 << 5) ^ c32;
+    }
+
+    fn sum(&self) -> u32 {
+        self.h1.wrapping_add(self.h2).wrapping_add(self.h3)
+    }
+}
+
+fn sum_hash(c: u8, h: u32) -> u32 {
+    h.wrapping_mul(HASH_PRIME) ^ c as u32
+}
+
+fn block_size(index: usize) -> u64 {
+    MIN_BLOCKSIZE << index
+}
+
+/// Incremental ssdeep hasher. Digests for every candidate block size are
+/// kept until the input is long enough to rule the small ones out.
+pub struct SsdeepHasher {
+    blocks: [BlockHash; NUM_BLOCKHASHES],
+    start: usize,
+    end: usize,
+    total_size: u64,
+    roll: RollingHash,
+}
+
+impl Default for SsdeepHasher {
+    fn default() -> Self {
+        SsdeepHasher {
+            blocks: [BlockHash::EMPTY; NUM_BLOCKHASHES],
+            start: 0,
+            end: 1,
+            total_size: 0,
+            roll: RollingHash::default(),
+        }
+    }
+}
+
+impl SsdeepHasher {
+    pub fn update(&mut self, data: &[u8]) {
+        self.total_size += data.len() as u64;
+        for &c in data {
+            self.step(c);
+        }
+    }
+
+    fn step(&mut self, c: u8) {
+        self.roll.update(c);
+        let h = self.roll.sum() as u64;
+        for block in &mut self.blocks[self.start..self.end] {
+            block.h = sum_hash(c, block.h);
+            block.half_h = sum_hash(c, block.half_h);
+        }
+
+        let mut i = self.start;
+        while i < self.end {
+            // A boundary for one block size is a boundary for every smaller one
+            if h % block_size(i) != block_size(i) - 1 {
+                break;
+            }
+            if self.blocks[i].len == 0 {
+                self.fork();
+            }
+            let block = &mut self.blocks[i];
+            block.digest[block.len] = B64[(block.h % 64) as usize];
+            block.half_digest = B64[(block.half_h % 64) as usize];
+            if block.len < SPAMSUM_LENGTH - 1 {
+                block.len += 1;
+                block.digest[block.len] = 0;
+                block.h = HASH_INIT;
+                if block.len < SPAMSUM_LENGTH / 2 {
+                    block.half_h = HASH_INIT;
+                    block.half_digest = 0;
+                }
+            } else {
+                self.reduce();
+            }
+            i += 1;
+        }
+    }
+
+    /// Starts tracking the next larger block size.
+    fn fork(&mut self) {
+        if self.end >= NUM_BLOCKHASHES {
+            return;
+        }
+        let previous = self.blocks[self.end - 1];
+        self.blocks[self.end] = BlockHash { h: previous.h, half_h: previous.half_h, ..BlockHash::EMPTY };
+        self.end += 1;
+    }
+
+    /// Stops tracking the smallest block size once the input is too long
+    /// for it and the next one has enough characters.
+    fn reduce(&mut self) {
+        if self.end - self.start < 2
+            || block_size(self.start) * SPAMSUM_LENGTH as u64 >= self.total_size
+            || self.blocks[self.start + 1].len < SPAMSUM_LENGTH / 2
+        {
+            return;
+        }
+        self.start += 1;
+    }
+
+    pub fn finalize(&self) -> String {
+        let mut index = self.start;
+        while block_size(index) * (SPAMSUM_LENGTH as u64) < self.total_size && index < NUM_BLOCKHASHES - 1 {
+            index += 1;
+        }
+        index = index.min(self.end - 1);
+        while index > self.start && self.blocks[index].len < SPAMSUM_LENGTH / 2 {
+            index -= 1;
+        }
+
+        let h = self.roll.sum();
+        let block = &self.blocks[index];
+        let mut digest = format!("{}:", block_size(index)).into_bytes();
+        digest.extend_from_slice(&block.digest[..block.len]);
+        if h != 0 {
+            digest.push(B64[(block.h % 64) as usize]);
+        } else if block.digest[block.len] != 0 {
+            digest.push(block.digest[block.len]);
+        }
+        digest.push(b':');
+        if index + 1 < self.end {
+            let next = &self.blocks[index + 1];
+            digest.extend_from_slice(&next.digest[..next.len.min(SPAMSUM_LENGTH / 2 - 1)]);
+            if h != 0 {
+                digest.push(B64[(next.half_h % 64) as usize]);
+            } else if next.half_digest != 0 {
+                digest.push(next.half_digest);
+            }
+        } else if h != 0 {
+            digest.push(B64[(block.h % 64) as usize]);
+        }
+        String::from_utf8(digest).expect("ssdeep digests are ASCII")
+    }
+}
+
+/// A parsed ssdeep digest, with runs of more than three identical
+/// characters cut down as the comparison expects.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SsdeepDigest {
+    block_size: u64,
+    first: Vec<u8>,
+    second: Vec<u8>,
+}
+
+impl SsdeepDigest {
+    pub fn parse(digest: &str) -> Result<Self, String> {
+        let invalid = || format!("invalid ssdeep digest '{}'", digest);
+        let mut parts = digest.trim().splitn(3, ':');
+        let block_size: u64 = parts.next().and_then(|s| s.parse().ok()).ok_or_else(invalid)?;
+        let first = parts.next().ok_or_else(invalid)?;
+        // A file name may follow the digest after a comma
+        let second = parts.next().ok_or_else(invalid)?.split(',').next().unwrap_or("");
+        if block_size < MIN_BLOCKSIZE
+            || first.len() > SPAMSUM_LENGTH
+            || second.len() > SPAMSUM_LENGTH
+            || !first.bytes().chain(second.bytes()).all(|c| B64.contains(&c))
+        {
+            return Err(invalid());
+        }
+        Ok(SsdeepDigest {
+            block_size,
+            first: eliminate_sequences(first.as_bytes()),
+            second: eliminate_sequences(second.as_bytes()),
+        })
+    }
+
+    /// Match score from 0 (unrelated) to 100. Digests can only be compared
+    /// when their block sizes are equal or a factor of two apart.
+    pub fn compare(&self, other: &SsdeepDigest) -> u32 {
+        let (a, b) = (self, other);
+        if a.block_size == b.block_size {
+            if a.first == b.first && a.second == b.second {
+                return 100;
+            }
+            score_strings(&a.first, &b.first, a.block_size).max(score_strings(&a.second, &b.second, a.block_size * 2))
+        } else if a.block_size * 2 == b.block_size {
+            score_strings(&a.second, &b.first, b.block_size)
+        } else if b.block_size * 2 == a.block_size {
+            score_strings(&a.first, &b.second, a.block_size)
+        } else {
+            0
+        }
+    }
+}
+
+fn eliminate_sequences(s: &[u8]) -> Vec<u8> {
+    let mut out: Vec<u8> = Vec::with_capacity(s.len());
+    for &c in s {
+        if out.len() < 3 || out[out.len() - 3..].iter().any(|&p| p != c) {
+            out.push(c);
+        }
+    }
+    out
+}
+
+fn score_strings(a: &[u8], b: &[u8], block_size: u64) -> u32 {
+    if a.is_empty() || b.is_empty() {
+        return 0;
+    }
+    let common = a.windows(ROLLING_WINDOW).any(|w| b.windows(ROLLING_WINDOW).any(|v| v == w));
+    if !common {
+        return 0;
+    }
+    let total = (a.len() + b.len()) as u64;
+    let distance = edit_distance(a, b) as u64;
+    let score = 100 - (100 * (distance * SPAMSUM_LENGTH as u64 / total)) / SPAMSUM_LENGTH as u64;
+    // Small block sizes are capped so short, common strings don't look like
+    // strong matches
+    let cap_from = (99 + ROLLING_WINDOW as u64) / ROLLING_WINDOW as u64 * MIN_BLOCKSIZE;
+    if block_size >= cap_from {
+        return score as u32;
+    }
+    score.min(block_size / MIN_BLOCKSIZE * a.len().min(b.len()) as u64) as u32
+}
+
+/// Edit distance with insertions and deletions costing 1 and
+/// substitutions 2, as ssdeep scores it.
+fn edit_distance(a: &[u8], b: &[u8]) -> usize {
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, &ca) in a.iter().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, &cb) in b.iter().enumerate() {
+            let substitute = diagonal + if ca == cb { 0 } else { 2 };
+            diagonal = row[j + 1];
+            row[j + 1] = substitute.min(row[j + 1] + 1).min(row[j] + 1);
+        }
+    }
+    row[b.len()]
+}
+
+const TLSH_BUCKETS: usize = 256;
+const TLSH_EFFECTIVE_BUCKETS: usize = 128;
+const TLSH_CODE_SIZE: usize = 32;
+const TLSH_WINDOW: usize = 5;
+const TLSH_MIN_LENGTH: u64 = 50;
+
+/// Pearson permutation used by TLSH.
+const V_TABLE: [u8; 256] = [
+    1, 87, 49, 12, 176, 178, 102, 166, 121, 193, 6, 84, 249, 230, 44, 163, 14, 197, 213, 181, 161, 85, 218, 80, 64,
+    239, 24, 226, 236, 142, 38, 200, 110, 177, 104, 103, 141, 253, 255, 50, 77, 101, 81, 18, 45, 96, 31, 222, 25, 107,
+    190, 70, 86, 237, 240, 34, 72, 242, 20, 214, 244, 227, 149, 235, 97, 234, 57, 22, 60, 250, 82, 175, 208, 5, 127,
+    199, 111, 62, 135, 248, 174, 169, 211, 58, 66, 154, 106, 195, 245, 171, 17, 187, 182, 179, 0, 243, 132, 56, 148,
+    75, 128, 133, 158, 100, 130, 126, 91, 13, 153, 246, 216, 219, 119, 68, 223, 78, 83, 88, 201, 99, 122, 11, 92, 32,
+    136, 114, 52, 10, 138, 30, 48, 183, 156, 35, 61, 26, 143, 74, 251, 94, 129, 162, 63, 152, 170, 7, 115, 167, 241,
+    206, 3, 150, 55, 59, 151, 220, 90, 53, 23, 131, 125, 173, 15, 238, 79, 95, 89, 16, 105, 137, 225, 224, 217, 160,
+    37, 123, 118, 73, 2, 157, 46, 116, 9, 145, 134, 228, 207, 212, 202, 215, 69, 229, 27, 188, 67, 124, 168, 252, 42,
+    4, 29, 108, 21, 247, 19, 205, 39, 203, 233, 40, 186, 147, 198, 192, 155, 33, 164, 191, 98, 204, 165, 180, 117, 76,
+    140, 36, 210, 172, 41, 54, 159, 8, 185, 232, 113, 196, 231, 47, 146, 120, 51, 65, 28, 144, 254, 221, 93, 189, 194,
+    139, 112, 43, 71, 109, 184, 209,
+];
+
+fn pearson(salt: u8, i: u8, j: u8, k: u8) -> u8 {
+    let h = V_TABLE[salt as usize];
+    let h = V_TABLE[(h ^ i) as usize];
+    let h = V_TABLE[(h ^ j) as usize];
+    V_TABLE[(h ^ k) as usize]
+}
+
+fn swap_nibbles(b: u8) -> u8 {
+    b.rotate_left(4)
+}
+
+/// Incremental TLSH hasher (128 buckets, 1 byte checksum).
+pub struct TlshHasher {
+    buckets: [u32; TLSH_BUCKETS],
+    window: [u8; TLSH_WINDOW],
+    checksum: u8,
+    len: u64,
+}
+
+impl Default for TlshHasher {
+    fn default() -> Self {
+        TlshHasher { buckets: [0; TLSH_BUCKETS], window: [0; TLSH_WINDOW], checksum: 0, len: 0 }
+    }
+}
+
+impl TlshHasher {
+    pub fn update(&mut self, data: &[u8]) {
+        for &c in data {
+            let j = (self.len % TLSH_WINDOW as u64) as usize;
+            self.window[j] = c;
+            if self.len >= TLSH_WINDOW as u64 - 1 {
+                let back = |n: usize| self.window[(j + TLSH_WINDOW - n) % TLSH_WINDOW];
+                let (w0, w1, w2, w3, w4) = (back(0), back(1), back(2), back(3), back(4));
+                self.checksum = pearson(0, w0, w1, self.checksum);
+                for (salt, a, b) in [(2, w1, w2), (3, w1, w3), (5, w2, w3), (7, w2, w4), (11, w1, w4), (13, w3, w4)] {
+                    self.buckets[pearson(salt, w0, a, b) as usize] += 1;
+                }
+            }
+            self.len += 1;
+        }
+    }
+
+    /// The `T1` digest, or `None` when the input is too short or too
+    /// uniform to be summarised.
+    pub fn finalize(&self) -> Option<String> {
+        if self.len < TLSH_MIN_LENGTH {
+            return None;
+        }
+        let buckets = &self.buckets[..TLSH_EFFECTIVE_BUCKETS];
+        let mut sorted = buckets.to_vec();
+        sorted.sort_unstable();
+        let (q1, q2, q3) = (sorted[31], sorted[63], sorted[95]);
+        let nonzero = buckets.iter().filter(|&&b| b > 0).count();
+        if q3 == 0 || nonzero <= TLSH_EFFECTIVE_BUCKETS / 2 {
+            return None;
+        }
+
+        let mut code = [0u8; TLSH_CODE_SIZE];
+        for (i, quad) in buckets.chunks_exact(4).enumerate() {
+            for (j, &count) in quad.iter().enumerate() {
+                let level = if count > q3 {
+                    3
+                } else if count > q2 {
+                    2
+                } else if count > q1 {
+                    1
+                } else {
+                    0
+                };
+                code[i] |= level << (j * 2);
+            }
+        }
+        let q1_ratio = ((q1.wrapping_mul(100)) as f32 / q3 as f32) as u32 % 16;
+        let q2_ratio = ((q2.wrapping_mul(100)) as f32 / q3 as f32) as u32 % 16;
+
+        let mut bytes = vec![
+            swap_nibbles(self.checksum),
+            swap_nibbles(length_code(self.len)),
+            ((q1_ratio << 4) | q2_ratio) as u8,
+        ];
+        bytes.extend(code.iter().rev());
+        Some(format!("T1{}", hex::encode_upper(bytes)))
+    }
+}
+
+/// Logarithmic bucket for the input length.
+fn length_code(len: u64) -> u8 {
+    let log = (len as f32 as f64).ln();
+    let code = if len <= 656 {
+        (log / 0.405_465_1).floor()
+    } else if len <= 3199 {
+        (log / 0.262_364_26 - 8.727_77).floor()
+    } else {
+        (log / 0.095_310_18 - 62.5472).floor()
+    };
+    (code as i64 & 0xff) as u8
+}
+
+/// A parsed TLSH digest.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TlshDigest {
+    checksum: u8,
+    length: u8,
+    q1_ratio: u8,
+    q2_ratio: u8,
+    code: [u8; TLSH_CODE_SIZE],
+}
+
+impl TlshDigest {
+    pub fn parse(digest: &str) -> Result<Self, String> {
+        let invalid = || format!("invalid TLSH digest '{}'", digest);
+        let trimmed = digest.trim();
+        let hex_part = trimmed.strip_prefix("T1").or_else(|| trimmed.strip_prefix("t1")).unwrap_or(trimmed);
+        let bytes = hex::decode(hex_part).map_err(|_| invalid())?;
+        if bytes.len() != TLSH_CODE_SIZE + 3 {
+            return Err(invalid());
+        }
+        let mut code = [0u8; TLSH_CODE_SIZE];
+        code.copy_from_slice(&bytes[3..]);
+        Ok(TlshDigest {
+            checksum: bytes[0],
+            length: swap_nibbles(bytes[1]),
+            q1_ratio: bytes[2] >> 4,
+            q2_ratio: bytes[2] & 0x0f,
+            code,
+        })
+    }
+
+    /// Distance including the length difference; 0 means identical.
+    pub fn distance(&self, other: &TlshDigest) -> u32 {
+        let mut diff = 0;
+        let length = mod_diff(self.length as u32, other.length as u32, 256);
+        diff += if length <= 1 { length } else { length * 12 };
+        for (a, b) in [(self.q1_ratio, other.q1_ratio), (self.q2_ratio, other.q2_ratio)] {
+            let q = mod_diff(a as u32, b as u32, 16);
+            diff += if q <= 1 { q } else { (q - 1) * 12 };
+        }
+        if self.checksum != other.checksum {
+            diff += 1;
+        }
+        for (&a, &b) in self.code.iter().zip(other.code.iter()) {
+            for shift in (0..8).step_by(2) {
+                let d = ((a >> shift) & 3).abs_diff((b >> shift) & 3) as u32;
+                diff += if d == 3 { 6 } else { d };
+            }
+        }
+        diff
+    }
+}
+
+fn mod_diff(x: u32, y: u32, range: u32) -> u32 {
+    let d = x.abs_diff(y);
+    d.min(range - d)
+}
+
+/// Fuzzy hashes of two files side by side, for analysts comparing samples.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FuzzyComparison {
+    pub first_ssdeep: String,
+    pub second_ssdeep: String,
+    pub first_tlsh: String,
+    pub second_tlsh: String,
+    /// 0 to 100, higher is more similar. `None` when a digest is missing.
+    pub ssdeep_score: Option<u32>,
+    /// 0 and up, lower is more similar.
+    pub tlsh_distance: Option<u32>,
+}
+
+impl FuzzyComparison {
+    pub fn new(first_ssdeep: &str, first_tlsh: &str, second_ssdeep: &str, second_tlsh: &str) -> Self {
+        let ssdeep_score = SsdeepDigest::parse(first_ssdeep)
+            .and_then(|a| SsdeepDigest::parse(second_ssdeep).map(|b| a.compare(&b)))
+            .ok();
+        let tlsh_distance = TlshDigest::parse(first_tlsh)
+            .and_then(|a| TlshDigest::parse(second_tlsh).map(|b| a.distance(&b)))
+            .ok();
+        FuzzyComparison {
+            first_ssdeep: first_ssdeep.to_string(),
+            second_ssdeep: second_ssdeep.to_string(),
+            first_tlsh: first_tlsh.to_string(),
+            second_tlsh: second_tlsh.to_string(),
+            ssdeep_score,
+            tlsh_distance,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Deterministic text of words, so both hashes see realistic input.
+    fn text(seed: u64, len: usize) -> Vec<u8> {
+        let mut state = seed;
+        let mut out = Vec::with_capacity(len);
+        while out.len() < len {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            let word = 2 + (state % 7) as usize;
+            out.extend((0..word).map(|i| b'a' + ((state >> (8 + i * 5)) % 26) as u8));
+            out.push(if state.is_multiple_of(11) { b'\n' } else { b' ' });
+        }
+        out.truncate(len);
+        out
+    }
+
+    fn ssdeep(data: &[u8]) -> String {
+        let mut hasher = SsdeepHasher::default();
+        hasher.update(data);
+        hasher.finalize()
+    }
+
+    fn tlsh(data: &[u8]) -> Option<String> {
+        let mut hasher = TlshHasher::default();
+        hasher.update(data);
+        hasher.finalize()
+    }
+
+    fn compare(a: &[u8], b: &[u8]) -> (u32, u32) {
+        let ssdeep = |data| SsdeepDigest::parse(&ssdeep(data)).unwrap();
+        let tlsh = |data| TlshDigest::parse(&tlsh(data).unwrap()).unwrap();
+        (ssdeep(a).compare(&ssdeep(b)), tlsh(a).distance(&tlsh(b)))
+    }
+
+    #[test]
+    fn empty_and_short_inputs() {
+        assert_eq!(ssdeep(b""), "3::");
+        assert_eq!(tlsh(b""), None);
+        assert_eq!(tlsh(&text(1, TLSH_MIN_LENGTH as usize - 1)), None);
+        // Long enough, but too uniform to fill half of the buckets
+        assert_eq!(tlsh(&[b'a'; 4096]), None);
+        assert!(tlsh(&text(1, 4096)).is_some());
+    }
+
+    #[test]
+    fn digests_have_the_expected_shape() {
+        for len in [100, 5_000, 200_000] {
+            let digest = ssdeep(&text(2, len));
+            let (block_size, rest) = digest.split_once(':').unwrap();
+            let block_size: u64 = block_size.parse().unwrap();
+            assert!(block_size >= MIN_BLOCKSIZE && (block_size / MIN_BLOCKSIZE).is_power_of_two(), "{}", digest);
+            let (first, second) = rest.split_once(':').unwrap();
+            assert!(first.len() <= SPAMSUM_LENGTH && second.len() <= SPAMSUM_LENGTH / 2, "{}", digest);
+            assert!(first.bytes().chain(second.bytes()).all(|c| B64.contains(&c)), "{}", digest);
+        }
+        let digest = tlsh(&text(2, 5_000)).unwrap();
+        assert_eq!(digest.len(), 2 + 2 * (TLSH_CODE_SIZE + 3));
+        assert!(digest.starts_with("T1"));
+    }
+
+    #[test]
+    fn streaming_matches_one_shot() {
+        let data = text(3, 50_000);
+        let mut ssdeep_hasher = SsdeepHasher::default();
+        let mut tlsh_hasher = TlshHasher::default();
+        for chunk in data.chunks(777) {
+            ssdeep_hasher.update(chunk);
+            tlsh_hasher.update(chunk);
+        }
+        assert_eq!(ssdeep_hasher.finalize(), ssdeep(&data));
+        assert_eq!(tlsh_hasher.finalize(), tlsh(&data));
+    }
+
+    #[test]
+    fn identical_inputs_match_exactly() {
+        let data = text(4, 30_000);
+        assert_eq!(compare(&data, &data), (100, 0));
+    }
+
+    #[test]
+    fn related_inputs_score_high() {
+        let original = text(5, 30_000);
+        let mut edited = original.clone();
+        edited[10_000..10_040].copy_from_slice(&text(99, 40));
+        edited.extend_from_slice(b" a short note appended at the end");
+        let (score, distance) = compare(&original, &edited);
+        assert!(score >= 80, "ssdeep score {}", score);
+        assert!(distance <= 20, "TLSH distance {}", distance);
+
+        let (score, distance) = compare(&original, &text(6, 30_000));
+        assert_eq!(score, 0);
+        assert!(distance > 50, "TLSH distance {}", distance);
+    }
+
+    #[test]
+    fn compares_across_neighbouring_block_sizes() {
+        let a = SsdeepDigest::parse("24:abcdefghijklmnop:qrstuvwxyzABCDEF").unwrap();
+        let b = SsdeepDigest::parse("48:qrstuvwxyzABCDEF:GHIJ").unwrap();
+        assert_eq!(a.compare(&b), 100);
+        assert_eq!(b.compare(&a), 100);
+        let c = SsdeepDigest::parse("96:qrstuvwxyzABCDEF:GHIJ").unwrap();
+        assert_eq!(a.compare(&c), 0);
+    }
+
+    #[test]
+    fn parses_digests_strictly() {
+        assert!(SsdeepDigest::parse("3:uG:uG,\"file.txt\"").is_ok());
+        assert_eq!(SsdeepDigest::parse("3:aaaaaaab:").unwrap().first, b"aaab");
+        for invalid in ["", "3:abc", "2:ab:cd", "x:ab:cd", "3:a-b:cd"] {
+            assert!(SsdeepDigest::parse(invalid).is_err(), "{}", invalid);
+        }
+
+        let digest = tlsh(&text(7, 5_000)).unwrap();
+        assert_eq!(TlshDigest::parse(&digest), TlshDigest::parse(&digest.to_lowercase()));
+        assert_eq!(TlshDigest::parse(&digest), TlshDigest::parse(&digest[2..]));
+        assert!(TlshDigest::parse(&digest[..digest.len() - 2]).is_err());
+        assert!(TlshDigest::parse("T1XYZ").is_err());
+    }
+
+    #[test]
+    fn comparisons_tolerate_missing_digests() {
+        let data = text(8, 10_000);
+        let (s, t) = (ssdeep(&data), tlsh(&data).unwrap());
+        let comparison = FuzzyComparison::new(&s, &t, &s, "");
+        assert_eq!(comparison.ssdeep_score, Some(100));
+        assert_eq!(comparison.tlsh_distance, None);
+    }
+}
@@ -0,0 +1,165 @@
+//! Streaming file hashing.
+//!
+//! Files are read once in fixed-size chunks and every chunk is fed to all
+//! digest algorithms, including the ssdeep and TLSH fuzzy hashes, so large
+//! files never have to be held in memory.
+
+use md5::Md5;
+use serde::{Deserialize, Serialize};
+use sha1::Sha1;
+use sha2::{Digest, Sha256, Sha512};
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::Path;
+
+use crate::fuzzy::{SsdeepHasher, TlshHasher};
+
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// Hex-encoded digests of a single file.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FileDigests {
+    pub md5: String,
+    pub sha1: String,
+    pub sha256: String,
+    pub sha512: String,
+    #[serde(default)]
+    pub ssdeep: String,
+    /// Empty when the file is too short or too uniform for TLSH.
+    #[serde(default)]
+    pub tlsh: String,
+}
+
+/// Incremental hasher computing all supported digests in one pass.
+#[derive(Default)]
+pub struct MultiHasher {
+    md5: Md5,
+    sha1: Sha1,
+    sha256: Sha256,
+    sha512: Sha512,
+    ssdeep: SsdeepHasher,
+    tlsh: TlshHasher,
+}
+
+impl MultiHasher {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        self.md5.update(data);
+        self.sha1.update(data);
+        self.sha256.update(data);
+        self.sha512.update(data);
+        self.ssdeep.update(data);
+        self.tlsh.update(data);
+    }
+
+    pub fn finalize(self) -> FileDigests {
+        FileDigests {
+            md5: hex::encode(self.md5.finalize()),
+            sha1: hex::encode(self.sha1.finalize()),
+            sha256: hex::encode(self.sha256.finalize()),
+            sha512: hex::encode(self.sha512.finalize()),
+            ssdeep: self.ssdeep.finalize(),
+            tlsh: self.tlsh.finalize().unwrap_or_default(),
+        }
+    }
+}
+
+pub fn digest_reader<R: Read>(reader: R) -> io::Result<FileDigests> {
+    digest_reader_keeping(reader, 0).map(|(digests, _)| digests)
+}
+
+/// Hashes everything `reader` yields and hands back the first `keep` bytes
+/// of it, so a file can be hashed in full and analysed in one read.
+pub fn digest_reader_keeping<R: Read>(mut reader: R, keep: u64) -> io::Result<(FileDigests, Vec<u8>)> {
+    let mut hasher = MultiHasher::new();
+    let mut kept = Vec::new();
+    let mut buf = vec![0u8; CHUNK_SIZE];
+
+    loop {
+        let n = match reader.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        hasher.update(&buf[..n]);
+        let room = keep.saturating_sub(kept.len() as u64).min(n as u64) as usize;
+        kept.extend_from_slice(&buf[..room]);
+    }
+
+    Ok((hasher.finalize(), kept))
+}
+
+pub fn digest_file(path: &Path) -> io::Result<FileDigests> {
+    digest_reader(File::open(path)?)
+}
+
+pub fn digest_bytes(data: &[u8]) -> FileDigests {
+    let mut hasher = MultiHasher::new();
+    hasher.update(data);
+    hasher.finalize()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_a_prefix_but_hashes_everything() {
+        let data: Vec<u8> = (0..3 * CHUNK_SIZE + 17).map(|i| (i % 251) as u8).collect();
+        let (digests, kept) = digest_reader_keeping(&data[..], CHUNK_SIZE as u64 + 5).unwrap();
+        assert_eq!(digests, digest_bytes(&data));
+        assert_eq!(kept, &data[..CHUNK_SIZE + 5]);
+
+        let (_, all) = digest_reader_keeping(&data[..], u64::MAX).unwrap();
+        assert_eq!(all, data);
+        assert_eq!(digest_reader(&data[..]).unwrap(), digests);
+    }
+
+    #[test]
+    fn known_digests() {
+        let empty = digest_bytes(b"");
+        assert_eq!(empty.md5, "d41d8cd98f00b204e9800998ecf8427e");
+        assert_eq!(empty.sha1, "da39a3ee5e6b4b0d3255bfef95601890afd80709");
+        assert_eq!(empty.sha256, "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855");
+        assert_eq!(
+            empty.sha512,
+            "cf83e1357eefb8bdf1542850d66d8007d620e4050b5715dc83f4a921d36ce9ce\
+             47d0d13c5d85f2b0ff8318d2877eec2f63b931bd47417a81a538327af927da3e"
+        );
+        assert_eq!(empty.ssdeep, "3::");
+        assert_eq!(empty.tlsh, "");
+
+        let abc = digest_bytes(b"abc");
+        assert_eq!(abc.md5, "900150983cd24fb0d6963f7d28e17f72");
+        assert_eq!(abc.sha1, "a9993e364706816aba3e25717850c26c9cd0d89d");
+        assert_eq!(abc.sha256, "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad");
+        assert_eq!(
+            abc.sha512,
+            "ddaf35a193617abacc417349ae20413112e6fa4e89a97ea20a9eeee64b55d39a\
+             2192992a274fc1a836ba3c23a3feebbd454d4423643ce80e2a9ac94fa54ca49f"
+        );
+        // One piece boundary after "ab", and no larger block size yet
+        assert_eq!(abc.ssdeep, "3:uG:uG");
+        // TLSH needs at least 50 bytes
+        assert_eq!(abc.tlsh, "");
+    }
+
+    #[test]
+    fn files_larger_than_a_chunk() {
+        let data: Vec<u8> = (0..2 * CHUNK_SIZE + 1).map(|i| (i % 251) as u8).collect();
+        let path = std::env::temp_dir().join(format!("varenizer-hashing-{}", std::process::id()));
+        std::fs::write(&path, &data).unwrap();
+        let digests = digest_file(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(digests.md5, "060bb13f48ee4b4ef00c6e28d0f4fce8");
+        assert_eq!(digests.sha256, "dd84db969f4ff2abb79c8c2fbc06e8d8e02c46d6481c958e057f7ad7a24c58a7");
+        // Fuzzy hashes don't depend on where the chunks were split
+        assert_eq!(digests, digest_bytes(&data));
+        assert!(!digests.tlsh.is_empty());
+    }
+}
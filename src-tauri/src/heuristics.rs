@@ -0,0 +1,504 @@
+//! Entropy and packer heuristics, and the scoring that turns heuristic
+//! findings into a verdict.
+//!
+//! Signature and threat-severity YARA hits are conclusive on their own. Every
+//! suspicious finding instead adds its weight to the file's score, and the
+//! score is compared against two thresholds, so a single weak indicator
+//! stays clean while several together make the file suspicious or a threat.
+//! Weights and thresholds are tunable through [`ScoringConfig`].
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+use crate::entropy;
+use crate::signatures::{Detection, Severity};
+
+const DEFAULT_SUSPICIOUS_THRESHOLD: f64 = 40.0;
+const DEFAULT_THREAT_THRESHOLD: f64 = 100.0;
+const DEFAULT_WEIGHT: f64 = 40.0;
+const DEFAULT_ENTROPY_WINDOW: usize = 4096;
+const MIN_ENTROPY_WINDOW: usize = 256;
+const MAX_ENTROPY_WINDOW: usize = 1024 * 1024;
+const DEFAULT_SSDEEP_SCORE: u32 = 60;
+const DEFAULT_TLSH_DISTANCE: u32 = 40;
+
+/// Whole files above this entropy are packed, compressed or encrypted.
+const HIGH_ENTROPY: f64 = 7.2;
+/// Windows above this entropy count towards a high-entropy region.
+const HIGH_WINDOW_ENTROPY: f64 = 7.5;
+/// Smaller files are too short for their entropy to mean much.
+const MIN_ENTROPY_SIZE: usize = 4096;
+/// A high-entropy region must be at least this big, and cover at least
+/// `1 / HIGH_REGION_FRACTION` of the file, to be reported.
+const MIN_HIGH_REGION: usize = 64 * 1024;
+const HIGH_REGION_FRACTION: usize = 4;
+
+/// Packer stubs identified by a marker in the headers (`head_only`) or
+/// anywhere in the file.
+const PACKER_MARKERS: [(&[u8], &str, bool); 14] = [
+    (b"UPX!", "UPX", true),
+    (b"This file is packed with the UPX", "UPX", false),
+    (b".MPRESS1", "MPRESS", true),
+    (b"MPRESS", "MPRESS", true),
+    (b".aspack", "ASPack", true),
+    (b"PECompact2", "PECompact", false),
+    (b".petite", "Petite", true),
+    (b".nsp0", "NsPack", true),
+    (b".themida", "Themida", true),
+    (b".vmp0", "VMProtect", true),
+    (b".enigma1", "Enigma", true),
+    (b"ConfusedByAttribute", "ConfuserEx", false),
+    (b"Ezuri", "Ezuri", true),
+    (b"$Id: burneye", "Burneye", true),
+];
+const PACKER_HEAD_SIZE: usize = 4096;
+
+/// Weights for the findings the analyzers raise. Names ending in `*` match
+/// by prefix.
+const DEFAULT_WEIGHTS: [(&str, f64); 52] = [
+    ("Heuristic.Archive.DecompressionBomb", 60.0),
+    ("Heuristic.ELF.EntryPointAnomaly", 25.0),
+    ("Heuristic.ELF.ExecutableStack", 20.0),
+    ("Heuristic.ELF.InsecureRunpath", 15.0),
+    ("Heuristic.ELF.NoSectionHeaders", 25.0),
+    ("Heuristic.ELF.PackedSegment", 25.0),
+    ("Heuristic.ELF.Packer.*", 40.0),
+    ("Heuristic.ELF.SectionSegmentMismatch", 20.0),
+    ("Heuristic.ELF.TextRelocations", 10.0),
+    ("Heuristic.ELF.UnusualInterpreter", 40.0),
+    ("Heuristic.ELF.WritableExecutableSegment", 30.0),
+    ("Heuristic.Email.AuthenticationFailed", 25.0),
+    ("Heuristic.Email.DangerousAttachment", 40.0),
+    ("Heuristic.Email.DeceptiveLink", 50.0),
+    ("Heuristic.Email.DisplayNameSpoof", 50.0),
+    ("Heuristic.Email.ReceivedChain", 25.0),
+    ("Heuristic.Email.ReplyToMismatch", 20.0),
+    ("Heuristic.Entropy.High", 20.0),
+    ("Heuristic.Entropy.HighRegion", 10.0),
+    ("Heuristic.ExtensionMismatch.*", 60.0),
+    ("Heuristic.Office.DDE", 70.0),
+    ("Heuristic.Office.Macro.AutoExec", 30.0),
+    ("Heuristic.Office.Macro.SuspiciousKeywords", 40.0),
+    ("Heuristic.PDF.AutoAction", 30.0),
+    ("Heuristic.PDF.EmbeddedExecutable", 60.0),
+    ("Heuristic.PDF.JavaScript", 30.0),
+    ("Heuristic.PDF.Launch", 60.0),
+    ("Heuristic.PDF.ObfuscatedNames", 50.0),
+    ("Heuristic.PE.EmbeddedExecutable", 30.0),
+    ("Heuristic.PE.EntryPointAnomaly", 30.0),
+    ("Heuristic.PE.MinimalImports", 15.0),
+    ("Heuristic.PE.PackedSection", 20.0),
+    ("Heuristic.PE.Packer.*", 40.0),
+    ("Heuristic.PE.SuspiciousImports.*", 30.0),
+    ("Heuristic.PE.WritableExecutableSection", 30.0),
+    ("Heuristic.Packer.*", 40.0),
+    ("Heuristic.Script.DownloadExecute", 60.0),
+    ("Heuristic.Script.EncodedCommand", 40.0),
+    ("Heuristic.Script.Obfuscated", 30.0),
+    ("Heuristic.Script.Persistence", 40.0),
+    ("Heuristic.Script.ReverseShell", 80.0),
+    ("Heuristic.URL.Credentials", 50.0),
+    ("Heuristic.URL.ExcessiveSubdomains", 20.0),
+    ("Heuristic.URL.Homoglyph", 60.0),
+    ("Heuristic.URL.IpHost", 30.0),
+    ("Heuristic.URL.Lookalike", 60.0),
+    ("Heuristic.URL.Punycode", 15.0),
+    ("Heuristic.URL.Shortener", 15.0),
+    ("Heuristic.URL.SuspiciousTld", 20.0),
+    ("IOC.*", 50.0),
+    ("Similar.*", 40.0),
+    ("YARA.*", 40.0),
+];
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ScoringConfig {
+    /// Score at which a file is reported as suspicious.
+    pub suspicious_threshold: f64,
+    /// Score at which heuristics alone make a file a threat.
+    pub threat_threshold: f64,
+    /// Weight of findings that `weights` doesn't cover.
+    pub default_weight: f64,
+    /// Weights by finding name. A name ending in `*` matches every finding
+    /// with that prefix, and the longest match wins. A weight of `0`
+    /// disables the finding.
+    pub weights: BTreeMap<String, f64>,
+    /// Block size for windowed entropy, in bytes.
+    pub entropy_window: usize,
+    /// Lowest ssdeep score, out of 100, that counts as similar to a known
+    /// sample.
+    pub ssdeep_threshold: u32,
+    /// Highest TLSH distance that counts as similar to a known sample.
+    pub tlsh_threshold: u32,
+}
+
+impl Default for ScoringConfig {
+    fn default() -> Self {
+        ScoringConfig {
+            suspicious_threshold: DEFAULT_SUSPICIOUS_THRESHOLD,
+            threat_threshold: DEFAULT_THREAT_THRESHOLD,
+            default_weight: DEFAULT_WEIGHT,
+            weights: DEFAULT_WEIGHTS.iter().map(|(name, weight)| (name.to_string(), *weight)).collect(),
+            entropy_window: DEFAULT_ENTROPY_WINDOW,
+            ssdeep_threshold: DEFAULT_SSDEEP_SCORE,
+            tlsh_threshold: DEFAULT_TLSH_DISTANCE,
+        }
+    }
+}
+
+impl ScoringConfig {
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let text = fs::read_to_string(path).map_err(|e| format!("Cannot read {}: {}", path.display(), e))?;
+        let config: ScoringConfig =
+            serde_json::from_str(&text).map_err(|e| format!("Invalid scoring config {}: {}", path.display(), e))?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        let text = serde_json::to_string_pretty(self).map_err(|e| format!("Cannot encode scoring config: {}", e))?;
+        fs::write(path, text).map_err(|e| format!("Cannot write {}: {}", path.display(), e))
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        let valid = |value: f64| value.is_finite() && value >= 0.0;
+        if !valid(self.suspicious_threshold) || self.suspicious_threshold == 0.0 {
+            return Err("Suspicious threshold must be a positive number".to_string());
+        }
+        if !valid(self.threat_threshold) || self.threat_threshold < self.suspicious_threshold {
+            return Err("Threat threshold must not be below the suspicious threshold".to_string());
+        }
+        if !valid(self.default_weight) {
+            return Err("Default weight must be a non-negative number".to_string());
+        }
+        if let Some((name, _)) = self.weights.iter().find(|(_, &weight)| !valid(weight)) {
+            return Err(format!("Weight for {} must be a non-negative number", name));
+        }
+        if !(MIN_ENTROPY_WINDOW..=MAX_ENTROPY_WINDOW).contains(&self.entropy_window) {
+            return Err(format!(
+                "Entropy window must be between {} and {} bytes",
+                MIN_ENTROPY_WINDOW, MAX_ENTROPY_WINDOW
+            ));
+        }
+        if self.ssdeep_threshold == 0 || self.ssdeep_threshold > 100 {
+            return Err("ssdeep threshold must be between 1 and 100".to_string());
+        }
+        Ok(())
+    }
+
+    pub fn weight(&self, name: &str) -> f64 {
+        if let Some(&weight) = self.weights.get(name) {
+            return weight;
+        }
+        self.weights
+            .iter()
+            .filter_map(|(pattern, &weight)| {
+                let prefix = pattern.strip_suffix('*')?;
+                name.starts_with(prefix).then_some((prefix.len(), weight))
+            })
+            .max_by_key(|&(len, _)| len)
+            .map_or(self.default_weight, |(_, weight)| weight)
+    }
+
+    /// Threat-severity detections decide on their own, everything else goes
+    /// by the score.
+    pub fn status(&self, detections: &[Detection], score: &HeuristicScore) -> &'static str {
+        if detections.iter().any(|d| d.severity == Severity::Threat) || score.score >= self.threat_threshold {
+            "threat"
+        } else if score.score >= self.suspicious_threshold {
+            "suspicious"
+        } else {
+            "clean"
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct HeuristicScore {
+    pub score: f64,
+    /// Suspicious findings that fired, in detection order.
+    pub contributions: Vec<ScoreContribution>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ScoreContribution {
+    pub name: String,
+    pub weight: f64,
+    pub description: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct EntropyProfile {
+    /// Whole-file entropy in bits per byte.
+    pub whole: f64,
+    pub window: usize,
+    pub windows: usize,
+    pub max_window: f64,
+    /// Windows above the high-entropy cut-off.
+    pub high_windows: usize,
+    /// The longest run of consecutive high-entropy windows.
+    pub high_region: Option<EntropyRegion>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EntropyRegion {
+    pub offset: usize,
+    pub size: usize,
+}
+
+pub fn entropy_profile(data: &[u8], window: usize) -> EntropyProfile {
+    let window = window.max(1);
+    let windows = entropy::windowed(data, window);
+    let mut high_region: Option<EntropyRegion> = None;
+    let mut run_start = None;
+    for (i, &e) in windows.iter().chain(std::iter::once(&0.0)).enumerate() {
+        match (e >= HIGH_WINDOW_ENTROPY, run_start) {
+            (true, None) => run_start = Some(i),
+            (false, Some(start)) => {
+                let size = ((i - start) * window).min(data.len());
+                if high_region.as_ref().is_none_or(|r| size > r.size) {
+                    high_region = Some(EntropyRegion { offset: start * window, size });
+                }
+                run_start = None;
+            }
+            _ => {}
+        }
+    }
+    EntropyProfile {
+        whole: entropy::shannon(data),
+        window,
+        windows: windows.len(),
+        max_window: windows.iter().copied().fold(0.0, f64::max),
+        high_windows: windows.iter().filter(|&&e| e >= HIGH_WINDOW_ENTROPY).count(),
+        high_region,
+    }
+}
+
+/// Entropy findings for executables and unidentified files, and packer
+/// stubs the format analyzers in `existing` haven't reported yet.
+pub fn findings(kind: &str, data: &[u8], profile: &EntropyProfile, existing: &[Detection]) -> Vec<Detection> {
+    let mut findings = Vec::new();
+    if matches!(kind, "pe" | "elf" | "macho" | "dex" | "unknown") && data.len() >= MIN_ENTROPY_SIZE {
+        if profile.whole >= HIGH_ENTROPY {
+            findings.push(Detection::heuristic(
+                "Heuristic.Entropy.High",
+                "entropy",
+                format!("Whole-file entropy is {:.2} bits per byte", profile.whole),
+            ));
+        } else if let Some(region) = profile
+            .high_region
+            .as_ref()
+            .filter(|r| r.size >= MIN_HIGH_REGION && r.size * HIGH_REGION_FRACTION >= data.len())
+        {
+            findings.push(Detection::heuristic(
+                "Heuristic.Entropy.HighRegion",
+                "entropy",
+                format!(
+                    "{} KiB at offset {:#x} has entropy above {:.1} bits per byte",
+                    region.size / 1024,
+                    region.offset,
+                    HIGH_WINDOW_ENTROPY
+                ),
+            ));
+        }
+    }
+
+    if matches!(kind, "pe" | "elf" | "macho") {
+        let head = &data[..data.len().min(PACKER_HEAD_SIZE)];
+        let contains = |haystack: &[u8], needle: &[u8]| haystack.windows(needle.len()).any(|w| w == needle);
+        let packer = PACKER_MARKERS
+            .iter()
+            .find(|(marker, _, head_only)| contains(if *head_only { head } else { data }, marker))
+            .map(|(_, name, _)| *name);
+        if let Some(packer) = packer {
+            let suffix = format!(".Packer.{}", packer);
+            if !existing.iter().any(|d| d.name.ends_with(&suffix)) {
+                findings.push(Detection::heuristic(
+                    format!("Heuristic.Packer.{}", packer),
+                    "entropy",
+                    format!("Contains the {} packer stub", packer),
+                ));
+            }
+        }
+    }
+    findings
+}
+
+/// Adds up the weights of the suspicious findings in `detections`.
+pub fn score(config: &ScoringConfig, detections: &[Detection]) -> HeuristicScore {
+    let contributions: Vec<ScoreContribution> = detections
+        .iter()
+        .filter(|d| d.severity == Severity::Suspicious)
+        .map(|d| ScoreContribution {
+            name: d.name.clone(),
+            weight: config.weight(&d.name),
+            description: d.description.clone(),
+        })
+        .filter(|c| c.weight > 0.0)
+        .collect();
+    HeuristicScore { score: contributions.iter().fold(0.0, |sum, c| sum + c.weight), contributions }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Deterministic bytes with close to 8 bits of entropy.
+    fn noise(len: usize) -> Vec<u8> {
+        let mut state = 0x2545_f491_4f6c_dd1d_u64;
+        (0..len)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                (state >> 24) as u8
+            })
+            .collect()
+    }
+
+    fn suspicious(names: &[&str]) -> Vec<Detection> {
+        names.iter().map(|name| Detection::heuristic(*name, "test", "")).collect()
+    }
+
+    fn names(detections: &[Detection]) -> Vec<&str> {
+        detections.iter().map(|d| d.name.as_str()).collect()
+    }
+
+    #[test]
+    fn weights_match_by_name_then_longest_prefix() {
+        let mut config = ScoringConfig::default();
+        assert_eq!(config.weight("Heuristic.Script.ReverseShell"), 80.0);
+        assert_eq!(config.weight("Heuristic.PE.Packer.UPX"), 40.0);
+        assert_eq!(config.weight("Heuristic.Unknown"), DEFAULT_WEIGHT);
+
+        config.weights.insert("Heuristic.PE.*".to_string(), 5.0);
+        config.weights.insert("Heuristic.PE.SuspiciousImports.Injection".to_string(), 0.0);
+        assert_eq!(config.weight("Heuristic.PE.MinimalImports"), 15.0);
+        assert_eq!(config.weight("Heuristic.PE.Overlay"), 5.0);
+        assert_eq!(config.weight("Heuristic.PE.SuspiciousImports.Keylogger"), 30.0);
+        assert_eq!(config.weight("Heuristic.PE.SuspiciousImports.Injection"), 0.0);
+    }
+
+    #[test]
+    fn scores_only_weighted_suspicious_findings() {
+        let mut config = ScoringConfig::default();
+        config.weights.insert("Heuristic.Entropy.High".to_string(), 0.0);
+        let mut detections = suspicious(&["Heuristic.Script.Obfuscated", "Heuristic.Entropy.High", "IOC.Hash"]);
+        detections.push(Detection { severity: Severity::Threat, ..Detection::heuristic("EICAR", "hash", "") });
+
+        let score = score(&config, &detections);
+        assert_eq!(score.score, 80.0);
+        let contributions: Vec<(&str, f64)> = score.contributions.iter().map(|c| (c.name.as_str(), c.weight)).collect();
+        assert_eq!(contributions, [("Heuristic.Script.Obfuscated", 30.0), ("IOC.Hash", 50.0)]);
+    }
+
+    #[test]
+    fn thresholds_decide_the_status() {
+        let config = ScoringConfig::default();
+        let status = |names: &[&str]| {
+            let detections = suspicious(names);
+            config.status(&detections, &score(&config, &detections))
+        };
+        assert_eq!(status(&[]), "clean");
+        // 30, just under the suspicious threshold of 40
+        assert_eq!(status(&["Heuristic.Script.Obfuscated"]), "clean");
+        assert_eq!(status(&["Heuristic.Script.Persistence"]), "suspicious");
+        // 40 + 50 = 90, still under the threat threshold of 100
+        assert_eq!(status(&["Heuristic.Script.Persistence", "IOC.Domain"]), "suspicious");
+        assert_eq!(status(&["Heuristic.Script.DownloadExecute", "Heuristic.Script.Persistence"]), "threat");
+
+        let eicar = Detection { severity: Severity::Threat, ..Detection::heuristic("EICAR", "hash", "") };
+        assert_eq!(config.status(std::slice::from_ref(&eicar), &HeuristicScore::default()), "threat");
+    }
+
+    #[test]
+    fn rejects_inconsistent_configs() {
+        assert!(ScoringConfig::default().validate().is_ok());
+        let invalid = [
+            ScoringConfig { suspicious_threshold: 0.0, ..Default::default() },
+            ScoringConfig { threat_threshold: 30.0, ..Default::default() },
+            ScoringConfig { default_weight: f64::NAN, ..Default::default() },
+            ScoringConfig { entropy_window: MIN_ENTROPY_WINDOW - 1, ..Default::default() },
+            ScoringConfig { ssdeep_threshold: 101, ..Default::default() },
+        ];
+        for config in invalid {
+            assert!(config.validate().is_err(), "{:?}", config);
+        }
+        let mut config = ScoringConfig::default();
+        config.weights.insert("IOC.*".to_string(), -1.0);
+        assert_eq!(config.validate().unwrap_err(), "Weight for IOC.* must be a non-negative number");
+    }
+
+    #[test]
+    fn configs_round_trip_through_files() {
+        let path = std::env::temp_dir().join(format!("varenizer-scoring-{}.json", std::process::id()));
+        let mut config = ScoringConfig { suspicious_threshold: 25.0, ..Default::default() };
+        config.weights.insert("Heuristic.Custom".to_string(), 12.5);
+        config.save(&path).unwrap();
+        let loaded = ScoringConfig::load(&path).unwrap();
+        assert_eq!(loaded.suspicious_threshold, 25.0);
+        assert_eq!(loaded.weight("Heuristic.Custom"), 12.5);
+
+        // Missing fields take their defaults, invalid values are refused
+        fs::write(&path, r#"{"threat_threshold": 150}"#).unwrap();
+        let loaded = ScoringConfig::load(&path).unwrap();
+        assert_eq!((loaded.suspicious_threshold, loaded.threat_threshold), (DEFAULT_SUSPICIOUS_THRESHOLD, 150.0));
+        fs::write(&path, r#"{"threat_threshold": 10}"#).unwrap();
+        assert!(ScoringConfig::load(&path).is_err());
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn profiles_the_longest_high_entropy_run() {
+        let mut data = vec![0u8; 4 * 4096];
+        data.extend(noise(3 * 4096));
+        data.extend(vec![0u8; 4096]);
+        data.extend(noise(4096));
+
+        let profile = entropy_profile(&data, 4096);
+        assert_eq!(profile.windows, 9);
+        assert_eq!(profile.high_windows, 4);
+        assert!(profile.max_window > HIGH_WINDOW_ENTROPY);
+        let region = profile.high_region.unwrap();
+        assert_eq!((region.offset, region.size), (4 * 4096, 3 * 4096));
+
+        assert!(entropy_profile(&[0u8; 100], 4096).high_region.is_none());
+    }
+
+    #[test]
+    fn entropy_findings_need_enough_high_entropy_data() {
+        let packed = noise(MIN_ENTROPY_SIZE);
+        let profile = entropy_profile(&packed, 4096);
+        assert_eq!(names(&findings("pe", &packed, &profile, &[])), ["Heuristic.Entropy.High"]);
+        assert!(findings("zip", &packed, &profile, &[]).is_empty());
+        let short = &packed[..MIN_ENTROPY_SIZE - 1];
+        assert!(findings("pe", short, &entropy_profile(short, 4096), &[]).is_empty());
+
+        // A quarter of the file, and at least 64 KiB, has to be high entropy
+        let mut data = noise(MIN_HIGH_REGION);
+        data.extend(vec![0u8; 3 * MIN_HIGH_REGION]);
+        let profile = entropy_profile(&data, 4096);
+        assert!(profile.whole < HIGH_ENTROPY);
+        assert_eq!(names(&findings("unknown", &data, &profile, &[])), ["Heuristic.Entropy.HighRegion"]);
+        data.push(0);
+        assert!(findings("unknown", &data, &entropy_profile(&data, 4096), &[]).is_empty());
+    }
+
+    #[test]
+    fn reports_packers_once() {
+        let mut data = vec![0u8; PACKER_HEAD_SIZE];
+        data[0x200..0x204].copy_from_slice(b"UPX!");
+        let profile = entropy_profile(&data, 4096);
+        assert_eq!(names(&findings("elf", &data, &profile, &[])), ["Heuristic.Packer.UPX"]);
+        assert!(findings("pdf", &data, &profile, &[]).is_empty());
+        let existing = suspicious(&["Heuristic.ELF.Packer.UPX"]);
+        assert!(findings("elf", &data, &profile, &existing).is_empty());
+
+        // Section names only count in the headers, stub strings anywhere
+        let mut data = vec![0u8; 2 * PACKER_HEAD_SIZE];
+        data.extend_from_slice(b".themida PECompact2");
+        assert_eq!(names(&findings("pe", &data, &profile, &[])), ["Heuristic.Packer.PECompact"]);
+    }
+}
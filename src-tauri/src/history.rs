@@ -0,0 +1,435 @@
+//! On-disk scan history.
+//!
+//! Sessions, per-file results and threat names live in a SQLite database in
+//! the app data directory. Each result also keeps its full JSON form, so
+//! fields added to `ScanResult` later survive a round trip without a schema
+//! change; the typed columns exist for filtering.
+//!
+//! The schema is versioned through `PRAGMA user_version`. Migrations are only
+//! ever appended to [`MIGRATIONS`], never edited, so an existing history is
+//! upgraded in place.
+
+use rusqlite::types::Value;
+use rusqlite::{params, params_from_iter, Connection, OptionalExtension, Transaction};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use std::sync::{Mutex, MutexGuard};
+
+use crate::models::{ScanResult, ScanSession};
+use crate::walker::ScanError;
+
+/// Schema migrations; entry `n` upgrades a database from version `n` to `n + 1`.
+const MIGRATIONS: [&str; 1] = [
+    "CREATE TABLE sessions (
+        id TEXT PRIMARY KEY,
+        scan_type TEXT NOT NULL,
+        status TEXT NOT NULL,
+        start_time TEXT NOT NULL,
+        end_time TEXT,
+        total_files INTEGER NOT NULL,
+        threats_found INTEGER NOT NULL,
+        suspicious_files INTEGER NOT NULL,
+        clean_files INTEGER NOT NULL,
+        skipped_files INTEGER NOT NULL,
+        errors TEXT NOT NULL
+    );
+    -- Result IDs are only unique within a session: the same result may be
+    -- stored with more than one session
+    CREATE TABLE results (
+        session_id TEXT NOT NULL REFERENCES sessions(id) ON DELETE CASCADE,
+        id TEXT NOT NULL,
+        position INTEGER NOT NULL,
+        path TEXT NOT NULL,
+        size INTEGER NOT NULL,
+        status TEXT NOT NULL,
+        scan_time TEXT NOT NULL,
+        md5 TEXT NOT NULL,
+        sha1 TEXT NOT NULL,
+        sha256 TEXT NOT NULL,
+        sha512 TEXT NOT NULL,
+        data TEXT NOT NULL,
+        PRIMARY KEY (session_id, id)
+    );
+    CREATE TABLE threats (
+        session_id TEXT NOT NULL,
+        result_id TEXT NOT NULL,
+        name TEXT NOT NULL,
+        FOREIGN KEY (session_id, result_id) REFERENCES results(session_id, id) ON DELETE CASCADE
+    );
+    CREATE INDEX sessions_start_time ON sessions(start_time);
+    CREATE INDEX results_session ON results(session_id, position);
+    CREATE INDEX results_status ON results(status);
+    CREATE INDEX results_md5 ON results(md5);
+    CREATE INDEX results_sha1 ON results(sha1);
+    CREATE INDEX results_sha256 ON results(sha256);
+    CREATE INDEX threats_result ON threats(session_id, result_id);
+    CREATE INDEX threats_name ON threats(name);",
+];
+
+const DEFAULT_PAGE_SIZE: usize = 20;
+const MAX_PAGE_SIZE: usize = 200;
+
+/// Filters for `list_scan_history`. Dates are `YYYY-MM-DD` or RFC 3339 and
+/// both bounds are inclusive.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct HistoryQuery {
+    pub offset: usize,
+    pub limit: Option<usize>,
+    /// Only sessions with at least one file of this status ("clean",
+    /// "threat", "suspicious").
+    pub status: Option<String>,
+    pub from: Option<String>,
+    pub to: Option<String>,
+    /// Only sessions that saw a file with this MD5, SHA-1, SHA-256 or SHA-512.
+    pub hash: Option<String>,
+}
+
+/// A session without its per-file results.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SessionSummary {
+    pub id: String,
+    pub scan_type: String,
+    pub status: String,
+    pub start_time: String,
+    pub end_time: Option<String>,
+    pub total_files: usize,
+    pub threats_found: usize,
+    pub suspicious_files: usize,
+    pub clean_files: usize,
+    pub skipped_files: usize,
+    pub error_count: usize,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HistoryPage {
+    pub sessions: Vec<SessionSummary>,
+    /// Number of sessions matching the filters, across all pages.
+    pub total: usize,
+    pub offset: usize,
+    pub limit: usize,
+}
+
+#[derive(Debug)]
+pub enum HistoryError {
+    Database(rusqlite::Error),
+    Json(serde_json::Error),
+    InvalidQuery(String),
+    /// The database was written by a newer build with unknown migrations.
+    UnsupportedVersion(i64),
+}
+
+impl std::fmt::Display for HistoryError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            HistoryError::Database(e) => write!(f, "database error: {}", e),
+            HistoryError::Json(e) => write!(f, "corrupt history record: {}", e),
+            HistoryError::InvalidQuery(message) => write!(f, "{}", message),
+            HistoryError::UnsupportedVersion(v) => {
+                write!(f, "history database version {} is newer than this build supports", v)
+            }
+        }
+    }
+}
+
+impl std::error::Error for HistoryError {}
+
+impl From<rusqlite::Error> for HistoryError {
+    fn from(e: rusqlite::Error) -> Self {
+        HistoryError::Database(e)
+    }
+}
+
+impl From<serde_json::Error> for HistoryError {
+    fn from(e: serde_json::Error) -> Self {
+        HistoryError::Json(e)
+    }
+}
+
+pub struct HistoryStore {
+    conn: Mutex<Connection>,
+}
+
+impl HistoryStore {
+    pub fn open(path: &Path) -> Result<Self, HistoryError> {
+        let mut conn = Connection::open(path)?;
+        conn.pragma_update(None, "foreign_keys", true)?;
+        migrate(&mut conn)?;
+        Ok(HistoryStore { conn: Mutex::new(conn) })
+    }
+
+    fn conn(&self) -> MutexGuard<'_, Connection> {
+        self.conn.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Stores a session with all of its results, replacing an earlier copy
+    /// with the same ID.
+    pub fn save_session(&self, session: &ScanSession) -> Result<(), HistoryError> {
+        let mut conn = self.conn();
+        let tx = conn.transaction()?;
+        tx.execute("DELETE FROM sessions WHERE id = ?1", [&session.id])?;
+        tx.execute(
+            "INSERT INTO sessions (id, scan_type, status, start_time, end_time, total_files, threats_found,
+                suspicious_files, clean_files, skipped_files, errors)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)",
+            params![
+                session.id,
+                session.scan_type,
+                session.status,
+                session.start_time,
+                session.end_time,
+                session.total_files as i64,
+                session.threats_found as i64,
+                session.suspicious_files as i64,
+                session.clean_files as i64,
+                session.skipped_files as i64,
+                serde_json::to_string(&session.errors)?,
+            ],
+        )?;
+        for (position, result) in session.files.iter().enumerate() {
+            insert_result(&tx, &session.id, position, result)?;
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    pub fn list_sessions(&self, query: &HistoryQuery) -> Result<HistoryPage, HistoryError> {
+        let limit = query.limit.unwrap_or(DEFAULT_PAGE_SIZE).clamp(1, MAX_PAGE_SIZE);
+        let (filter, args) = build_filter(query)?;
+        let conn = self.conn();
+
+        let total: i64 = conn.query_row(
+            &format!("SELECT COUNT(*) FROM sessions s {}", filter),
+            params_from_iter(&args),
+            |row| row.get(0),
+        )?;
+
+        let mut page_args = args.clone();
+        page_args.push(Value::Integer(limit as i64));
+        page_args.push(Value::Integer(query.offset as i64));
+        let mut stmt = conn.prepare(&format!(
+            "SELECT s.id, s.scan_type, s.status, s.start_time, s.end_time, s.total_files, s.threats_found,
+                s.suspicious_files, s.clean_files, s.skipped_files, s.errors
+             FROM sessions s {} ORDER BY s.start_time DESC, s.id
+             LIMIT ?{} OFFSET ?{}",
+            filter,
+            args.len() + 1,
+            args.len() + 2,
+        ))?;
+        let sessions = stmt
+            .query_map(params_from_iter(&page_args), |row| {
+                let errors: String = row.get(10)?;
+                Ok(SessionSummary {
+                    id: row.get(0)?,
+                    scan_type: row.get(1)?,
+                    status: row.get(2)?,
+                    start_time: row.get(3)?,
+                    end_time: row.get(4)?,
+                    total_files: row.get::<_, i64>(5)? as usize,
+                    threats_found: row.get::<_, i64>(6)? as usize,
+                    suspicious_files: row.get::<_, i64>(7)? as usize,
+                    clean_files: row.get::<_, i64>(8)? as usize,
+                    skipped_files: row.get::<_, i64>(9)? as usize,
+                    error_count: serde_json::from_str::<Vec<ScanError>>(&errors).map_or(0, |e| e.len()),
+                })
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(HistoryPage { sessions, total: total as usize, offset: query.offset, limit })
+    }
+
+    pub fn get_session(&self, id: &str) -> Result<Option<ScanSession>, HistoryError> {
+        let conn = self.conn();
+        let row = conn
+            .query_row(
+                "SELECT scan_type, status, start_time, end_time, total_files, threats_found, suspicious_files,
+                    clean_files, skipped_files, errors
+                 FROM sessions WHERE id = ?1",
+                [id],
+                |row| {
+                    Ok((
+                        ScanSession {
+                            id: id.to_string(),
+                            files: Vec::new(),
+                            scan_type: row.get(0)?,
+                            status: row.get(1)?,
+                            start_time: row.get(2)?,
+                            end_time: row.get(3)?,
+                            total_files: row.get::<_, i64>(4)? as usize,
+                            threats_found: row.get::<_, i64>(5)? as usize,
+                            suspicious_files: row.get::<_, i64>(6)? as usize,
+                            clean_files: row.get::<_, i64>(7)? as usize,
+                            skipped_files: row.get::<_, i64>(8)? as usize,
+                            errors: Vec::new(),
+                        },
+                        row.get::<_, String>(9)?,
+                    ))
+                },
+            )
+            .optional()?;
+        let Some((mut session, errors)) = row else {
+            return Ok(None);
+        };
+        session.errors = serde_json::from_str(&errors)?;
+
+        let mut stmt = conn.prepare("SELECT data FROM results WHERE session_id = ?1 ORDER BY position")?;
+        let rows = stmt.query_map([id], |row| row.get::<_, String>(0))?;
+        for data in rows {
+            session.files.push(serde_json::from_str(&data?)?);
+        }
+        Ok(Some(session))
+    }
+
+    /// Deletes the given sessions and returns how many existed.
+    pub fn delete_sessions(&self, ids: &[String]) -> Result<usize, HistoryError> {
+        let mut conn = self.conn();
+        let tx = conn.transaction()?;
+        let mut deleted = 0;
+        for id in ids {
+            deleted += tx.execute("DELETE FROM sessions WHERE id = ?1", [id])?;
+        }
+        tx.commit()?;
+        Ok(deleted)
+    }
+}
+
+fn migrate(conn: &mut Connection) -> Result<(), HistoryError> {
+    let version: i64 = conn.pragma_query_value(None, "user_version", |row| row.get(0))?;
+    if version as usize > MIGRATIONS.len() {
+        return Err(HistoryError::UnsupportedVersion(version));
+    }
+
+    for (index, migration) in MIGRATIONS.iter().enumerate().skip(version as usize) {
+        // Each step commits on its own so an interrupted upgrade resumes
+        // from the last completed version
+        let tx = conn.transaction()?;
+        tx.execute_batch(migration)?;
+        tx.pragma_update(None, "user_version", (index + 1) as i64)?;
+        tx.commit()?;
+    }
+    Ok(())
+}
+
+fn insert_result(tx: &Transaction, session_id: &str, position: usize, result: &ScanResult) -> Result<(), HistoryError> {
+    tx.execute(
+        "INSERT INTO results (id, session_id, position, path, size, status, scan_time, md5, sha1, sha256, sha512, data)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12)",
+        params![
+            result.id,
+            session_id,
+            position as i64,
+            result.file_info.path,
+            result.file_info.size as i64,
+            result.status,
+            result.scan_time,
+            result.hashes.md5,
+            result.hashes.sha1,
+            result.hashes.sha256,
+            result.hashes.sha512,
+            serde_json::to_string(result)?,
+        ],
+    )?;
+    for threat in &result.threats {
+        tx.execute(
+            "INSERT INTO threats (session_id, result_id, name) VALUES (?1, ?2, ?3)",
+            params![session_id, result.id, threat],
+        )?;
+    }
+    Ok(())
+}
+
+/// Builds the `WHERE` clause for a query. All values are bound as parameters.
+fn build_filter(query: &HistoryQuery) -> Result<(String, Vec<Value>), HistoryError> {
+    let mut clauses = Vec::new();
+    let mut args = Vec::new();
+
+    if let Some(status) = &query.status {
+        args.push(Value::Text(status.to_ascii_lowercase()));
+        clauses.push(format!(
+            "EXISTS (SELECT 1 FROM results r WHERE r.session_id = s.id AND r.status = ?{})",
+            args.len()
+        ));
+    }
+    if let Some(from) = &query.from {
+        args.push(Value::Text(date_bound(from, false)?));
+        clauses.push(format!("s.start_time >= ?{}", args.len()));
+    }
+    if let Some(to) = &query.to {
+        args.push(Value::Text(date_bound(to, true)?));
+        clauses.push(format!("s.start_time <= ?{}", args.len()));
+    }
+    if let Some(hash) = &query.hash {
+        args.push(Value::Text(hash.trim().to_ascii_lowercase()));
+        let n = args.len();
+        clauses.push(format!(
+            "EXISTS (SELECT 1 FROM results r WHERE r.session_id = s.id
+                AND (r.md5 = ?{n} OR r.sha1 = ?{n} OR r.sha256 = ?{n} OR r.sha512 = ?{n}))"
+        ));
+    }
+
+    let filter = if clauses.is_empty() { String::new() } else { format!("WHERE {}", clauses.join(" AND ")) };
+    Ok((filter, args))
+}
+
+/// Converts a user supplied date into the format `models::timestamp` writes,
+/// which sorts lexicographically.
+fn date_bound(value: &str, end_of_day: bool) -> Result<String, HistoryError> {
+    let value = value.trim();
+    let datetime = if let Ok(date) = chrono::NaiveDate::parse_from_str(value, "%Y-%m-%d") {
+        let time = if end_of_day { date.and_hms_opt(23, 59, 59) } else { date.and_hms_opt(0, 0, 0) };
+        time.expect("valid time of day")
+    } else if let Ok(datetime) = chrono::DateTime::parse_from_rfc3339(value) {
+        datetime.naive_utc()
+    } else {
+        return Err(HistoryError::InvalidQuery(format!("Invalid date: {}", value)));
+    };
+    Ok(datetime.format("%Y-%m-%d %H:%M:%S UTC").to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::ScanEngine;
+    use crate::scanner;
+
+    struct TempDir(std::path::PathBuf);
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn session_with(result: &ScanResult) -> ScanSession {
+        let mut session = ScanSession::new("files");
+        session.record(result.clone());
+        session
+    }
+
+    #[test]
+    fn results_are_keyed_per_session() {
+        let dir = TempDir(std::env::temp_dir().join(format!("varenizer-history-{}", std::process::id())));
+        std::fs::create_dir_all(&dir.0).unwrap();
+        let sample = dir.0.join("sample.txt");
+        std::fs::write(&sample, b"hello").unwrap();
+        let engine = ScanEngine::new(dir.0.join("signatures"), dir.0.join("rules"));
+        let mut result = scanner::scan_file(&engine, &sample).unwrap();
+        result.threats = vec!["Test.Threat".to_string()];
+
+        let store = HistoryStore::open(&dir.0.join("history.db")).unwrap();
+        let first = session_with(&result);
+        let second = session_with(&result);
+        store.save_session(&first).unwrap();
+        store.save_session(&second).unwrap();
+        for session in [&first, &second] {
+            let stored = store.get_session(&session.id).unwrap().unwrap();
+            assert_eq!(stored.files.len(), 1);
+            assert_eq!(stored.files[0].id, result.id);
+        }
+
+        assert_eq!(store.delete_sessions(std::slice::from_ref(&first.id)).unwrap(), 1);
+        let threats: i64 = store.conn().query_row("SELECT COUNT(*) FROM threats", [], |row| row.get(0)).unwrap();
+        assert_eq!(threats, 1);
+        assert!(store.get_session(&second.id).unwrap().is_some());
+    }
+}
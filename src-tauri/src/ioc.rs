@@ -0,0 +1,1294 @@
+//! Local indicator of compromise (IOC) database.
+//!
+//! Indicators are file hashes, domains, IP addresses or CIDR ranges, and
+//! URLs. They live in a SQLite database in the app data directory, keyed by
+//! kind, value and source so an indicator listed by two feeds keeps both
+//! attributions. The MISP events that list an indicator are kept in a table
+//! of their own, since one feed often publishes the same indicator in
+//! several events. Scans never query SQLite: an [`IocIndex`] is built from
+//! the store after every change and handed to the scan engine, so lookups
+//! are hash map probes.
+//!
+//! Files can be imported as JSON (an array of [`NewIndicator`]s, a STIX 2.1
+//! bundle or MISP events), CSV with a header row naming the same fields, or
+//! plain text with one value per line whose kind is guessed.
+
+use rusqlite::types::Value;
+use rusqlite::{params, params_from_iter, Connection, OptionalExtension, Transaction};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::net::IpAddr;
+use std::path::Path;
+use std::sync::{Mutex, MutexGuard};
+
+use crate::hashing::FileDigests;
+use crate::misp::{self, MispEvent, MispEventRef};
+use crate::models::timestamp;
+use crate::signatures::{Detection, Severity};
+use crate::stix;
+
+/// Schema migrations; entry `n` upgrades a database from version `n` to `n + 1`.
+const MIGRATIONS: [&str; 4] = [
+    "CREATE TABLE indicators (
+        id INTEGER PRIMARY KEY,
+        kind TEXT NOT NULL,
+        value TEXT NOT NULL,
+        source TEXT NOT NULL,
+        confidence INTEGER NOT NULL,
+        first_seen TEXT NOT NULL,
+        last_seen TEXT NOT NULL,
+        valid_until TEXT,
+        tags TEXT NOT NULL,
+        description TEXT,
+        UNIQUE (kind, value, source)
+    );
+    CREATE INDEX indicators_value ON indicators(value);
+    CREATE INDEX indicators_last_seen ON indicators(last_seen);
+    CREATE INDEX indicators_valid_until ON indicators(valid_until);",
+    "ALTER TABLE indicators ADD COLUMN external_id TEXT;",
+    "ALTER TABLE indicators ADD COLUMN event_id TEXT;
+    ALTER TABLE indicators ADD COLUMN event_uuid TEXT;
+    ALTER TABLE indicators ADD COLUMN event_info TEXT;
+    CREATE INDEX indicators_event ON indicators(source, event_uuid);",
+    "CREATE TABLE indicator_events (
+        indicator_id INTEGER NOT NULL REFERENCES indicators(id) ON DELETE CASCADE,
+        event_uuid TEXT NOT NULL,
+        event_id TEXT NOT NULL,
+        event_info TEXT NOT NULL,
+        PRIMARY KEY (indicator_id, event_uuid)
+    );
+    CREATE INDEX indicator_events_event ON indicator_events(event_uuid);
+    INSERT INTO indicator_events (indicator_id, event_uuid, event_id, event_info)
+        SELECT id, event_uuid, COALESCE(event_id, event_uuid), COALESCE(event_info, '')
+        FROM indicators WHERE event_uuid IS NOT NULL;
+    DROP INDEX indicators_event;
+    ALTER TABLE indicators DROP COLUMN event_id;
+    ALTER TABLE indicators DROP COLUMN event_uuid;
+    ALTER TABLE indicators DROP COLUMN event_info;",
+];
+
+const DEFAULT_SOURCE: &str = "local";
+const DEFAULT_CONFIDENCE: u8 = 50;
+/// Hash indicators at or above this confidence make a file a threat rather
+/// than suspicious.
+const THREAT_CONFIDENCE: u8 = 75;
+const DEFAULT_PAGE_SIZE: usize = 50;
+const MAX_PAGE_SIZE: usize = 500;
+/// Import errors beyond this many are only counted.
+const MAX_REPORTED_ERRORS: usize = 100;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum IocKind {
+    Hash,
+    Domain,
+    Ip,
+    Url,
+}
+
+impl IocKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            IocKind::Hash => "hash",
+            IocKind::Domain => "domain",
+            IocKind::Ip => "ip",
+            IocKind::Url => "url",
+        }
+    }
+
+    fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "hash" | "md5" | "sha1" | "sha256" | "sha512" | "filehash" => Some(IocKind::Hash),
+            "domain" | "hostname" | "fqdn" => Some(IocKind::Domain),
+            "ip" | "ipv4" | "ipv6" | "cidr" | "ip-dst" | "ip-src" => Some(IocKind::Ip),
+            "url" | "uri" => Some(IocKind::Url),
+            _ => None,
+        }
+    }
+
+    /// Guesses the kind of a bare value from a plain text list.
+    pub fn detect(value: &str) -> Option<Self> {
+        let value = value.trim();
+        if parse_network(value).is_some() {
+            Some(IocKind::Ip)
+        } else if value.contains("://") || (value.contains('/') && normalize_url(value).is_some()) {
+            Some(IocKind::Url)
+        } else if normalize_hash(value).is_some() {
+            Some(IocKind::Hash)
+        } else if normalize_domain(value).is_some() {
+            Some(IocKind::Domain)
+        } else {
+            None
+        }
+    }
+}
+
+/// A stored indicator. Timestamps use the same format as scan results.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Indicator {
+    pub id: i64,
+    pub kind: IocKind,
+    pub value: String,
+    pub source: String,
+    /// 0 to 100.
+    pub confidence: u8,
+    pub first_seen: String,
+    pub last_seen: String,
+    /// The indicator is dropped by the next expiry run after this time.
+    pub valid_until: Option<String>,
+    pub tags: Vec<String>,
+    pub description: Option<String>,
+    /// The indicator's id in the feed it came from, such as a STIX
+    /// `indicator--` id, so matches can be traced back to it.
+    pub external_id: Option<String>,
+    /// The MISP events that list the indicator, in the order they were
+    /// imported.
+    pub events: Vec<MispEventRef>,
+}
+
+/// An indicator to import. Only `value` is required; the kind is guessed
+/// when missing and the value is normalised either way.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct NewIndicator {
+    pub kind: Option<IocKind>,
+    pub value: String,
+    pub source: Option<String>,
+    pub confidence: Option<u8>,
+    pub first_seen: Option<String>,
+    pub last_seen: Option<String>,
+    pub valid_until: Option<String>,
+    pub tags: Vec<String>,
+    pub description: Option<String>,
+    pub external_id: Option<String>,
+    pub event: Option<MispEventRef>,
+}
+
+impl NewIndicator {
+    fn into_indicator(self, default_source: &str, now: &str) -> Result<Indicator, String> {
+        let kind = match self.kind {
+            Some(kind) => kind,
+            None => IocKind::detect(&self.value).ok_or_else(|| format!("Unrecognised indicator '{}'", self.value))?,
+        };
+        let value = normalize(kind, &self.value)
+            .ok_or_else(|| format!("Invalid {} indicator '{}'", kind.as_str(), self.value))?;
+        let first_seen = self.first_seen.as_deref().map(normalize_time).transpose()?;
+        let last_seen = self.last_seen.as_deref().map(normalize_time).transpose()?;
+        let mut tags: Vec<String> = self.tags.iter().map(|t| t.trim().to_string()).filter(|t| !t.is_empty()).collect();
+        tags.sort();
+        tags.dedup();
+        Ok(Indicator {
+            id: 0,
+            kind,
+            value,
+            source: self.source.filter(|s| !s.trim().is_empty()).unwrap_or_else(|| default_source.to_string()),
+            confidence: self.confidence.unwrap_or(DEFAULT_CONFIDENCE).min(100),
+            first_seen: first_seen.clone().or_else(|| last_seen.clone()).unwrap_or_else(|| now.to_string()),
+            last_seen: last_seen.or(first_seen).unwrap_or_else(|| now.to_string()),
+            valid_until: self.valid_until.as_deref().map(normalize_time).transpose()?,
+            tags,
+            description: self.description.filter(|d| !d.trim().is_empty()),
+            external_id: self.external_id.filter(|id| !id.trim().is_empty()),
+            events: self.event.into_iter().collect(),
+        })
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ImportSummary {
+    pub added: usize,
+    pub updated: usize,
+    pub skipped: usize,
+    pub errors: Vec<String>,
+}
+
+impl ImportSummary {
+    pub fn error(&mut self, message: String) {
+        self.skipped += 1;
+        if self.errors.len() < MAX_REPORTED_ERRORS {
+            self.errors.push(message);
+        }
+    }
+
+    pub fn merge(&mut self, other: ImportSummary) {
+        self.added += other.added;
+        self.updated += other.updated;
+        self.skipped += other.skipped;
+        let room = MAX_REPORTED_ERRORS.saturating_sub(self.errors.len());
+        self.errors.extend(other.errors.into_iter().take(room));
+    }
+}
+
+/// Filters for `search_iocs` and `export_iocs`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct IocQuery {
+    pub offset: usize,
+    pub limit: Option<usize>,
+    /// Substring of the value.
+    pub text: Option<String>,
+    pub kind: Option<IocKind>,
+    pub source: Option<String>,
+    pub tag: Option<String>,
+    pub min_confidence: Option<u8>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IocPage {
+    pub indicators: Vec<Indicator>,
+    /// Number of indicators matching the filters, across all pages.
+    pub total: usize,
+    pub offset: usize,
+    pub limit: usize,
+}
+
+#[derive(Debug)]
+pub enum IocError {
+    Database(rusqlite::Error),
+    Io(std::io::Error),
+    Invalid(String),
+    /// The database was written by a newer build with unknown migrations.
+    UnsupportedVersion(i64),
+}
+
+impl std::fmt::Display for IocError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            IocError::Database(e) => write!(f, "database error: {}", e),
+            IocError::Io(e) => write!(f, "{}", e),
+            IocError::Invalid(message) => write!(f, "{}", message),
+            IocError::UnsupportedVersion(v) => {
+                write!(f, "IOC database version {} is newer than this build supports", v)
+            }
+        }
+    }
+}
+
+impl std::error::Error for IocError {}
+
+impl From<rusqlite::Error> for IocError {
+    fn from(e: rusqlite::Error) -> Self {
+        IocError::Database(e)
+    }
+}
+
+impl From<std::io::Error> for IocError {
+    fn from(e: std::io::Error) -> Self {
+        IocError::Io(e)
+    }
+}
+
+pub struct IocStore {
+    conn: Mutex<Connection>,
+}
+
+impl IocStore {
+    pub fn open(path: &Path) -> Result<Self, IocError> {
+        let mut conn = Connection::open(path)?;
+        conn.pragma_update(None, "foreign_keys", true)?;
+        migrate(&mut conn)?;
+        Ok(IocStore { conn: Mutex::new(conn) })
+    }
+
+    fn conn(&self) -> MutexGuard<'_, Connection> {
+        self.conn.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Adds indicators, or refreshes ones already listed by the same source:
+    /// the seen range widens and everything else is replaced.
+    pub fn upsert(&self, indicators: Vec<NewIndicator>, default_source: &str) -> Result<ImportSummary, IocError> {
+        let mut summary = ImportSummary::default();
+        let mut conn = self.conn();
+        let tx = conn.transaction()?;
+        upsert_into(&tx, indicators, default_source, &mut summary)?;
+        tx.commit()?;
+        Ok(summary)
+    }
+
+    /// Imports MISP events. Each event replaces what an earlier import of it
+    /// from the same source listed: indicators since removed from it go too,
+    /// unless another event of the source still lists them, and an event
+    /// without indicators just removes its own.
+    pub fn import_misp(&self, events: Vec<MispEvent>, source: &str) -> Result<ImportSummary, IocError> {
+        let mut summary = ImportSummary::default();
+        let mut conn = self.conn();
+        let tx = conn.transaction()?;
+        for event in events {
+            let uuid = &event.reference.uuid;
+            let listed: Vec<i64> = tx
+                .prepare(
+                    "SELECT e.indicator_id FROM indicator_events e JOIN indicators i ON i.id = e.indicator_id
+                     WHERE i.source = ?1 AND e.event_uuid = ?2",
+                )?
+                .query_map(params![source, uuid], |row| row.get(0))?
+                .collect::<Result<_, _>>()?;
+            tx.execute(
+                "DELETE FROM indicator_events
+                 WHERE event_uuid = ?2 AND indicator_id IN (SELECT id FROM indicators WHERE source = ?1)",
+                params![source, uuid],
+            )?;
+            let indicators = event.indicators.into_iter().map(|i| NewIndicator { source: None, ..i }).collect();
+            upsert_into(&tx, indicators, source, &mut summary)?;
+            for id in listed {
+                tx.execute(
+                    "DELETE FROM indicators
+                     WHERE id = ?1 AND NOT EXISTS (SELECT 1 FROM indicator_events WHERE indicator_id = ?1)",
+                    [id],
+                )?;
+            }
+        }
+        tx.commit()?;
+        Ok(summary)
+    }
+
+    /// Imports a JSON, CSV or plain text file, chosen by extension.
+    /// Indicators without a source are attributed to `source`, or to the
+    /// file name.
+    pub fn import_file(&self, path: &Path, source: Option<&str>) -> Result<ImportSummary, IocError> {
+        let text = fs::read_to_string(path)?;
+        let default_source = source
+            .map(str::to_string)
+            .or_else(|| path.file_stem().and_then(|s| s.to_str()).map(str::to_string))
+            .unwrap_or_else(|| DEFAULT_SOURCE.to_string());
+        let extension = path.extension().and_then(|e| e.to_str()).unwrap_or("").to_ascii_lowercase();
+        let (indicators, errors) = match extension.as_str() {
+            "json" => {
+                let value: serde_json::Value = serde_json::from_str(&text)
+                    .map_err(|e| IocError::Invalid(format!("Invalid indicator file: {}", e)))?;
+                if let Some(events) = misp::events(&value) {
+                    return self.import_misp(events, &default_source);
+                }
+                parse_json(value)?
+            }
+            "csv" => parse_csv(&text)?,
+            _ => parse_text(&text),
+        };
+        let mut summary = self.upsert(indicators, &default_source)?;
+        for error in errors {
+            summary.error(error);
+        }
+        Ok(summary)
+    }
+
+    /// Imports the indicators among STIX objects, such as those polled from
+    /// a TAXII collection.
+    pub fn import_stix(&self, objects: &[serde_json::Value], source: &str) -> Result<ImportSummary, IocError> {
+        let (indicators, errors) = stix::indicators(objects);
+        let mut summary = self.upsert(indicators, source)?;
+        for error in errors {
+            summary.error(error);
+        }
+        Ok(summary)
+    }
+
+    pub fn search(&self, query: &IocQuery) -> Result<IocPage, IocError> {
+        let limit = query.limit.unwrap_or(DEFAULT_PAGE_SIZE).clamp(1, MAX_PAGE_SIZE);
+        let (filter, args) = build_filter(query);
+        let conn = self.conn();
+        let total: i64 =
+            conn.query_row(&format!("SELECT COUNT(*) FROM indicators i {}", filter), params_from_iter(&args), |row| {
+                row.get(0)
+            })?;
+        let mut page_args = args.clone();
+        page_args.push(Value::Integer(limit as i64));
+        page_args.push(Value::Integer(query.offset as i64));
+        let indicators = select(
+            &conn,
+            &format!(
+                "{} ORDER BY i.last_seen DESC, i.id LIMIT ?{} OFFSET ?{}",
+                filter,
+                args.len() + 1,
+                args.len() + 2
+            ),
+            &page_args,
+        )?;
+        Ok(IocPage { indicators, total: total as usize, offset: query.offset, limit })
+    }
+
+    /// Removes indicators whose `valid_until` has passed, and with a cutoff
+    /// also those last seen before it. Returns how many were removed.
+    pub fn expire(&self, last_seen_before: Option<&str>) -> Result<usize, IocError> {
+        let now = timestamp();
+        let conn = self.conn();
+        let mut removed = conn.execute("DELETE FROM indicators WHERE valid_until IS NOT NULL AND valid_until <= ?1", [&now])?;
+        if let Some(cutoff) = last_seen_before {
+            let cutoff = normalize_time(cutoff).map_err(IocError::Invalid)?;
+            removed += conn.execute("DELETE FROM indicators WHERE last_seen < ?1", [&cutoff])?;
+        }
+        Ok(removed)
+    }
+
+    /// Writes every indicator matching `query` (ignoring paging) as JSON, or
+    /// CSV when `path` ends in `.csv`. Returns how many were written.
+    pub fn export_file(&self, path: &Path, query: &IocQuery) -> Result<usize, IocError> {
+        let (filter, args) = build_filter(query);
+        let indicators = select(&self.conn(), &format!("{} ORDER BY i.kind, i.value, i.source", filter), &args)?;
+        let extension = path.extension().and_then(|e| e.to_str()).unwrap_or("").to_ascii_lowercase();
+        let text = if extension == "csv" {
+            to_csv(&indicators)
+        } else {
+            serde_json::to_string_pretty(&indicators).map_err(|e| IocError::Invalid(e.to_string()))?
+        };
+        fs::write(path, text)?;
+        Ok(indicators.len())
+    }
+
+    /// Loads every indicator into a lookup index for the scanners.
+    pub fn index(&self) -> Result<IocIndex, IocError> {
+        let mut index = IocIndex::default();
+        for indicator in select(&self.conn(), "", &[])? {
+            index.insert(indicator);
+        }
+        Ok(index)
+    }
+}
+
+fn upsert_into(
+    tx: &Transaction<'_>,
+    indicators: Vec<NewIndicator>,
+    default_source: &str,
+    summary: &mut ImportSummary,
+) -> Result<(), IocError> {
+    let now = timestamp();
+    for new in indicators {
+        let indicator = match new.into_indicator(default_source, &now) {
+            Ok(indicator) => indicator,
+            Err(e) => {
+                summary.error(e);
+                continue;
+            }
+        };
+        let exists = tx
+            .query_row(
+                "SELECT 1 FROM indicators WHERE kind = ?1 AND value = ?2 AND source = ?3",
+                params![indicator.kind.as_str(), indicator.value, indicator.source],
+                |_| Ok(()),
+            )
+            .optional()?
+            .is_some();
+        let id: i64 = tx.query_row(
+            "INSERT INTO indicators (kind, value, source, confidence, first_seen, last_seen, valid_until, tags,
+                description, external_id)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)
+             ON CONFLICT (kind, value, source) DO UPDATE SET
+                confidence = excluded.confidence,
+                first_seen = MIN(first_seen, excluded.first_seen),
+                last_seen = MAX(last_seen, excluded.last_seen),
+                valid_until = excluded.valid_until,
+                tags = excluded.tags,
+                description = COALESCE(excluded.description, description),
+                external_id = COALESCE(excluded.external_id, external_id)
+             RETURNING id",
+            params![
+                indicator.kind.as_str(),
+                indicator.value,
+                indicator.source,
+                indicator.confidence,
+                indicator.first_seen,
+                indicator.last_seen,
+                indicator.valid_until,
+                serde_json::to_string(&indicator.tags).expect("tags serialize"),
+                indicator.description,
+                indicator.external_id,
+            ],
+            |row| row.get(0),
+        )?;
+        for event in &indicator.events {
+            tx.execute(
+                "INSERT INTO indicator_events (indicator_id, event_uuid, event_id, event_info) VALUES (?1, ?2, ?3, ?4)
+                 ON CONFLICT (indicator_id, event_uuid) DO UPDATE SET
+                    event_id = excluded.event_id,
+                    event_info = excluded.event_info",
+                params![id, event.uuid, event.id, event.info],
+            )?;
+        }
+        if exists {
+            summary.updated += 1;
+        } else {
+            summary.added += 1;
+        }
+    }
+    Ok(())
+}
+
+fn migrate(conn: &mut Connection) -> Result<(), IocError> {
+    let version: i64 = conn.pragma_query_value(None, "user_version", |row| row.get(0))?;
+    if version as usize > MIGRATIONS.len() {
+        return Err(IocError::UnsupportedVersion(version));
+    }
+    for (index, migration) in MIGRATIONS.iter().enumerate().skip(version as usize) {
+        let tx = conn.transaction()?;
+        tx.execute_batch(migration)?;
+        tx.pragma_update(None, "user_version", (index + 1) as i64)?;
+        tx.commit()?;
+    }
+    Ok(())
+}
+
+fn select(conn: &Connection, clauses: &str, args: &[Value]) -> Result<Vec<Indicator>, IocError> {
+    let mut stmt = conn.prepare(&format!(
+        "SELECT i.id, i.kind, i.value, i.source, i.confidence, i.first_seen, i.last_seen, i.valid_until, i.tags,
+            i.description, i.external_id,
+            (SELECT json_group_array(json_object('id', e.event_id, 'uuid', e.event_uuid, 'info', e.event_info)
+                ORDER BY e.rowid)
+             FROM indicator_events e WHERE e.indicator_id = i.id)
+         FROM indicators i {}",
+        clauses
+    ))?;
+    let rows = stmt.query_map(params_from_iter(args), |row| {
+        let kind: String = row.get(1)?;
+        let tags: String = row.get(8)?;
+        let events: String = row.get(11)?;
+        Ok(Indicator {
+            id: row.get(0)?,
+            kind: IocKind::parse(&kind).unwrap_or(IocKind::Domain),
+            value: row.get(2)?,
+            source: row.get(3)?,
+            confidence: row.get::<_, i64>(4)?.clamp(0, 100) as u8,
+            first_seen: row.get(5)?,
+            last_seen: row.get(6)?,
+            valid_until: row.get(7)?,
+            tags: serde_json::from_str(&tags).unwrap_or_default(),
+            description: row.get(9)?,
+            external_id: row.get(10)?,
+            events: serde_json::from_str(&events).unwrap_or_default(),
+        })
+    })?;
+    Ok(rows.collect::<Result<Vec<_>, _>>()?)
+}
+
+/// Builds the `WHERE` clause for a query. All values are bound as parameters.
+fn build_filter(query: &IocQuery) -> (String, Vec<Value>) {
+    let mut clauses = Vec::new();
+    let mut args = Vec::new();
+    if let Some(text) = query.text.as_deref().map(str::trim).filter(|t| !t.is_empty()) {
+        let escaped = text.to_ascii_lowercase().replace('\\', "\\\\").replace('%', "\\%").replace('_', "\\_");
+        args.push(Value::Text(format!("%{}%", escaped)));
+        clauses.push(format!("i.value LIKE ?{} ESCAPE '\\'", args.len()));
+    }
+    if let Some(kind) = query.kind {
+        args.push(Value::Text(kind.as_str().to_string()));
+        clauses.push(format!("i.kind = ?{}", args.len()));
+    }
+    if let Some(source) = &query.source {
+        args.push(Value::Text(source.clone()));
+        clauses.push(format!("i.source = ?{}", args.len()));
+    }
+    if let Some(tag) = &query.tag {
+        args.push(Value::Text(tag.trim().to_string()));
+        clauses.push(format!("EXISTS (SELECT 1 FROM json_each(i.tags) WHERE json_each.value = ?{})", args.len()));
+    }
+    if let Some(confidence) = query.min_confidence {
+        args.push(Value::Integer(confidence as i64));
+        clauses.push(format!("i.confidence >= ?{}", args.len()));
+    }
+    let filter = if clauses.is_empty() { String::new() } else { format!("WHERE {}", clauses.join(" AND ")) };
+    (filter, args)
+}
+
+fn parse_json(value: serde_json::Value) -> Result<(Vec<NewIndicator>, Vec<String>), IocError> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Document {
+        List(Vec<NewIndicator>),
+        Wrapped { indicators: Vec<NewIndicator> },
+    }
+    let invalid = |e: serde_json::Error| IocError::Invalid(format!("Invalid indicator file: {}", e));
+    if value.get("type").and_then(|t| t.as_str()) == Some("bundle") {
+        let objects = value.get("objects").and_then(|o| o.as_array()).map_or(&[][..], Vec::as_slice);
+        return Ok(stix::indicators(objects));
+    }
+    match serde_json::from_value(value).map_err(invalid)? {
+        Document::List(indicators) | Document::Wrapped { indicators } => Ok((indicators, Vec::new())),
+    }
+}
+
+/// CSV with a header row. Tags are separated by `;` or `|` within their
+/// column.
+fn parse_csv(text: &str) -> Result<(Vec<NewIndicator>, Vec<String>), IocError> {
+    let mut lines = text.lines().filter(|l| !l.trim().is_empty());
+    let header: Vec<String> = lines
+        .next()
+        .map(|l| split_csv(l).into_iter().map(|h| h.trim().to_ascii_lowercase()).collect())
+        .ok_or_else(|| IocError::Invalid("CSV file is empty".to_string()))?;
+    let column = |names: &[&str]| header.iter().position(|h| names.contains(&h.as_str()));
+    let value_column = column(&["value", "indicator", "ioc"])
+        .ok_or_else(|| IocError::Invalid("CSV header has no value column".to_string()))?;
+    let kind_column = column(&["kind", "type"]);
+    let source_column = column(&["source"]);
+    let confidence_column = column(&["confidence"]);
+    let first_seen_column = column(&["first_seen"]);
+    let last_seen_column = column(&["last_seen"]);
+    let valid_until_column = column(&["valid_until", "expires"]);
+    let tags_column = column(&["tags"]);
+    let description_column = column(&["description", "comment"]);
+    let external_id_column = column(&["external_id", "id", "uuid"]);
+    let event_columns = (column(&["event_id"]), column(&["event_uuid"]), column(&["event_info"]));
+
+    let mut indicators = Vec::new();
+    let mut errors = Vec::new();
+    for (n, line) in lines.enumerate() {
+        let fields = split_csv(line);
+        let field = |column: Option<usize>| {
+            column.and_then(|c| fields.get(c)).map(|f| f.trim().to_string()).filter(|f| !f.is_empty())
+        };
+        let kind = match field(kind_column) {
+            Some(kind) => match IocKind::parse(&kind) {
+                Some(kind) => Some(kind),
+                None => {
+                    errors.push(format!("Line {}: unknown indicator type '{}'", n + 2, kind));
+                    continue;
+                }
+            },
+            None => None,
+        };
+        indicators.push(NewIndicator {
+            kind,
+            value: field(Some(value_column)).unwrap_or_default(),
+            source: field(source_column),
+            confidence: field(confidence_column).and_then(|c| c.parse().ok()),
+            first_seen: field(first_seen_column),
+            last_seen: field(last_seen_column),
+            valid_until: field(valid_until_column),
+            tags: field(tags_column)
+                .map(|t| t.split([';', '|']).map(str::to_string).collect())
+                .unwrap_or_default(),
+            description: field(description_column),
+            external_id: field(external_id_column),
+            event: match (field(event_columns.0), field(event_columns.1)) {
+                (Some(id), Some(uuid)) => {
+                    Some(MispEventRef { id, uuid, info: field(event_columns.2).unwrap_or_default() })
+                }
+                _ => None,
+            },
+        });
+    }
+    Ok((indicators, errors))
+}
+
+/// Splits one CSV line, honouring double-quoted fields.
+fn split_csv(line: &str) -> Vec<String> {
+    let mut fields = Vec::new();
+    let mut current = String::new();
+    let mut quoted = false;
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                current.push('"');
+                chars.next();
+            }
+            '"' => quoted = !quoted,
+            ',' if !quoted => fields.push(std::mem::take(&mut current)),
+            _ => current.push(c),
+        }
+    }
+    fields.push(current);
+    fields
+}
+
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+/// One row per indicator, or per MISP event for indicators listed in
+/// several, so the events survive a round trip.
+fn to_csv(indicators: &[Indicator]) -> String {
+    let mut out = String::from(
+        "kind,value,source,confidence,first_seen,last_seen,valid_until,tags,description,external_id,\
+         event_id,event_uuid,event_info\n",
+    );
+    let rows = indicators.iter().flat_map(|i| {
+        let events: Vec<Option<&MispEventRef>> =
+            if i.events.is_empty() { vec![None] } else { i.events.iter().map(Some).collect() };
+        events.into_iter().map(move |event| (i, event))
+    });
+    for (i, event) in rows {
+        let row = [
+            i.kind.as_str().to_string(),
+            i.value.clone(),
+            i.source.clone(),
+            i.confidence.to_string(),
+            i.first_seen.clone(),
+            i.last_seen.clone(),
+            i.valid_until.clone().unwrap_or_default(),
+            i.tags.join(";"),
+            i.description.clone().unwrap_or_default(),
+            i.external_id.clone().unwrap_or_default(),
+            event.map(|e| e.id.clone()).unwrap_or_default(),
+            event.map(|e| e.uuid.clone()).unwrap_or_default(),
+            event.map(|e| e.info.clone()).unwrap_or_default(),
+        ];
+        out.push_str(&row.iter().map(|f| csv_field(f)).collect::<Vec<_>>().join(","));
+        out.push('\n');
+    }
+    out
+}
+
+/// One value per line; blank lines and `#` comments are ignored.
+fn parse_text(text: &str) -> (Vec<NewIndicator>, Vec<String>) {
+    let indicators = text
+        .lines()
+        .map(str::trim)
+        .filter(|l| !l.is_empty() && !l.starts_with('#'))
+        .map(|value| NewIndicator { value: value.to_string(), ..NewIndicator::default() })
+        .collect();
+    (indicators, Vec::new())
+}
+
+/// Canonical form of a value, or `None` when it isn't valid for `kind`.
+pub fn normalize(kind: IocKind, value: &str) -> Option<String> {
+    match kind {
+        IocKind::Hash => normalize_hash(value),
+        IocKind::Domain => normalize_domain(value),
+        IocKind::Ip => parse_network(value).map(|n| n.to_string()),
+        IocKind::Url => normalize_url(value),
+    }
+}
+
+fn normalize_hash(value: &str) -> Option<String> {
+    let value = value.trim().to_ascii_lowercase();
+    (matches!(value.len(), 32 | 40 | 64 | 128) && value.bytes().all(|c| c.is_ascii_hexdigit())).then_some(value)
+}
+
+fn normalize_domain(value: &str) -> Option<String> {
+    let value = value.trim().trim_start_matches("*.").trim_end_matches('.').to_ascii_lowercase();
+    let labels: Vec<&str> = value.split('.').collect();
+    let valid = labels.len() >= 2
+        && value.len() <= 253
+        && labels.iter().all(|l| {
+            !l.is_empty() && l.len() <= 63 && l.bytes().all(|c| c.is_ascii_alphanumeric() || c == b'-' || c == b'_')
+        })
+        && labels.last().is_some_and(|tld| tld.bytes().any(|c| c.is_ascii_alphabetic()));
+    valid.then_some(value)
+}
+
+/// Lowercases the scheme and host, drops user info, default ports and the
+/// fragment, and gives an empty path a `/`. Values without a scheme are
+/// taken as `http://`.
+pub fn normalize_url(value: &str) -> Option<String> {
+    let value = value.trim();
+    let (scheme, rest) = match value.split_once("://") {
+        Some((scheme, rest)) => (scheme.to_ascii_lowercase(), rest),
+        None => ("http".to_string(), value),
+    };
+    if scheme.is_empty() || !scheme.bytes().all(|c| c.is_ascii_alphanumeric() || matches!(c, b'+' | b'-' | b'.')) {
+        return None;
+    }
+    let rest = rest.split('#').next().unwrap_or("");
+    let split = rest.find(['/', '?']).unwrap_or(rest.len());
+    let (authority, path) = rest.split_at(split);
+    let authority = authority.rsplit('@').next().unwrap_or(authority).to_ascii_lowercase();
+    let (host, port) = split_port(&authority);
+    let host = host.trim_end_matches('.');
+    let host_valid = normalize_domain(host).is_some()
+        || host.parse::<IpAddr>().is_ok()
+        || host.trim_start_matches('[').trim_end_matches(']').parse::<IpAddr>().is_ok();
+    if !host_valid {
+        return None;
+    }
+    let port = match (scheme.as_str(), port) {
+        ("http", Some("80")) | ("https", Some("443")) | (_, None) => String::new(),
+        (_, Some(port)) => format!(":{}", port),
+    };
+    let path = if path.is_empty() || path.starts_with('?') { format!("/{}", path) } else { path.to_string() };
+    Some(format!("{}://{}{}{}", scheme, host, port, path))
+}
+
+pub fn split_port(authority: &str) -> (&str, Option<&str>) {
+    if authority.starts_with('[') {
+        return match authority.find(']') {
+            Some(end) => (&authority[..=end], authority[end + 1..].strip_prefix(':')),
+            None => (authority, None),
+        };
+    }
+    match authority.rsplit_once(':') {
+        Some((host, port)) if !host.contains(':') && port.bytes().all(|c| c.is_ascii_digit()) => (host, Some(port)),
+        _ => (authority, None),
+    }
+}
+
+/// The host of a URL, without brackets around IPv6 addresses.
+pub fn url_host(url: &str) -> Option<String> {
+    let normalized = normalize_url(url)?;
+    let rest = normalized.split_once("://")?.1;
+    let authority = &rest[..rest.find('/').unwrap_or(rest.len())];
+    let (host, _) = split_port(authority);
+    Some(host.trim_start_matches('[').trim_end_matches(']').to_string())
+}
+
+/// An IP address or CIDR range with the host bits cleared.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+struct Network {
+    v6: bool,
+    bits: u128,
+    prefix: u8,
+}
+
+impl Network {
+    fn new(addr: IpAddr, prefix: u8) -> Self {
+        let (v6, bits, width) = match addr {
+            IpAddr::V4(a) => (false, u32::from(a) as u128, 32),
+            IpAddr::V6(a) => match a.to_ipv4_mapped() {
+                Some(v4) => (false, u32::from(v4) as u128, 32),
+                None => (true, u128::from(a), 128),
+            },
+        };
+        let prefix = prefix.min(width);
+        let mask = if prefix == 0 { 0 } else { (!0u128 >> (128 - width as u32)) & !((1u128 << (width - prefix)) - 1) };
+        Network { v6, bits: bits & mask, prefix }
+    }
+
+    fn width(&self) -> u8 {
+        if self.v6 {
+            128
+        } else {
+            32
+        }
+    }
+}
+
+impl std::fmt::Display for Network {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let addr: IpAddr = if self.v6 {
+            std::net::Ipv6Addr::from(self.bits).into()
+        } else {
+            std::net::Ipv4Addr::from(self.bits as u32).into()
+        };
+        if self.prefix == self.width() {
+            write!(f, "{}", addr)
+        } else {
+            write!(f, "{}/{}", addr, self.prefix)
+        }
+    }
+}
+
+fn parse_network(value: &str) -> Option<Network> {
+    let value = value.trim();
+    let (addr, prefix) = match value.split_once('/') {
+        Some((addr, prefix)) => (addr, Some(prefix.parse::<u8>().ok()?)),
+        None => (value, None),
+    };
+    let addr: IpAddr = addr.trim_start_matches('[').trim_end_matches(']').parse().ok()?;
+    let width = if addr.is_ipv4() || matches!(addr, IpAddr::V6(a) if a.to_ipv4_mapped().is_some()) { 32 } else { 128 };
+    let prefix = prefix.unwrap_or(width);
+    (prefix <= width).then(|| Network::new(addr, prefix))
+}
+
+/// Accepts the scan result timestamp format, RFC 3339, `YYYY-MM-DD` and Unix
+/// seconds, and returns the scan result format, which sorts as text.
+pub fn normalize_time(value: &str) -> Result<String, String> {
+    let value = value.trim();
+    let datetime = if let Ok(datetime) = chrono::NaiveDateTime::parse_from_str(value, "%Y-%m-%d %H:%M:%S UTC") {
+        datetime
+    } else if let Ok(datetime) = chrono::DateTime::parse_from_rfc3339(value) {
+        datetime.naive_utc()
+    } else if let Ok(datetime) = chrono::NaiveDateTime::parse_from_str(value, "%Y-%m-%dT%H:%M:%S%.f") {
+        datetime
+    } else if let Ok(date) = chrono::NaiveDate::parse_from_str(value, "%Y-%m-%d") {
+        date.and_hms_opt(0, 0, 0).expect("valid time of day")
+    } else if let Some(datetime) = value.parse::<i64>().ok().and_then(|s| chrono::DateTime::from_timestamp(s, 0)) {
+        datetime.naive_utc()
+    } else {
+        return Err(format!("Invalid date: {}", value));
+    };
+    Ok(datetime.format("%Y-%m-%d %H:%M:%S UTC").to_string())
+}
+
+/// What the scanners learn about a matching indicator.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IocHit {
+    pub kind: IocKind,
+    pub value: String,
+    pub source: String,
+    pub confidence: u8,
+    pub tags: Vec<String>,
+    pub description: Option<String>,
+    pub external_id: Option<String>,
+    pub events: Vec<MispEventRef>,
+}
+
+impl IocHit {
+    /// Hashes listed with high confidence are threats, everything else is
+    /// suspicious.
+    fn detection(&self) -> Detection {
+        let severity = if self.kind == IocKind::Hash && self.confidence >= THREAT_CONFIDENCE {
+            Severity::Threat
+        } else {
+            Severity::Suspicious
+        };
+        let kind = match self.kind {
+            IocKind::Hash => "Hash",
+            IocKind::Domain => "Domain",
+            IocKind::Ip => "Ip",
+            IocKind::Url => "Url",
+        };
+        let mut description = format!("{} is listed by {} ({}% confidence)", self.value, self.source, self.confidence);
+        if !self.tags.is_empty() {
+            description.push_str(&format!(", tagged {}", self.tags.join(", ")));
+        }
+        if let Some(note) = &self.description {
+            description.push_str(&format!(": {}", note));
+        }
+        if !self.events.is_empty() {
+            let events: Vec<String> =
+                self.events.iter().map(|e| format!("MISP event {}: {}", e.id, e.info)).collect();
+            description.push_str(&format!(" ({})", events.join("; ")));
+        } else if let Some(id) = &self.external_id {
+            description.push_str(&format!(" [{}]", id));
+        }
+        Detection {
+            name: format!("IOC.{}", kind),
+            severity,
+            source: "ioc".to_string(),
+            offset: None,
+            description: Some(description),
+        }
+    }
+}
+
+/// In-memory lookup tables over every stored indicator.
+#[derive(Default)]
+pub struct IocIndex {
+    exact: HashMap<(IocKind, String), Vec<IocHit>>,
+    networks: HashMap<(bool, u8), HashMap<u128, Vec<IocHit>>>,
+    len: usize,
+}
+
+impl IocIndex {
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    fn insert(&mut self, indicator: Indicator) {
+        let hit = IocHit {
+            kind: indicator.kind,
+            value: indicator.value.clone(),
+            source: indicator.source,
+            confidence: indicator.confidence,
+            tags: indicator.tags,
+            description: indicator.description,
+            external_id: indicator.external_id,
+            events: indicator.events,
+        };
+        self.len += 1;
+        if indicator.kind == IocKind::Ip {
+            if let Some(network) = parse_network(&indicator.value) {
+                self.networks.entry((network.v6, network.prefix)).or_default().entry(network.bits).or_default().push(hit);
+            }
+            return;
+        }
+        self.exact.entry((indicator.kind, indicator.value)).or_default().push(hit);
+    }
+
+    fn exact(&self, kind: IocKind, value: &str) -> &[IocHit] {
+        self.exact.get(&(kind, value.to_string())).map_or(&[], Vec::as_slice)
+    }
+
+    pub fn hash(&self, digests: &FileDigests) -> Vec<&IocHit> {
+        [&digests.md5, &digests.sha1, &digests.sha256, &digests.sha512]
+            .iter()
+            .flat_map(|h| self.exact(IocKind::Hash, &h.to_ascii_lowercase()))
+            .collect()
+    }
+
+    /// Indicators for the domain or any parent domain of it.
+    pub fn domain(&self, host: &str) -> Vec<&IocHit> {
+        let Some(host) = normalize_domain(host) else {
+            return Vec::new();
+        };
+        let mut hits = Vec::new();
+        let mut rest = host.as_str();
+        loop {
+            hits.extend(self.exact(IocKind::Domain, rest));
+            match rest.split_once('.') {
+                Some((_, parent)) if parent.contains('.') => rest = parent,
+                _ => break,
+            }
+        }
+        hits
+    }
+
+    /// Indicators for the address or any range containing it.
+    pub fn ip(&self, addr: IpAddr) -> Vec<&IocHit> {
+        let address = Network::new(addr, 128);
+        self.networks
+            .iter()
+            .filter(|((v6, _), _)| *v6 == address.v6)
+            .filter_map(|((_, prefix), table)| table.get(&Network::new(addr, *prefix).bits))
+            .flatten()
+            .collect()
+    }
+
+    /// Indicators for the URL itself or its host.
+    pub fn url(&self, url: &str) -> Vec<&IocHit> {
+        let Some(normalized) = normalize_url(url) else {
+            return Vec::new();
+        };
+        let mut hits: Vec<&IocHit> = self.exact(IocKind::Url, &normalized).iter().collect();
+        if let Some(host) = url_host(&normalized) {
+            match host.parse::<IpAddr>() {
+                Ok(addr) => hits.extend(self.ip(addr)),
+                Err(_) => hits.extend(self.domain(&host)),
+            }
+        }
+        hits
+    }
+
+    /// Every indicator matching a scanned file's hashes or the links found
+    /// in it, once per listing.
+    pub fn matches(&self, digests: &FileDigests, urls: &[String]) -> Vec<IocHit> {
+        if self.is_empty() {
+            return Vec::new();
+        }
+        let mut hits = self.hash(digests);
+        for url in urls {
+            hits.extend(self.url(url));
+        }
+        let mut matches: Vec<IocHit> = Vec::new();
+        for hit in hits {
+            if !matches.iter().any(|m| m.kind == hit.kind && m.value == hit.value && m.source == hit.source) {
+                matches.push(hit.clone());
+            }
+        }
+        matches
+    }
+}
+
+/// One finding per matched value, described from its most confident listing.
+pub fn detections(matches: &[IocHit]) -> Vec<Detection> {
+    let mut best: Vec<&IocHit> = Vec::new();
+    for hit in matches {
+        match best.iter_mut().find(|b| b.value == hit.value) {
+            Some(b) if b.confidence < hit.confidence => *b = hit,
+            Some(_) => {}
+            None => best.push(hit),
+        }
+    }
+    best.into_iter().map(IocHit::detection).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("varenizer-ioc-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn all(store: &IocStore) -> Vec<Indicator> {
+        let query = IocQuery { limit: Some(MAX_PAGE_SIZE), ..Default::default() };
+        let mut indicators = store.search(&query).unwrap().indicators;
+        indicators.sort_by(|a, b| (a.kind.as_str(), &a.value).cmp(&(b.kind.as_str(), &b.value)));
+        indicators
+    }
+
+    fn values(indicators: &[Indicator]) -> Vec<(IocKind, &str, &str)> {
+        indicators.iter().map(|i| (i.kind, i.value.as_str(), i.source.as_str())).collect()
+    }
+
+    #[test]
+    fn imports_csv_files() {
+        let dir = temp_dir("csv");
+        let store = IocStore::open(&dir.join("ioc.db")).unwrap();
+        let path = dir.join("feed.csv");
+        fs::write(
+            &path,
+            "Type,Value,Confidence,Tags,Description,Last_Seen\n\
+             sha256,E3B0C44298FC1C149AFBF4C8996FB92427AE41E4649B934CA495991B7852B855,90,ransomware;loader|ransomware,\
+             \"Dropper, \"\"stage\"\" 1\",2024-03-01\n\
+             \n\
+             domain,*.Evil.Example.,,,,\n\
+             email,a@b.example,,,,\n\
+             url,not a url,,,,\n\
+             ip,198.51.100.7/24,,,,\n",
+        )
+        .unwrap();
+
+        let summary = store.import_file(&path, None).unwrap();
+        assert_eq!((summary.added, summary.updated, summary.skipped), (3, 0, 2));
+        assert_eq!(summary.errors, ["Invalid url indicator 'not a url'", "Line 4: unknown indicator type 'email'"]);
+
+        let indicators = all(&store);
+        assert_eq!(
+            values(&indicators),
+            [
+                (IocKind::Domain, "evil.example", "feed"),
+                (IocKind::Hash, "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855", "feed"),
+                (IocKind::Ip, "198.51.100.0/24", "feed"),
+            ]
+        );
+        let hash = &indicators[1];
+        assert_eq!(hash.confidence, 90);
+        assert_eq!(hash.tags, ["loader", "ransomware"]);
+        assert_eq!(hash.description.as_deref(), Some("Dropper, \"stage\" 1"));
+        assert_eq!(hash.first_seen, "2024-03-01 00:00:00 UTC");
+        assert_eq!(hash.last_seen, "2024-03-01 00:00:00 UTC");
+        assert_eq!(indicators[0].confidence, DEFAULT_CONFIDENCE);
+
+        // An export reads back as the same indicators
+        let exported = dir.join("export.csv");
+        assert_eq!(store.export_file(&exported, &IocQuery::default()).unwrap(), 3);
+        let copy = IocStore::open(&dir.join("copy.db")).unwrap();
+        let summary = copy.import_file(&exported, Some("ignored")).unwrap();
+        assert_eq!((summary.added, summary.skipped), (3, 0));
+        let copied = all(&copy);
+        assert_eq!(values(&copied), values(&indicators));
+        assert_eq!(copied[1].description, hash.description);
+        assert_eq!(copied[1].tags, hash.tags);
+
+        assert!(store.import_file(&dir.join("missing.csv"), None).is_err());
+        fs::write(&path, "kind,tags\nhash,x\n").unwrap();
+        assert_eq!(store.import_file(&path, None).unwrap_err().to_string(), "CSV header has no value column");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn imports_plain_text_lists() {
+        let dir = temp_dir("text");
+        let store = IocStore::open(&dir.join("ioc.db")).unwrap();
+        let path = dir.join("blocklist.txt");
+        fs::write(
+            &path,
+            "# exported 2024-03-01\n\
+             D41D8CD98F00B204E9800998ECF8427E\n\
+             \n\
+             10.1.2.3/8\n\
+             https://Evil.Example:443/path?q=1#frag\n\
+             login.evil.example\n\
+             just words\n",
+        )
+        .unwrap();
+
+        let summary = store.import_file(&path, None).unwrap();
+        assert_eq!((summary.added, summary.skipped), (4, 1));
+        assert_eq!(summary.errors, ["Unrecognised indicator 'just words'"]);
+        assert_eq!(
+            values(&all(&store)),
+            [
+                (IocKind::Domain, "login.evil.example", "blocklist"),
+                (IocKind::Hash, "d41d8cd98f00b204e9800998ecf8427e", "blocklist"),
+                (IocKind::Ip, "10.0.0.0/8", "blocklist"),
+                (IocKind::Url, "https://evil.example/path?q=1", "blocklist"),
+            ]
+        );
+
+        // Importing again refreshes the same rows, another source adds its own
+        let summary = store.import_file(&path, None).unwrap();
+        assert_eq!((summary.added, summary.updated), (0, 4));
+        let summary = store.import_file(&path, Some("partner")).unwrap();
+        assert_eq!((summary.added, summary.updated), (4, 0));
+        assert_eq!(all(&store).len(), 8);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn imports_stix_bundles() {
+        let dir = temp_dir("stix");
+        let store = IocStore::open(&dir.join("ioc.db")).unwrap();
+        let bundle = serde_json::json!({
+            "type": "bundle",
+            "id": "bundle--1",
+            "objects": [
+                {"type": "identity", "id": "identity--1", "name": "ACME CERT"},
+                {"type": "indicator", "id": "indicator--1", "created_by_ref": "identity--1", "pattern_type": "stix",
+                 "pattern": "[url:value = 'http://evil.example/a'] OR [ipv4-addr:value = '203.0.113.9']",
+                 "confidence": 80, "valid_from": "2024-01-01T00:00:00Z", "valid_until": "2099-01-01T00:00:00Z"},
+                {"type": "indicator", "id": "indicator--2", "pattern_type": "stix",
+                 "pattern": "[domain-name:value = 'Evil.Example']"},
+                {"type": "indicator", "id": "indicator--3", "pattern_type": "stix", "pattern": "[file:name = 'a.exe']"},
+            ]
+        });
+        let path = dir.join("bundle.json");
+        fs::write(&path, bundle.to_string()).unwrap();
+
+        let summary = store.import_file(&path, None).unwrap();
+        assert_eq!((summary.added, summary.skipped), (3, 1));
+        assert_eq!(summary.errors, ["indicator--3: unsupported property file:name"]);
+        let indicators = all(&store);
+        assert_eq!(
+            values(&indicators),
+            [
+                (IocKind::Domain, "evil.example", "bundle"),
+                (IocKind::Ip, "203.0.113.9", "ACME CERT"),
+                (IocKind::Url, "http://evil.example/a", "ACME CERT"),
+            ]
+        );
+        let url = &indicators[2];
+        assert_eq!(url.confidence, 80);
+        assert_eq!(url.first_seen, "2024-01-01 00:00:00 UTC");
+        assert_eq!(url.valid_until.as_deref(), Some("2099-01-01 00:00:00 UTC"));
+        assert_eq!(url.external_id.as_deref(), Some("indicator--1"));
+
+        // Objects polled from a collection come without the bundle around them
+        let objects = bundle["objects"].as_array().unwrap();
+        let summary = store.import_stix(&objects[2..], "taxii").unwrap();
+        assert_eq!((summary.added, summary.skipped), (1, 1));
+        let query = IocQuery { source: Some("taxii".to_string()), ..Default::default() };
+        assert_eq!(store.search(&query).unwrap().total, 1);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn event_columns_move_to_their_own_table() {
+        let dir = temp_dir("migrate");
+        let path = dir.join("ioc.db");
+        {
+            let conn = Connection::open(&path).unwrap();
+            conn.execute_batch(&MIGRATIONS[..3].join("\n")).unwrap();
+            conn.execute_batch(
+                "INSERT INTO indicators (kind, value, source, confidence, first_seen, last_seen, tags, event_id,
+                    event_uuid, event_info)
+                 VALUES ('domain', 'evil.example', 'partner', 50, '2024-01-01 00:00:00 UTC',
+                    '2024-01-01 00:00:00 UTC', '[]', '7', 'uuid-7', 'Event 7');
+                 INSERT INTO indicators (kind, value, source, confidence, first_seen, last_seen, tags)
+                 VALUES ('domain', 'plain.example', 'local', 50, '2024-01-01 00:00:00 UTC',
+                    '2024-01-01 00:00:00 UTC', '[]');
+                 PRAGMA user_version = 3;",
+            )
+            .unwrap();
+        }
+
+        let store = IocStore::open(&path).unwrap();
+        let indicators = all(&store);
+        assert_eq!(indicators[0].value, "evil.example");
+        assert_eq!(
+            indicators[0].events,
+            [MispEventRef { id: "7".to_string(), uuid: "uuid-7".to_string(), info: "Event 7".to_string() }]
+        );
+        assert!(indicators[1].events.is_empty());
+
+        // Memberships go with their indicator
+        assert_eq!(store.expire(Some("2030-01-01")).unwrap(), 2);
+        let orphans: i64 =
+            store.conn().query_row("SELECT COUNT(*) FROM indicator_events", [], |row| row.get(0)).unwrap();
+        assert_eq!(orphans, 0);
+        drop(store);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn imported_indicators_reach_the_index() {
+        let dir = temp_dir("index");
+        let store = IocStore::open(&dir.join("ioc.db")).unwrap();
+        let path = dir.join("feed.txt");
+        fs::write(&path, "evil.example\n198.51.100.0/24\n").unwrap();
+        store.import_file(&path, None).unwrap();
+
+        let index = store.index().unwrap();
+        assert_eq!(index.domain("cdn.Evil.Example").len(), 1);
+        assert!(index.domain("notevil.example").is_empty());
+        assert_eq!(index.url("http://198.51.100.20:8080/x").len(), 1);
+        assert!(index.url("http://198.51.101.1/").is_empty());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
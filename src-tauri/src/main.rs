@@ -1,81 +1,705 @@
 // Prevents additional console window on Windows in release, DO NOT REMOVE!!
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
 
-use tauri::{Manager, Window, WindowEvent};
-use serde::{Deserialize, Serialize};
+use tauri::{AppHandle, Emitter, Manager, State, WindowEvent};
 use std::collections::HashMap;
-use std::path::PathBuf;
-use uuid::Uuid;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 
-#[derive(Debug, Serialize, Deserialize)]
-struct FileInfo {
-    name: String,
-    path: String,
-    size: u64,
-    extension: String,
+mod archive;
+mod clamav;
+mod elf;
+mod email;
+mod engine;
+mod entropy;
+mod filetype;
+mod fuzzy;
+mod hashing;
+mod heuristics;
+mod history;
+mod ioc;
+mod misp;
+mod models;
+mod office;
+mod pdf;
+mod pe;
+mod quarantine;
+mod realtime;
+mod scanner;
+mod script;
+mod session;
+mod signatures;
+mod stix;
+mod taxii;
+mod update;
+mod urlscan;
+mod walker;
+mod yara;
+
+use engine::ScanEngine;
+use fuzzy::FuzzyComparison;
+use hashing::FileDigests;
+use heuristics::ScoringConfig;
+use history::{HistoryPage, HistoryQuery, HistoryStore};
+use ioc::{ImportSummary, IocPage, IocQuery, IocStore};
+use misp::MispFeed;
+use models::ScanSession;
+use office::OfficeAnalysis;
+use pe::PeAnalysis;
+use quarantine::{QuarantineEntry, QuarantineListing, QuarantineVault, RestoreConflict, RestoreOutcome};
+use realtime::{RealtimeConfig, RealtimeEvent, RealtimeObserver, RealtimeProtection, RealtimeStatus};
+use session::{NoopObserver, PoolLimits, ScanEvent, ScanManager, ScanObserver, ScanTargets, SessionControl};
+use signatures::{LoadSummary, SimilarSignatureDef};
+use taxii::TaxiiFeed;
+use update::{DatabaseStatus, UpdateCheck, UpdateConfig, UpdateReport};
+use urlscan::UrlScanResult;
+use walker::{ScanOptions, ScanProfile, ScanType};
+use yara::{RuleLoadReport, RuleSet};
+
+const SCORING_CONFIG_FILE: &str = "scoring.json";
+const TAXII_FEEDS_FILE: &str = "taxii.json";
+const MISP_FEEDS_FILE: &str = "misp.json";
+const UPDATE_CONFIG_FILE: &str = "updates.json";
+
+// Tauri commands
+#[tauri::command]
+async fn scan_files(
+    files: Vec<String>,
+    options: Option<ScanOptions>,
+    engine: State<'_, Arc<ScanEngine>>,
+) -> Result<ScanSession, String> {
+    let limits = PoolLimits::from_options(&options.unwrap_or_default());
+    let targets = ScanTargets::Files(files.iter().map(PathBuf::from).collect());
+    let engine = engine.inner().clone();
+    
+    // Reading and matching is blocking work, keep it off the async runtime threads.
+    // Files that can't be scanned end up in the session's errors next to the results.
+    tokio::task::spawn_blocking(move || {
+        session::run_session(&engine, ScanSession::new("files"), targets, limits, &SessionControl::default(), &NoopObserver)
+    })
+    .await
+    .map_err(|e| format!("Scan task failed: {}", e))
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-struct ScanResult {
-    id: String,
-    file_info: FileInfo,
-    status: String, // "clean", "threat", "suspicious"
-    threats: Vec<String>,
-    scan_time: String,
-    hash: String,
+#[tauri::command]
+async fn scan_paths(
+    paths: Vec<String>,
+    scan_type: ScanType,
+    options: Option<ScanOptions>,
+    engine: State<'_, Arc<ScanEngine>>,
+) -> Result<ScanSession, String> {
+    let options = options.unwrap_or_default();
+    let paths: Vec<PathBuf> = paths.iter().map(PathBuf::from).collect();
+    let profile = ScanProfile::new(scan_type, &paths, &options);
+    if profile.roots.is_empty() {
+        return Err("No scan locations found".to_string());
+    }
+    
+    let engine = engine.inner().clone();
+    tokio::task::spawn_blocking(move || {
+        let session = ScanSession::new(profile.scan_type.as_str());
+        let limits = PoolLimits::from_options(&options);
+        let targets = ScanTargets::Profile(profile, options.symlinks);
+        session::run_session(&engine, session, targets, limits, &SessionControl::default(), &NoopObserver)
+    })
+    .await
+    .map_err(|e| format!("Scan task failed: {}", e))
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-struct ScanSession {
-    id: String,
-    files: Vec<ScanResult>,
-    scan_type: String,
-    start_time: String,
-    end_time: Option<String>,
-    total_files: usize,
-    threats_found: usize,
-    suspicious_files: usize,
-    clean_files: usize,
+/// Forwards session and real-time events to the webview.
+struct EventEmitter(AppHandle);
+
+impl ScanObserver for EventEmitter {
+    fn notify(&self, event: &ScanEvent) {
+        if let Err(e) = self.0.emit(event.name(), event) {
+            eprintln!("Failed to emit {}: {}", event.name(), e);
+        }
+    }
 }
 
-// Tauri commands
+impl RealtimeObserver for EventEmitter {
+    fn notify(&self, event: &RealtimeEvent) {
+        if let Err(e) = self.0.emit(event.name(), event) {
+            eprintln!("Failed to emit {}: {}", event.name(), e);
+        }
+    }
+}
+
+/// Starts a scan in the background and returns its session ID right away.
+/// Without a scan type the paths are scanned as individual files, like
+/// `scan_files` does.
 #[tauri::command]
-async fn scan_files(files: Vec<String>) -> Result<Vec<ScanResult>, String> {
-    // Simulate file scanning process
-    let mut results = Vec::new();
+async fn start_scan(
+    paths: Vec<String>,
+    scan_type: Option<ScanType>,
+    options: Option<ScanOptions>,
+    app: AppHandle,
+    engine: State<'_, Arc<ScanEngine>>,
+    manager: State<'_, Arc<ScanManager>>,
+) -> Result<String, String> {
+    let options = options.unwrap_or_default();
+    let paths: Vec<PathBuf> = paths.iter().map(PathBuf::from).collect();
+    let (session, targets) = match scan_type {
+        Some(scan_type) => {
+            let profile = ScanProfile::new(scan_type, &paths, &options);
+            if profile.roots.is_empty() {
+                return Err("No scan locations found".to_string());
+            }
+            (ScanSession::new(scan_type.as_str()), ScanTargets::Profile(profile, options.symlinks))
+        }
+        None if paths.is_empty() => return Err("No files to scan".to_string()),
+        None => (ScanSession::new("files"), ScanTargets::Files(paths)),
+    };
+    
+    let limits = PoolLimits::from_options(&options);
+    let session_id = session.id.clone();
+    let control = manager.register(&session_id);
+    let engine = engine.inner().clone();
+    let manager = manager.inner().clone();
+    tokio::task::spawn_blocking(move || {
+        let session = session::run_session(&engine, session, targets, limits, &control, &EventEmitter(app));
+        manager.complete(session);
+    });
     
-    for file_path in files {
+    Ok(session_id)
+}
+
+fn session_control(manager: &ScanManager, session_id: &str) -> Result<Arc<SessionControl>, String> {
+    manager.control(session_id)
+        .ok_or_else(|| format!("No running scan with ID {}", session_id))
+}
+
+#[tauri::command]
+async fn cancel_scan(session_id: String, manager: State<'_, Arc<ScanManager>>) -> Result<(), String> {
+    session_control(&manager, &session_id)?.cancel();
+    Ok(())
+}
+
+#[tauri::command]
+async fn pause_scan(session_id: String, manager: State<'_, Arc<ScanManager>>) -> Result<(), String> {
+    session_control(&manager, &session_id)?.pause();
+    Ok(())
+}
+
+#[tauri::command]
+async fn resume_scan(session_id: String, manager: State<'_, Arc<ScanManager>>) -> Result<(), String> {
+    session_control(&manager, &session_id)?.resume();
+    Ok(())
+}
+
+#[tauri::command]
+async fn get_scan_session(session_id: String, manager: State<'_, Arc<ScanManager>>) -> Result<ScanSession, String> {
+    if manager.control(&session_id).is_some() {
+        return Err(format!("Scan {} is still running", session_id));
+    }
+    manager.finished_session(&session_id)
+        .ok_or_else(|| format!("Unknown scan session: {}", session_id))
+}
+
+#[tauri::command]
+async fn reload_signatures(engine: State<'_, Arc<ScanEngine>>) -> Result<LoadSummary, String> {
+    let engine = engine.inner().clone();
+    tokio::task::spawn_blocking(move || engine.reload_signatures())
+        .await
+        .map_err(|e| format!("Failed to reload signatures: {}", e))
+}
+
+#[tauri::command]
+async fn import_clamav_database(path: String, engine: State<'_, Arc<ScanEngine>>) -> Result<LoadSummary, String> {
+    let source = PathBuf::from(&path);
+    if !clamav::is_database(&source) {
+        return Err(format!("{} is not a ClamAV database file", path));
+    }
+    let file_name = source.file_name()
+        .ok_or_else(|| format!("Invalid database path: {}", path))?
+        .to_owned();
+    
+    // Copy into the signature directory so the database is picked up on every reload
+    let engine = engine.inner().clone();
+    tokio::task::spawn_blocking(move || {
+        std::fs::copy(&source, engine.signature_dir().join(file_name))
+            .map_err(|e| format!("Failed to import {}: {}", path, e))?;
+        Ok(engine.reload_signatures())
+    })
+    .await
+    .map_err(|e| format!("Failed to import database: {}", e))?
+}
+
+#[tauri::command]
+async fn load_yara_rules(directory: Option<String>, engine: State<'_, Arc<ScanEngine>>) -> Result<RuleLoadReport, String> {
+    let engine = engine.inner().clone();
+    tokio::task::spawn_blocking(move || engine.load_rules(directory.as_deref().map(Path::new)))
+        .await
+        .map_err(|e| format!("Failed to load rules: {}", e))
+}
+
+#[tauri::command]
+async fn validate_yara_rules(directory: String) -> Result<RuleLoadReport, String> {
+    // Compile without activating, so analysts can check a directory before switching to it
+    tokio::task::spawn_blocking(move || RuleSet::load_dir(Path::new(&directory)).1)
+        .await
+        .map_err(|e| format!("Failed to validate rules: {}", e))
+}
+
+#[tauri::command]
+async fn get_scoring_config(engine: State<'_, Arc<ScanEngine>>) -> Result<ScoringConfig, String> {
+    Ok(engine.scoring())
+}
+
+/// Replaces the heuristic weights and thresholds and saves them so they
+/// survive a restart. Results already produced keep their old scores.
+#[tauri::command]
+async fn set_scoring_config(
+    config: ScoringConfig,
+    app: AppHandle,
+    engine: State<'_, Arc<ScanEngine>>,
+) -> Result<ScoringConfig, String> {
+    config.validate()?;
+    let path = app
+        .path()
+        .app_data_dir()
+        .map_err(|e| format!("Failed to locate app data directory: {}", e))?
+        .join(SCORING_CONFIG_FILE);
+    config.save(&path)?;
+    engine.set_scoring(config.clone());
+    Ok(config)
+}
+
+/// Moves a file into the quarantine vault. Without explicit detection names
+/// the file is scanned first so the record says why it was quarantined.
+#[tauri::command]
+async fn quarantine_file(
+    file_path: String,
+    detections: Option<Vec<String>>,
+    engine: State<'_, Arc<ScanEngine>>,
+    vault: State<'_, Arc<QuarantineVault>>,
+) -> Result<QuarantineEntry, String> {
+    let engine = engine.inner().clone();
+    let vault = vault.inner().clone();
+    tokio::task::spawn_blocking(move || {
         let path = PathBuf::from(&file_path);
-        
-        // Get file info
-        let file_info = match get_file_info(&path) {
-            Ok(info) => info,
-            Err(e) => return Err(format!("Failed to get file info: {}", e)),
+        let detections = match detections {
+            Some(detections) => detections,
+            None => scanner::scan_file(&engine, &path)
+                .map_err(|e| format!("Failed to scan {}: {}", file_path, e))?
+                .threats,
         };
-        
-        // Simulate scanning
-        tokio::time::sleep(tokio::time::Duration::from_millis(500)).await;
-        
-        // Generate mock scan result
-        let scan_result = generate_mock_scan_result(file_info);
-        results.push(scan_result);
+        vault.quarantine(&path, detections)
+            .map_err(|e| format!("Failed to quarantine {}: {}", file_path, e))
+    })
+    .await
+    .map_err(|e| format!("Quarantine task failed: {}", e))?
+}
+
+#[tauri::command]
+async fn list_quarantine(vault: State<'_, Arc<QuarantineVault>>) -> Result<QuarantineListing, String> {
+    let vault = vault.inner().clone();
+    tokio::task::spawn_blocking(move || vault.list())
+        .await
+        .map_err(|e| format!("Failed to list quarantine: {}", e))?
+        .map_err(|e| format!("Failed to list quarantine: {}", e))
+}
+
+#[tauri::command]
+async fn restore_file(
+    id: String,
+    destination: Option<String>,
+    conflict: Option<RestoreConflict>,
+    vault: State<'_, Arc<QuarantineVault>>,
+) -> Result<RestoreOutcome, String> {
+    let vault = vault.inner().clone();
+    tokio::task::spawn_blocking(move || {
+        vault.restore(&id, destination.as_deref().map(Path::new), conflict.unwrap_or_default())
+    })
+    .await
+    .map_err(|e| format!("Failed to restore file: {}", e))?
+    .map_err(|e| format!("Failed to restore file: {}", e))
+}
+
+#[tauri::command]
+async fn purge_quarantine(ids: Option<Vec<String>>, vault: State<'_, Arc<QuarantineVault>>) -> Result<usize, String> {
+    let vault = vault.inner().clone();
+    tokio::task::spawn_blocking(move || vault.purge(ids.as_deref()))
+        .await
+        .map_err(|e| format!("Failed to purge quarantine: {}", e))?
+        .map_err(|e| format!("Failed to purge quarantine: {}", e))
+}
+
+#[tauri::command]
+async fn start_realtime_protection(
+    config: RealtimeConfig,
+    app: AppHandle,
+    engine: State<'_, Arc<ScanEngine>>,
+    realtime: State<'_, Arc<RealtimeProtection>>,
+) -> Result<RealtimeStatus, String> {
+    // Our own database and vault writes must never trigger scans
+    let excluded = app.path().app_data_dir().into_iter().collect();
+    let engine = engine.inner().clone();
+    let realtime = realtime.inner().clone();
+    tokio::task::spawn_blocking(move || realtime.start(engine, config, excluded, Arc::new(EventEmitter(app))))
+        .await
+        .map_err(|e| format!("Failed to start real-time protection: {}", e))?
+}
+
+#[tauri::command]
+async fn stop_realtime_protection(realtime: State<'_, Arc<RealtimeProtection>>) -> Result<RealtimeStatus, String> {
+    let realtime = realtime.inner().clone();
+    tokio::task::spawn_blocking(move || realtime.stop())
+        .await
+        .map_err(|e| format!("Failed to stop real-time protection: {}", e))
+}
+
+#[tauri::command]
+async fn get_realtime_status(realtime: State<'_, Arc<RealtimeProtection>>) -> Result<RealtimeStatus, String> {
+    Ok(realtime.status())
+}
+
+#[tauri::command]
+async fn add_watch_path(path: String, realtime: State<'_, Arc<RealtimeProtection>>) -> Result<RealtimeStatus, String> {
+    realtime.add_path(&path)
+}
+
+#[tauri::command]
+async fn remove_watch_path(path: String, realtime: State<'_, Arc<RealtimeProtection>>) -> Result<RealtimeStatus, String> {
+    realtime.remove_path(&path)
+}
+
+/// Checks a URL against the IOC database and the offline URL heuristics.
+#[tauri::command]
+async fn scan_url(url: String, engine: State<'_, Arc<ScanEngine>>) -> Result<UrlScanResult, String> {
+    let engine = engine.inner().clone();
+    tokio::task::spawn_blocking(move || urlscan::scan(&engine, &url))
+        .await
+        .map_err(|e| format!("Failed to scan URL: {}", e))?
+}
+
+#[tauri::command]
+async fn get_file_hash(file_path: String) -> Result<FileDigests, String> {
+    hash_file(PathBuf::from(file_path))
+        .await
+        .map_err(|e| format!("Failed to hash file: {}", e))
+}
+
+/// Compares the ssdeep and TLSH digests of two files.
+#[tauri::command]
+async fn compare_files(first_path: String, second_path: String) -> Result<FuzzyComparison, String> {
+    let first = hash_file(PathBuf::from(&first_path))
+        .await
+        .map_err(|e| format!("Failed to hash {}: {}", first_path, e))?;
+    let second = hash_file(PathBuf::from(&second_path))
+        .await
+        .map_err(|e| format!("Failed to hash {}: {}", second_path, e))?;
+    Ok(FuzzyComparison::new(&first.ssdeep, &first.tlsh, &second.ssdeep, &second.tlsh))
+}
+
+/// Records a known-bad file in the local similarity database under `name`.
+#[tauri::command]
+async fn add_similarity_sample(
+    file_path: String,
+    name: String,
+    engine: State<'_, Arc<ScanEngine>>,
+) -> Result<LoadSummary, String> {
+    let digests = hash_file(PathBuf::from(&file_path))
+        .await
+        .map_err(|e| format!("Failed to hash {}: {}", file_path, e))?;
+    let sample = SimilarSignatureDef {
+        name,
+        ssdeep: Some(digests.ssdeep),
+        tlsh: Some(digests.tlsh).filter(|t| !t.is_empty()),
+    };
+    let engine = engine.inner().clone();
+    tokio::task::spawn_blocking(move || engine.add_similar_sample(sample))
+        .await
+        .map_err(|e| format!("Failed to add similarity sample: {}", e))?
+}
+
+#[tauri::command]
+async fn analyze_pe(file_path: String) -> Result<PeAnalysis, String> {
+    tokio::task::spawn_blocking(move || {
+        let data = std::fs::read(&file_path).map_err(|e| format!("Failed to read {}: {}", file_path, e))?;
+        pe::analyze(&data)
+    })
+    .await
+    .map_err(|e| format!("Failed to analyze PE: {}", e))?
+}
+
+#[tauri::command]
+async fn extract_macros(file_path: String) -> Result<OfficeAnalysis, String> {
+    tokio::task::spawn_blocking(move || {
+        let data = std::fs::read(&file_path).map_err(|e| format!("Failed to read {}: {}", file_path, e))?;
+        office::analyze(&data)
+    })
+    .await
+    .map_err(|e| format!("Failed to extract macros: {}", e))?
+}
+
+#[tauri::command]
+async fn save_scan_results(session: ScanSession, history: State<'_, Arc<HistoryStore>>) -> Result<String, String> {
+    let history = history.inner().clone();
+    tokio::task::spawn_blocking(move || history.save_session(&session).map(|_| session.id))
+        .await
+        .map_err(|e| format!("Failed to save scan results: {}", e))?
+        .map_err(|e| format!("Failed to save scan results: {}", e))
+}
+
+#[tauri::command]
+async fn list_scan_history(query: Option<HistoryQuery>, history: State<'_, Arc<HistoryStore>>) -> Result<HistoryPage, String> {
+    let history = history.inner().clone();
+    tokio::task::spawn_blocking(move || history.list_sessions(&query.unwrap_or_default()))
+        .await
+        .map_err(|e| format!("Failed to read scan history: {}", e))?
+        .map_err(|e| format!("Failed to read scan history: {}", e))
+}
+
+#[tauri::command]
+async fn get_history_session(session_id: String, history: State<'_, Arc<HistoryStore>>) -> Result<ScanSession, String> {
+    let history = history.inner().clone();
+    tokio::task::spawn_blocking(move || history.get_session(&session_id))
+        .await
+        .map_err(|e| format!("Failed to read scan history: {}", e))?
+        .map_err(|e| format!("Failed to read scan history: {}", e))?
+        .ok_or_else(|| "Scan session not found in history".to_string())
+}
+
+#[tauri::command]
+async fn delete_history_sessions(session_ids: Vec<String>, history: State<'_, Arc<HistoryStore>>) -> Result<usize, String> {
+    let history = history.inner().clone();
+    tokio::task::spawn_blocking(move || history.delete_sessions(&session_ids))
+        .await
+        .map_err(|e| format!("Failed to delete scan history: {}", e))?
+        .map_err(|e| format!("Failed to delete scan history: {}", e))
+}
+
+/// Imports indicators from a JSON, CSV or plain text file. Indicators
+/// without a source are attributed to `source`, or to the file name.
+#[tauri::command]
+async fn import_iocs(
+    file_path: String,
+    source: Option<String>,
+    store: State<'_, Arc<IocStore>>,
+    engine: State<'_, Arc<ScanEngine>>,
+) -> Result<ImportSummary, String> {
+    let store = store.inner().clone();
+    let engine = engine.inner().clone();
+    let path = PathBuf::from(&file_path);
+    tokio::task::spawn_blocking(move || {
+        let summary = store.import_file(&path, source.as_deref())?;
+        engine.set_iocs(store.index()?);
+        Ok::<_, ioc::IocError>(summary)
+    })
+    .await
+    .map_err(|e| format!("Failed to import indicators: {}", e))?
+    .map_err(|e| format!("Failed to import indicators from {}: {}", file_path, e))
+}
+
+#[tauri::command]
+async fn search_iocs(query: Option<IocQuery>, store: State<'_, Arc<IocStore>>) -> Result<IocPage, String> {
+    let store = store.inner().clone();
+    tokio::task::spawn_blocking(move || store.search(&query.unwrap_or_default()))
+        .await
+        .map_err(|e| format!("Failed to search indicators: {}", e))?
+        .map_err(|e| format!("Failed to search indicators: {}", e))
+}
+
+/// Drops indicators past their `valid_until`, and with `last_seen_before`
+/// also those not seen since. Returns how many were removed.
+#[tauri::command]
+async fn expire_iocs(
+    last_seen_before: Option<String>,
+    store: State<'_, Arc<IocStore>>,
+    engine: State<'_, Arc<ScanEngine>>,
+) -> Result<usize, String> {
+    let store = store.inner().clone();
+    let engine = engine.inner().clone();
+    tokio::task::spawn_blocking(move || {
+        let removed = store.expire(last_seen_before.as_deref())?;
+        engine.set_iocs(store.index()?);
+        Ok::<_, ioc::IocError>(removed)
+    })
+    .await
+    .map_err(|e| format!("Failed to expire indicators: {}", e))?
+    .map_err(|e| format!("Failed to expire indicators: {}", e))
+}
+
+/// Writes the indicators matching `query` to a JSON or CSV file. Returns how
+/// many were written.
+#[tauri::command]
+async fn export_iocs(file_path: String, query: Option<IocQuery>, store: State<'_, Arc<IocStore>>) -> Result<usize, String> {
+    let store = store.inner().clone();
+    let path = PathBuf::from(&file_path);
+    tokio::task::spawn_blocking(move || store.export_file(&path, &query.unwrap_or_default()))
+        .await
+        .map_err(|e| format!("Failed to export indicators: {}", e))?
+        .map_err(|e| format!("Failed to export indicators to {}: {}", file_path, e))
+}
+
+fn taxii_feeds_path(app: &AppHandle) -> Result<PathBuf, String> {
+    Ok(app
+        .path()
+        .app_data_dir()
+        .map_err(|e| format!("Failed to locate app data directory: {}", e))?
+        .join(TAXII_FEEDS_FILE))
+}
+
+#[tauri::command]
+async fn list_taxii_feeds(app: AppHandle) -> Result<Vec<TaxiiFeed>, String> {
+    taxii::load_feeds(&taxii_feeds_path(&app)?)
+}
+
+/// Adds a feed, or replaces the one with the same name.
+#[tauri::command]
+async fn save_taxii_feed(feed: TaxiiFeed, app: AppHandle) -> Result<Vec<TaxiiFeed>, String> {
+    feed.validate()?;
+    let path = taxii_feeds_path(&app)?;
+    let mut feeds = taxii::load_feeds(&path)?;
+    match feeds.iter_mut().find(|f| f.name == feed.name) {
+        Some(existing) => *existing = feed,
+        None => feeds.push(feed),
     }
-    
-    Ok(results)
+    taxii::save_feeds(&path, &feeds)?;
+    Ok(feeds)
 }
 
 #[tauri::command]
-async fn get_file_hash(file_path: String) -> Result<String, String> {
-    // Simulate hash generation
-    let hash = format!("sha256:{}", Uuid::new_v4().to_string().replace("-", ""));
-    Ok(hash)
+async fn remove_taxii_feed(name: String, app: AppHandle) -> Result<Vec<TaxiiFeed>, String> {
+    let path = taxii_feeds_path(&app)?;
+    let mut feeds = taxii::load_feeds(&path)?;
+    feeds.retain(|f| f.name != name);
+    taxii::save_feeds(&path, &feeds)?;
+    Ok(feeds)
 }
 
+/// Imports the indicators added to a feed's collection since its last poll,
+/// then remembers where this poll ended.
 #[tauri::command]
-async fn save_scan_results(session: ScanSession) -> Result<String, String> {
-    // In a real application, this would save to a database or file
-    // For now, we'll just return a success message
-    Ok(format!("Scan results saved with ID: {}", session.id))
+async fn poll_taxii_feed(
+    name: String,
+    app: AppHandle,
+    store: State<'_, Arc<IocStore>>,
+    engine: State<'_, Arc<ScanEngine>>,
+) -> Result<ImportSummary, String> {
+    let path = taxii_feeds_path(&app)?;
+    let store = store.inner().clone();
+    let engine = engine.inner().clone();
+    tokio::task::spawn_blocking(move || {
+        let mut feeds = taxii::load_feeds(&path)?;
+        let feed = feeds
+            .iter_mut()
+            .find(|f| f.name == name)
+            .ok_or_else(|| format!("No TAXII feed named {}", name))?;
+        let summary = taxii::poll_feed(feed, &store)?;
+        engine.set_iocs(store.index().map_err(|e| format!("Failed to load indicators: {}", e))?);
+        taxii::save_feeds(&path, &feeds)?;
+        Ok(summary)
+    })
+    .await
+    .map_err(|e| format!("Failed to poll TAXII feed: {}", e))?
+}
+
+fn misp_feeds_path(app: &AppHandle) -> Result<PathBuf, String> {
+    Ok(app
+        .path()
+        .app_data_dir()
+        .map_err(|e| format!("Failed to locate app data directory: {}", e))?
+        .join(MISP_FEEDS_FILE))
+}
+
+#[tauri::command]
+async fn list_misp_feeds(app: AppHandle) -> Result<Vec<MispFeed>, String> {
+    misp::load_feeds(&misp_feeds_path(&app)?)
+}
+
+/// Adds a feed, or replaces the one with the same name.
+#[tauri::command]
+async fn save_misp_feed(feed: MispFeed, app: AppHandle) -> Result<Vec<MispFeed>, String> {
+    feed.validate()?;
+    let path = misp_feeds_path(&app)?;
+    let mut feeds = misp::load_feeds(&path)?;
+    match feeds.iter_mut().find(|f| f.name == feed.name) {
+        Some(existing) => *existing = feed,
+        None => feeds.push(feed),
+    }
+    misp::save_feeds(&path, &feeds)?;
+    Ok(feeds)
+}
+
+#[tauri::command]
+async fn remove_misp_feed(name: String, app: AppHandle) -> Result<Vec<MispFeed>, String> {
+    let path = misp_feeds_path(&app)?;
+    let mut feeds = misp::load_feeds(&path)?;
+    feeds.retain(|f| f.name != name);
+    misp::save_feeds(&path, &feeds)?;
+    Ok(feeds)
+}
+
+/// Imports the events of a feed that changed since its last poll.
+#[tauri::command]
+async fn poll_misp_feed(
+    name: String,
+    app: AppHandle,
+    store: State<'_, Arc<IocStore>>,
+    engine: State<'_, Arc<ScanEngine>>,
+) -> Result<ImportSummary, String> {
+    let path = misp_feeds_path(&app)?;
+    let store = store.inner().clone();
+    let engine = engine.inner().clone();
+    tokio::task::spawn_blocking(move || {
+        let mut feeds = misp::load_feeds(&path)?;
+        let feed = feeds
+            .iter_mut()
+            .find(|f| f.name == name)
+            .ok_or_else(|| format!("No MISP feed named {}", name))?;
+        let summary = misp::poll_feed(feed, &store)?;
+        engine.set_iocs(store.index().map_err(|e| format!("Failed to load indicators: {}", e))?);
+        misp::save_feeds(&path, &feeds)?;
+        Ok(summary)
+    })
+    .await
+    .map_err(|e| format!("Failed to poll MISP feed: {}", e))?
+}
+
+fn update_config_path(app: &AppHandle) -> Result<PathBuf, String> {
+    Ok(app
+        .path()
+        .app_data_dir()
+        .map_err(|e| format!("Failed to locate app data directory: {}", e))?
+        .join(UPDATE_CONFIG_FILE))
+}
+
+#[tauri::command]
+async fn get_update_config(app: AppHandle) -> Result<UpdateConfig, String> {
+    UpdateConfig::load(&update_config_path(&app)?)
+}
+
+#[tauri::command]
+async fn set_update_config(config: UpdateConfig, app: AppHandle) -> Result<(), String> {
+    config.validate()?;
+    config.save(&update_config_path(&app)?)
+}
+
+/// Reports the version the update source publishes without installing it.
+#[tauri::command]
+async fn check_for_updates(app: AppHandle, engine: State<'_, Arc<ScanEngine>>) -> Result<UpdateCheck, String> {
+    let config = UpdateConfig::load(&update_config_path(&app)?)?;
+    let engine = engine.inner().clone();
+    tokio::task::spawn_blocking(move || update::check(&config, engine.signature_dir()))
+        .await
+        .map_err(|e| format!("Failed to check for updates: {}", e))?
+}
+
+#[tauri::command]
+async fn update_signatures(app: AppHandle, engine: State<'_, Arc<ScanEngine>>) -> Result<UpdateReport, String> {
+    let config = UpdateConfig::load(&update_config_path(&app)?)?;
+    let engine = engine.inner().clone();
+    tokio::task::spawn_blocking(move || update::update(&config, &engine))
+        .await
+        .map_err(|e| format!("Failed to update signatures: {}", e))?
+}
+
+/// Version and age of the installed signature database, for the status bar.
+#[tauri::command]
+async fn get_database_status(engine: State<'_, Arc<ScanEngine>>) -> Result<DatabaseStatus, String> {
+    Ok(update::status(engine.signature_dir()))
 }
 
 #[tauri::command]
@@ -97,48 +721,11 @@ async fn show_notification(title: String, body: String) -> Result<(), String> {
 }
 
 // Helper functions
-fn get_file_info(path: &PathBuf) -> Result<FileInfo, std::io::Error> {
-    let metadata = std::fs::metadata(path)?;
-    let name = path.file_name()
-        .and_then(|n| n.to_str())
-        .unwrap_or("Unknown")
-        .to_string();
-    
-    let extension = path.extension()
-        .and_then(|ext| ext.to_str())
-        .unwrap_or("")
-        .to_string();
-    
-    Ok(FileInfo {
-        name,
-        path: path.to_string_lossy().to_string(),
-        size: metadata.len(),
-        extension,
-    })
-}
-
-fn generate_mock_scan_result(file_info: FileInfo) -> ScanResult {
-    use std::time::{SystemTime, UNIX_EPOCH};
-    
-    // Simulate threat detection (20% chance of threat, 10% suspicious)
-    let rand_val: f32 = (SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_nanos() % 100) as f32 / 100.0;
-    
-    let (status, threats) = if rand_val < 0.2 {
-        ("threat".to_string(), vec!["Trojan.Generic.KD".to_string(), "PUP.Optional.Bundle".to_string()])
-    } else if rand_val < 0.3 {
-        ("suspicious".to_string(), vec!["Potentially Unwanted Program".to_string()])
-    } else {
-        ("clean".to_string(), vec![])
-    };
-    
-    ScanResult {
-        id: Uuid::new_v4().to_string(),
-        file_info,
-        status,
-        threats,
-        scan_time: chrono::Utc::now().format("%Y-%m-%d %H:%M:%S UTC").to_string(),
-        hash: format!("sha256:{}", Uuid::new_v4().to_string().replace("-", "")),
-    }
+async fn hash_file(path: PathBuf) -> Result<FileDigests, std::io::Error> {
+    // Hashing is blocking I/O, keep it off the async runtime threads
+    tokio::task::spawn_blocking(move || hashing::digest_file(&path))
+        .await
+        .map_err(std::io::Error::other)?
 }
 
 fn main() {
@@ -150,8 +737,54 @@ fn main() {
         .plugin(tauri_plugin_window::init())
         .invoke_handler(tauri::generate_handler![
             scan_files,
+            scan_paths,
+            start_scan,
+            cancel_scan,
+            pause_scan,
+            resume_scan,
+            get_scan_session,
+            reload_signatures,
+            import_clamav_database,
+            load_yara_rules,
+            validate_yara_rules,
+            get_scoring_config,
+            set_scoring_config,
+            quarantine_file,
+            list_quarantine,
+            restore_file,
+            purge_quarantine,
+            start_realtime_protection,
+            stop_realtime_protection,
+            get_realtime_status,
+            add_watch_path,
+            remove_watch_path,
+            scan_url,
             get_file_hash,
+            compare_files,
+            add_similarity_sample,
+            analyze_pe,
+            extract_macros,
             save_scan_results,
+            list_scan_history,
+            get_history_session,
+            delete_history_sessions,
+            import_iocs,
+            search_iocs,
+            expire_iocs,
+            export_iocs,
+            list_taxii_feeds,
+            save_taxii_feed,
+            remove_taxii_feed,
+            poll_taxii_feed,
+            list_misp_feeds,
+            save_misp_feed,
+            remove_misp_feed,
+            poll_misp_feed,
+            get_update_config,
+            set_update_config,
+            check_for_updates,
+            update_signatures,
+            get_database_status,
             get_system_info,
             show_notification
         ])
@@ -175,6 +808,39 @@ fn main() {
             // Set window properties
             window.set_title("Varenizer - Advanced File Security & Malware Detection").unwrap();
             
+            // Load the signature database and YARA rules from the app data directory
+            let data_dir = app.path().app_data_dir()?;
+            let signature_dir = data_dir.join("signatures");
+            let rules_dir = data_dir.join("rules");
+            std::fs::create_dir_all(&signature_dir)?;
+            std::fs::create_dir_all(&rules_dir)?;
+            let engine = ScanEngine::new(signature_dir, rules_dir);
+            let scoring_path = data_dir.join(SCORING_CONFIG_FILE);
+            if scoring_path.exists() {
+                match ScoringConfig::load(&scoring_path) {
+                    Ok(config) => engine.set_scoring(config),
+                    Err(e) => eprintln!("Scoring config error: {}", e),
+                }
+            }
+            let summary = engine.reload_signatures();
+            for error in &summary.errors {
+                eprintln!("Signature load error: {}", error);
+            }
+            let report = engine.load_rules(None);
+            for error in &report.errors {
+                eprintln!("Rule load error: {}:{}: {}", error.file, error.line, error.message);
+            }
+            let iocs = IocStore::open(&data_dir.join("iocs.db"))?;
+            engine.set_iocs(iocs.index()?);
+            app.manage(Arc::new(engine));
+            app.manage(Arc::new(ScanManager::new()));
+            
+            let history = HistoryStore::open(&data_dir.join("history.db"))?;
+            app.manage(Arc::new(history));
+            app.manage(Arc::new(iocs));
+            app.manage(Arc::new(QuarantineVault::new(data_dir.join("quarantine"))));
+            app.manage(Arc::new(RealtimeProtection::new()));
+            
             Ok(())
         })
         .run(tauri::generate_context!())
@@ -0,0 +1,460 @@
+//! MISP event and feed import for the IOC store.
+//!
+//! Only attributes flagged `to_ids` are imported, since that is how MISP
+//! marks what is fit for detection; context attributes are left out. Every
+//! indicator remembers the events it was published in so matches can name
+//! them.
+//!
+//! Feeds are configured in `misp.json` in the app data directory and read
+//! over HTTP or from a local directory. A feed's `manifest.json` lists its
+//! events with their last change, and a poll only fetches the events that
+//! changed since the previous one.
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+use std::time::Duration;
+
+use crate::ioc::{ImportSummary, IocKind, IocStore, NewIndicator};
+
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+/// The MISP event an indicator was published in.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct MispEventRef {
+    /// The event's id on the MISP instance, or its UUID when exported
+    /// without one, as feeds are.
+    pub id: String,
+    pub uuid: String,
+    pub info: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct MispEvent {
+    pub reference: MispEventRef,
+    pub indicators: Vec<NewIndicator>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MispFeed {
+    /// Identifies the feed, and is the source of the indicators it imports.
+    pub name: String,
+    /// Base URL of the feed, or a local directory holding its `manifest.json`.
+    pub url: String,
+    /// Sent as the `Authorization` header, for feeds served by a MISP instance.
+    #[serde(default)]
+    pub auth_key: Option<String>,
+    /// Manifest timestamp of every event imported so far, by event UUID.
+    #[serde(default)]
+    pub events: BTreeMap<String, i64>,
+}
+
+impl MispFeed {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.name.trim().is_empty() {
+            return Err("Feed name must not be empty".to_string());
+        }
+        if self.url.trim().is_empty() {
+            return Err("Feed URL must not be empty".to_string());
+        }
+        Ok(())
+    }
+
+    fn fetch(&self, agent: &ureq::Agent, file: &str) -> Result<Value, String> {
+        if self.url.starts_with("http://") || self.url.starts_with("https://") {
+            let url = format!("{}/{}", self.url.trim_end_matches('/'), file);
+            let mut request = agent.get(&url).set("Accept", "application/json");
+            if let Some(key) = &self.auth_key {
+                request = request.set("Authorization", key);
+            }
+            let response = request.call().map_err(|e| format!("MISP feed request failed: {}", e))?;
+            response.into_json().map_err(|e| format!("Invalid JSON from {}: {}", url, e))
+        } else {
+            let path = Path::new(&self.url).join(file);
+            let text = fs::read_to_string(&path).map_err(|e| format!("Cannot read {}: {}", path.display(), e))?;
+            serde_json::from_str(&text).map_err(|e| format!("Invalid JSON in {}: {}", path.display(), e))
+        }
+    }
+}
+
+/// Configured feeds; none when the file doesn't exist yet.
+pub fn load_feeds(path: &Path) -> Result<Vec<MispFeed>, String> {
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+    let text = fs::read_to_string(path).map_err(|e| format!("Cannot read {}: {}", path.display(), e))?;
+    serde_json::from_str(&text).map_err(|e| format!("Invalid MISP feed config {}: {}", path.display(), e))
+}
+
+pub fn save_feeds(path: &Path, feeds: &[MispFeed]) -> Result<(), String> {
+    let text = serde_json::to_string_pretty(feeds).map_err(|e| format!("Cannot encode MISP feeds: {}", e))?;
+    fs::write(path, text).map_err(|e| format!("Cannot write {}: {}", path.display(), e))
+}
+
+/// Imports the events whose manifest timestamp changed since the last poll
+/// and drops the indicators of events no longer in the manifest. Events that
+/// fail to download are reported and retried on the next poll.
+pub fn poll_feed(feed: &mut MispFeed, store: &IocStore) -> Result<ImportSummary, String> {
+    feed.validate()?;
+    let agent = ureq::AgentBuilder::new().timeout(TIMEOUT).build();
+    let manifest = feed.fetch(&agent, "manifest.json")?;
+    let manifest = manifest.as_object().ok_or_else(|| "Feed manifest is not a JSON object".to_string())?;
+    let timestamps: BTreeMap<&String, i64> = manifest
+        .iter()
+        .map(|(uuid, entry)| (uuid, entry.get("timestamp").and_then(as_i64).unwrap_or(0)))
+        .collect();
+
+    let mut summary = ImportSummary::default();
+    let removed: Vec<MispEvent> = feed
+        .events
+        .keys()
+        .filter(|uuid| !timestamps.contains_key(uuid))
+        .map(|uuid| MispEvent {
+            reference: MispEventRef { uuid: uuid.clone(), ..MispEventRef::default() },
+            indicators: Vec::new(),
+        })
+        .collect();
+    if !removed.is_empty() {
+        store.import_misp(removed, &feed.name).map_err(|e| e.to_string())?;
+        feed.events.retain(|uuid, _| timestamps.contains_key(uuid));
+    }
+    for (uuid, timestamp) in timestamps {
+        if feed.events.get(uuid).is_some_and(|seen| *seen >= timestamp) {
+            continue;
+        }
+        // Manifest keys become file names
+        if uuid.is_empty() || !uuid.bytes().all(|c| c.is_ascii_hexdigit() || c == b'-') {
+            summary.error(format!("Invalid event UUID in manifest: {}", uuid));
+            continue;
+        }
+        let fetched = feed
+            .fetch(&agent, &format!("{}.json", uuid))
+            .and_then(|value| events(&value).ok_or_else(|| "not a MISP event".to_string()));
+        match fetched {
+            Ok(events) => {
+                summary.merge(store.import_misp(events, &feed.name).map_err(|e| e.to_string())?);
+                feed.events.insert(uuid.clone(), timestamp);
+            }
+            Err(e) => summary.error(format!("Event {}: {}", uuid, e)),
+        }
+    }
+    Ok(summary)
+}
+
+/// The events in MISP JSON: one `{"Event": …}`, a list of them, or a REST
+/// search response `{"response": […]}`. `None` when `value` is something
+/// else.
+pub fn events(value: &Value) -> Option<Vec<MispEvent>> {
+    let items = match value {
+        Value::Array(items) => items.as_slice(),
+        Value::Object(map) if map.contains_key("Event") => std::slice::from_ref(value),
+        Value::Object(map) => map.get("response")?.as_array()?.as_slice(),
+        _ => return None,
+    };
+    let events: Vec<&Value> = items.iter().filter_map(|item| item.get("Event")).collect();
+    if events.is_empty() || events.len() != items.len() {
+        return None;
+    }
+    Some(events.into_iter().map(event).collect())
+}
+
+fn event(event: &Value) -> MispEvent {
+    let uuid = text(event, "uuid").unwrap_or_default();
+    let reference = MispEventRef {
+        id: text(event, "id").unwrap_or_else(|| uuid.clone()),
+        uuid,
+        info: text(event, "info").unwrap_or_default(),
+    };
+    let confidence = match text(event, "threat_level_id").as_deref() {
+        Some("1") => 90,
+        Some("2") => 75,
+        _ => 50,
+    };
+    let date = text(event, "date");
+    let event_tags = tags(event);
+
+    let objects = event.get("Object").and_then(Value::as_array).map_or(&[][..], Vec::as_slice);
+    let attributes = std::iter::once(event)
+        .chain(objects)
+        .filter_map(|o| o.get("Attribute").and_then(Value::as_array))
+        .flatten();
+    let mut indicators = Vec::new();
+    for attribute in attributes {
+        let flag = |field: &str| match attribute.get(field) {
+            Some(Value::Bool(flag)) => *flag,
+            Some(other) => text_value(other).is_some_and(|t| t == "1" || t == "true"),
+            None => false,
+        };
+        if !flag("to_ids") || flag("deleted") {
+            continue;
+        }
+        let (Some(kind), Some(value)) = (text(attribute, "type"), text(attribute, "value")) else {
+            continue;
+        };
+        let mut attribute_tags = event_tags.clone();
+        attribute_tags.extend(tags(attribute));
+        for (kind, value) in observables(&kind, &value) {
+            indicators.push(NewIndicator {
+                kind: Some(kind),
+                value: value.to_string(),
+                confidence: Some(confidence),
+                first_seen: text(attribute, "first_seen").or_else(|| date.clone()),
+                last_seen: text(attribute, "last_seen").or_else(|| text(attribute, "timestamp")),
+                tags: attribute_tags.clone(),
+                description: text(attribute, "comment")
+                    .filter(|c| !c.is_empty())
+                    .or_else(|| text(attribute, "category")),
+                external_id: text(attribute, "uuid"),
+                event: Some(reference.clone()),
+                ..NewIndicator::default()
+            });
+        }
+    }
+    MispEvent { reference, indicators }
+}
+
+/// The hashes, domains, IPs and URLs in an attribute. Composite types such
+/// as `filename|sha256` or `ip-dst|port` hold the indicator on one side.
+fn observables<'a>(kind: &str, value: &'a str) -> Vec<(IocKind, &'a str)> {
+    let (left, right) = value.split_once('|').unwrap_or((value, ""));
+    match kind {
+        "md5" | "sha1" | "sha256" | "sha512" => vec![(IocKind::Hash, value)],
+        "filename|md5" | "filename|sha1" | "filename|sha256" | "filename|sha512" => vec![(IocKind::Hash, right)],
+        "domain" | "hostname" => vec![(IocKind::Domain, value)],
+        "domain|ip" => vec![(IocKind::Domain, left), (IocKind::Ip, right)],
+        "hostname|port" => vec![(IocKind::Domain, left)],
+        "ip-src" | "ip-dst" => vec![(IocKind::Ip, value)],
+        "ip-src|port" | "ip-dst|port" => vec![(IocKind::Ip, left)],
+        "url" => vec![(IocKind::Url, value)],
+        _ => Vec::new(),
+    }
+}
+
+fn tags(object: &Value) -> Vec<String> {
+    object
+        .get("Tag")
+        .and_then(Value::as_array)
+        .map(|tags| tags.iter().filter_map(|t| text(t, "name")).collect())
+        .unwrap_or_default()
+}
+
+/// MISP writes numbers as strings in some exports and as numbers in others.
+fn text(object: &Value, field: &str) -> Option<String> {
+    object.get(field).and_then(text_value)
+}
+
+fn text_value(value: &Value) -> Option<String> {
+    match value {
+        Value::String(s) => Some(s.clone()),
+        Value::Number(n) => Some(n.to_string()),
+        _ => None,
+    }
+}
+
+fn as_i64(value: &Value) -> Option<i64> {
+    value.as_i64().or_else(|| value.as_str()?.parse().ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ioc::{Indicator, IocQuery};
+    use serde_json::json;
+    use std::path::PathBuf;
+
+    const A: &str = "aaaaaaaa-0000-4000-8000-000000000001";
+    const B: &str = "bbbbbbbb-0000-4000-8000-000000000002";
+    const C: &str = "cccccccc-0000-4000-8000-000000000003";
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("varenizer-misp-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn event_json(uuid: &str, info: &str, domains: &[&str]) -> Value {
+        let attributes: Vec<Value> =
+            domains.iter().map(|d| json!({"type": "domain", "value": d, "to_ids": true})).collect();
+        json!({"Event": {"uuid": uuid, "info": info, "threat_level_id": "2", "Attribute": attributes}})
+    }
+
+    /// Writes a feed directory: the manifest and the given event files.
+    fn publish(dir: &Path, manifest: &[(&str, i64)], events: &[(&str, &str, &[&str])]) {
+        let manifest: serde_json::Map<String, Value> =
+            manifest.iter().map(|(uuid, timestamp)| (uuid.to_string(), json!({"timestamp": timestamp}))).collect();
+        fs::write(dir.join("manifest.json"), Value::Object(manifest).to_string()).unwrap();
+        for (uuid, info, domains) in events {
+            fs::write(dir.join(format!("{}.json", uuid)), event_json(uuid, info, domains).to_string()).unwrap();
+        }
+    }
+
+    fn feed(dir: &Path) -> MispFeed {
+        MispFeed { name: "partner".into(), url: dir.display().to_string(), auth_key: None, events: BTreeMap::new() }
+    }
+
+    fn stored(store: &IocStore) -> Vec<Indicator> {
+        let mut indicators = store.search(&IocQuery::default()).unwrap().indicators;
+        indicators.sort_by(|a, b| a.value.cmp(&b.value));
+        indicators
+    }
+
+    fn memberships(store: &IocStore) -> Vec<(String, Vec<String>)> {
+        stored(store).into_iter().map(|i| (i.value, i.events.into_iter().map(|e| e.info).collect())).collect()
+    }
+
+    fn listing(entries: &[(&str, &[&str])]) -> Vec<(String, Vec<String>)> {
+        entries
+            .iter()
+            .map(|(value, events)| (value.to_string(), events.iter().map(|e| e.to_string()).collect()))
+            .collect()
+    }
+
+    #[test]
+    fn reads_attributes_fit_for_detection() {
+        let value = json!({"response": [{"Event": {
+            "id": "42",
+            "uuid": A,
+            "info": "Phishing wave",
+            "threat_level_id": 1,
+            "date": "2024-03-01",
+            "Tag": [{"name": "tlp:green"}],
+            "Attribute": [
+                {"type": "filename|sha256",
+                 "value": "a.exe|E3B0C44298FC1C149AFBF4C8996FB92427AE41E4649B934CA495991B7852B855",
+                 "to_ids": "1", "comment": "Dropper", "Tag": [{"name": "stage:1"}]},
+                {"type": "domain|ip", "value": "evil.example|203.0.113.9", "to_ids": true,
+                 "category": "Network activity"},
+                {"type": "url", "value": "http://evil.example/x", "to_ids": false},
+                {"type": "ip-dst", "value": "198.51.100.1", "to_ids": true, "deleted": true},
+                {"type": "email-src", "value": "a@evil.example", "to_ids": true},
+            ],
+            "Object": [{"Attribute": [{"type": "hostname|port", "value": "c2.evil.example|443", "to_ids": true}]}],
+        }}]});
+        let events = events(&value).unwrap();
+        assert_eq!(events.len(), 1);
+        let event = &events[0];
+        assert_eq!(event.reference, MispEventRef { id: "42".into(), uuid: A.into(), info: "Phishing wave".into() });
+
+        let indicators: Vec<(Option<IocKind>, &str)> =
+            event.indicators.iter().map(|i| (i.kind, i.value.as_str())).collect();
+        assert_eq!(
+            indicators,
+            [
+                (Some(IocKind::Hash), "E3B0C44298FC1C149AFBF4C8996FB92427AE41E4649B934CA495991B7852B855"),
+                (Some(IocKind::Domain), "evil.example"),
+                (Some(IocKind::Ip), "203.0.113.9"),
+                (Some(IocKind::Domain), "c2.evil.example"),
+            ]
+        );
+        let hash = &event.indicators[0];
+        assert_eq!(hash.confidence, Some(90));
+        assert_eq!(hash.tags, ["tlp:green", "stage:1"]);
+        assert_eq!(hash.description.as_deref(), Some("Dropper"));
+        assert_eq!(hash.first_seen.as_deref(), Some("2024-03-01"));
+        assert_eq!(event.indicators[1].description.as_deref(), Some("Network activity"));
+
+        // Feeds leave out the id, the UUID stands in for it
+        let event = &super::events(&event_json(B, "Feed event", &["b.example"])).unwrap()[0];
+        assert_eq!(event.reference.id, B);
+        assert_eq!(event.indicators[0].confidence, Some(75));
+
+        assert!(super::events(&json!([{"Event": {}}, {"other": 1}])).is_none());
+        assert!(super::events(&json!({"type": "bundle"})).is_none());
+        assert!(super::events(&json!([])).is_none());
+    }
+
+    #[test]
+    fn polls_only_changed_events() {
+        let dir = temp_dir("poll");
+        let store = IocStore::open(&dir.join("ioc.db")).unwrap();
+        let feed_dir = dir.join("feed");
+        fs::create_dir_all(&feed_dir).unwrap();
+        let mut feed = feed(&feed_dir);
+
+        publish(&feed_dir, &[(A, 100), (B, 100)], &[(A, "Event A", &["a.example"]), (B, "Event B", &["b.example"])]);
+        let summary = poll_feed(&mut feed, &store).unwrap();
+        assert_eq!((summary.added, summary.updated, summary.skipped), (2, 0, 0));
+        assert_eq!(feed.events, BTreeMap::from([(A.to_string(), 100), (B.to_string(), 100)]));
+
+        // Unchanged timestamps aren't fetched again, not even when the file is gone
+        fs::remove_file(feed_dir.join(format!("{}.json", A))).unwrap();
+        let summary = poll_feed(&mut feed, &store).unwrap();
+        assert_eq!((summary.added, summary.updated, summary.skipped), (0, 0, 0));
+
+        // A newer timestamp refetches the event, a failed download is retried
+        publish(&feed_dir, &[(A, 100), (B, 200), (C, 100)], &[(B, "Event B", &["b.example", "b2.example"])]);
+        let summary = poll_feed(&mut feed, &store).unwrap();
+        assert_eq!((summary.added, summary.updated, summary.skipped), (1, 1, 1));
+        assert!(summary.errors[0].starts_with(&format!("Event {}: Cannot read", C)));
+        assert_eq!(feed.events.get(B), Some(&200));
+        assert!(!feed.events.contains_key(C));
+
+        publish(&feed_dir, &[(A, 100), (B, 200), (C, 100)], &[(C, "Event C", &["c.example"])]);
+        let summary = poll_feed(&mut feed, &store).unwrap();
+        assert_eq!((summary.added, summary.skipped), (1, 0));
+        assert_eq!(feed.events.get(C), Some(&100));
+
+        // Events dropped from the manifest take their indicators with them
+        publish(&feed_dir, &[(B, 200), (C, 100)], &[]);
+        poll_feed(&mut feed, &store).unwrap();
+        assert!(!feed.events.contains_key(A));
+        let values: Vec<String> = stored(&store).into_iter().map(|i| i.value).collect();
+        assert_eq!(values, ["b.example", "b2.example", "c.example"]);
+
+        publish(&feed_dir, &[("../../etc/passwd", 1)], &[]);
+        let summary = poll_feed(&mut feed, &store).unwrap();
+        assert_eq!(summary.errors, ["Invalid event UUID in manifest: ../../etc/passwd"]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn indicators_shared_by_events_stay_while_one_lists_them() {
+        let dir = temp_dir("shared");
+        let store = IocStore::open(&dir.join("ioc.db")).unwrap();
+        let feed_dir = dir.join("feed");
+        fs::create_dir_all(&feed_dir).unwrap();
+        let mut feed = feed(&feed_dir);
+
+        publish(
+            &feed_dir,
+            &[(A, 1), (B, 1)],
+            &[(A, "Event A", &["a.example", "shared.example"]), (B, "Event B", &["b.example", "shared.example"])],
+        );
+        let summary = poll_feed(&mut feed, &store).unwrap();
+        assert_eq!((summary.added, summary.updated), (3, 1));
+        assert_eq!(
+            memberships(&store),
+            listing(&[
+                ("a.example", &["Event A"]),
+                ("b.example", &["Event B"]),
+                ("shared.example", &["Event A", "Event B"]),
+            ])
+        );
+
+        // B stops listing the shared domain, A still does
+        publish(&feed_dir, &[(A, 1), (B, 2)], &[(B, "Event B", &["b.example"])]);
+        poll_feed(&mut feed, &store).unwrap();
+        assert_eq!(
+            memberships(&store),
+            listing(&[("a.example", &["Event A"]), ("b.example", &["Event B"]), ("shared.example", &["Event A"])])
+        );
+
+        // Once no event lists it, it goes
+        publish(&feed_dir, &[(B, 2)], &[]);
+        poll_feed(&mut feed, &store).unwrap();
+        assert_eq!(memberships(&store), listing(&[("b.example", &["Event B"])]));
+
+        // Matches name every event
+        publish(&feed_dir, &[(A, 3), (B, 3)], &[(A, "Event A", &["b.example"]), (B, "Event B", &["b.example"])]);
+        poll_feed(&mut feed, &store).unwrap();
+        let index = store.index().unwrap();
+        let detections = crate::ioc::detections(&index.matches(&Default::default(), &["http://b.example/".into()]));
+        let description = detections[0].description.as_deref().unwrap();
+        let events = format!("(MISP event {}: Event A; MISP event {}: Event B)", A, B);
+        assert!(description.ends_with(&events), "{}", description);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
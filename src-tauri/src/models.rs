@@ -0,0 +1,128 @@
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::email::EmailReport;
+use crate::hashing::FileDigests;
+use crate::heuristics::{EntropyProfile, HeuristicScore};
+use crate::ioc::IocHit;
+use crate::script::DecodedLayer;
+use crate::signatures::Detection;
+use crate::walker::ScanError;
+use crate::yara::RuleMatch;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FileInfo {
+    pub name: String,
+    pub path: String,
+    pub size: u64,
+    pub extension: String,
+    /// Type identified from the content, see `filetype::identify`.
+    #[serde(default)]
+    pub detected_type: String,
+    #[serde(default)]
+    pub mime_type: String,
+    #[serde(default)]
+    pub encoding: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ScanResult {
+    pub id: String,
+    pub file_info: FileInfo,
+    pub status: String, // "clean", "threat", "suspicious", "encrypted"
+    pub threats: Vec<String>,
+    pub scan_time: String,
+    pub hash: String,
+    pub hashes: FileDigests,
+    pub rule_matches: Vec<RuleMatch>,
+    /// Detections in this file's own content, including heuristic findings.
+    #[serde(default)]
+    pub detections: Vec<Detection>,
+    /// Archive members, at every nesting level, with paths like
+    /// `outer.zip!/inner.tar!/evil.sh`.
+    #[serde(default)]
+    pub members: Vec<ScanResult>,
+    /// Parts of the file that weren't scanned, and why.
+    #[serde(default)]
+    pub warnings: Vec<String>,
+    /// Links found in the file's content, such as PDF `/URI` actions.
+    #[serde(default)]
+    pub uris: Vec<String>,
+    /// What the script deobfuscator decoded from this file.
+    #[serde(default)]
+    pub decoded_layers: Vec<DecodedLayer>,
+    /// Headers, links and attachments, for `.eml` and `.msg` files.
+    #[serde(default)]
+    pub email: Option<EmailReport>,
+    /// Weighted score of the suspicious findings, and what each added.
+    #[serde(default)]
+    pub heuristics: HeuristicScore,
+    #[serde(default)]
+    pub entropy: Option<EntropyProfile>,
+    /// Local IOC database listings that matched the file's hashes or links,
+    /// with their feed ids.
+    #[serde(default)]
+    pub ioc_matches: Vec<IocHit>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ScanSession {
+    pub id: String,
+    pub files: Vec<ScanResult>,
+    pub scan_type: String,
+    pub start_time: String,
+    pub end_time: Option<String>,
+    pub total_files: usize,
+    pub threats_found: usize,
+    pub suspicious_files: usize,
+    pub clean_files: usize,
+    #[serde(default)]
+    pub skipped_files: usize,
+    #[serde(default)]
+    pub errors: Vec<ScanError>,
+    #[serde(default = "default_session_status")]
+    pub status: String, // "running", "completed", "cancelled"
+}
+
+fn default_session_status() -> String {
+    "completed".to_string()
+}
+
+impl ScanSession {
+    pub fn new(scan_type: &str) -> Self {
+        ScanSession {
+            id: Uuid::new_v4().to_string(),
+            files: Vec::new(),
+            scan_type: scan_type.to_string(),
+            start_time: timestamp(),
+            end_time: None,
+            total_files: 0,
+            threats_found: 0,
+            suspicious_files: 0,
+            clean_files: 0,
+            skipped_files: 0,
+            errors: Vec::new(),
+            status: "running".to_string(),
+        }
+    }
+
+    pub fn record(&mut self, result: ScanResult) {
+        self.total_files += 1;
+        match result.status.as_str() {
+            "threat" => self.threats_found += 1,
+            "suspicious" => self.suspicious_files += 1,
+            // Nothing to vouch for, the content wasn't scanned
+            "encrypted" => self.skipped_files += 1,
+            _ => self.clean_files += 1,
+        }
+        self.files.push(result);
+    }
+
+    pub fn finish(&mut self) {
+        self.end_time = Some(timestamp());
+    }
+}
+
+pub fn timestamp() -> String {
+    chrono::Utc::now().format("%Y-%m-%d %H:%M:%S UTC").to_string()
+}
@@ -0,0 +1,497 @@
+//! VBA macro extraction and DDE detection for Office documents.
+//!
+//! Macros live in a VBA project: an OLE2 storage with a `VBA/dir` stream
+//! listing the modules and one stream per module holding compiled p-code
+//! followed by the compressed source. Legacy documents (`.doc`, `.xls`) are
+//! OLE2 files themselves; OOXML documents carry the project as
+//! `vbaProject.bin` inside the ZIP. The source is decompressed as described
+//! in MS-OVBA 2.4.1 and checked for auto-execute entry points and keywords
+//! that macro droppers rely on.
+//!
+//! DDE fields run commands without any macro, so they are looked for
+//! separately: field instructions in Word XML, `ddeLink`s in Excel external
+//! links, and `DDEAUTO` in the text of legacy Word documents.
+
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::io::{Cursor, Read};
+use std::path::PathBuf;
+use std::sync::OnceLock;
+
+use crate::filetype;
+use crate::signatures::Detection;
+
+/// Bound on a single decompressed part, documents are far smaller.
+const MAX_PART_SIZE: u64 = 64 * 1024 * 1024;
+const MAX_MODULES: usize = 512;
+/// Decompressed size of a full MS-OVBA chunk.
+const CHUNK_SIZE: usize = 4096;
+
+/// Procedures Office runs without the user starting them.
+const AUTO_EXEC: [&str; 20] = [
+    "AutoExec",
+    "AutoOpen",
+    "Auto_Open",
+    "AutoClose",
+    "Auto_Close",
+    "AutoNew",
+    "AutoExit",
+    "Document_Open",
+    "Document_Close",
+    "Document_New",
+    "DocumentOpen",
+    "DocumentBeforeClose",
+    "Document_ContentControlOnEnter",
+    "Workbook_Open",
+    "Workbook_Activate",
+    "Workbook_BeforeClose",
+    "Workbook_Close",
+    "Auto_Activate",
+    "Presentation_Open",
+    "InkPicture1_Painted",
+];
+
+/// Calls and objects used to download, drop and start payloads.
+const SUSPICIOUS_KEYWORDS: [&str; 24] = [
+    "Shell",
+    "WScript.Shell",
+    "Shell.Application",
+    "CreateObject",
+    "GetObject",
+    "URLDownloadToFile",
+    "MSXML2.XMLHTTP",
+    "Microsoft.XMLHTTP",
+    "WinHttp.WinHttpRequest",
+    "ADODB.Stream",
+    "SaveToFile",
+    "PowerShell",
+    "cmd.exe",
+    "Environ",
+    "CallByName",
+    "ExecuteExcel4Macro",
+    "MacroOptions",
+    "Lib",
+    "VirtualAlloc",
+    "RtlMoveMemory",
+    "CreateThread",
+    "Kill",
+    "FileCopy",
+    "Chr",
+];
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct VbaModule {
+    pub name: String,
+    /// Path of the module stream inside the compound file.
+    pub stream: String,
+    pub code: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OfficeAnalysis {
+    /// `ole2` or `ooxml`.
+    pub container: String,
+    pub has_macros: bool,
+    pub modules: Vec<VbaModule>,
+    pub auto_exec: Vec<String>,
+    pub suspicious_keywords: Vec<String>,
+    /// Instructions of DDE fields and links.
+    pub dde: Vec<String>,
+    pub findings: Vec<Detection>,
+}
+
+/// Analyzes an OLE2 or OOXML document.
+pub fn analyze(data: &[u8]) -> Result<OfficeAnalysis, String> {
+    let (container, modules, dde) = match filetype::identify(data).kind.as_str() {
+        "ole2" => {
+            let (modules, dde) = analyze_ole(data)?;
+            ("ole2", modules, dde)
+        }
+        kind if is_ooxml(kind) => {
+            let mut modules = Vec::new();
+            for project in zip_parts(data, |name| name.to_ascii_lowercase().ends_with("vbaproject.bin"))? {
+                modules.extend(analyze_ole(&project.1)?.0);
+            }
+            ("ooxml", modules, ooxml_dde(data)?)
+        }
+        _ => return Err("Not an OLE2 or OOXML document".to_string()),
+    };
+
+    let auto_exec = matching_keywords(auto_exec_pattern(), &modules);
+    let suspicious_keywords = matching_keywords(keyword_pattern(), &modules);
+    let mut analysis = OfficeAnalysis {
+        container: container.to_string(),
+        has_macros: !modules.is_empty(),
+        modules,
+        auto_exec,
+        suspicious_keywords,
+        dde,
+        findings: Vec::new(),
+    };
+    analysis.findings = findings(&analysis);
+    Ok(analysis)
+}
+
+/// Findings for DDE fields in an OOXML document. Its macros are reported
+/// when the embedded `vbaProject.bin` is scanned as an archive member.
+pub fn ooxml_findings(data: &[u8]) -> Vec<Detection> {
+    let dde = ooxml_dde(data).unwrap_or_default();
+    dde_finding(&dde).into_iter().collect()
+}
+
+pub fn is_ooxml(kind: &str) -> bool {
+    matches!(kind, "docx" | "docm" | "xlsx" | "xlsm" | "pptx" | "pptm" | "ooxml")
+}
+
+fn findings(analysis: &OfficeAnalysis) -> Vec<Detection> {
+    let mut findings = Vec::new();
+    if !analysis.auto_exec.is_empty() {
+        findings.push(Detection::heuristic(
+            "Heuristic.Office.Macro.AutoExec",
+            "office",
+            format!("Macro runs automatically through {}", analysis.auto_exec.join(", ")),
+        ));
+    }
+    if !analysis.suspicious_keywords.is_empty() {
+        findings.push(Detection::heuristic(
+            "Heuristic.Office.Macro.SuspiciousKeywords",
+            "office",
+            format!("Macro uses {}", analysis.suspicious_keywords.join(", ")),
+        ));
+    }
+    findings.extend(dde_finding(&analysis.dde));
+    findings
+}
+
+fn dde_finding(dde: &[String]) -> Option<Detection> {
+    let first = dde.first()?;
+    Some(Detection::heuristic("Heuristic.Office.DDE", "office", format!("DDE field: {}", first)))
+}
+
+fn auto_exec_pattern() -> &'static Regex {
+    static PATTERN: OnceLock<Regex> = OnceLock::new();
+    PATTERN.get_or_init(|| keyword_regex(&AUTO_EXEC))
+}
+
+fn keyword_pattern() -> &'static Regex {
+    static PATTERN: OnceLock<Regex> = OnceLock::new();
+    PATTERN.get_or_init(|| keyword_regex(&SUSPICIOUS_KEYWORDS))
+}
+
+fn keyword_regex(words: &[&str]) -> Regex {
+    let alternatives: Vec<String> = words.iter().map(|w| regex::escape(w)).collect();
+    Regex::new(&format!(r"(?i)\b({})\b", alternatives.join("|"))).expect("keyword pattern is valid")
+}
+
+/// Distinct keywords found in the source, in the spelling of the list.
+fn matching_keywords(pattern: &Regex, modules: &[VbaModule]) -> Vec<String> {
+    let mut found: Vec<String> = Vec::new();
+    for module in modules {
+        for m in pattern.find_iter(&module.code) {
+            let word = m.as_str();
+            let canonical = AUTO_EXEC
+                .iter()
+                .chain(SUSPICIOUS_KEYWORDS.iter())
+                .find(|k| k.eq_ignore_ascii_case(word))
+                .map_or(word, |k| *k);
+            if !found.iter().any(|f| f == canonical) {
+                found.push(canonical.to_string());
+            }
+        }
+    }
+    found
+}
+
+fn read_stream(file: &mut cfb::CompoundFile<Cursor<&[u8]>>, path: &std::path::Path) -> Result<Vec<u8>, String> {
+    let mut data = Vec::new();
+    file.open_stream(path)
+        .and_then(|stream| stream.take(MAX_PART_SIZE).read_to_end(&mut data))
+        .map_err(|e| format!("Cannot read {}: {}", path.display(), e))?;
+    Ok(data)
+}
+
+/// Extracts every VBA project in the compound file and DDE fields from the
+/// Word text stream.
+fn analyze_ole(data: &[u8]) -> Result<(Vec<VbaModule>, Vec<String>), String> {
+    let mut file = cfb::CompoundFile::open(Cursor::new(data)).map_err(|e| format!("Invalid OLE2 file: {}", e))?;
+
+    // Any storage holding `VBA/dir` is a project root; Excel nests it in
+    // `_VBA_PROJECT_CUR`, Word in `Macros`, vbaProject.bin at the top
+    let streams: Vec<PathBuf> = file.walk().filter(|e| e.is_stream()).map(|e| e.path().to_path_buf()).collect();
+    let vba_dirs: Vec<PathBuf> = streams
+        .iter()
+        .filter(|p| {
+            p.file_name().is_some_and(|n| n.eq_ignore_ascii_case("dir"))
+                && p.parent().and_then(|d| d.file_name()).is_some_and(|n| n.eq_ignore_ascii_case("VBA"))
+        })
+        .filter_map(|p| p.parent().map(|d| d.to_path_buf()))
+        .collect();
+
+    let mut modules = Vec::new();
+    for vba_dir in vba_dirs {
+        let dir = decompress(&read_stream(&mut file, &vba_dir.join("dir"))?)
+            .ok_or_else(|| format!("Corrupt VBA dir stream in {}", vba_dir.display()))?;
+        for entry in parse_dir(&dir) {
+            if modules.len() >= MAX_MODULES {
+                break;
+            }
+            let path = vba_dir.join(&entry.stream);
+            let Ok(stream) = read_stream(&mut file, &path) else { continue };
+            let Some(source) = stream.get(entry.offset as usize..).and_then(decompress) else { continue };
+            modules.push(VbaModule {
+                name: entry.name,
+                stream: path.to_string_lossy().to_string(),
+                code: decode_text(&source),
+            });
+        }
+    }
+
+    let mut dde = Vec::new();
+    if let Some(word) = streams.iter().find(|p| p.to_string_lossy().eq_ignore_ascii_case("/WordDocument")) {
+        dde = word_binary_dde(&read_stream(&mut file, word)?);
+    }
+    Ok((modules, dde))
+}
+
+struct DirModule {
+    name: String,
+    stream: String,
+    offset: u32,
+}
+
+/// Walks the records of a decompressed `dir` stream (MS-OVBA 2.3.4.2). All
+/// records are id/size/data except PROJECTVERSION, whose size field lies.
+fn parse_dir(dir: &[u8]) -> Vec<DirModule> {
+    let mut modules = Vec::new();
+    let mut current: Option<DirModule> = None;
+    let mut pos = 0;
+    while pos + 6 <= dir.len() {
+        let id = u16::from_le_bytes([dir[pos], dir[pos + 1]]);
+        let size = u32::from_le_bytes([dir[pos + 2], dir[pos + 3], dir[pos + 4], dir[pos + 5]]) as usize;
+        pos += 6;
+        if id == 0x0009 {
+            pos += 6;
+            continue;
+        }
+        let Some(body) = dir.get(pos..pos.saturating_add(size)) else { break };
+        pos += size;
+
+        match id {
+            // MODULENAME starts a module record
+            0x0019 => current = Some(DirModule { name: decode_text(body), stream: decode_text(body), offset: 0 }),
+            // MODULESTREAMNAME
+            0x001A => {
+                if let Some(module) = current.as_mut() {
+                    module.stream = decode_text(body);
+                }
+            }
+            // MODULEOFFSET
+            0x0031 if body.len() >= 4 => {
+                if let Some(module) = current.as_mut() {
+                    module.offset = u32::from_le_bytes([body[0], body[1], body[2], body[3]]);
+                }
+            }
+            // Module terminator
+            0x002B => modules.extend(current.take()),
+            _ => {}
+        }
+    }
+    modules
+}
+
+/// MS-OVBA 2.4.1 decompression. Returns `None` for data that isn't a
+/// compressed container. Every chunk expands to at most 4096 bytes; copy
+/// tokens reaching past that are cut off there.
+fn decompress(data: &[u8]) -> Option<Vec<u8>> {
+    if data.first() != Some(&0x01) {
+        return None;
+    }
+    let mut out = Vec::new();
+    let mut pos = 1;
+    while pos + 2 <= data.len() {
+        let header = u16::from_le_bytes([data[pos], data[pos + 1]]);
+        let chunk_end = (pos + 2 + (header & 0x0FFF) as usize + 1).min(data.len());
+        let compressed = header & 0x8000 != 0;
+        pos += 2;
+
+        if !compressed {
+            out.extend_from_slice(&data[pos..(pos + CHUNK_SIZE).min(data.len())]);
+            pos += CHUNK_SIZE;
+            continue;
+        }
+
+        let chunk_start = out.len();
+        'chunk: while pos < chunk_end {
+            let flags = data[pos];
+            pos += 1;
+            for bit in 0..8 {
+                let decompressed = out.len() - chunk_start;
+                if pos >= chunk_end || decompressed >= CHUNK_SIZE {
+                    break 'chunk;
+                }
+                if flags & (1 << bit) == 0 {
+                    out.push(data[pos]);
+                    pos += 1;
+                    continue;
+                }
+                if pos + 2 > chunk_end {
+                    return Some(out);
+                }
+                let token = u16::from_le_bytes([data[pos], data[pos + 1]]);
+                pos += 2;
+
+                // The split between offset and length bits grows with the
+                // amount already decompressed in this chunk
+                let bit_count = (usize::BITS - decompressed.saturating_sub(1).leading_zeros()).clamp(4, 12);
+                let length_mask = 0xFFFFu16 >> bit_count;
+                let offset = (token >> (16 - bit_count)) as usize + 1;
+                let length = ((token & length_mask) as usize + 3).min(CHUNK_SIZE - decompressed);
+                if offset > decompressed {
+                    return None;
+                }
+                for _ in 0..length {
+                    out.push(out[out.len() - offset]);
+                }
+            }
+        }
+        pos = chunk_end;
+    }
+    Some(out)
+}
+
+/// VBA source is stored in the project's ANSI code page; Windows-1252 is
+/// close enough to read it and keeps every byte visible.
+fn decode_text(bytes: &[u8]) -> String {
+    match std::str::from_utf8(bytes) {
+        Ok(text) => text.to_string(),
+        Err(_) => bytes.iter().map(|&b| b as char).collect(),
+    }
+}
+
+fn zip_parts(data: &[u8], wanted: impl Fn(&str) -> bool) -> Result<Vec<(String, Vec<u8>)>, String> {
+    let mut zip = zip::ZipArchive::new(Cursor::new(data)).map_err(|e| e.to_string())?;
+    let names: Vec<String> = zip.file_names().filter(|n| wanted(n)).map(str::to_string).collect();
+    let mut parts = Vec::new();
+    for name in names {
+        let Ok(entry) = zip.by_name(&name) else { continue };
+        let mut part = Vec::new();
+        if entry.take(MAX_PART_SIZE).read_to_end(&mut part).is_ok() {
+            parts.push((name, part));
+        }
+    }
+    Ok(parts)
+}
+
+fn field_pattern() -> &'static Regex {
+    static PATTERN: OnceLock<Regex> = OnceLock::new();
+    // Field code text may be split across runs, the XML between them is
+    // stripped before matching
+    PATTERN.get_or_init(|| Regex::new(r"(?i)\bDDE(?:AUTO)?\b[^<]{0,256}").expect("field pattern is valid"))
+}
+
+fn ooxml_dde(data: &[u8]) -> Result<Vec<String>, String> {
+    let parts = zip_parts(data, |name| {
+        let name = name.to_ascii_lowercase();
+        (name.starts_with("word/") && name.ends_with(".xml")) || name.starts_with("xl/externallinks/")
+    })?;
+
+    let mut dde = Vec::new();
+    for (name, part) in parts {
+        let xml = String::from_utf8_lossy(&part);
+        if name.to_ascii_lowercase().starts_with("xl/") {
+            // <ddeLink ddeService="cmd" ddeTopic="/c calc">
+            if let Some(start) = xml.find("<ddeLink") {
+                let end = xml[start..].find('>').map_or(xml.len(), |e| start + e + 1);
+                dde.push(xml[start..end].to_string());
+            }
+            continue;
+        }
+        let mut instructions = String::new();
+        for segment in xml.split("<w:instrText").skip(1) {
+            let text = segment.split_once('>').map_or("", |(_, rest)| rest);
+            instructions.push_str(text.split("</w:instrText>").next().unwrap_or(""));
+        }
+        for segment in xml.split("w:instr=\"").skip(1) {
+            instructions.push_str(segment.split('"').next().unwrap_or(""));
+            instructions.push(' ');
+        }
+        dde.extend(field_pattern().find_iter(&instructions).map(|m| m.as_str().trim().to_string()));
+    }
+    Ok(dde)
+}
+
+/// Field codes in a `.doc` sit in the text stream between 0x13 and 0x14
+/// markers, as 8-bit or UTF-16 text.
+fn word_binary_dde(stream: &[u8]) -> Vec<String> {
+    let narrow: String = stream.iter().map(|&b| if b == 0 { ' ' } else { b as char }).collect();
+    let wide: String = stream
+        .chunks_exact(2)
+        .map(|c| char::from_u32(u16::from_le_bytes([c[0], c[1]]) as u32).unwrap_or(' '))
+        .collect();
+    let mut dde = Vec::new();
+    for text in [narrow, wide] {
+        for field in text.split('\u{13}').skip(1) {
+            let instruction = field.split(['\u{14}', '\u{15}']).next().unwrap_or("");
+            dde.extend(field_pattern().find_iter(instruction).map(|m| m.as_str().trim().to_string()));
+        }
+    }
+    dde.dedup();
+    dde
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Compressed containers from MS-OVBA 3.2.
+    #[test]
+    fn decompresses_the_specification_examples() {
+        let no_compression = [
+            0x01, 0x19, 0xB0, 0x00, 0x61, 0x62, 0x63, 0x64, 0x65, 0x66, 0x67, 0x68, 0x00, 0x69, 0x6A, 0x6B, 0x6C, 0x6D,
+            0x6E, 0x6F, 0x70, 0x00, 0x71, 0x72, 0x73, 0x74, 0x75, 0x76, 0x2E,
+        ];
+        assert_eq!(decompress(&no_compression).unwrap(), b"abcdefghijklmnopqrstuv.");
+
+        let normal = [
+            0x01, 0x2F, 0xB0, 0x00, 0x23, 0x61, 0x61, 0x61, 0x62, 0x63, 0x64, 0x65, 0x82, 0x66, 0x00, 0x70, 0x61, 0x67,
+            0x68, 0x69, 0x6A, 0x01, 0x38, 0x08, 0x61, 0x6B, 0x6C, 0x00, 0x30, 0x6D, 0x6E, 0x6F, 0x70, 0x06, 0x71, 0x02,
+            0x70, 0x04, 0x10, 0x72, 0x73, 0x74, 0x75, 0x76, 0x10, 0x77, 0x78, 0x79, 0x7A, 0x00, 0x3C,
+        ];
+        assert_eq!(
+            decompress(&normal).unwrap(),
+            b"#aaabcdefaaaaghijaaaaaklaaamnopqaaaaaaaaaaaarstuvwxyzaaa"
+        );
+
+        let maximum = [0x01, 0x03, 0xB0, 0x02, 0x61, 0x45, 0x00];
+        assert_eq!(decompress(&maximum).unwrap(), [b'a'; 73]);
+    }
+
+    #[test]
+    fn uncompressed_chunks_are_copied() {
+        let mut data = vec![0x01, 0xFF, 0x3F];
+        data.extend((0..CHUNK_SIZE).map(|i| i as u8));
+        data.extend_from_slice(&[0x03, 0xB0, 0x00, b'x', b'y', b'z']);
+        let out = decompress(&data).unwrap();
+        assert_eq!(out.len(), CHUNK_SIZE + 3);
+        assert_eq!(&out[CHUNK_SIZE - 2..], [0xFE, 0xFF, b'x', b'y', b'z']);
+    }
+
+    #[test]
+    fn malformed_chunks_are_bounded() {
+        // Not a compressed container
+        assert_eq!(decompress(&[0x00, 0x01, 0xB0]), None);
+        // Copy from before the start of the chunk
+        assert_eq!(decompress(&[0x01, 0x03, 0xB0, 0x01, 0x00, 0x10]), None);
+
+        // A copy token running past the 4096 byte chunk is cut off there,
+        // and the next chunk still decodes
+        let out = decompress(&[0x01, 0x03, 0xB0, 0x02, b'a', 0xFF, 0x0F, 0x02, 0xB0, 0x00, b'b', b'c']).unwrap();
+        assert_eq!(out.len(), CHUNK_SIZE + 2);
+        assert!(out[..CHUNK_SIZE].iter().all(|&b| b == b'a'));
+        assert_eq!(&out[CHUNK_SIZE..], b"bc");
+
+        // Chunks claiming more bytes than remain
+        assert_eq!(decompress(&[0x01, 0xFF, 0xBF, 0x00, b'a', b'b']).unwrap(), b"ab");
+        assert_eq!(decompress(&[0x01, 0x05, 0xB0, 0x02, b'a', 0x00]).unwrap(), b"a");
+    }
+}
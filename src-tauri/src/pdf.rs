@@ -0,0 +1,808 @@
+//! PDF structure analysis.
+//!
+//! Objects are found by scanning for `N G obj` rather than by following the
+//! cross-reference table: malicious files routinely ship broken or lying
+//! xref tables, and readers repair them the same way. Only object streams
+//! and attachments are decoded (Flate, ASCIIHex, ASCII85, LZW, RunLength),
+//! all of them from one byte budget per document. Object streams are
+//! expanded, and `#xx` escapes in names are resolved before keywords are
+//! counted, so `/J#61vaScript` counts as `/JavaScript`.
+//!
+//! A [`Document`] is parsed once; [`analyze`] reports on it and
+//! [`Document::embedded_files`] hands its attachments to the archive code to
+//! be scanned as members. URIs end up on the scan result.
+
+use regex::bytes::Regex;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::io::Read;
+use std::sync::OnceLock;
+
+use crate::filetype;
+use crate::signatures::Detection;
+
+/// Bound on a single decoded stream.
+const MAX_STREAM_SIZE: u64 = 64 * 1024 * 1024;
+/// Decoded bytes across all streams of one document, intermediate filter
+/// output included.
+const MAX_DECODED_SIZE: u64 = 256 * 1024 * 1024;
+const MAX_OBJECTS: usize = 100_000;
+const MAX_URIS: usize = 1000;
+
+/// Dictionary keys worth reporting, counted after name normalization.
+const KEYWORDS: [&str; 12] = [
+    "/JavaScript",
+    "/JS",
+    "/OpenAction",
+    "/AA",
+    "/Launch",
+    "/EmbeddedFile",
+    "/URI",
+    "/SubmitForm",
+    "/GoToR",
+    "/RichMedia",
+    "/XFA",
+    "/AcroForm",
+];
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PdfEmbeddedFile {
+    pub name: String,
+    pub size: usize,
+    pub file_type: String,
+    pub executable: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PdfAnalysis {
+    pub version: String,
+    pub objects: usize,
+    pub streams: usize,
+    pub encrypted: bool,
+    /// Occurrences of each interesting key, e.g. `/JavaScript: 2`.
+    pub keywords: BTreeMap<String, usize>,
+    /// Keywords that were hidden with `#xx` escapes.
+    pub obfuscated_names: Vec<String>,
+    pub uris: Vec<String>,
+    pub embedded_files: Vec<PdfEmbeddedFile>,
+    pub findings: Vec<Detection>,
+}
+
+struct PdfObject {
+    number: u32,
+    /// Dictionary text with names normalized.
+    dict: Vec<u8>,
+    /// Decoded content, kept for attachments only.
+    stream: Option<Vec<u8>>,
+}
+
+pub struct Document {
+    version: String,
+    encrypted: bool,
+    objects: Vec<PdfObject>,
+    streams: usize,
+    obfuscated_names: Vec<String>,
+}
+
+impl Document {
+    /// Decoded attachments with their file names, for scanning as members.
+    pub fn embedded_files(&self) -> Vec<(String, &[u8])> {
+        let mut names: BTreeMap<u32, String> = BTreeMap::new();
+        for object in &self.objects {
+            let Some(ef) = find(&object.dict, b"/EF") else { continue };
+            let dict = &object.dict[ef..];
+            let Some(reference) = reference_after(dict, b"/F").or_else(|| reference_after(dict, b"/UF")) else {
+                continue;
+            };
+            let name = string_after(&object.dict, b"/UF").or_else(|| string_after(&object.dict, b"/F"));
+            if let Some(name) = name.filter(|n| !n.is_empty()) {
+                names.insert(reference, name);
+            }
+        }
+
+        self.objects
+            .iter()
+            .filter_map(|o| {
+                let data = o.stream.as_deref()?;
+                let name = names.get(&o.number).cloned().unwrap_or_else(|| format!("embedded-{}", o.number));
+                // Names come from the document, keep them from escaping the member path
+                Some((name.replace(['/', '\\'], "_"), data))
+            })
+            .collect()
+    }
+}
+
+pub fn analyze(document: &Document) -> PdfAnalysis {
+    let mut keywords = BTreeMap::new();
+    for object in &document.objects {
+        for keyword in KEYWORDS {
+            let count = count_name(&object.dict, keyword);
+            if count > 0 {
+                *keywords.entry(keyword.to_string()).or_insert(0) += count;
+            }
+        }
+    }
+
+    let mut uris = Vec::new();
+    for object in &document.objects {
+        for uri in extract_uris(&object.dict) {
+            if uris.len() < MAX_URIS && !uris.contains(&uri) {
+                uris.push(uri);
+            }
+        }
+    }
+
+    let embedded_files = document
+        .embedded_files()
+        .into_iter()
+        .map(|(name, data)| {
+            let file_type = filetype::identify(data);
+            PdfEmbeddedFile { name, size: data.len(), executable: file_type.is_executable(), file_type: file_type.kind }
+        })
+        .collect();
+
+    let mut analysis = PdfAnalysis {
+        version: document.version.clone(),
+        objects: document.objects.len(),
+        streams: document.streams,
+        encrypted: document.encrypted,
+        keywords,
+        obfuscated_names: document.obfuscated_names.clone(),
+        uris,
+        embedded_files,
+        findings: Vec::new(),
+    };
+    analysis.findings = findings(&analysis);
+    analysis
+}
+
+fn findings(analysis: &PdfAnalysis) -> Vec<Detection> {
+    let count = |key: &str| analysis.keywords.get(key).copied().unwrap_or(0);
+    let javascript = count("/JavaScript") + count("/JS");
+    let launch = count("/Launch");
+    let mut findings = Vec::new();
+
+    if javascript > 0 {
+        findings.push(Detection::heuristic(
+            "Heuristic.PDF.JavaScript",
+            "pdf",
+            format!("Document contains JavaScript ({} references)", javascript),
+        ));
+    }
+    if launch > 0 {
+        findings.push(Detection::heuristic("Heuristic.PDF.Launch", "pdf", "Document launches an external program"));
+    }
+    if count("/OpenAction") + count("/AA") > 0 && javascript + launch > 0 {
+        findings.push(Detection::heuristic(
+            "Heuristic.PDF.AutoAction",
+            "pdf",
+            "Document runs an action as soon as it is opened",
+        ));
+    }
+    if !analysis.obfuscated_names.is_empty() {
+        findings.push(Detection::heuristic(
+            "Heuristic.PDF.ObfuscatedNames",
+            "pdf",
+            format!("Names hidden with hex escapes: {}", analysis.obfuscated_names.join(", ")),
+        ));
+    }
+    if let Some(file) = analysis.embedded_files.iter().find(|f| f.executable) {
+        findings.push(Detection::heuristic(
+            "Heuristic.PDF.EmbeddedExecutable",
+            "pdf",
+            format!("Attachment {} is a {} executable", file.name, file.file_type),
+        ));
+    }
+    findings
+}
+
+fn object_pattern() -> &'static Regex {
+    static PATTERN: OnceLock<Regex> = OnceLock::new();
+    PATTERN.get_or_init(|| Regex::new(r"(\d+)\s+(\d+)\s+obj\b").expect("object pattern is valid"))
+}
+
+pub fn parse(data: &[u8]) -> Result<Document, String> {
+    if !data.starts_with(b"%PDF-") && !data[..data.len().min(1024)].windows(5).any(|w| w == b"%PDF-") {
+        return Err("Not a PDF file".to_string());
+    }
+    let version = data
+        .windows(5)
+        .position(|w| w == b"%PDF-")
+        .and_then(|p| data.get(p + 5..p + 8))
+        .map(|v| String::from_utf8_lossy(v).to_string())
+        .unwrap_or_default();
+
+    let starts: Vec<(usize, usize, u32)> = object_pattern()
+        .captures_iter(data)
+        .take(MAX_OBJECTS)
+        .filter_map(|c| {
+            let whole = c.get(0)?;
+            let number = std::str::from_utf8(&c[1]).ok()?.parse().ok()?;
+            Some((whole.start(), whole.end(), number))
+        })
+        .collect();
+
+    let mut document = Document {
+        version,
+        encrypted: trailer_has(data, b"/Encrypt"),
+        objects: Vec::new(),
+        streams: 0,
+        obfuscated_names: Vec::new(),
+    };
+    let mut budget = MAX_DECODED_SIZE;
+    for (i, &(_, body_start, number)) in starts.iter().enumerate() {
+        let next = starts.get(i + 1).map_or(data.len(), |s| s.0);
+        let body = &data[body_start..next];
+        let body = find(body, b"endobj").map_or(body, |end| &body[..end]);
+
+        let (raw_dict, raw_stream) = match find(body, b"stream") {
+            Some(s) if !body[..s].ends_with(b"end") => {
+                let mut start = s + b"stream".len();
+                if body[start..].starts_with(b"\r\n") {
+                    start += 2;
+                } else if body[start..].starts_with(b"\n") || body[start..].starts_with(b"\r") {
+                    start += 1;
+                }
+                let end = rfind(&body[start..], b"endstream").map_or(body.len(), |e| start + e);
+                (&body[..s], Some(trim_eol(&body[start..end])))
+            }
+            _ => (body, None),
+        };
+
+        let dict = normalize_names(raw_dict, &mut document.obfuscated_names);
+        let mut stream = None;
+        if let Some(raw) = raw_stream {
+            document.streams += 1;
+            if contains_name(&dict, b"/ObjStm") {
+                let decoded = decode_stream(&dict, raw, &mut budget);
+                expand_object_stream(&dict, &decoded, &mut document);
+            } else if contains_name(&dict, b"/EmbeddedFile") {
+                stream = Some(decode_stream(&dict, raw, &mut budget));
+            }
+        }
+        document.objects.push(PdfObject { number, dict, stream });
+    }
+    Ok(document)
+}
+
+/// Objects packed into an `/ObjStm`: a header of `number offset` pairs,
+/// then the objects from `/First` on.
+fn expand_object_stream(dict: &[u8], decoded: &[u8], document: &mut Document) {
+    let Some(first) = integer_after(dict, b"/First") else { return };
+    let count = integer_after(dict, b"/N").unwrap_or(0).min(MAX_OBJECTS);
+    let header = String::from_utf8_lossy(&decoded[..first.min(decoded.len())]).to_string();
+    let numbers: Vec<usize> = header.split_ascii_whitespace().filter_map(|n| n.parse().ok()).collect();
+    let pairs: Vec<(usize, usize)> = numbers.chunks_exact(2).take(count).map(|p| (p[0], p[1])).collect();
+
+    for (i, &(number, offset)) in pairs.iter().enumerate() {
+        let start = first.saturating_add(offset).min(decoded.len());
+        let end = pairs.get(i + 1).map_or(decoded.len(), |p| first.saturating_add(p.1).min(decoded.len()));
+        if start >= end || document.objects.len() >= MAX_OBJECTS {
+            continue;
+        }
+        let dict = normalize_names(&decoded[start..end], &mut document.obfuscated_names);
+        document.objects.push(PdfObject { number: number as u32, dict, stream: None });
+    }
+}
+
+fn trailer_has(data: &[u8], name: &[u8]) -> bool {
+    let Some(trailer) = rfind(data, b"trailer") else {
+        // Cross-reference streams carry the trailer keys in their dictionary
+        return find(data, b"/XRef").is_some() && find(data, name).is_some();
+    };
+    find(&data[trailer..], name).is_some()
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|w| w == needle)
+}
+
+fn rfind(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).rposition(|w| w == needle)
+}
+
+fn trim_eol(mut data: &[u8]) -> &[u8] {
+    while let Some((&last, rest)) = data.split_last() {
+        if last != b'\r' && last != b'\n' {
+            break;
+        }
+        data = rest;
+    }
+    data
+}
+
+fn is_delimiter(b: u8) -> bool {
+    b.is_ascii_whitespace() || b"()<>[]{}/%".contains(&b)
+}
+
+/// Occurrences of `name` as a whole name, so `/JS` doesn't count `/JSON`.
+fn count_name(dict: &[u8], name: &str) -> usize {
+    let name = name.as_bytes();
+    dict.windows(name.len())
+        .enumerate()
+        .filter(|(i, w)| *w == name && dict.get(i + name.len()).is_none_or(|&b| is_delimiter(b)))
+        .count()
+}
+
+fn contains_name(dict: &[u8], name: &[u8]) -> bool {
+    count_name(dict, &String::from_utf8_lossy(name)) > 0
+}
+
+/// Resolves `#xx` escapes in names, remembering keywords that used them.
+fn normalize_names(raw: &[u8], obfuscated: &mut Vec<String>) -> Vec<u8> {
+    let mut out = Vec::with_capacity(raw.len());
+    let mut i = 0;
+    while i < raw.len() {
+        if raw[i] != b'/' {
+            out.push(raw[i]);
+            i += 1;
+            continue;
+        }
+        let end = raw[i + 1..].iter().position(|&b| is_delimiter(b)).map_or(raw.len(), |p| i + 1 + p);
+        let name = &raw[i..end];
+        if name.contains(&b'#') {
+            let mut decoded = Vec::with_capacity(name.len());
+            let mut j = 0;
+            while j < name.len() {
+                let hex = name.get(j + 1..j + 3).and_then(|h| std::str::from_utf8(h).ok());
+                match (name[j], hex.and_then(|h| u8::from_str_radix(h, 16).ok())) {
+                    (b'#', Some(byte)) => {
+                        decoded.push(byte);
+                        j += 3;
+                    }
+                    (b, _) => {
+                        decoded.push(b);
+                        j += 1;
+                    }
+                }
+            }
+            let text = String::from_utf8_lossy(&decoded).to_string();
+            if KEYWORDS.contains(&text.as_str()) && !obfuscated.contains(&text) {
+                obfuscated.push(text);
+            }
+            out.extend(decoded);
+        } else {
+            out.extend_from_slice(name);
+        }
+        i = end;
+    }
+    out
+}
+
+fn integer_after(dict: &[u8], key: &[u8]) -> Option<usize> {
+    let mut pos = 0;
+    while let Some(found) = find(&dict[pos..], key) {
+        let start = pos + found + key.len();
+        pos = start;
+        if dict.get(start).is_some_and(|&b| !is_delimiter(b)) {
+            continue;
+        }
+        let rest = &dict[start..];
+        let digits: Vec<u8> = rest
+            .iter()
+            .skip_while(|b| b.is_ascii_whitespace())
+            .take_while(|b| b.is_ascii_digit())
+            .copied()
+            .collect();
+        return std::str::from_utf8(&digits).ok()?.parse().ok();
+    }
+    None
+}
+
+/// The object number of an indirect reference `key N G R`.
+fn reference_after(dict: &[u8], key: &[u8]) -> Option<u32> {
+    static PATTERN: OnceLock<Regex> = OnceLock::new();
+    let pattern = PATTERN.get_or_init(|| Regex::new(r"^\s*(\d+)\s+\d+\s+R").expect("reference pattern is valid"));
+    let mut pos = 0;
+    while let Some(found) = find(&dict[pos..], key) {
+        let start = pos + found + key.len();
+        pos = start;
+        if let Some(c) = pattern.captures(&dict[start..]) {
+            return std::str::from_utf8(&c[1]).ok()?.parse().ok();
+        }
+    }
+    None
+}
+
+/// The literal or hex string following `key`.
+fn string_after(dict: &[u8], key: &[u8]) -> Option<String> {
+    let mut pos = 0;
+    while let Some(found) = find(&dict[pos..], key) {
+        let start = pos + found + key.len();
+        pos = start;
+        if dict.get(start).is_some_and(|&b| !is_delimiter(b)) {
+            continue;
+        }
+        let offset = dict[start..].iter().position(|b| !b.is_ascii_whitespace())?;
+        let rest = &dict[start + offset..];
+        let bytes = match rest.first() {
+            Some(b'(') => literal_string(rest),
+            Some(b'<') if rest.get(1) != Some(&b'<') => hex_string(rest),
+            _ => continue,
+        };
+        return Some(text_string(&bytes));
+    }
+    None
+}
+
+fn extract_uris(dict: &[u8]) -> Vec<String> {
+    let mut uris = Vec::new();
+    let mut pos = 0;
+    while let Some(found) = find(&dict[pos..], b"/URI") {
+        let start = pos + found;
+        pos = start + 4;
+        if let Some(uri) = string_after(&dict[start..], b"/URI") {
+            let uri = uri.trim().to_string();
+            if !uri.is_empty() {
+                uris.push(uri);
+            }
+        }
+    }
+    uris
+}
+
+/// `(...)` with balanced parentheses and backslash escapes.
+fn literal_string(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::new();
+    let mut depth = 0;
+    let mut i = 0;
+    while i < data.len() {
+        let b = data[i];
+        match b {
+            b'(' => {
+                depth += 1;
+                if depth > 1 {
+                    out.push(b);
+                }
+            }
+            b')' => {
+                depth -= 1;
+                if depth == 0 {
+                    break;
+                }
+                out.push(b);
+            }
+            b'\\' if i + 1 < data.len() => {
+                i += 1;
+                match data[i] {
+                    b'n' => out.push(b'\n'),
+                    b'r' => out.push(b'\r'),
+                    b't' => out.push(b'\t'),
+                    b'b' => out.push(8),
+                    b'f' => out.push(12),
+                    b'\r' | b'\n' => {}
+                    d @ b'0'..=b'7' => {
+                        let mut value = (d - b'0') as u32;
+                        for _ in 0..2 {
+                            match data.get(i + 1) {
+                                Some(&d @ b'0'..=b'7') => {
+                                    value = value * 8 + (d - b'0') as u32;
+                                    i += 1;
+                                }
+                                _ => break,
+                            }
+                        }
+                        out.push(value as u8);
+                    }
+                    other => out.push(other),
+                }
+            }
+            _ => out.push(b),
+        }
+        i += 1;
+    }
+    out
+}
+
+fn hex_string(data: &[u8]) -> Vec<u8> {
+    let end = data.iter().position(|&b| b == b'>').unwrap_or(data.len());
+    ascii_hex(&data[1..end], usize::MAX)
+}
+
+/// PDF text strings are PDFDocEncoding or UTF-16BE with a BOM.
+fn text_string(bytes: &[u8]) -> String {
+    if let Some(utf16) = bytes.strip_prefix(&[0xFE, 0xFF]) {
+        let units: Vec<u16> = utf16.chunks_exact(2).map(|c| u16::from_be_bytes([c[0], c[1]])).collect();
+        return String::from_utf16_lossy(&units);
+    }
+    bytes.iter().map(|&b| b as char).collect()
+}
+
+fn filter_names(dict: &[u8]) -> Vec<String> {
+    static PATTERN: OnceLock<Regex> = OnceLock::new();
+    let pattern = PATTERN.get_or_init(|| Regex::new(r"/Filter\s*(\[[^\]]*\]|/[A-Za-z0-9]+)").expect("filter pattern is valid"));
+    let Some(c) = pattern.captures(dict) else { return Vec::new() };
+    String::from_utf8_lossy(&c[1])
+        .split(['/', '[', ']', ' ', '\r', '\n', '\t'])
+        .filter(|n| !n.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+/// Applies the stream's filters in order. A filter it can't handle, such as
+/// an image codec, leaves the data as decoded so far. Every step is bounded
+/// by what is left of `budget` and charged to it.
+fn decode_stream(dict: &[u8], raw: &[u8], budget: &mut u64) -> Vec<u8> {
+    let mut data: Option<Vec<u8>> = None;
+    for filter in filter_names(dict) {
+        let input = data.as_deref().unwrap_or(raw);
+        let limit = (*budget).min(MAX_STREAM_SIZE) as usize;
+        let decoded = match filter.as_str() {
+            "FlateDecode" | "Fl" => flate(input, limit),
+            "ASCIIHexDecode" | "AHx" => ascii_hex(input, limit),
+            "ASCII85Decode" | "A85" => ascii85(input, limit),
+            "LZWDecode" | "LZW" => lzw(input, integer_after(dict, b"/EarlyChange").unwrap_or(1) != 0, limit),
+            "RunLengthDecode" | "RL" => run_length(input, limit),
+            _ => break,
+        };
+        *budget -= decoded.len() as u64;
+        data = Some(decoded);
+    }
+    data.unwrap_or_else(|| {
+        let kept = &raw[..raw.len().min(*budget as usize)];
+        *budget -= kept.len() as u64;
+        kept.to_vec()
+    })
+}
+
+/// zlib first, raw deflate for streams with a damaged header. Whatever
+/// decoded before an error is kept, truncated streams are common.
+fn flate(data: &[u8], limit: usize) -> Vec<u8> {
+    let mut out = Vec::new();
+    let _ = flate2::read::ZlibDecoder::new(data).take(limit as u64).read_to_end(&mut out);
+    if out.is_empty() && data.len() > 2 {
+        let _ = flate2::read::DeflateDecoder::new(&data[2..]).take(limit as u64).read_to_end(&mut out);
+    }
+    out
+}
+
+fn ascii_hex(data: &[u8], limit: usize) -> Vec<u8> {
+    let digits: Vec<u8> = data
+        .iter()
+        .take_while(|&&b| b != b'>')
+        .filter_map(|&b| (b as char).to_digit(16).map(|d| d as u8))
+        .take(limit.saturating_mul(2))
+        .collect();
+    // An odd final digit is followed by an implicit 0
+    digits.chunks(2).map(|pair| pair[0] << 4 | pair.get(1).copied().unwrap_or(0)).collect()
+}
+
+fn ascii85(data: &[u8], limit: usize) -> Vec<u8> {
+    let mut out = Vec::new();
+    let mut group = Vec::with_capacity(5);
+    let data = data.strip_prefix(b"<~").unwrap_or(data);
+    for &b in data {
+        if out.len() >= limit {
+            out.truncate(limit);
+            return out;
+        }
+        match b {
+            b'~' => break,
+            b'z' if group.is_empty() => out.extend_from_slice(&[0; 4]),
+            b'!'..=b'u' => {
+                group.push(b - b'!');
+                if group.len() == 5 {
+                    let value = group.iter().fold(0u64, |acc, &d| acc * 85 + d as u64);
+                    out.extend_from_slice(&(value as u32).to_be_bytes());
+                    group.clear();
+                }
+            }
+            _ => {}
+        }
+    }
+    if group.len() > 1 {
+        let missing = 5 - group.len();
+        group.resize(5, 84);
+        let value = group.iter().fold(0u64, |acc, &d| acc * 85 + d as u64);
+        out.extend_from_slice(&(value as u32).to_be_bytes()[..4 - missing]);
+    }
+    out.truncate(limit);
+    out
+}
+
+fn run_length(data: &[u8], limit: usize) -> Vec<u8> {
+    let mut out = Vec::new();
+    let mut i = 0;
+    while i < data.len() && out.len() < limit {
+        let length = data[i];
+        i += 1;
+        match length {
+            128 => break,
+            0..=127 => {
+                let end = (i + length as usize + 1).min(data.len());
+                out.extend_from_slice(&data[i..end]);
+                i = end;
+            }
+            _ => {
+                if let Some(&b) = data.get(i) {
+                    out.extend(std::iter::repeat_n(b, 257 - length as usize));
+                }
+                i += 1;
+            }
+        }
+    }
+    out.truncate(limit);
+    out
+}
+
+/// Variable-width LZW, 9 to 12 bits, most significant bit first. With
+/// `early_change` the code width grows one code early, as most writers do.
+fn lzw(data: &[u8], early_change: bool, limit: usize) -> Vec<u8> {
+    const CLEAR: usize = 256;
+    const END: usize = 257;
+    let mut table: Vec<Vec<u8>> = (0..=255u8).map(|b| vec![b]).chain([Vec::new(), Vec::new()]).collect();
+    let mut out = Vec::new();
+    let mut width = 9;
+    let mut previous: Option<usize> = None;
+    let (mut buffer, mut bits) = (0u32, 0u32);
+
+    for &byte in data {
+        buffer = buffer << 8 | byte as u32;
+        bits += 8;
+        while bits >= width {
+            let code = ((buffer >> (bits - width)) & ((1 << width) - 1)) as usize;
+            bits -= width;
+            match code {
+                CLEAR => {
+                    table.truncate(258);
+                    width = 9;
+                    previous = None;
+                    continue;
+                }
+                END => return out,
+                _ => {}
+            }
+
+            let entry = match (table.get(code), previous) {
+                (Some(entry), _) => entry.clone(),
+                // The code being defined right now: previous + its first byte
+                (None, Some(prev)) if code == table.len() => {
+                    let mut entry = table[prev].clone();
+                    entry.push(table[prev][0]);
+                    entry
+                }
+                _ => return out,
+            };
+            if let Some(prev) = previous {
+                if table.len() < 4096 {
+                    let mut new = table[prev].clone();
+                    new.push(entry[0]);
+                    table.push(new);
+                }
+            }
+            out.extend_from_slice(&entry);
+            if out.len() >= limit {
+                out.truncate(limit);
+                return out;
+            }
+            previous = Some(code);
+
+            let next = table.len() + usize::from(early_change);
+            width = match next {
+                n if n >= 2048 => 12,
+                n if n >= 1024 => 11,
+                n if n >= 512 => 10,
+                _ => 9,
+            };
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    fn zlib(data: &[u8]) -> Vec<u8> {
+        let mut encoder = flate2::write::ZlibEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    /// A PDF holding the given `(dictionary, stream)` objects, numbered from 1.
+    fn pdf(objects: &[(&str, Option<&[u8]>)]) -> Vec<u8> {
+        let mut data = b"%PDF-1.7\n".to_vec();
+        for (i, (dict, stream)) in objects.iter().enumerate() {
+            data.extend_from_slice(format!("{} 0 obj\n{}\n", i + 1, dict).as_bytes());
+            if let Some(stream) = stream {
+                data.extend_from_slice(b"stream\n");
+                data.extend_from_slice(stream);
+                data.extend_from_slice(b"\nendstream\n");
+            }
+            data.extend_from_slice(b"endobj\n");
+        }
+        data.extend_from_slice(b"trailer\n<< /Root 1 0 R >>\n%%EOF\n");
+        data
+    }
+
+    #[test]
+    fn decodes_every_supported_filter() {
+        let text = b"Hello, PDF world!!";
+        assert_eq!(flate(&zlib(text), usize::MAX), text);
+        // Raw deflate behind a damaged zlib header
+        let mut damaged = zlib(text);
+        damaged[0] = 0;
+        assert_eq!(flate(&damaged, usize::MAX), text);
+
+        assert_eq!(ascii_hex(b"48 65 6c6C 6\n>ignored", usize::MAX), b"Hel\x6c\x60");
+        assert_eq!(ascii85(b"<~87cURD_*#-6q/;CDfTZ)+X$~>", usize::MAX), text);
+        assert_eq!(ascii85(b"<~z@:E^~>", usize::MAX), b"\0\0\0\0abc");
+        assert_eq!(run_length(&[2, b'a', b'b', b'c', 254, b'x', 128, b'z'], usize::MAX), b"abcxxx");
+        // The example from the PDF reference, with and without early change
+        let codes = [0x80, 0x0B, 0x60, 0x50, 0x22, 0x0C, 0x0C, 0x85, 0x01];
+        assert_eq!(lzw(&codes, true, usize::MAX), b"-----A---B");
+
+        let dict = b"<< /Filter [/ASCIIHexDecode /FlateDecode] >>";
+        let hex: String = zlib(text).iter().map(|b| format!("{:02x}", b)).collect();
+        let mut budget = u64::MAX;
+        assert_eq!(decode_stream(dict, format!("{}>", hex).as_bytes(), &mut budget), text);
+        // Image codecs are left alone
+        assert_eq!(decode_stream(b"/Filter /DCTDecode", b"\xff\xd8", &mut budget), b"\xff\xd8");
+    }
+
+    #[test]
+    fn decoding_is_charged_to_the_budget() {
+        let bomb: Vec<u8> = [129, b'a'].repeat(1000);
+        let mut budget = 300;
+        assert_eq!(decode_stream(b"/Filter /RunLengthDecode", &bomb, &mut budget).len(), 300);
+        assert_eq!(budget, 0);
+        assert!(decode_stream(b"/Filter /FlateDecode", &zlib(&[0; 1000]), &mut budget).is_empty());
+
+        // Intermediate output counts too, and unfiltered data is cut to what is left
+        let mut budget = 1000;
+        let decoded = decode_stream(b"/Filter [/RL /Fl]", &[255, b'x'], &mut budget);
+        assert!(decoded.is_empty());
+        assert_eq!(budget, 1000 - 2);
+        assert_eq!(decode_stream(b"<< >>", &[7; 2000], &mut budget).len(), 998);
+        assert_eq!(budget, 0);
+
+        let mut budget = 10;
+        assert_eq!(ascii85(b"zzzzzzzz", 10).len(), 10);
+        assert_eq!(lzw(&[0x80, 0x0B, 0x60, 0x50, 0x22, 0x0C, 0x0C, 0x85, 0x01], true, 4), b"----");
+        assert_eq!(decode_stream(b"/Filter /AHx", b"414243444546474849504142>", &mut budget), b"ABCDEFGHIP");
+    }
+
+    #[test]
+    fn expands_object_streams() {
+        let packed = ["<< /S /J#61vaScript /JS (app.alert(1)) >>", "<< /S /URI /URI (http://evil.example/x) >>"];
+        let header = format!("5 0 6 {} ", packed[0].len() + 1);
+        let body = format!("{}{} {}", header, packed[0], packed[1]);
+        let dict = format!("<< /Type /ObjStm /N 2 /First {} /Filter /FlateDecode >>", header.len());
+        let compressed = zlib(body.as_bytes());
+        let data = pdf(&[("<< /Type /Catalog /OpenAction 5 0 R >>", None), (&dict, Some(&compressed))]);
+
+        let analysis = analyze(&parse(&data).unwrap());
+        assert_eq!((analysis.version.as_str(), analysis.objects, analysis.streams), ("1.7", 4, 1));
+        assert_eq!(analysis.keywords.get("/JavaScript"), Some(&1));
+        assert_eq!(analysis.keywords.get("/JS"), Some(&1));
+        assert_eq!(analysis.obfuscated_names, ["/JavaScript"]);
+        assert_eq!(analysis.uris, ["http://evil.example/x"]);
+        let names: Vec<&str> = analysis.findings.iter().map(|f| f.name.as_str()).collect();
+        assert_eq!(names, ["Heuristic.PDF.JavaScript", "Heuristic.PDF.AutoAction", "Heuristic.PDF.ObfuscatedNames"]);
+    }
+
+    #[test]
+    fn returns_attachments_by_name() {
+        let content = zlib(b"not decoded");
+        let data = pdf(&[
+            ("<< /Type /Filespec /F (invoice.pdf.exe) /UF (..\\\\invoice.exe) /EF << /F 2 0 R >> >>", None),
+            ("<< /Type /EmbeddedFile /Filter /ASCIIHexDecode >>", Some(b"4D5A9000>")),
+            ("<< /Length 20 /Filter /FlateDecode >>", Some(&content)),
+            ("<< /Type /EmbeddedFile >>", Some(b"plain")),
+        ]);
+        let document = parse(&data).unwrap();
+        let files = document.embedded_files();
+        // The escaped backslash would have made the name a path
+        assert_eq!(files[0], (".._invoice.exe".to_string(), &b"MZ\x90\x00"[..]));
+        assert_eq!(files[1], ("embedded-4".to_string(), &b"plain"[..]));
+        assert_eq!(files.len(), 2);
+        assert!(document.objects[2].stream.is_none(), "content streams stay encoded");
+
+        let analysis = analyze(&document);
+        assert_eq!(analysis.streams, 3);
+        assert!(analysis.embedded_files[0].executable);
+        assert_eq!(analysis.findings[0].name, "Heuristic.PDF.EmbeddedExecutable");
+        assert!(parse(b"MZ not a pdf").is_err());
+    }
+}
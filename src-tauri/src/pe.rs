@@ -0,0 +1,667 @@
+//! Static analysis of Windows PE executables.
+//!
+//! Headers, imports, exports, TLS callbacks and version information come from
+//! `goblin`; the resource tree is walked here so every resource can be sized,
+//! measured for entropy and checked for embedded executables. Authenticode
+//! signatures are detected but not verified.
+//!
+//! [`findings`] turns the analysis into suspicious detections: packer
+//! sections, high-entropy code, writable+executable sections, entry points in
+//! odd places, API combinations typical for injection, keylogging or
+//! downloading, and executables hidden in resources.
+
+use goblin::pe::section_table::SectionTable;
+use goblin::pe::PE;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashSet};
+
+use crate::entropy;
+use crate::signatures::Detection;
+
+const IMAGE_SCN_MEM_EXECUTE: u32 = 0x2000_0000;
+const IMAGE_SCN_MEM_READ: u32 = 0x4000_0000;
+const IMAGE_SCN_MEM_WRITE: u32 = 0x8000_0000;
+
+const MAX_RESOURCES: usize = 2048;
+/// Directory entries read across the whole resource tree. Each directory
+/// may claim up to 131070 entries, so a few crafted levels would otherwise
+/// multiply into billions.
+const MAX_RESOURCE_ENTRIES: usize = 16 * 1024;
+const MIN_PACKED_SECTION_SIZE: u32 = 1024;
+
+/// Section names left behind by common packers and protectors.
+const PACKER_SECTIONS: [(&str, &str); 22] = [
+    ("UPX0", "UPX"),
+    ("UPX1", "UPX"),
+    ("UPX2", "UPX"),
+    (".aspack", "ASPack"),
+    (".adata", "ASPack"),
+    (".MPRESS1", "MPRESS"),
+    (".MPRESS2", "MPRESS"),
+    (".petite", "Petite"),
+    (".nsp0", "NsPack"),
+    (".nsp1", "NsPack"),
+    (".themida", "Themida"),
+    (".winlice", "Themida"),
+    (".vmp0", "VMProtect"),
+    (".vmp1", "VMProtect"),
+    ("PEC2", "PECompact"),
+    ("pec1", "PECompact"),
+    (".enigma1", "Enigma"),
+    (".enigma2", "Enigma"),
+    ("FSG!", "FSG"),
+    (".packed", "Generic"),
+    (".yP", "YodaCrypter"),
+    (".perplex", "Perplex"),
+];
+
+/// Import combinations that on their own say a lot about intent. Each group
+/// needs one function from every inner list.
+const SUSPICIOUS_IMPORTS: [(&str, &str, &[&[&str]]); 5] = [
+    (
+        "Injection",
+        "imports APIs used to inject code into other processes",
+        &[
+            &["VirtualAllocEx"],
+            &["WriteProcessMemory"],
+            &["CreateRemoteThread", "CreateRemoteThreadEx", "NtCreateThreadEx", "RtlCreateUserThread", "QueueUserAPC"],
+        ],
+    ),
+    (
+        "Hollowing",
+        "imports APIs used for process hollowing",
+        &[&["NtUnmapViewOfSection", "ZwUnmapViewOfSection"], &["SetThreadContext", "WriteProcessMemory"]],
+    ),
+    (
+        "Keylogger",
+        "imports APIs used to capture keystrokes",
+        &[&["SetWindowsHookEx"], &["GetAsyncKeyState", "GetKeyState", "GetKeyboardState"]],
+    ),
+    (
+        "Downloader",
+        "downloads a file and starts a program",
+        &[&["URLDownloadToFile"], &["WinExec", "ShellExecute", "ShellExecuteEx", "CreateProcess"]],
+    ),
+    (
+        "AntiDebug",
+        "imports several debugger detection APIs",
+        &[
+            &["CheckRemoteDebuggerPresent", "NtSetInformationThread"],
+            &["NtQueryInformationProcess", "ZwQueryInformationProcess", "OutputDebugString"],
+        ],
+    ),
+];
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PeSection {
+    pub name: String,
+    pub virtual_address: u32,
+    pub virtual_size: u32,
+    pub raw_offset: u32,
+    pub raw_size: u32,
+    pub entropy: f64,
+    pub characteristics: u32,
+    /// `rwx` style summary of the memory protection flags.
+    pub permissions: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PeImportLibrary {
+    pub dll: String,
+    pub functions: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PeResource {
+    pub resource_type: String,
+    pub name: String,
+    pub language: u32,
+    pub offset: usize,
+    pub size: usize,
+    pub entropy: f64,
+    /// The resource data starts with an `MZ` header.
+    pub embedded_pe: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PeSignature {
+    pub present: bool,
+    pub certificates: usize,
+    pub size: u32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PeOverlay {
+    pub offset: usize,
+    pub size: usize,
+    pub entropy: f64,
+    /// The overlay is exactly the Authenticode certificate table.
+    pub is_signature: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PeAnalysis {
+    pub machine: String,
+    pub is_64: bool,
+    pub is_dll: bool,
+    pub subsystem: String,
+    pub compile_time: String,
+    pub timestamp: u32,
+    pub image_base: u64,
+    pub entry_point: u32,
+    pub checksum: u32,
+    pub characteristics: u16,
+    /// Enabled mitigations such as `ASLR`, `DEP` and `CFG`.
+    pub security_features: Vec<String>,
+    pub sections: Vec<PeSection>,
+    pub imports: Vec<PeImportLibrary>,
+    pub import_count: usize,
+    pub exports: Vec<String>,
+    pub imphash: Option<String>,
+    pub resources: Vec<PeResource>,
+    pub version_info: BTreeMap<String, String>,
+    pub signature: PeSignature,
+    pub overlay: Option<PeOverlay>,
+    pub tls_callbacks: Vec<u64>,
+    pub findings: Vec<Detection>,
+}
+
+pub fn analyze(data: &[u8]) -> Result<PeAnalysis, String> {
+    let pe = PE::parse(data).map_err(|e| format!("Not a valid PE file: {}", e))?;
+    let coff = &pe.header.coff_header;
+    let optional = pe.header.optional_header.as_ref();
+    let windows = optional.map(|o| &o.windows_fields);
+
+    let sections: Vec<PeSection> = pe.sections.iter().map(|s| section_info(s, data)).collect();
+
+    let mut imports: Vec<PeImportLibrary> = Vec::new();
+    for import in &pe.imports {
+        let function = import_name(&import.name);
+        match imports.iter_mut().find(|lib| lib.dll == import.dll) {
+            Some(lib) => lib.functions.push(function),
+            None => imports.push(PeImportLibrary { dll: import.dll.to_string(), functions: vec![function] }),
+        }
+    }
+
+    let certificate_table = optional.and_then(|o| o.data_directories.get_certificate_table()).copied();
+    let signature = PeSignature {
+        present: !pe.certificates.is_empty(),
+        certificates: pe.certificates.len(),
+        size: certificate_table.map_or(0, |dd| dd.size),
+    };
+
+    // Anything past the last section's raw data isn't mapped by the loader
+    let sections_end = pe
+        .sections
+        .iter()
+        .map(|s| s.pointer_to_raw_data as usize + s.size_of_raw_data as usize)
+        .max()
+        .unwrap_or(0);
+    let overlay = (sections_end > 0 && sections_end < data.len()).then(|| {
+        let overlay = &data[sections_end..];
+        PeOverlay {
+            offset: sections_end,
+            size: overlay.len(),
+            entropy: entropy::shannon(overlay),
+            // The certificate directory holds a file offset, not an RVA
+            is_signature: certificate_table
+                .is_some_and(|dd| dd.virtual_address as usize == sections_end && dd.size as usize == overlay.len()),
+        }
+    });
+
+    let resources = optional
+        .and_then(|o| o.data_directories.get_resource_table())
+        .map(|dd| walk_resources(data, &pe.sections, dd.virtual_address))
+        .unwrap_or_default();
+
+    let mut analysis = PeAnalysis {
+        machine: machine_name(coff.machine),
+        is_64: pe.is_64,
+        is_dll: pe.is_lib,
+        subsystem: windows.map_or_else(|| "unknown".to_string(), |w| subsystem_name(w.subsystem)),
+        compile_time: chrono::DateTime::from_timestamp(coff.time_date_stamp as i64, 0)
+            .map(|t| t.format("%Y-%m-%d %H:%M:%S UTC").to_string())
+            .unwrap_or_default(),
+        timestamp: coff.time_date_stamp,
+        image_base: pe.image_base,
+        entry_point: pe.entry,
+        checksum: windows.map_or(0, |w| w.check_sum),
+        characteristics: coff.characteristics,
+        security_features: windows.map(|w| security_features(w.dll_characteristics)).unwrap_or_default(),
+        sections,
+        import_count: pe.imports.len(),
+        imphash: imphash(&imports),
+        imports,
+        exports: pe.exports.iter().filter_map(|e| e.name.map(str::to_string)).collect(),
+        resources,
+        version_info: version_info(&pe),
+        signature,
+        overlay,
+        tls_callbacks: pe.tls_data.as_ref().map(|tls| tls.callbacks.clone()).unwrap_or_default(),
+        findings: Vec::new(),
+    };
+    analysis.findings = findings(&analysis);
+    Ok(analysis)
+}
+
+fn section_info(section: &SectionTable, data: &[u8]) -> PeSection {
+    let start = (section.pointer_to_raw_data as usize).min(data.len());
+    let end = start.saturating_add(section.size_of_raw_data as usize).min(data.len());
+    let c = section.characteristics;
+    let flag = |bit: u32, ch: char| if c & bit != 0 { ch } else { '-' };
+
+    PeSection {
+        name: section.name().unwrap_or("").trim_end_matches('\0').to_string(),
+        virtual_address: section.virtual_address,
+        virtual_size: section.virtual_size,
+        raw_offset: section.pointer_to_raw_data,
+        raw_size: section.size_of_raw_data,
+        entropy: entropy::shannon(&data[start..end]),
+        characteristics: c,
+        permissions: [flag(IMAGE_SCN_MEM_READ, 'r'), flag(IMAGE_SCN_MEM_WRITE, 'w'), flag(IMAGE_SCN_MEM_EXECUTE, 'x')]
+            .iter()
+            .collect(),
+    }
+}
+
+/// goblin names ordinal imports `ORDINAL n`, pefile (and so imphash) uses `ordn`.
+fn import_name(name: &str) -> String {
+    match name.strip_prefix("ORDINAL ") {
+        Some(ordinal) => format!("ord{}", ordinal),
+        None => name.to_string(),
+    }
+}
+
+/// Mandiant's import hash: MD5 over `dll.function` pairs in import order.
+/// Ordinals stay `ordN`; pefile resolves a few ws2_32/oleaut32 ordinals to
+/// names, so hashes of binaries importing those by ordinal will differ.
+fn imphash(imports: &[PeImportLibrary]) -> Option<String> {
+    use md5::{Digest, Md5};
+
+    let mut parts = Vec::new();
+    for lib in imports {
+        let dll = lib.dll.to_ascii_lowercase();
+        let dll = ["dll", "ocx", "sys"]
+            .iter()
+            .find_map(|ext| dll.strip_suffix(&format!(".{}", ext)))
+            .unwrap_or(&dll)
+            .to_string();
+        for function in &lib.functions {
+            parts.push(format!("{}.{}", dll, function.to_ascii_lowercase()));
+        }
+    }
+    if parts.is_empty() {
+        return None;
+    }
+    Some(hex::encode(Md5::digest(parts.join(",").as_bytes())))
+}
+
+fn rva_to_offset(sections: &[SectionTable], rva: u32) -> Option<usize> {
+    sections.iter().find_map(|s| {
+        let size = s.virtual_size.max(s.size_of_raw_data);
+        (rva >= s.virtual_address && rva < s.virtual_address.saturating_add(size))
+            .then(|| (rva - s.virtual_address) as usize + s.pointer_to_raw_data as usize)
+    })
+}
+
+fn resource_type_name(id: u32) -> String {
+    match id {
+        1 => "RT_CURSOR",
+        2 => "RT_BITMAP",
+        3 => "RT_ICON",
+        4 => "RT_MENU",
+        5 => "RT_DIALOG",
+        6 => "RT_STRING",
+        7 => "RT_FONTDIR",
+        8 => "RT_FONT",
+        9 => "RT_ACCELERATOR",
+        10 => "RT_RCDATA",
+        11 => "RT_MESSAGETABLE",
+        12 => "RT_GROUP_CURSOR",
+        14 => "RT_GROUP_ICON",
+        16 => "RT_VERSION",
+        17 => "RT_DLGINCLUDE",
+        19 => "RT_PLUGPLAY",
+        20 => "RT_VXD",
+        21 => "RT_ANICURSOR",
+        22 => "RT_ANIICON",
+        23 => "RT_HTML",
+        24 => "RT_MANIFEST",
+        _ => return id.to_string(),
+    }
+    .to_string()
+}
+
+/// Walks the three level type/name/language resource tree. Directories are
+/// visited once each, so loops in a crafted tree end the walk instead of
+/// repeating it.
+fn walk_resources(data: &[u8], sections: &[SectionTable], root_rva: u32) -> Vec<PeResource> {
+    let Some(root) = rva_to_offset(sections, root_rva) else { return Vec::new() };
+    let u16_at = |o: usize| data.get(o..o + 2).map(|b| u16::from_le_bytes([b[0], b[1]]));
+    let u32_at = |o: usize| data.get(o..o + 4).map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]));
+
+    // Entry names are either IDs or offsets to length-prefixed UTF-16 strings
+    let entry_name = |value: u32, as_type: bool| -> String {
+        if value & 0x8000_0000 == 0 {
+            return if as_type { resource_type_name(value) } else { value.to_string() };
+        }
+        let offset = root + (value & 0x7fff_ffff) as usize;
+        let len = u16_at(offset).unwrap_or(0) as usize;
+        let units: Vec<u16> = (0..len).filter_map(|i| u16_at(offset + 2 + i * 2)).collect();
+        String::from_utf16_lossy(&units)
+    };
+    let mut seen = HashSet::new();
+    let mut entries_left = MAX_RESOURCE_ENTRIES;
+    let mut entries = |dir: usize| -> Vec<(u32, u32)> {
+        if !seen.insert(dir) {
+            return Vec::new();
+        }
+        let count = u16_at(dir + 12).unwrap_or(0) as usize + u16_at(dir + 14).unwrap_or(0) as usize;
+        let found: Vec<(u32, u32)> = (0..count.min(entries_left))
+            .map_while(|i| Some((u32_at(dir + 16 + i * 8)?, u32_at(dir + 20 + i * 8)?)))
+            .collect();
+        entries_left -= found.len();
+        found
+    };
+
+    let mut resources = Vec::new();
+    'walk: for (type_id, type_target) in entries(root) {
+        if type_target & 0x8000_0000 == 0 {
+            continue;
+        }
+        let resource_type = entry_name(type_id, true);
+        for (name_id, name_target) in entries(root + (type_target & 0x7fff_ffff) as usize) {
+            if name_target & 0x8000_0000 == 0 {
+                continue;
+            }
+            let name = entry_name(name_id, false);
+            for (language, data_entry) in entries(root + (name_target & 0x7fff_ffff) as usize) {
+                if resources.len() >= MAX_RESOURCES {
+                    break 'walk;
+                }
+                if data_entry & 0x8000_0000 != 0 {
+                    continue;
+                }
+                let entry = root + data_entry as usize;
+                let (Some(rva), Some(size)) = (u32_at(entry), u32_at(entry + 4)) else { continue };
+                let Some(offset) = rva_to_offset(sections, rva) else { continue };
+                let bytes = &data[offset.min(data.len())..offset.saturating_add(size as usize).min(data.len())];
+                resources.push(PeResource {
+                    resource_type: resource_type.clone(),
+                    name: name.clone(),
+                    language,
+                    offset,
+                    size: size as usize,
+                    entropy: entropy::shannon(bytes),
+                    embedded_pe: bytes.starts_with(b"MZ"),
+                });
+            }
+        }
+    }
+    resources
+}
+
+fn version_info(pe: &PE) -> BTreeMap<String, String> {
+    let mut info = BTreeMap::new();
+    let Some(version) = pe.resource_data.as_ref().and_then(|r| r.version_info.as_ref()) else { return info };
+    let strings = &version.string_info;
+    for (key, value) in [
+        ("CompanyName", strings.company_name()),
+        ("FileDescription", strings.file_description()),
+        ("FileVersion", strings.file_version()),
+        ("InternalName", strings.internal_name()),
+        ("LegalCopyright", strings.legal_copyright()),
+        ("OriginalFilename", strings.original_filename()),
+        ("ProductName", strings.product_name()),
+        ("ProductVersion", strings.product_version()),
+        ("Comments", strings.comments()),
+    ] {
+        if let Some(value) = value.filter(|v| !v.is_empty()) {
+            info.insert(key.to_string(), value);
+        }
+    }
+    info
+}
+
+fn machine_name(machine: u16) -> String {
+    match machine {
+        0x014c => "x86".to_string(),
+        0x8664 => "x64".to_string(),
+        0xaa64 => "arm64".to_string(),
+        0x01c0 | 0x01c4 => "arm".to_string(),
+        0x0200 => "ia64".to_string(),
+        other => format!("0x{:04x}", other),
+    }
+}
+
+fn subsystem_name(subsystem: u16) -> String {
+    match subsystem {
+        1 => "native",
+        2 => "windows_gui",
+        3 => "windows_cui",
+        7 => "posix_cui",
+        9 => "windows_ce_gui",
+        10 => "efi_application",
+        11 => "efi_boot_service_driver",
+        12 => "efi_runtime_driver",
+        14 => "xbox",
+        16 => "windows_boot_application",
+        _ => "unknown",
+    }
+    .to_string()
+}
+
+fn security_features(dll_characteristics: u16) -> Vec<String> {
+    [
+        (0x0020, "HighEntropyVA"),
+        (0x0040, "ASLR"),
+        (0x0080, "ForceIntegrity"),
+        (0x0100, "DEP"),
+        (0x0400, "NoSEH"),
+        (0x4000, "CFG"),
+    ]
+    .iter()
+    .filter(|(bit, _)| dll_characteristics & bit != 0)
+    .map(|(_, name)| name.to_string())
+    .collect()
+}
+
+/// True when any import is `name` or its `A`/`W` variant.
+fn imports_function(analysis: &PeAnalysis, name: &str) -> bool {
+    analysis.imports.iter().flat_map(|lib| &lib.functions).any(|f| {
+        f.strip_prefix(name).is_some_and(|rest| rest.is_empty() || rest == "A" || rest == "W")
+    })
+}
+
+pub fn findings(analysis: &PeAnalysis) -> Vec<Detection> {
+    let mut findings = Vec::new();
+
+    let mut packers: Vec<&str> = analysis
+        .sections
+        .iter()
+        .filter_map(|s| PACKER_SECTIONS.iter().find(|(name, _)| *name == s.name).map(|(_, packer)| *packer))
+        .collect();
+    packers.dedup();
+    for packer in packers {
+        findings.push(Detection::heuristic(
+            format!("Heuristic.PE.Packer.{}", packer),
+            "pe",
+            format!("Section names typical of the {} packer", packer),
+        ));
+    }
+
+    let packed: Vec<&PeSection> = analysis
+        .sections
+        .iter()
+        .filter(|s| {
+            s.characteristics & IMAGE_SCN_MEM_EXECUTE != 0
+                && s.raw_size >= MIN_PACKED_SECTION_SIZE
+                && s.entropy >= entropy::PACKED
+        })
+        .collect();
+    if let Some(section) = packed.first() {
+        findings.push(Detection::heuristic(
+            "Heuristic.PE.PackedSection",
+            "pe",
+            format!("Executable section {} has entropy {:.2}", section.name, section.entropy),
+        ));
+    }
+
+    let wx: Vec<&str> = analysis
+        .sections
+        .iter()
+        .filter(|s| s.characteristics & IMAGE_SCN_MEM_WRITE != 0 && s.characteristics & IMAGE_SCN_MEM_EXECUTE != 0)
+        .map(|s| s.name.as_str())
+        .collect();
+    if !wx.is_empty() {
+        findings.push(Detection::heuristic(
+            "Heuristic.PE.WritableExecutableSection",
+            "pe",
+            format!("Sections both writable and executable: {}", wx.join(", ")),
+        ));
+    }
+
+    if analysis.entry_point != 0 {
+        let entry_section = analysis.sections.iter().find(|s| {
+            let size = s.virtual_size.max(s.raw_size);
+            analysis.entry_point >= s.virtual_address && analysis.entry_point < s.virtual_address.saturating_add(size)
+        });
+        let anomaly = match entry_section {
+            None => Some("Entry point lies outside every section".to_string()),
+            Some(s) if s.characteristics & IMAGE_SCN_MEM_EXECUTE == 0 => {
+                Some(format!("Entry point lies in non-executable section {}", s.name))
+            }
+            Some(s) if analysis.sections.last().is_some_and(|last| last.name == s.name) && analysis.sections.len() > 1 => {
+                Some(format!("Entry point lies in the last section {}", s.name))
+            }
+            _ => None,
+        };
+        if let Some(description) = anomaly {
+            findings.push(Detection::heuristic("Heuristic.PE.EntryPointAnomaly", "pe", description));
+        }
+    }
+
+    for (name, description, groups) in SUSPICIOUS_IMPORTS {
+        if groups.iter().all(|group| group.iter().any(|f| imports_function(analysis, f))) {
+            findings.push(Detection::heuristic(
+                format!("Heuristic.PE.SuspiciousImports.{}", name),
+                "pe",
+                format!("Executable {}", description),
+            ));
+        }
+    }
+
+    // Packed binaries resolve everything at runtime from a tiny import table
+    if analysis.import_count > 0
+        && analysis.import_count <= 5
+        && imports_function(analysis, "LoadLibrary")
+        && imports_function(analysis, "GetProcAddress")
+    {
+        findings.push(Detection::heuristic(
+            "Heuristic.PE.MinimalImports",
+            "pe",
+            format!("Only {} imports, resolved dynamically through GetProcAddress", analysis.import_count),
+        ));
+    }
+
+    if let Some(resource) = analysis.resources.iter().find(|r| r.embedded_pe) {
+        findings.push(Detection::heuristic(
+            "Heuristic.PE.EmbeddedExecutable",
+            "pe",
+            format!("Resource {}/{} contains an executable", resource.resource_type, resource.name),
+        ));
+    }
+
+    findings
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SECTION_RVA: u32 = 0x1000;
+
+    /// One section mapping the whole of `data` at `SECTION_RVA`.
+    fn sections(data: &[u8]) -> Vec<SectionTable> {
+        vec![SectionTable {
+            virtual_address: SECTION_RVA,
+            virtual_size: data.len() as u32,
+            size_of_raw_data: data.len() as u32,
+            ..Default::default()
+        }]
+    }
+
+    /// Appends a directory with the given ID entries at the end of `data`.
+    fn directory(data: &mut Vec<u8>, entries: &[(u32, u32)]) {
+        data.extend_from_slice(&[0; 14]);
+        data.extend_from_slice(&(entries.len() as u16).to_le_bytes());
+        for (id, target) in entries {
+            data.extend_from_slice(&id.to_le_bytes());
+            data.extend_from_slice(&target.to_le_bytes());
+        }
+    }
+
+    /// RT_RCDATA #1, language 1033, `languages` times over, holding "MZ\0\0".
+    fn tree(languages: usize) -> Vec<u8> {
+        let mut data = Vec::new();
+        directory(&mut data, &[(10, 0x8000_0000 | 0x18)]);
+        directory(&mut data, &[(1, 0x8000_0000 | 0x30)]);
+        let data_entry = 0x30 + 16 + languages as u32 * 8;
+        directory(&mut data, &vec![(1033, data_entry); languages]);
+        data.extend_from_slice(&(SECTION_RVA + data_entry + 16).to_le_bytes());
+        data.extend_from_slice(&4u32.to_le_bytes());
+        data.extend_from_slice(&[0; 8]);
+        data.extend_from_slice(b"MZ\0\0");
+        data
+    }
+
+    #[test]
+    fn walks_the_resource_tree() {
+        let data = tree(1);
+        let resources = walk_resources(&data, &sections(&data), SECTION_RVA);
+        assert_eq!(resources.len(), 1);
+        let resource = &resources[0];
+        assert_eq!((resource.resource_type.as_str(), resource.name.as_str()), ("RT_RCDATA", "1"));
+        assert_eq!((resource.language, resource.offset, resource.size), (1033, data.len() - 4, 4));
+        assert!(resource.embedded_pe);
+
+        assert_eq!(walk_resources(&data, &sections(&data), 0x9000).len(), 0, "root outside every section");
+
+        let many = tree(3000);
+        assert_eq!(walk_resources(&many, &sections(&many), SECTION_RVA).len(), MAX_RESOURCES);
+    }
+
+    #[test]
+    fn self_referencing_directories_end_the_walk() {
+        // Every entry points back at the root, at every level
+        let mut data = Vec::new();
+        directory(&mut data, &vec![(10, 0x8000_0000); 0xffff]);
+        assert!(walk_resources(&data, &sections(&data), SECTION_RVA).is_empty());
+
+        // A language directory pointing at its parent
+        let mut data = Vec::new();
+        directory(&mut data, &[(10, 0x8000_0000 | 0x18)]);
+        directory(&mut data, &[(1, 0x8000_0000 | 0x18)]);
+        assert!(walk_resources(&data, &sections(&data), SECTION_RVA).is_empty());
+    }
+
+    #[test]
+    fn truncated_trees_keep_what_is_there() {
+        let data = tree(1);
+        // The language directory is cut off in its header
+        let cut = &data[..0x3c];
+        assert!(walk_resources(cut, &sections(cut), SECTION_RVA).is_empty());
+
+        // The directory claims more entries than the file holds
+        let mut claimed = data.clone();
+        claimed[0x30 + 14..0x30 + 16].copy_from_slice(&0xffffu16.to_le_bytes());
+        let resources = walk_resources(&claimed, &sections(&claimed), SECTION_RVA);
+        assert!(!resources.is_empty() && resources.len() < MAX_RESOURCES);
+
+        // Resource data running past the end is measured as far as it goes
+        let cut = &data[..data.len() - 2];
+        let resources = walk_resources(cut, &sections(cut), SECTION_RVA);
+        assert_eq!((resources.len(), resources[0].size), (1, 4));
+        assert!(resources[0].embedded_pe);
+    }
+}
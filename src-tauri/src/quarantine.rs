@@ -0,0 +1,679 @@
+//! Encrypted quarantine vault.
+//!
+//! Quarantined files are moved into the vault directory encrypted with
+//! ChaCha20-Poly1305 in STREAM mode, so the stored copy can neither be
+//! executed nor matched by other scanners, and files of any size are processed
+//! in fixed-size chunks. The vault key is generated on first use and kept next
+//! to the items. Every item has a JSON record with the original location,
+//! permissions, owner, SHA-256 and detection names; restores decrypt to a
+//! temporary file and only move it into place once the hash matches.
+
+use chacha20poly1305::aead::stream::{DecryptorBE32, EncryptorBE32, Nonce, StreamBE32};
+use chacha20poly1305::aead::{AeadCore, KeyInit, OsRng};
+use chacha20poly1305::{ChaCha20Poly1305, Key};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use uuid::Uuid;
+
+use crate::models::timestamp;
+
+const KEY_FILE: &str = "vault.key";
+const ITEM_MAGIC: &[u8; 4] = b"VQ01";
+const CHUNK_SIZE: usize = 64 * 1024;
+const TAG_SIZE: usize = 16;
+/// STREAM reserves five bytes of the 96-bit nonce for its counter.
+const STREAM_NONCE_SIZE: usize = 7;
+
+type ItemNonce = Nonce<ChaCha20Poly1305, StreamBE32<ChaCha20Poly1305>>;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct QuarantineEntry {
+    pub id: String,
+    pub original_path: String,
+    pub file_name: String,
+    pub size: u64,
+    pub sha256: String,
+    pub detections: Vec<String>,
+    pub quarantined_at: String,
+    /// Unix mode bits of the original file.
+    pub mode: Option<u32>,
+    pub uid: Option<u32>,
+    pub gid: Option<u32>,
+    pub readonly: bool,
+}
+
+/// A record that could not be read; its item stays in the vault until purged.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct QuarantineRecordError {
+    pub id: String,
+    pub message: String,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct QuarantineListing {
+    pub items: Vec<QuarantineEntry>,
+    pub errors: Vec<QuarantineRecordError>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RestoreConflict {
+    /// Refuse to restore when the destination exists.
+    #[default]
+    Fail,
+    Overwrite,
+    /// Restore next to the existing file as `name (restored N).ext`.
+    Rename,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RestoreOutcome {
+    pub id: String,
+    pub restored_path: String,
+    /// Set when the original owner or permissions could not be reapplied.
+    pub warnings: Vec<String>,
+}
+
+#[derive(Debug)]
+pub enum QuarantineError {
+    Io(io::Error),
+    Json(serde_json::Error),
+    NotFound(String),
+    NotAFile(String),
+    Exists(String),
+    /// The vault item failed authentication; it was modified or truncated.
+    Corrupt(String),
+    HashMismatch { expected: String, actual: String },
+}
+
+impl std::fmt::Display for QuarantineError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            QuarantineError::Io(e) => write!(f, "{}", e),
+            QuarantineError::Json(e) => write!(f, "corrupt quarantine record: {}", e),
+            QuarantineError::NotFound(id) => write!(f, "no quarantined item with ID {}", id),
+            QuarantineError::NotAFile(path) => write!(f, "{} is not a regular file", path),
+            QuarantineError::Exists(path) => write!(f, "{} already exists", path),
+            QuarantineError::Corrupt(id) => write!(f, "quarantined item {} is corrupt", id),
+            QuarantineError::HashMismatch { expected, actual } => {
+                write!(f, "hash mismatch: expected {}, got {}", expected, actual)
+            }
+        }
+    }
+}
+
+impl std::error::Error for QuarantineError {}
+
+impl From<io::Error> for QuarantineError {
+    fn from(e: io::Error) -> Self {
+        QuarantineError::Io(e)
+    }
+}
+
+impl From<serde_json::Error> for QuarantineError {
+    fn from(e: serde_json::Error) -> Self {
+        QuarantineError::Json(e)
+    }
+}
+
+pub struct QuarantineVault {
+    dir: PathBuf,
+}
+
+impl QuarantineVault {
+    pub fn new(dir: PathBuf) -> Self {
+        QuarantineVault { dir }
+    }
+
+    fn item_path(&self, id: &str) -> PathBuf {
+        self.dir.join(format!("{}.vault", id))
+    }
+
+    fn record_path(&self, id: &str) -> PathBuf {
+        self.dir.join(format!("{}.json", id))
+    }
+
+    fn key(&self) -> Result<Key, QuarantineError> {
+        let path = self.dir.join(KEY_FILE);
+        match fs::read(&path) {
+            Ok(bytes) if bytes.len() == 32 => return Ok(*Key::from_slice(&bytes)),
+            Ok(_) => return Err(QuarantineError::Corrupt(KEY_FILE.to_string())),
+            Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e.into()),
+            Err(_) => {}
+        }
+
+        fs::create_dir_all(&self.dir)?;
+        let key = ChaCha20Poly1305::generate_key(&mut OsRng);
+        let mut file = create_private(&path)?;
+        file.write_all(&key)?;
+        file.sync_all()?;
+        Ok(key)
+    }
+
+    /// Moves `path` into the vault. The original is only deleted once the
+    /// encrypted copy and its record are on disk.
+    pub fn quarantine(&self, path: &Path, detections: Vec<String>) -> Result<QuarantineEntry, QuarantineError> {
+        let metadata = fs::symlink_metadata(path)?;
+        if !metadata.is_file() {
+            return Err(QuarantineError::NotAFile(path.to_string_lossy().to_string()));
+        }
+        let key = self.key()?;
+
+        let id = Uuid::new_v4().to_string();
+        let item_path = self.item_path(&id);
+        let sha256 = match encrypt_file(&key, path, &item_path) {
+            Ok(sha256) => sha256,
+            Err(e) => {
+                let _ = fs::remove_file(&item_path);
+                return Err(e);
+            }
+        };
+
+        let (mode, uid, gid) = ownership(&metadata);
+        let entry = QuarantineEntry {
+            id: id.clone(),
+            original_path: path.to_string_lossy().to_string(),
+            file_name: path.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default(),
+            size: metadata.len(),
+            sha256,
+            detections,
+            quarantined_at: timestamp(),
+            mode,
+            uid,
+            gid,
+            readonly: metadata.permissions().readonly(),
+        };
+
+        let stored = fs::write(self.record_path(&id), serde_json::to_vec_pretty(&entry)?)
+            .map_err(QuarantineError::from)
+            .and_then(|_| fs::remove_file(path).map_err(QuarantineError::from));
+        if let Err(e) = stored {
+            self.remove_item(&id);
+            return Err(e);
+        }
+        Ok(entry)
+    }
+
+    /// Lists the vault, newest first. Records that can't be read are skipped
+    /// and reported one by one instead of failing the whole listing.
+    pub fn list(&self) -> Result<QuarantineListing, QuarantineError> {
+        let mut listing = QuarantineListing::default();
+        for id in self.record_ids()? {
+            let record = fs::read(self.record_path(&id))
+                .map_err(QuarantineError::from)
+                .and_then(|bytes| Ok(serde_json::from_slice::<QuarantineEntry>(&bytes)?));
+            match record {
+                Ok(entry) => listing.items.push(entry),
+                Err(e) => listing.errors.push(QuarantineRecordError { id, message: e.to_string() }),
+            }
+        }
+        listing.items.sort_by(|a, b| b.quarantined_at.cmp(&a.quarantined_at).then_with(|| a.id.cmp(&b.id)));
+        listing.errors.sort_by(|a, b| a.id.cmp(&b.id));
+        Ok(listing)
+    }
+
+    /// IDs of all records in the vault, readable or not.
+    fn record_ids(&self) -> Result<Vec<String>, QuarantineError> {
+        let entries = match fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e.into()),
+        };
+
+        let mut ids = Vec::new();
+        for entry in entries {
+            let path = entry?.path();
+            if path.extension().and_then(|e| e.to_str()) != Some("json") {
+                continue;
+            }
+            if let Some(id) = path.file_stem().and_then(|s| s.to_str()).filter(|s| Uuid::parse_str(s).is_ok()) {
+                ids.push(id.to_string());
+            }
+        }
+        Ok(ids)
+    }
+
+    pub fn get(&self, id: &str) -> Result<QuarantineEntry, QuarantineError> {
+        // IDs come from the frontend, never let them address other files
+        if Uuid::parse_str(id).is_err() {
+            return Err(QuarantineError::NotFound(id.to_string()));
+        }
+        match fs::read(self.record_path(id)) {
+            Ok(bytes) => Ok(serde_json::from_slice(&bytes)?),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Err(QuarantineError::NotFound(id.to_string())),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Restores an item to its original location, or to `destination` when
+    /// given, and removes it from the vault.
+    pub fn restore(
+        &self,
+        id: &str,
+        destination: Option<&Path>,
+        conflict: RestoreConflict,
+    ) -> Result<RestoreOutcome, QuarantineError> {
+        let entry = self.get(id)?;
+        let key = self.key()?;
+
+        let wanted = destination.map_or_else(|| PathBuf::from(&entry.original_path), Path::to_path_buf);
+        let mut target = wanted.clone();
+        if target.exists() {
+            match conflict {
+                RestoreConflict::Fail => return Err(QuarantineError::Exists(target.to_string_lossy().to_string())),
+                RestoreConflict::Overwrite => {}
+                RestoreConflict::Rename => target = free_name(&wanted),
+            }
+        }
+        let parent = target.parent().filter(|p| !p.as_os_str().is_empty()).unwrap_or(Path::new("."));
+        fs::create_dir_all(parent)?;
+
+        // Decrypt next to the destination so the final rename stays on one filesystem
+        let temp = parent.join(format!(".{}.restore", entry.id));
+        let actual = match decrypt_file(&key, &self.item_path(id), &temp, id) {
+            Ok(actual) => actual,
+            Err(e) => {
+                let _ = fs::remove_file(&temp);
+                return Err(e);
+            }
+        };
+        if actual != entry.sha256 {
+            let _ = fs::remove_file(&temp);
+            return Err(QuarantineError::HashMismatch { expected: entry.sha256, actual });
+        }
+
+        let warnings = restore_attributes(&temp, &entry);
+        // The destination may have appeared while decrypting, only replace it when asked to
+        let moved = loop {
+            let result = match conflict {
+                RestoreConflict::Overwrite => fs::rename(&temp, &target),
+                RestoreConflict::Fail | RestoreConflict::Rename => rename_no_replace(&temp, &target),
+            };
+            match result {
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists && conflict == RestoreConflict::Rename => {
+                    target = free_name(&wanted);
+                }
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {
+                    break Err(QuarantineError::Exists(target.to_string_lossy().to_string()));
+                }
+                result => break result.map_err(QuarantineError::from),
+            }
+        };
+        if let Err(e) = moved {
+            let _ = fs::remove_file(&temp);
+            return Err(e);
+        }
+        self.remove_item(id);
+
+        Ok(RestoreOutcome {
+            id: entry.id,
+            restored_path: target.to_string_lossy().to_string(),
+            warnings,
+        })
+    }
+
+    /// Permanently deletes the given items, or the whole vault when `ids` is
+    /// `None`. Items with damaged records can be purged too. Returns the
+    /// number of items removed.
+    pub fn purge(&self, ids: Option<&[String]>) -> Result<usize, QuarantineError> {
+        let ids: Vec<String> = match ids {
+            Some(ids) => ids.iter().map(|id| self.existing_id(id)).collect::<Result<_, _>>()?,
+            None => self.record_ids()?,
+        };
+        for id in &ids {
+            self.remove_item(id);
+        }
+        Ok(ids.len())
+    }
+
+    /// Validates an ID from the frontend without parsing its record.
+    fn existing_id(&self, id: &str) -> Result<String, QuarantineError> {
+        if Uuid::parse_str(id).is_err() || !(self.record_path(id).exists() || self.item_path(id).exists()) {
+            return Err(QuarantineError::NotFound(id.to_string()));
+        }
+        Ok(id.to_string())
+    }
+
+    fn remove_item(&self, id: &str) {
+        let _ = fs::remove_file(self.item_path(id));
+        let _ = fs::remove_file(self.record_path(id));
+    }
+}
+
+fn create_private(path: &Path) -> io::Result<File> {
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    options.open(path)
+}
+
+/// Renames `from` to `to`, failing with `AlreadyExists` instead of replacing
+/// a file that is already there.
+#[cfg(target_os = "linux")]
+fn rename_no_replace(from: &Path, to: &Path) -> io::Result<()> {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+
+    let c_string = |path: &Path| {
+        CString::new(path.as_os_str().as_bytes()).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))
+    };
+    let (c_from, c_to) = (c_string(from)?, c_string(to)?);
+    // SAFETY: both paths are NUL-terminated and outlive the call
+    let renamed = unsafe {
+        libc::renameat2(libc::AT_FDCWD, c_from.as_ptr(), libc::AT_FDCWD, c_to.as_ptr(), libc::RENAME_NOREPLACE)
+    };
+    if renamed == 0 {
+        return Ok(());
+    }
+    let error = io::Error::last_os_error();
+    match error.raw_os_error() {
+        // Filesystems without RENAME_NOREPLACE support, and old kernels
+        Some(libc::EINVAL) | Some(libc::ENOSYS) => link_and_unlink(from, to),
+        _ => Err(error),
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn rename_no_replace(from: &Path, to: &Path) -> io::Result<()> {
+    link_and_unlink(from, to)
+}
+
+/// Creating the hard link fails if `to` exists, which makes it a
+/// non-replacing rename within one filesystem.
+fn link_and_unlink(from: &Path, to: &Path) -> io::Result<()> {
+    fs::hard_link(from, to)?;
+    fs::remove_file(from)
+}
+
+/// Reads until `buf` is full or the reader is exhausted.
+fn fill(reader: &mut impl Read, buf: &mut [u8]) -> io::Result<usize> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match reader.read(&mut buf[filled..]) {
+            Ok(0) => break,
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(filled)
+}
+
+/// Encrypts `source` into `dest` and returns the plaintext SHA-256.
+fn encrypt_file(key: &Key, source: &Path, dest: &Path) -> Result<String, QuarantineError> {
+    let mut reader = BufReader::new(File::open(source)?);
+    let mut writer = BufWriter::new(create_private(dest)?);
+
+    let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+    let nonce = ItemNonce::from_slice(&nonce[..STREAM_NONCE_SIZE]);
+    let mut encryptor = EncryptorBE32::<ChaCha20Poly1305>::new(key, nonce);
+    writer.write_all(ITEM_MAGIC)?;
+    writer.write_all(nonce)?;
+
+    let mut hasher = Sha256::new();
+    let mut current = vec![0u8; CHUNK_SIZE];
+    let mut next = vec![0u8; CHUNK_SIZE];
+    let mut len = fill(&mut reader, &mut current)?;
+    // Read one chunk ahead so the final chunk can be sealed as the last one
+    loop {
+        hasher.update(&current[..len]);
+        let next_len = fill(&mut reader, &mut next)?;
+        if next_len == 0 {
+            let sealed = encryptor
+                .encrypt_last(&current[..len])
+                .map_err(|_| QuarantineError::Corrupt(dest.to_string_lossy().to_string()))?;
+            writer.write_all(&sealed)?;
+            break;
+        }
+        let sealed = encryptor
+            .encrypt_next(&current[..len])
+            .map_err(|_| QuarantineError::Corrupt(dest.to_string_lossy().to_string()))?;
+        writer.write_all(&sealed)?;
+        std::mem::swap(&mut current, &mut next);
+        len = next_len;
+    }
+
+    writer.into_inner().map_err(|e| e.into_error())?.sync_all()?;
+    Ok(hex::encode(hasher.finalize()))
+}
+
+/// Decrypts a vault item into `dest` and returns the plaintext SHA-256.
+fn decrypt_file(key: &Key, source: &Path, dest: &Path, id: &str) -> Result<String, QuarantineError> {
+    let corrupt = || QuarantineError::Corrupt(id.to_string());
+    let file = File::open(source)?;
+    let mut remaining = file.metadata()?.len();
+    let mut reader = BufReader::new(file);
+
+    let mut header = [0u8; ITEM_MAGIC.len() + STREAM_NONCE_SIZE];
+    if fill(&mut reader, &mut header)? != header.len() || &header[..ITEM_MAGIC.len()] != ITEM_MAGIC {
+        return Err(corrupt());
+    }
+    remaining -= header.len() as u64;
+    let nonce = ItemNonce::from_slice(&header[ITEM_MAGIC.len()..]);
+    let mut decryptor = DecryptorBE32::<ChaCha20Poly1305>::new(key, nonce);
+
+    let _ = fs::remove_file(dest);
+    let mut writer = BufWriter::new(create_private(dest)?);
+    let mut hasher = Sha256::new();
+    let mut chunk = vec![0u8; CHUNK_SIZE + TAG_SIZE];
+    loop {
+        let len = fill(&mut reader, &mut chunk)?;
+        remaining = remaining.saturating_sub(len as u64);
+        if remaining == 0 {
+            let plain = decryptor.decrypt_last(&chunk[..len]).map_err(|_| corrupt())?;
+            hasher.update(&plain);
+            writer.write_all(&plain)?;
+            break;
+        }
+        let plain = decryptor.decrypt_next(&chunk[..len]).map_err(|_| corrupt())?;
+        hasher.update(&plain);
+        writer.write_all(&plain)?;
+    }
+
+    writer.into_inner().map_err(|e| e.into_error())?.sync_all()?;
+    Ok(hex::encode(hasher.finalize()))
+}
+
+#[cfg(unix)]
+fn ownership(metadata: &fs::Metadata) -> (Option<u32>, Option<u32>, Option<u32>) {
+    use std::os::unix::fs::MetadataExt;
+    (Some(metadata.mode() & 0o7777), Some(metadata.uid()), Some(metadata.gid()))
+}
+
+#[cfg(not(unix))]
+fn ownership(_metadata: &fs::Metadata) -> (Option<u32>, Option<u32>, Option<u32>) {
+    (None, None, None)
+}
+
+/// Reapplies the recorded owner and permissions. Failures are reported, not
+/// fatal; restoring as an unprivileged user can't hand a file back to root.
+fn restore_attributes(path: &Path, entry: &QuarantineEntry) -> Vec<String> {
+    let mut warnings = Vec::new();
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        use std::os::unix::fs::MetadataExt;
+        let current = fs::metadata(path).map(|m| (m.uid(), m.gid())).ok();
+        let recorded = entry.uid.zip(entry.gid);
+        if recorded.is_some() && recorded != current {
+            if let Err(e) = std::os::unix::fs::chown(path, entry.uid, entry.gid) {
+                warnings.push(format!("Could not restore owner: {}", e));
+            }
+        }
+        if let Some(mode) = entry.mode {
+            if let Err(e) = fs::set_permissions(path, fs::Permissions::from_mode(mode)) {
+                warnings.push(format!("Could not restore permissions: {}", e));
+            }
+        }
+    }
+    #[cfg(not(unix))]
+    if entry.readonly {
+        let result = fs::metadata(path).and_then(|m| {
+            let mut permissions = m.permissions();
+            permissions.set_readonly(true);
+            fs::set_permissions(path, permissions)
+        });
+        if let Err(e) = result {
+            warnings.push(format!("Could not restore permissions: {}", e));
+        }
+    }
+
+    warnings
+}
+
+/// First `name (restored N).ext` next to `path` that doesn't exist yet.
+fn free_name(path: &Path) -> PathBuf {
+    let stem = path.file_stem().map(|s| s.to_string_lossy().to_string()).unwrap_or_default();
+    let extension = path.extension().map(|e| format!(".{}", e.to_string_lossy())).unwrap_or_default();
+    (1..)
+        .map(|n| path.with_file_name(format!("{} (restored {}){}", stem, n, extension)))
+        .find(|candidate| !candidate.exists())
+        .expect("unbounded candidate range")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn workspace(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("varenizer-quarantine-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// Spans several chunks with a partial last one.
+    fn sample() -> Vec<u8> {
+        (0..3 * CHUNK_SIZE + 17).map(|i| (i % 253) as u8).collect()
+    }
+
+    #[test]
+    fn round_trip() {
+        let dir = workspace("round-trip");
+        let vault = QuarantineVault::new(dir.join("vault"));
+        let original = dir.join("sample.bin");
+        fs::write(&original, sample()).unwrap();
+
+        let entry = vault.quarantine(&original, vec!["Test.Detection".to_string()]).unwrap();
+        assert!(!original.exists());
+        assert_eq!(entry.size, sample().len() as u64);
+        let listing = vault.list().unwrap();
+        assert_eq!(listing.items.len(), 1);
+        assert!(listing.errors.is_empty());
+        assert_eq!(listing.items[0].detections, ["Test.Detection"]);
+
+        let outcome = vault.restore(&entry.id, None, RestoreConflict::Fail).unwrap();
+        assert_eq!(PathBuf::from(&outcome.restored_path), original);
+        assert_eq!(fs::read(&original).unwrap(), sample());
+        assert!(vault.list().unwrap().items.is_empty());
+        assert!(!vault.item_path(&entry.id).exists());
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn items_are_encrypted_and_authenticated() {
+        let dir = workspace("encrypted");
+        let vault = QuarantineVault::new(dir.join("vault"));
+        let original = dir.join("sample.bin");
+        let marker = b"X5O!P%@AP[4\\PZX54(P^)7CC)7}$EICAR-STANDARD-ANTIVIRUS-TEST-FILE!$H+H*";
+        let mut content = sample();
+        content.extend_from_slice(marker);
+        fs::write(&original, &content).unwrap();
+
+        let entry = vault.quarantine(&original, Vec::new()).unwrap();
+        let stored = fs::read(vault.item_path(&entry.id)).unwrap();
+        assert!(stored.starts_with(ITEM_MAGIC));
+        assert!(!stored.windows(marker.len()).any(|w| w == marker));
+        assert!(!stored.windows(64).any(|w| content.windows(64).next() == Some(w)));
+
+        // Flipping a byte or dropping the last chunk must both be noticed
+        let mut tampered = stored.clone();
+        tampered[ITEM_MAGIC.len() + STREAM_NONCE_SIZE + 100] ^= 1;
+        fs::write(vault.item_path(&entry.id), &tampered).unwrap();
+        assert!(matches!(vault.restore(&entry.id, None, RestoreConflict::Fail), Err(QuarantineError::Corrupt(_))));
+
+        let truncated = &stored[..ITEM_MAGIC.len() + STREAM_NONCE_SIZE + CHUNK_SIZE + TAG_SIZE];
+        fs::write(vault.item_path(&entry.id), truncated).unwrap();
+        assert!(matches!(vault.restore(&entry.id, None, RestoreConflict::Fail), Err(QuarantineError::Corrupt(_))));
+        assert!(!original.exists());
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1, "no temporary file left behind");
+
+        fs::write(vault.item_path(&entry.id), &stored).unwrap();
+        vault.restore(&entry.id, None, RestoreConflict::Fail).unwrap();
+        assert_eq!(fs::read(&original).unwrap(), content);
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn restore_conflicts() {
+        let dir = workspace("conflicts");
+        let vault = QuarantineVault::new(dir.join("vault"));
+        let original = dir.join("report.pdf");
+        fs::write(&original, b"quarantined").unwrap();
+        let entry = vault.quarantine(&original, Vec::new()).unwrap();
+        fs::write(&original, b"replacement").unwrap();
+
+        assert!(matches!(vault.restore(&entry.id, None, RestoreConflict::Fail), Err(QuarantineError::Exists(_))));
+        assert_eq!(fs::read(&original).unwrap(), b"replacement");
+
+        let outcome = vault.restore(&entry.id, None, RestoreConflict::Rename).unwrap();
+        assert_eq!(PathBuf::from(&outcome.restored_path), dir.join("report (restored 1).pdf"));
+        assert_eq!(fs::read(&outcome.restored_path).unwrap(), b"quarantined");
+        assert_eq!(fs::read(&original).unwrap(), b"replacement");
+
+        let entry = vault.quarantine(&dir.join("report (restored 1).pdf"), Vec::new()).unwrap();
+        vault.restore(&entry.id, Some(&original), RestoreConflict::Overwrite).unwrap();
+        assert_eq!(fs::read(&original).unwrap(), b"quarantined");
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn renames_never_replace() {
+        let dir = workspace("no-replace");
+        let (from, to) = (dir.join("from"), dir.join("to"));
+        fs::write(&from, b"new").unwrap();
+        fs::write(&to, b"old").unwrap();
+        assert_eq!(rename_no_replace(&from, &to).unwrap_err().kind(), io::ErrorKind::AlreadyExists);
+        assert_eq!(fs::read(&to).unwrap(), b"old");
+        assert!(from.exists());
+
+        fs::remove_file(&to).unwrap();
+        rename_no_replace(&from, &to).unwrap();
+        assert_eq!(fs::read(&to).unwrap(), b"new");
+        assert!(!from.exists());
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn damaged_records_are_reported_and_purged() {
+        let dir = workspace("damaged");
+        let vault = QuarantineVault::new(dir.join("vault"));
+        for name in ["a.bin", "b.bin"] {
+            fs::write(dir.join(name), name).unwrap();
+        }
+        let good = vault.quarantine(&dir.join("a.bin"), Vec::new()).unwrap();
+        let bad = vault.quarantine(&dir.join("b.bin"), Vec::new()).unwrap();
+        fs::write(vault.record_path(&bad.id), b"{\"id\": ").unwrap();
+
+        let listing = vault.list().unwrap();
+        assert_eq!(listing.items.iter().map(|e| &e.id).collect::<Vec<_>>(), [&good.id]);
+        assert_eq!(listing.errors.len(), 1);
+        assert_eq!(listing.errors[0].id, bad.id);
+        assert!(listing.errors[0].message.starts_with("corrupt quarantine record"));
+
+        assert_eq!(vault.purge(Some(std::slice::from_ref(&bad.id))).unwrap(), 1);
+        assert!(!vault.item_path(&bad.id).exists());
+        assert!(matches!(vault.purge(Some(&["../vault".to_string()])), Err(QuarantineError::NotFound(_))));
+        assert_eq!(vault.purge(None).unwrap(), 1);
+        assert!(vault.list().unwrap().items.is_empty());
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,210 @@
+//! Notification-only fanotify backend.
+//!
+//! Marks are placed on whole mounts, so events arrive for every file on the
+//! filesystem and are filtered down to the watched directories here. The
+//! kernel hands over an open descriptor per event; its path is resolved
+//! through `/proc/self/fd` and the descriptor closed right away. A mount's
+//! mark is removed once no watched directory is left on it.
+
+use std::ffi::{CString, OsString};
+use std::io;
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread::JoinHandle;
+
+/// Poll timeout, bounds how long dropping the watcher waits for its thread.
+const POLL_TIMEOUT_MS: i32 = 200;
+const EVENT_BUFFER_SIZE: usize = 64 * 1024;
+
+pub struct FanotifyWatcher {
+    fd: i32,
+    roots: Arc<Mutex<Vec<PathBuf>>>,
+    /// Mount point carrying the mark for each watched directory.
+    mounts: Vec<(PathBuf, PathBuf)>,
+    stop: Arc<AtomicBool>,
+    reader: Option<JoinHandle<()>>,
+}
+
+impl FanotifyWatcher {
+    pub fn new(sender: mpsc::Sender<PathBuf>) -> io::Result<Self> {
+        // SAFETY: plain syscall, the returned descriptor is owned by the watcher
+        let fd = unsafe {
+            libc::fanotify_init(
+                libc::FAN_CLASS_NOTIF | libc::FAN_CLOEXEC | libc::FAN_NONBLOCK,
+                (libc::O_RDONLY | libc::O_LARGEFILE | libc::O_CLOEXEC) as u32,
+            )
+        };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        let roots = Arc::new(Mutex::new(Vec::new()));
+        let stop = Arc::new(AtomicBool::new(false));
+        let reader = {
+            let (roots, stop) = (roots.clone(), stop.clone());
+            std::thread::spawn(move || read_events(fd, &roots, &stop, &sender))
+        };
+        Ok(FanotifyWatcher { fd, roots, mounts: Vec::new(), stop, reader: Some(reader) })
+    }
+
+    pub fn watch(&mut self, path: &Path) -> io::Result<()> {
+        let path = path.canonicalize()?;
+        let mounts = std::fs::read_to_string("/proc/self/mountinfo")?;
+        let mount = mount_point(&mounts, &path).unwrap_or_else(|| path.clone());
+        self.mark(libc::FAN_MARK_ADD, &path)?;
+        self.roots.lock().unwrap_or_else(|e| e.into_inner()).push(path.clone());
+        self.mounts.push((path, mount));
+        Ok(())
+    }
+
+    /// Stops reporting events below `path`, and removes the mount mark when
+    /// no other watched directory lives on that mount.
+    pub fn unwatch(&mut self, path: &Path) -> io::Result<()> {
+        let path = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+        self.roots.lock().unwrap_or_else(|e| e.into_inner()).retain(|root| *root != path);
+        let Some(index) = self.mounts.iter().position(|(root, _)| *root == path) else { return Ok(()) };
+        let (_, mount) = self.mounts.remove(index);
+        if self.mounts.iter().any(|(_, other)| *other == mount) {
+            return Ok(());
+        }
+        self.mark(libc::FAN_MARK_REMOVE, &mount)
+    }
+
+    fn mark(&self, action: libc::c_uint, path: &Path) -> io::Result<()> {
+        let c_path = CString::new(path.as_os_str().as_bytes())
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        // SAFETY: `c_path` outlives the call and `self.fd` is a live fanotify descriptor
+        let marked = unsafe {
+            libc::fanotify_mark(
+                self.fd,
+                action | libc::FAN_MARK_MOUNT,
+                libc::FAN_CLOSE_WRITE,
+                libc::AT_FDCWD,
+                c_path.as_ptr(),
+            )
+        };
+        if marked < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+}
+
+/// Mount point of the mount `path` is on, from `/proc/self/mountinfo`
+/// contents. Later entries shadow earlier ones mounted at the same place.
+fn mount_point(mountinfo: &str, path: &Path) -> Option<PathBuf> {
+    let mut best: Option<PathBuf> = None;
+    for line in mountinfo.lines() {
+        let Some(field) = line.split(' ').nth(4) else { continue };
+        let point = unescape(field);
+        let longer = best.as_ref().is_none_or(|b| point.components().count() >= b.components().count());
+        if path.starts_with(&point) && longer {
+            best = Some(point);
+        }
+    }
+    best
+}
+
+/// Undoes the octal escapes (`\040` for a space) mountinfo uses in paths.
+fn unescape(field: &str) -> PathBuf {
+    let bytes = field.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let octal = bytes.get(i + 1..i + 4).filter(|d| d.iter().all(|b| (b'0'..=b'7').contains(b)));
+        match (bytes[i], octal) {
+            (b'\\', Some(digits)) => {
+                out.push(digits.iter().fold(0u8, |n, d| n.wrapping_mul(8) + (d - b'0')));
+                i += 4;
+            }
+            (byte, _) => {
+                out.push(byte);
+                i += 1;
+            }
+        }
+    }
+    PathBuf::from(OsString::from_vec(out))
+}
+
+impl Drop for FanotifyWatcher {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+        if let Some(reader) = self.reader.take() {
+            let _ = reader.join();
+        }
+        // SAFETY: the reader thread has exited, nothing else uses the descriptor
+        unsafe { libc::close(self.fd) };
+    }
+}
+
+fn read_events(fd: i32, roots: &Mutex<Vec<PathBuf>>, stop: &AtomicBool, sender: &mpsc::Sender<PathBuf>) {
+    let mut buffer = vec![0u8; EVENT_BUFFER_SIZE];
+    let header_len = std::mem::size_of::<libc::fanotify_event_metadata>();
+
+    while !stop.load(Ordering::SeqCst) {
+        let mut poll_fd = libc::pollfd { fd, events: libc::POLLIN, revents: 0 };
+        // SAFETY: `poll_fd` is a valid pollfd for the duration of the call
+        let ready = unsafe { libc::poll(&mut poll_fd, 1, POLL_TIMEOUT_MS) };
+        if ready <= 0 {
+            continue;
+        }
+
+        // SAFETY: `buffer` is valid for writes of its full length
+        let len = unsafe { libc::read(fd, buffer.as_mut_ptr().cast(), buffer.len()) };
+        if len <= 0 {
+            continue;
+        }
+
+        let mut offset = 0;
+        while offset + header_len <= len as usize {
+            // SAFETY: the kernel writes whole, possibly unaligned, metadata records
+            let event: libc::fanotify_event_metadata =
+                unsafe { std::ptr::read_unaligned(buffer[offset..].as_ptr().cast()) };
+            if event.vers != libc::FANOTIFY_METADATA_VERSION || (event.event_len as usize) < header_len {
+                break;
+            }
+            offset += event.event_len as usize;
+            if event.fd < 0 {
+                // Queue overflow, nothing to resolve
+                continue;
+            }
+
+            let path = std::fs::read_link(format!("/proc/self/fd/{}", event.fd));
+            // SAFETY: the descriptor was handed to us by the kernel and is ours to close
+            unsafe { libc::close(event.fd) };
+
+            if let Ok(path) = path {
+                let watched = roots.lock().unwrap_or_else(|e| e.into_inner()).iter().any(|r| path.starts_with(r));
+                if watched && sender.send(path).is_err() {
+                    return;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_the_innermost_mount() {
+        let mountinfo = "\
+28 1 254:0 / / rw,relatime - ext4 /dev/vda rw
+40 28 0:40 / /home rw,relatime - ext4 /dev/vdb rw
+41 40 0:41 / /home/shared\\040files rw,relatime - nfs server:/files rw
+42 28 0:42 / /home rw,relatime - tmpfs tmpfs rw
+";
+        assert_eq!(mount_point(mountinfo, Path::new("/etc/passwd")), Some(PathBuf::from("/")));
+        assert_eq!(mount_point(mountinfo, Path::new("/home/user")), Some(PathBuf::from("/home")));
+        assert_eq!(mount_point(mountinfo, Path::new("/homework")), Some(PathBuf::from("/")));
+        assert_eq!(
+            mount_point(mountinfo, Path::new("/home/shared files/a")),
+            Some(PathBuf::from("/home/shared files"))
+        );
+        assert_eq!(mount_point("", Path::new("/")), None);
+        assert_eq!(unescape("/a\\011b\\134c\\9"), PathBuf::from("/a\tb\\c\\9"));
+    }
+}
@@ -0,0 +1,447 @@
+//! Real-time protection.
+//!
+//! Watches configured directories and scans files once they have stopped
+//! changing for the debounce interval. Two backends exist:
+//!
+//! - `inotify` (through `notify`, which uses FSEvents/ReadDirectoryChangesW on
+//!   other platforms): recursive, and picks up directories created later.
+//! - `fanotify` (Linux only): marks whole mounts and reports close-after-write
+//!   for every file on them, which catches writes inotify can miss on large
+//!   trees. It needs `CAP_SYS_ADMIN`; without it the watcher falls back to
+//!   inotify and says so in its status.
+
+#[cfg(target_os = "linux")]
+mod fanotify;
+
+use notify::event::{AccessKind, AccessMode, EventKind, ModifyKind};
+use notify::{RecommendedWatcher, RecursiveMode, Watcher};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+use crate::engine::ScanEngine;
+use crate::models::ScanResult;
+use crate::scanner;
+
+const DEFAULT_DEBOUNCE_MS: u64 = 500;
+const DEFAULT_MAX_FILE_SIZE: u64 = 64 * 1024 * 1024;
+/// How often pending paths are checked against the debounce interval.
+const TICK: Duration = Duration::from_millis(100);
+/// How long stopping waits for the scan in progress before leaving the
+/// scanner thread to finish on its own.
+const STOP_TIMEOUT: Duration = Duration::from_secs(2);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum WatchMode {
+    #[default]
+    Inotify,
+    Fanotify,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RealtimeConfig {
+    pub paths: Vec<String>,
+    pub mode: WatchMode,
+    /// Quiet time after the last change before a file is scanned.
+    pub debounce_ms: Option<u64>,
+    /// Larger files are not scanned on change, `0` disables the limit.
+    pub max_file_size: Option<u64>,
+    pub exclude: Vec<String>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct RealtimeStatus {
+    pub running: bool,
+    pub mode: WatchMode,
+    pub paths: Vec<String>,
+    /// Why the requested mode isn't the active one.
+    pub fallback_reason: Option<String>,
+    pub files_scanned: u64,
+    pub detections: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RealtimeError {
+    pub path: String,
+    pub message: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(untagged)]
+pub enum RealtimeEvent {
+    Detection(Box<ScanResult>),
+    Error(RealtimeError),
+}
+
+impl RealtimeEvent {
+    pub fn name(&self) -> &'static str {
+        match self {
+            RealtimeEvent::Detection(_) => "realtime-detection",
+            RealtimeEvent::Error(_) => "realtime-error",
+        }
+    }
+}
+
+pub trait RealtimeObserver: Send + Sync {
+    fn notify(&self, event: &RealtimeEvent);
+}
+
+enum Backend {
+    Notify(RecommendedWatcher),
+    #[cfg(target_os = "linux")]
+    Fanotify(fanotify::FanotifyWatcher),
+}
+
+impl Backend {
+    fn watch(&mut self, path: &Path) -> Result<(), String> {
+        match self {
+            Backend::Notify(watcher) => watcher.watch(path, RecursiveMode::Recursive).map_err(|e| e.to_string()),
+            #[cfg(target_os = "linux")]
+            Backend::Fanotify(watcher) => watcher.watch(path).map_err(|e| e.to_string()),
+        }
+    }
+
+    fn unwatch(&mut self, path: &Path) -> Result<(), String> {
+        match self {
+            Backend::Notify(watcher) => watcher.unwatch(path).map_err(|e| e.to_string()),
+            #[cfg(target_os = "linux")]
+            Backend::Fanotify(watcher) => watcher.unwatch(path).map_err(|e| e.to_string()),
+        }
+    }
+}
+
+fn notify_backend(sender: mpsc::Sender<PathBuf>) -> Result<Backend, String> {
+    let watcher = notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+        let Ok(event) = event else { return };
+        let relevant = matches!(
+            event.kind,
+            EventKind::Create(_)
+                | EventKind::Modify(ModifyKind::Data(_) | ModifyKind::Name(_) | ModifyKind::Any)
+                | EventKind::Access(AccessKind::Close(AccessMode::Write))
+        );
+        if relevant {
+            for path in event.paths {
+                let _ = sender.send(path);
+            }
+        }
+    })
+    .map_err(|e| e.to_string())?;
+    Ok(Backend::Notify(watcher))
+}
+
+struct Counters {
+    scanned: AtomicU64,
+    detections: AtomicU64,
+}
+
+struct ActiveWatcher {
+    backend: Backend,
+    status: RealtimeStatus,
+    counters: Arc<Counters>,
+    stop: Arc<AtomicBool>,
+    scanner: Option<JoinHandle<()>>,
+}
+
+impl Drop for ActiveWatcher {
+    fn drop(&mut self) {
+        // The scanner checks the flag between files and while reading one
+        self.stop.store(true, Ordering::SeqCst);
+        if let Some(handle) = self.scanner.take() {
+            join_within(handle, STOP_TIMEOUT);
+        }
+    }
+}
+
+/// Joins `handle` if it finishes within `timeout`, otherwise detaches it.
+/// Returns whether the thread was joined.
+fn join_within(handle: JoinHandle<()>, timeout: Duration) -> bool {
+    let deadline = Instant::now() + timeout;
+    while !handle.is_finished() {
+        if Instant::now() >= deadline {
+            return false;
+        }
+        std::thread::sleep(Duration::from_millis(10));
+    }
+    let _ = handle.join();
+    true
+}
+
+#[derive(Default)]
+pub struct RealtimeProtection {
+    active: Mutex<Option<ActiveWatcher>>,
+}
+
+impl RealtimeProtection {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Starts watching, replacing a watcher that is already running.
+    pub fn start(
+        &self,
+        engine: Arc<ScanEngine>,
+        config: RealtimeConfig,
+        excluded: Vec<PathBuf>,
+        observer: Arc<dyn RealtimeObserver>,
+    ) -> Result<RealtimeStatus, String> {
+        let mut active = self.active.lock().unwrap_or_else(|e| e.into_inner());
+        // Stop the old watcher first so it releases its inotify/fanotify handles
+        *active = None;
+
+        let (sender, receiver) = mpsc::channel();
+        let mut fallback_reason = None;
+        let (mut backend, mode) = match config.mode {
+            #[cfg(target_os = "linux")]
+            WatchMode::Fanotify => match fanotify::FanotifyWatcher::new(sender.clone()) {
+                Ok(watcher) => (Backend::Fanotify(watcher), WatchMode::Fanotify),
+                Err(e) => {
+                    fallback_reason = Some(format!("fanotify unavailable: {}", e));
+                    (notify_backend(sender)?, WatchMode::Inotify)
+                }
+            },
+            #[cfg(not(target_os = "linux"))]
+            WatchMode::Fanotify => {
+                fallback_reason = Some("fanotify is only available on Linux".to_string());
+                (notify_backend(sender)?, WatchMode::Inotify)
+            }
+            WatchMode::Inotify => (notify_backend(sender)?, WatchMode::Inotify),
+        };
+
+        let mut paths = Vec::new();
+        for path in &config.paths {
+            backend.watch(Path::new(path)).map_err(|e| format!("Cannot watch {}: {}", path, e))?;
+            paths.push(path.clone());
+        }
+
+        let mut excluded = excluded;
+        excluded.extend(config.exclude.iter().map(PathBuf::from));
+        let settings = ScanSettings {
+            debounce: Duration::from_millis(config.debounce_ms.unwrap_or(DEFAULT_DEBOUNCE_MS)),
+            max_file_size: match config.max_file_size {
+                Some(0) => None,
+                limit => Some(limit.unwrap_or(DEFAULT_MAX_FILE_SIZE)),
+            },
+            excluded,
+        };
+        let counters = Arc::new(Counters { scanned: AtomicU64::new(0), detections: AtomicU64::new(0) });
+        let stop = Arc::new(AtomicBool::new(false));
+        let scanner = {
+            let (counters, stop) = (counters.clone(), stop.clone());
+            std::thread::spawn(move || scan_loop(&engine, receiver, &settings, &counters, &stop, observer.as_ref()))
+        };
+
+        let watcher = ActiveWatcher {
+            backend,
+            status: RealtimeStatus { running: true, mode, paths, fallback_reason, files_scanned: 0, detections: 0 },
+            counters,
+            stop,
+            scanner: Some(scanner),
+        };
+        let status = snapshot(&watcher);
+        *active = Some(watcher);
+        Ok(status)
+    }
+
+    pub fn stop(&self) -> RealtimeStatus {
+        let mut active = self.active.lock().unwrap_or_else(|e| e.into_inner());
+        let mut status = active.as_ref().map(snapshot).unwrap_or_default();
+        status.running = false;
+        *active = None;
+        status
+    }
+
+    pub fn status(&self) -> RealtimeStatus {
+        self.active.lock().unwrap_or_else(|e| e.into_inner()).as_ref().map(snapshot).unwrap_or_default()
+    }
+
+    pub fn add_path(&self, path: &str) -> Result<RealtimeStatus, String> {
+        let mut active = self.active.lock().unwrap_or_else(|e| e.into_inner());
+        let watcher = active.as_mut().ok_or("Real-time protection is not running")?;
+        if !watcher.status.paths.iter().any(|p| p == path) {
+            watcher.backend.watch(Path::new(path)).map_err(|e| format!("Cannot watch {}: {}", path, e))?;
+            watcher.status.paths.push(path.to_string());
+        }
+        Ok(snapshot(watcher))
+    }
+
+    pub fn remove_path(&self, path: &str) -> Result<RealtimeStatus, String> {
+        let mut active = self.active.lock().unwrap_or_else(|e| e.into_inner());
+        let watcher = active.as_mut().ok_or("Real-time protection is not running")?;
+        if let Some(index) = watcher.status.paths.iter().position(|p| p == path) {
+            watcher.backend.unwatch(Path::new(path)).map_err(|e| format!("Cannot unwatch {}: {}", path, e))?;
+            watcher.status.paths.remove(index);
+        }
+        Ok(snapshot(watcher))
+    }
+}
+
+fn snapshot(watcher: &ActiveWatcher) -> RealtimeStatus {
+    RealtimeStatus {
+        files_scanned: watcher.counters.scanned.load(Ordering::Relaxed),
+        detections: watcher.counters.detections.load(Ordering::Relaxed),
+        ..watcher.status.clone()
+    }
+}
+
+struct ScanSettings {
+    debounce: Duration,
+    max_file_size: Option<u64>,
+    excluded: Vec<PathBuf>,
+}
+
+impl ScanSettings {
+    /// Exclusions cover whole path components, `/tmp/a` doesn't exclude `/tmp/ab`.
+    fn is_excluded(&self, path: &Path) -> bool {
+        self.excluded.iter().any(|excluded| path.starts_with(excluded))
+    }
+}
+
+/// Collects changed paths and scans each once it has been quiet for the
+/// debounce interval, so a file being written in many small chunks is
+/// scanned once, after the last write.
+fn scan_loop(
+    engine: &ScanEngine,
+    receiver: mpsc::Receiver<PathBuf>,
+    settings: &ScanSettings,
+    counters: &Counters,
+    stop: &AtomicBool,
+    observer: &dyn RealtimeObserver,
+) {
+    let mut pending = Pending::default();
+
+    while !stop.load(Ordering::SeqCst) {
+        match receiver.recv_timeout(TICK) {
+            Ok(path) => {
+                if !settings.is_excluded(&path) {
+                    pending.changed(path, Instant::now());
+                }
+            }
+            Err(mpsc::RecvTimeoutError::Timeout) => {}
+            Err(mpsc::RecvTimeoutError::Disconnected) => break,
+        }
+        // A steady stream of events must not starve the pending files
+        let Some(ready) = pending.take_ready(Instant::now(), settings.debounce) else { continue };
+
+        for path in ready {
+            if stop.load(Ordering::SeqCst) {
+                return;
+            }
+            // Deleted or renamed away in the meantime, or not a regular file
+            let Ok(metadata) = std::fs::metadata(&path) else { continue };
+            if !metadata.is_file() || settings.max_file_size.is_some_and(|limit| metadata.len() > limit) {
+                continue;
+            }
+
+            match scanner::scan_file_until(engine, &path, stop) {
+                Ok(result) => {
+                    counters.scanned.fetch_add(1, Ordering::Relaxed);
+                    if result.status != "clean" {
+                        counters.detections.fetch_add(1, Ordering::Relaxed);
+                        observer.notify(&RealtimeEvent::Detection(Box::new(result)));
+                    }
+                }
+                Err(_) if stop.load(Ordering::SeqCst) => return,
+                Err(e) => observer.notify(&RealtimeEvent::Error(RealtimeError {
+                    path: path.to_string_lossy().to_string(),
+                    message: e.to_string(),
+                })),
+            }
+        }
+    }
+}
+
+/// Changed paths waiting out the debounce interval.
+#[derive(Default)]
+struct Pending {
+    changed: HashMap<PathBuf, Instant>,
+    last_check: Option<Instant>,
+}
+
+impl Pending {
+    /// Records a change, restarting the path's quiet period.
+    fn changed(&mut self, path: PathBuf, at: Instant) {
+        self.changed.insert(path, at);
+    }
+
+    /// Paths that have been quiet for `debounce` at `now`, in order. Returns
+    /// `None` when the last check was less than a tick ago.
+    fn take_ready(&mut self, now: Instant, debounce: Duration) -> Option<Vec<PathBuf>> {
+        if self.last_check.is_some_and(|last| now.duration_since(last) < TICK) {
+            return None;
+        }
+        self.last_check = Some(now);
+
+        let mut ready: Vec<PathBuf> = self
+            .changed
+            .iter()
+            .filter(|(_, changed)| now.duration_since(**changed) >= debounce)
+            .map(|(path, _)| path.clone())
+            .collect();
+        ready.sort();
+        for path in &ready {
+            self.changed.remove(path);
+        }
+        Some(ready)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn files_are_scanned_once_they_stop_changing() {
+        let debounce = Duration::from_millis(500);
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+        let mut pending = Pending::default();
+
+        pending.changed(PathBuf::from("/data/a"), at(0));
+        pending.changed(PathBuf::from("/data/b"), at(100));
+        assert_eq!(pending.take_ready(at(100), debounce), Some(Vec::new()));
+        // Checks are limited to one per tick
+        assert_eq!(pending.take_ready(at(150), debounce), None);
+
+        // Another write restarts the quiet period of that file only
+        pending.changed(PathBuf::from("/data/a"), at(400));
+        assert_eq!(pending.take_ready(at(600), debounce), Some(vec![PathBuf::from("/data/b")]));
+        assert_eq!(pending.take_ready(at(800), debounce), Some(Vec::new()));
+        assert_eq!(pending.take_ready(at(900), debounce), Some(vec![PathBuf::from("/data/a")]));
+        assert_eq!(pending.take_ready(at(5_000), debounce), Some(Vec::new()));
+    }
+
+    #[test]
+    fn exclusions_cover_whole_components() {
+        let settings = ScanSettings {
+            debounce: Duration::ZERO,
+            max_file_size: None,
+            excluded: vec![PathBuf::from("/home/user/.cache"), PathBuf::from("/tmp/build")],
+        };
+        assert!(settings.is_excluded(Path::new("/home/user/.cache/thumb.png")));
+        assert!(settings.is_excluded(Path::new("/tmp/build")));
+        assert!(!settings.is_excluded(Path::new("/tmp/builder/out.bin")));
+        assert!(!settings.is_excluded(Path::new("/home/user/.cache2/x")));
+        assert!(!settings.is_excluded(Path::new("/home/user/Downloads/setup.exe")));
+    }
+
+    #[test]
+    fn stopping_does_not_wait_forever() {
+        let quick = std::thread::spawn(|| {});
+        assert!(join_within(quick, Duration::from_secs(5)));
+
+        let (release, wait) = mpsc::channel::<()>();
+        let stuck = std::thread::spawn(move || {
+            let _ = wait.recv();
+        });
+        let started = Instant::now();
+        assert!(!join_within(stuck, Duration::from_millis(50)));
+        assert!(started.elapsed() < Duration::from_secs(5));
+        drop(release);
+    }
+}
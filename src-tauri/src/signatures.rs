@@ -0,0 +1,1206 @@
+//! Local signature database and matcher.
+//!
+//! Two kinds of signatures are supported:
+//!
+//! * hash signatures, matched against the MD5/SHA-1/SHA-256 of a file
+//! * byte-pattern signatures written as hex with wildcards (`??`, `?a`, `a?`),
+//!   gaps (`{n}`, `{n-m}`, `{-m}`, `{n-}`, `*`) and an optional offset
+//!   constraint (`*`, `n`, `n,range`, `EOF-n`)
+//!
+//! * similarity signatures, ssdeep and TLSH digests of known samples that
+//!   flag files whose fuzzy hashes come close
+//!
+//! Signature files are JSON documents placed in the signatures directory;
+//! ClamAV databases found there are imported through [`crate::clamav`].
+//! The EICAR test signatures are compiled into the binary so detection can
+//! be verified on a machine without any database installed.
+
+use aho_corasick::{AhoCorasick, MatchKind};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::Path;
+
+use crate::clamav;
+use crate::fuzzy::{SsdeepDigest, TlshDigest};
+use crate::hashing::FileDigests;
+
+const BUILTIN_SIGNATURES: &str = include_str!("../signatures/test.json");
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    #[default]
+    Threat,
+    Suspicious,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Detection {
+    pub name: String,
+    pub severity: Severity,
+    pub source: String, // "hash", "pattern", "logical", "yara", or the analyzer name
+    pub offset: Option<usize>,
+    /// Human readable reason, set for heuristic findings.
+    #[serde(default)]
+    pub description: Option<String>,
+}
+
+impl Detection {
+    /// A suspicious finding raised by one of the static analyzers.
+    pub fn heuristic(name: impl Into<String>, source: &str, description: impl Into<String>) -> Self {
+        Detection {
+            name: name.into(),
+            severity: Severity::Suspicious,
+            source: source.to_string(),
+            offset: None,
+            description: Some(description.into()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum HashKind {
+    Md5,
+    Sha1,
+    Sha256,
+}
+
+impl HashKind {
+    fn from_hex_len(len: usize) -> Option<Self> {
+        match len {
+            32 => Some(HashKind::Md5),
+            40 => Some(HashKind::Sha1),
+            64 => Some(HashKind::Sha256),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct HashSignature {
+    name: String,
+    severity: Severity,
+    size: Option<u64>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ByteMatcher {
+    Exact(u8),
+    HighNibble(u8),
+    LowNibble(u8),
+    Any,
+}
+
+impl ByteMatcher {
+    fn matches(self, b: u8) -> bool {
+        match self {
+            ByteMatcher::Exact(v) => b == v,
+            ByteMatcher::HighNibble(v) => b & 0xf0 == v,
+            ByteMatcher::LowNibble(v) => b & 0x0f == v,
+            ByteMatcher::Any => true,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PatternPart {
+    Bytes(Vec<ByteMatcher>),
+    Gap { min: usize, max: Option<usize> },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Offset {
+    Any,
+    Absolute { start: usize, range: usize },
+    FromEnd { distance: usize, range: usize },
+}
+
+impl Offset {
+    pub fn parse(s: &str) -> Result<Self, String> {
+        let s = s.trim();
+        if s.is_empty() || s == "*" {
+            return Ok(Offset::Any);
+        }
+
+        let (base, range) = match s.split_once(',') {
+            Some((base, range)) => (
+                base,
+                range
+                    .trim()
+                    .parse::<usize>()
+                    .map_err(|_| format!("invalid offset range '{}'", range))?,
+            ),
+            None => (s, 0),
+        };
+
+        if let Some(distance) = base.strip_prefix("EOF-") {
+            let distance = distance
+                .parse::<usize>()
+                .map_err(|_| format!("invalid offset '{}'", s))?;
+            return Ok(Offset::FromEnd { distance, range });
+        }
+
+        let start = base
+            .parse::<usize>()
+            .map_err(|_| format!("unsupported offset '{}'", s))?;
+        Ok(Offset::Absolute { start, range })
+    }
+
+    /// Inclusive window of start positions allowed for a match, or `None` when
+    /// the constraint can't be satisfied for this buffer length.
+    fn window(self, len: usize) -> Option<(usize, usize)> {
+        match self {
+            Offset::Any => Some((0, len)),
+            Offset::Absolute { start, range } => {
+                if start > len {
+                    None
+                } else {
+                    Some((start, start.saturating_add(range).min(len)))
+                }
+            }
+            Offset::FromEnd { distance, range } => {
+                let start = len.checked_sub(distance)?;
+                Some((start, start.saturating_add(range).min(len)))
+            }
+        }
+    }
+}
+
+/// File type a body signature applies to, numbered like ClamAV target types.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum Target {
+    #[default]
+    Any,
+    Pe,
+    Ole2,
+    Html,
+    Mail,
+    Graphics,
+    Elf,
+    Ascii,
+    MachO,
+    Pdf,
+    Flash,
+    Java,
+}
+
+impl Target {
+    pub fn from_clamav(n: u32) -> Option<Self> {
+        Some(match n {
+            0 => Target::Any,
+            1 => Target::Pe,
+            2 => Target::Ole2,
+            3 => Target::Html,
+            4 => Target::Mail,
+            5 => Target::Graphics,
+            6 => Target::Elf,
+            7 => Target::Ascii,
+            9 => Target::MachO,
+            10 => Target::Pdf,
+            11 => Target::Flash,
+            12 => Target::Java,
+            _ => return None,
+        })
+    }
+
+    /// Cheap magic-byte check; ClamAV normalizes HTML and text before matching,
+    /// here those targets simply require the buffer to look like text.
+    fn matches(self, data: &[u8]) -> bool {
+        let head = &data[..data.len().min(4096)];
+        let is_text = || !head.is_empty() && head.iter().all(|&b| b >= 0x20 || matches!(b, b'\t' | b'\n' | b'\r' | 0x0c));
+        match self {
+            Target::Any => true,
+            Target::Pe => data.starts_with(b"MZ"),
+            Target::Ole2 => data.starts_with(&[0xd0, 0xcf, 0x11, 0xe0, 0xa1, 0xb1, 0x1a, 0xe1]),
+            Target::Html => is_text() && head.to_ascii_lowercase().windows(5).any(|w| w == b"<html" || w == b"<scri" || w == b"<body"),
+            Target::Mail => is_text() && [&b"From:"[..], b"Received:", b"Return-Path:", b"From "].iter().any(|m| head.starts_with(m)),
+            Target::Graphics => [&b"\x89PNG"[..], b"GIF8", b"\xff\xd8\xff", b"BM", b"II*\x00", b"MM\x00*"].iter().any(|m| data.starts_with(m)),
+            Target::Elf => data.starts_with(b"\x7fELF"),
+            Target::Ascii => is_text(),
+            Target::MachO => [[0xfe, 0xed, 0xfa, 0xce], [0xfe, 0xed, 0xfa, 0xcf], [0xce, 0xfa, 0xed, 0xfe], [0xcf, 0xfa, 0xed, 0xfe], [0xca, 0xfe, 0xba, 0xbe]]
+                .iter()
+                .any(|m| data.starts_with(m)),
+            Target::Pdf => head.windows(5).any(|w| w == b"%PDF-"),
+            Target::Flash => [&b"FWS"[..], b"CWS", b"ZWS"].iter().any(|m| data.starts_with(m)),
+            Target::Java => data.starts_with(&[0xca, 0xfe, 0xba, 0xbe]),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct PatternSignature {
+    pub name: String,
+    pub severity: Severity,
+    pub offset: Offset,
+    pub parts: Vec<PatternPart>,
+    pub target: Target,
+}
+
+impl PatternSignature {
+    /// Parses a signature the matcher can locate, see [`Self::is_anchored`].
+    pub fn new(name: &str, pattern: &str, offset: &str, severity: Severity) -> Result<Self, String> {
+        let signature = Self::parse(name, pattern, offset, severity)?;
+        if !signature.is_anchored() {
+            return Err(format!("pattern '{}' has no {} exact bytes to anchor on", pattern, MIN_ANCHOR_LEN));
+        }
+        Ok(signature)
+    }
+
+    /// Parses a signature without checking that it can be matched.
+    pub fn parse(name: &str, pattern: &str, offset: &str, severity: Severity) -> Result<Self, String> {
+        let parts = parse_pattern(pattern)?;
+        let offset = Offset::parse(offset)?;
+        Ok(PatternSignature {
+            name: name.to_string(),
+            severity,
+            offset,
+            parts,
+            target: Target::Any,
+        })
+    }
+
+    /// Whether the pattern has a run of at least [`MIN_ANCHOR_LEN`] exact
+    /// bytes before its first variable gap. Matching only ever starts from
+    /// where that run occurs; patterns without one are never matched.
+    pub fn is_anchored(&self) -> bool {
+        self.anchor().is_some()
+    }
+
+    /// Returns the offset of the first match in `data` and the number of
+    /// matches (capped at `limit`), trying each place the anchor occurs.
+    fn find_all(&self, data: &[u8], limit: usize) -> Option<(usize, usize)> {
+        let (anchor_offset, anchor) = self.anchor()?;
+        let mut matcher = PartMatcher::new(&self.parts, data);
+        let mut matches = data
+            .windows(anchor.len())
+            .enumerate()
+            .filter(|(_, window)| *window == anchor.as_slice())
+            .filter_map(|(at, _)| at.checked_sub(anchor_offset))
+            .filter(|&start| self.allows_start(start, data.len()) && matcher.matches_at(start));
+        let first = matches.next()?;
+        Some((first, 1 + matches.take(limit.saturating_sub(1)).count()))
+    }
+
+    fn allows_start(&self, start: usize, len: usize) -> bool {
+        self.offset.window(len).is_some_and(|(lo, hi)| (lo..=hi).contains(&start))
+    }
+
+    /// Longest run of exact bytes at a fixed distance from the start of the
+    /// pattern, that is before its first variable gap, together with that
+    /// distance.
+    fn anchor(&self) -> Option<(usize, Vec<u8>)> {
+        let mut best: Option<(usize, Vec<u8>)> = None;
+        let mut pos = 0;
+        for part in &self.parts {
+            let bytes = match part {
+                PatternPart::Bytes(bytes) => bytes,
+                PatternPart::Gap { min, max: Some(max) } if min == max => {
+                    pos += min;
+                    continue;
+                }
+                PatternPart::Gap { .. } => break,
+            };
+            let mut run_start = 0;
+            let mut run = Vec::new();
+            for (i, m) in bytes.iter().chain(std::iter::once(&ByteMatcher::Any)).enumerate() {
+                if let ByteMatcher::Exact(b) = m {
+                    if run.is_empty() {
+                        run_start = pos + i;
+                    }
+                    run.push(*b);
+                } else {
+                    if run.len() > best.as_ref().map(|(_, r)| r.len()).unwrap_or(0) {
+                        best = Some((run_start, std::mem::take(&mut run)));
+                    }
+                    run.clear();
+                }
+            }
+            pos += bytes.len();
+        }
+        best.filter(|(_, run)| run.len() >= MIN_ANCHOR_LEN)
+    }
+}
+
+/// Shortest literal a pattern is located by. Single bytes occur too often
+/// for every occurrence to be worth a match attempt.
+pub const MIN_ANCHOR_LEN: usize = 2;
+
+/// Bytes one pattern may examine in one buffer. Common anchors and long
+/// gaps could otherwise make a single pattern cost time quadratic in the
+/// buffer size; once it is spent the pattern stops matching there.
+const MAX_PATTERN_WORK: usize = 1 << 22;
+
+/// Matches one pattern against one buffer from any number of start offsets.
+/// Gaps are crossed by searching forward for the next byte block, and what
+/// is learned about open gaps is kept between starts, so the buffer is
+/// searched past each open gap about once.
+struct PartMatcher<'a> {
+    parts: &'a [PatternPart],
+    data: &'a [u8],
+    /// Per part, for open gaps: a position the rest of the pattern matches
+    /// at, and the lowest position from which it was found not to.
+    open: Vec<(Option<usize>, Option<usize>)>,
+    work: usize,
+}
+
+impl<'a> PartMatcher<'a> {
+    fn new(parts: &'a [PatternPart], data: &'a [u8]) -> Self {
+        PartMatcher { parts, data, open: vec![(None, None); parts.len()], work: 0 }
+    }
+
+    fn matches_at(&mut self, start: usize) -> bool {
+        self.match_from(0, start)
+    }
+
+    fn match_from(&mut self, part: usize, pos: usize) -> bool {
+        if self.work >= MAX_PATTERN_WORK {
+            return false;
+        }
+        match self.parts.get(part) {
+            None => true,
+            Some(PatternPart::Bytes(bytes)) => {
+                self.block_at(bytes, pos) && self.match_from(part + 1, pos + bytes.len())
+            }
+            Some(&PatternPart::Gap { min, max }) => {
+                self.match_gap(part, pos.saturating_add(min), max.map(|max| pos.saturating_add(max)))
+            }
+        }
+    }
+
+    /// Matches the rest of the pattern after the gap at `part`, with the
+    /// next block starting anywhere from `lo` to `hi` (unbounded if `None`).
+    fn match_gap(&mut self, part: usize, lo: usize, hi: Option<usize>) -> bool {
+        if lo > self.data.len() {
+            return false;
+        }
+        // The parser merges adjacent gaps, so only a trailing gap has no block after it
+        let next = match self.parts.get(part + 1) {
+            Some(PatternPart::Bytes(next)) => next,
+            _ => return true,
+        };
+        if hi.is_none() {
+            let (matched_at, failed_from) = self.open[part];
+            // Starting earlier only adds candidates, starting later only removes them
+            if matched_at.is_some_and(|at| lo <= at) {
+                return true;
+            }
+            if failed_from.is_some_and(|from| lo >= from) {
+                return false;
+            }
+        }
+
+        let Some(mut last) = self.data.len().checked_sub(next.len()) else { return false };
+        last = last.min(hi.unwrap_or(usize::MAX));
+        let mut from = lo;
+        while let Some(at) = self.find_block(next, from, last) {
+            if self.match_from(part + 1, at) {
+                if hi.is_none() {
+                    self.open[part].0 = Some(at);
+                }
+                return true;
+            }
+            from = at + 1;
+        }
+        if hi.is_none() {
+            self.open[part].1 = Some(self.open[part].1.map_or(lo, |from| from.min(lo)));
+        }
+        false
+    }
+
+    /// First position from `from` to `last` where `block` matches, skipping
+    /// ahead between occurrences of its first exact byte.
+    fn find_block(&mut self, block: &[ByteMatcher], mut from: usize, last: usize) -> Option<usize> {
+        let key = block.iter().enumerate().find_map(|(i, m)| match m {
+            ByteMatcher::Exact(b) => Some((i, *b)),
+            _ => None,
+        });
+        while from <= last && self.work < MAX_PATTERN_WORK {
+            if let Some((i, b)) = key {
+                let window = &self.data[from + i..=last + i];
+                match window.iter().position(|&x| x == b) {
+                    Some(skip) => {
+                        self.work += skip;
+                        from += skip;
+                    }
+                    None => {
+                        self.work += window.len();
+                        return None;
+                    }
+                }
+            }
+            if self.block_at(block, from) {
+                return Some(from);
+            }
+            from += 1;
+        }
+        None
+    }
+
+    fn block_at(&mut self, block: &[ByteMatcher], pos: usize) -> bool {
+        self.work += block.len();
+        match pos.checked_add(block.len()) {
+            Some(end) if end <= self.data.len() => block.iter().zip(&self.data[pos..end]).all(|(m, b)| m.matches(*b)),
+            _ => false,
+        }
+    }
+}
+
+fn parse_hex_nibble(c: u8) -> Option<u8> {
+    (c as char).to_digit(16).map(|d| d as u8)
+}
+
+/// Parses a hex pattern such as `4d5a??00{2-4}50450000*deadbeef`.
+pub fn parse_pattern(pattern: &str) -> Result<Vec<PatternPart>, String> {
+    let chars: Vec<u8> = pattern.bytes().filter(|c| !c.is_ascii_whitespace()).collect();
+    let mut parts = Vec::new();
+    let mut bytes = Vec::new();
+    let mut i = 0;
+
+    let flush = |bytes: &mut Vec<ByteMatcher>, parts: &mut Vec<PatternPart>| {
+        if !bytes.is_empty() {
+            parts.push(PatternPart::Bytes(std::mem::take(bytes)));
+        }
+    };
+
+    while i < chars.len() {
+        match chars[i] {
+            b'*' => {
+                flush(&mut bytes, &mut parts);
+                push_gap(&mut parts, PatternPart::Gap { min: 0, max: None });
+                i += 1;
+            }
+            b'{' => {
+                let close = chars[i..]
+                    .iter()
+                    .position(|&c| c == b'}')
+                    .ok_or_else(|| format!("unterminated gap in pattern '{}'", pattern))?;
+                let spec = std::str::from_utf8(&chars[i + 1..i + close]).unwrap_or("");
+                flush(&mut bytes, &mut parts);
+                push_gap(&mut parts, parse_gap(spec)?);
+                i += close + 1;
+            }
+            hi => {
+                let lo = *chars
+                    .get(i + 1)
+                    .ok_or_else(|| format!("odd number of hex digits in pattern '{}'", pattern))?;
+                let matcher = match (hi, lo) {
+                    (b'?', b'?') => ByteMatcher::Any,
+                    (b'?', lo) => ByteMatcher::LowNibble(
+                        parse_hex_nibble(lo).ok_or_else(|| format!("invalid hex in pattern '{}'", pattern))?,
+                    ),
+                    (hi, b'?') => ByteMatcher::HighNibble(
+                        parse_hex_nibble(hi).ok_or_else(|| format!("invalid hex in pattern '{}'", pattern))? << 4,
+                    ),
+                    (hi, lo) => match (parse_hex_nibble(hi), parse_hex_nibble(lo)) {
+                        (Some(h), Some(l)) => ByteMatcher::Exact(h << 4 | l),
+                        _ => return Err(format!("invalid hex in pattern '{}'", pattern)),
+                    },
+                };
+                bytes.push(matcher);
+                i += 2;
+            }
+        }
+    }
+    flush(&mut bytes, &mut parts);
+
+    if !parts.iter().any(|p| matches!(p, PatternPart::Bytes(_))) {
+        return Err(format!("pattern '{}' contains no bytes", pattern));
+    }
+    if matches!(parts.first(), Some(PatternPart::Gap { .. })) {
+        return Err(format!("pattern '{}' must not start with a gap", pattern));
+    }
+    Ok(parts)
+}
+
+/// Appends a gap, folding it into one directly before it.
+fn push_gap(parts: &mut Vec<PatternPart>, gap: PatternPart) {
+    match (parts.last_mut(), gap) {
+        (Some(PatternPart::Gap { min, max }), PatternPart::Gap { min: more, max: more_max }) => {
+            *min = min.saturating_add(more);
+            *max = max.zip(more_max).map(|(a, b)| a.saturating_add(b));
+        }
+        (_, gap) => parts.push(gap),
+    }
+}
+
+fn parse_gap(spec: &str) -> Result<PatternPart, String> {
+    let num = |s: &str| -> Result<usize, String> {
+        s.trim().parse::<usize>().map_err(|_| format!("invalid gap '{{{}}}'", spec))
+    };
+
+    match spec.split_once('-') {
+        None => {
+            let n = num(spec)?;
+            Ok(PatternPart::Gap { min: n, max: Some(n) })
+        }
+        Some(("", max)) => Ok(PatternPart::Gap { min: 0, max: Some(num(max)?) }),
+        Some((min, "")) => Ok(PatternPart::Gap { min: num(min)?, max: None }),
+        Some((min, max)) => {
+            let (min, max) = (num(min)?, num(max)?);
+            if min > max {
+                return Err(format!("invalid gap '{{{}}}'", spec));
+            }
+            Ok(PatternPart::Gap { min, max: Some(max) })
+        }
+    }
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct SignatureFile {
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    hashes: Vec<HashSignatureDef>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    patterns: Vec<PatternSignatureDef>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub similar: Vec<SimilarSignatureDef>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct HashSignatureDef {
+    name: String,
+    hash: String,
+    #[serde(default)]
+    size: Option<u64>,
+    #[serde(default)]
+    severity: Severity,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct PatternSignatureDef {
+    name: String,
+    pattern: String,
+    #[serde(default)]
+    offset: Option<String>,
+    #[serde(default)]
+    severity: Severity,
+}
+
+/// Fuzzy hashes of a known sample. Either digest may be missing.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SimilarSignatureDef {
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ssdeep: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tlsh: Option<String>,
+}
+
+struct SimilarSignature {
+    name: String,
+    ssdeep: Option<SsdeepDigest>,
+    tlsh: Option<TlshDigest>,
+}
+
+/// Boolean/count expression over the sub-signatures of a logical signature.
+#[derive(Debug, Clone)]
+pub enum LogicalExpr {
+    Sub(usize),
+    And(Vec<LogicalExpr>),
+    Or(Vec<LogicalExpr>),
+    /// `expr=N`, `expr>N`, `expr<N`, optionally requiring `distinct` different
+    /// sub-signatures to have matched (`expr>N,M`).
+    Count {
+        expr: Box<LogicalExpr>,
+        op: CountOp,
+        value: usize,
+        distinct: Option<usize>,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CountOp {
+    Eq,
+    Gt,
+    Lt,
+}
+
+impl LogicalExpr {
+    /// Returns (matched, total match count, distinct sub-signatures matched).
+    fn eval(&self, counts: &[usize]) -> (bool, usize, usize) {
+        match self {
+            LogicalExpr::Sub(i) => {
+                let n = counts.get(*i).copied().unwrap_or(0);
+                (n > 0, n, (n > 0) as usize)
+            }
+            LogicalExpr::And(items) | LogicalExpr::Or(items) => {
+                let results: Vec<_> = items.iter().map(|e| e.eval(counts)).collect();
+                let matched = if matches!(self, LogicalExpr::And(_)) {
+                    results.iter().all(|r| r.0)
+                } else {
+                    results.iter().any(|r| r.0)
+                };
+                (
+                    matched,
+                    results.iter().map(|r| r.1).sum(),
+                    results.iter().map(|r| r.2).sum(),
+                )
+            }
+            LogicalExpr::Count { expr, op, value, distinct } => {
+                let (_, total, unique) = expr.eval(counts);
+                let count_ok = match op {
+                    CountOp::Eq => total == *value,
+                    CountOp::Gt => total > *value,
+                    CountOp::Lt => total < *value,
+                };
+                let matched = count_ok && distinct.is_none_or(|d| unique >= d);
+                (matched, total, unique)
+            }
+        }
+    }
+
+    pub fn max_subsig(&self) -> Option<usize> {
+        match self {
+            LogicalExpr::Sub(i) => Some(*i),
+            LogicalExpr::And(items) | LogicalExpr::Or(items) => items.iter().filter_map(|e| e.max_subsig()).max(),
+            LogicalExpr::Count { expr, .. } => expr.max_subsig(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct LogicalSignature {
+    name: String,
+    severity: Severity,
+    target: Target,
+    expr: LogicalExpr,
+    // Indices into `SignatureDb::patterns`
+    subsigs: Vec<usize>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct LoadSummary {
+    pub files_loaded: usize,
+    pub hash_signatures: usize,
+    pub pattern_signatures: usize,
+    pub logical_signatures: usize,
+    pub similarity_signatures: usize,
+    /// Signatures or database files that were skipped because their type or
+    /// syntax isn't supported, keyed by reason.
+    pub unsupported: BTreeMap<String, usize>,
+    pub errors: Vec<String>,
+}
+
+impl LoadSummary {
+    pub fn skip(&mut self, reason: &str) {
+        *self.unsupported.entry(reason.to_string()).or_insert(0) += 1;
+    }
+
+    pub fn merge(&mut self, other: LoadSummary) {
+        self.files_loaded += other.files_loaded;
+        self.hash_signatures += other.hash_signatures;
+        self.pattern_signatures += other.pattern_signatures;
+        self.logical_signatures += other.logical_signatures;
+        self.similarity_signatures += other.similarity_signatures;
+        for (reason, count) in other.unsupported {
+            *self.unsupported.entry(reason).or_insert(0) += count;
+        }
+        self.errors.extend(other.errors);
+    }
+}
+
+/// Cap on counted matches per pattern; logical signatures rarely need more.
+const MAX_PATTERN_MATCHES: usize = 256;
+
+#[derive(Default)]
+pub struct SignatureDb {
+    hashes: HashMap<(HashKind, String), HashSignature>,
+    // Standalone body signatures and logical sub-signatures share one matcher
+    patterns: Vec<PatternSignature>,
+    body: Vec<usize>,
+    logical: Vec<LogicalSignature>,
+    similar: Vec<SimilarSignature>,
+    // Patterns are only tried where the automaton finds their anchor
+    automaton: Option<AhoCorasick>,
+    anchors: Vec<(usize, usize)>, // (pattern index, anchor offset in pattern)
+}
+
+impl SignatureDb {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Database containing only the compiled-in test signatures.
+    pub fn builtin() -> Self {
+        let mut db = SignatureDb::new();
+        db.load_json(BUILTIN_SIGNATURES)
+            .expect("builtin signature set must be valid");
+        db.build();
+        db
+    }
+
+    pub fn add_hash(&mut self, hash: &str, name: &str, size: Option<u64>, severity: Severity) -> Result<(), String> {
+        let hash = hash.trim().to_ascii_lowercase();
+        let kind = HashKind::from_hex_len(hash.len())
+            .filter(|_| hash.bytes().all(|c| c.is_ascii_hexdigit()))
+            .ok_or_else(|| format!("invalid hash '{}' for signature {}", hash, name))?;
+        self.hashes.insert(
+            (kind, hash),
+            HashSignature {
+                name: name.to_string(),
+                severity,
+                size,
+            },
+        );
+        Ok(())
+    }
+
+    pub fn add_similar(&mut self, def: &SimilarSignatureDef) -> Result<(), String> {
+        let signature = SimilarSignature {
+            name: def.name.clone(),
+            ssdeep: def.ssdeep.as_deref().map(SsdeepDigest::parse).transpose()?,
+            tlsh: def.tlsh.as_deref().map(TlshDigest::parse).transpose()?,
+        };
+        if signature.ssdeep.is_none() && signature.tlsh.is_none() {
+            return Err(format!("similarity signature {} has no ssdeep or TLSH digest", def.name));
+        }
+        self.similar.push(signature);
+        Ok(())
+    }
+
+    pub fn add_pattern(&mut self, signature: PatternSignature) {
+        self.body.push(self.patterns.len());
+        self.patterns.push(signature);
+        self.automaton = None;
+    }
+
+    pub fn add_logical(
+        &mut self,
+        name: &str,
+        severity: Severity,
+        target: Target,
+        expr: LogicalExpr,
+        subsigs: Vec<PatternSignature>,
+    ) {
+        let start = self.patterns.len();
+        let count = subsigs.len();
+        self.patterns.extend(subsigs);
+        self.logical.push(LogicalSignature {
+            name: name.to_string(),
+            severity,
+            target,
+            expr,
+            subsigs: (start..start + count).collect(),
+        });
+        self.automaton = None;
+    }
+
+    /// Loads a JSON signature document, returning how many hash, pattern and
+    /// similarity signatures were added.
+    pub fn load_json(&mut self, json: &str) -> Result<(usize, usize, usize), String> {
+        let file: SignatureFile =
+            serde_json::from_str(json).map_err(|e| format!("invalid signature file: {}", e))?;
+
+        for def in &file.hashes {
+            self.add_hash(&def.hash, &def.name, def.size, def.severity)?;
+        }
+        for def in &file.patterns {
+            let offset = def.offset.as_deref().unwrap_or("*");
+            let signature = PatternSignature::new(&def.name, &def.pattern, offset, def.severity)
+                .map_err(|e| format!("signature {}: {}", def.name, e))?;
+            self.add_pattern(signature);
+        }
+        for def in &file.similar {
+            self.add_similar(def)?;
+        }
+
+        Ok((file.hashes.len(), file.patterns.len(), file.similar.len()))
+    }
+
+    /// Loads every JSON signature file and ClamAV database in `dir`. Broken
+    /// files are recorded in the summary and skipped so one bad file doesn't
+    /// disable the rest.
+    pub fn load_dir(&mut self, dir: &Path) -> LoadSummary {
+        let mut summary = LoadSummary::default();
+
+        let entries = match fs::read_dir(dir) {
+            Ok(entries) => entries,
+            Err(e) => {
+                summary.errors.push(format!("{}: {}", dir.display(), e));
+                return summary;
+            }
+        };
+
+        let mut paths: Vec<_> = entries
+            .filter_map(|e| e.ok().map(|e| e.path()))
+            .filter(|p| p.is_file())
+            .collect();
+        paths.sort();
+
+        for path in paths {
+            let extension = path.extension().and_then(|e| e.to_str()).unwrap_or("").to_ascii_lowercase();
+
+            if extension == "json" {
+                let result = fs::read_to_string(&path)
+                    .map_err(|e| e.to_string())
+                    .and_then(|json| self.load_json(&json));
+                match result {
+                    Ok((hashes, patterns, similar)) => {
+                        summary.files_loaded += 1;
+                        summary.hash_signatures += hashes;
+                        summary.pattern_signatures += patterns;
+                        summary.similarity_signatures += similar;
+                    }
+                    Err(e) => summary.errors.push(format!("{}: {}", path.display(), e)),
+                }
+            } else if clamav::is_database(&path) {
+                match clamav::load_file(self, &path, &mut summary) {
+                    Ok(()) => summary.files_loaded += 1,
+                    Err(e) => summary.errors.push(format!("{}: {}", path.display(), e)),
+                }
+            }
+        }
+
+        summary
+    }
+
+    /// Compiles the pattern anchors. Must be called after loading and before
+    /// scanning; `scan` falls back to searching each anchor in turn otherwise.
+    pub fn build(&mut self) {
+        let mut needles = Vec::new();
+        self.anchors.clear();
+
+        for (idx, signature) in self.patterns.iter().enumerate() {
+            if let Some((offset, bytes)) = signature.anchor() {
+                needles.push(bytes);
+                self.anchors.push((idx, offset));
+            }
+        }
+
+        self.automaton = AhoCorasick::builder()
+            .match_kind(MatchKind::Standard)
+            .build(&needles)
+            .ok();
+    }
+
+    pub fn match_hashes(&self, digests: &FileDigests, size: u64) -> Vec<Detection> {
+        let candidates = [
+            (HashKind::Md5, &digests.md5),
+            (HashKind::Sha1, &digests.sha1),
+            (HashKind::Sha256, &digests.sha256),
+        ];
+
+        let mut detections: Vec<Detection> = Vec::new();
+        for (kind, hash) in candidates {
+            if let Some(sig) = self.hashes.get(&(kind, hash.to_ascii_lowercase())) {
+                if sig.size.is_some_and(|s| s != size) {
+                    continue;
+                }
+                if detections.iter().any(|d| d.name == sig.name) {
+                    continue;
+                }
+                detections.push(Detection {
+                    name: sig.name.clone(),
+                    severity: sig.severity,
+                    source: "hash".to_string(),
+                    offset: None,
+                    description: None,
+                });
+            }
+        }
+        detections
+    }
+
+    /// Known samples whose fuzzy hashes are within the given limits of
+    /// `digests`, as suspicious `Similar.<name>` findings with the closest
+    /// match for each name.
+    pub fn match_similar(&self, digests: &FileDigests, min_ssdeep_score: u32, max_tlsh_distance: u32) -> Vec<Detection> {
+        let ssdeep = SsdeepDigest::parse(&digests.ssdeep).ok();
+        let tlsh = TlshDigest::parse(&digests.tlsh).ok();
+
+        let mut best: Vec<(&str, String, u32)> = Vec::new();
+        for sig in &self.similar {
+            let ssdeep_score = ssdeep.as_ref().zip(sig.ssdeep.as_ref()).map(|(a, b)| a.compare(b));
+            let tlsh_distance = tlsh.as_ref().zip(sig.tlsh.as_ref()).map(|(a, b)| a.distance(b));
+            // Rank both measures on one 0-100 scale so the closest match wins
+            let (closeness, reason) = match (ssdeep_score, tlsh_distance) {
+                (_, Some(d)) if d <= max_tlsh_distance => (100u32.saturating_sub(d), format!("TLSH distance {}", d)),
+                (Some(s), _) if s >= min_ssdeep_score => (s, format!("ssdeep score {}", s)),
+                _ => continue,
+            };
+            match best.iter_mut().find(|(name, _, _)| *name == sig.name) {
+                Some(entry) if entry.2 < closeness => *entry = (&sig.name, reason, closeness),
+                Some(_) => {}
+                None => best.push((&sig.name, reason, closeness)),
+            }
+        }
+
+        best.into_iter()
+            .map(|(name, reason, _)| {
+                Detection::heuristic(
+                    format!("Similar.{}", name),
+                    "similarity",
+                    format!("Similar to {} ({})", name, reason),
+                )
+            })
+            .collect()
+    }
+
+    /// Locates every pattern in `data`, returning for each matching pattern
+    /// its first offset and (capped) match count.
+    fn pattern_hits(&self, data: &[u8]) -> HashMap<usize, (usize, usize)> {
+        let mut hits: HashMap<usize, (usize, usize)> = HashMap::new();
+        let mut target_cache: HashMap<Target, bool> = HashMap::new();
+        let mut target_ok = |target: Target| *target_cache.entry(target).or_insert_with(|| target.matches(data));
+
+        let Some(automaton) = &self.automaton else {
+            for (idx, signature) in self.patterns.iter().enumerate() {
+                if !target_ok(signature.target) {
+                    continue;
+                }
+                if let Some(hit) = signature.find_all(data, MAX_PATTERN_MATCHES) {
+                    hits.insert(idx, hit);
+                }
+            }
+            return hits;
+        };
+
+        let mut matchers: HashMap<usize, PartMatcher> = HashMap::new();
+        for m in automaton.find_overlapping_iter(data) {
+            let (idx, anchor_offset) = self.anchors[m.pattern().as_usize()];
+            if hits.get(&idx).is_some_and(|(_, n)| *n >= MAX_PATTERN_MATCHES) {
+                continue;
+            }
+            let start = match m.start().checked_sub(anchor_offset) {
+                Some(start) => start,
+                None => continue,
+            };
+            let signature = &self.patterns[idx];
+            if !signature.allows_start(start, data.len()) || !target_ok(signature.target) {
+                continue;
+            }
+            let matcher = matchers.entry(idx).or_insert_with(|| PartMatcher::new(&signature.parts, data));
+            if matcher.matches_at(start) {
+                hits.entry(idx).and_modify(|(_, n)| *n += 1).or_insert((start, 1));
+            }
+        }
+
+        hits
+    }
+
+    pub fn match_patterns(&self, data: &[u8]) -> Vec<Detection> {
+        let hits = self.pattern_hits(data);
+        let mut detections = Vec::new();
+
+        for &idx in &self.body {
+            if let Some((offset, _)) = hits.get(&idx) {
+                detections.push(Detection {
+                    name: self.patterns[idx].name.clone(),
+                    severity: self.patterns[idx].severity,
+                    source: "pattern".to_string(),
+                    offset: Some(*offset),
+                    description: None,
+                });
+            }
+        }
+
+        for signature in &self.logical {
+            if !signature.target.matches(data) {
+                continue;
+            }
+            let counts: Vec<usize> = signature
+                .subsigs
+                .iter()
+                .map(|idx| hits.get(idx).map(|(_, n)| *n).unwrap_or(0))
+                .collect();
+            if signature.expr.eval(&counts).0 {
+                let offset = signature.subsigs.iter().filter_map(|idx| hits.get(idx).map(|(o, _)| *o)).min();
+                detections.push(Detection {
+                    name: signature.name.clone(),
+                    severity: signature.severity,
+                    source: "logical".to_string(),
+                    offset,
+                    description: None,
+                });
+            }
+        }
+
+        detections
+    }
+
+    /// Matches a file against every hash, pattern and logical signature.
+    /// `data` may be just the start of the `size` bytes `digests` cover.
+    pub fn scan(&self, data: &[u8], digests: &FileDigests, size: u64) -> Vec<Detection> {
+        let mut detections = self.match_hashes(digests, size);
+        for detection in self.match_patterns(data) {
+            if !detections.iter().any(|d| d.name == detection.name) {
+                detections.push(detection);
+            }
+        }
+        detections
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hashing;
+
+    const EICAR: &[u8] = br"X5O!P%@AP[4\PZX54(P^)7CC)7}$EICAR-STANDARD-ANTIVIRUS-TEST-FILE!$H+H*";
+
+    /// Offset of the first match of a single pattern signature in `data`.
+    fn find(pattern: &str, offset: &str, data: &[u8]) -> Option<usize> {
+        let mut db = SignatureDb::new();
+        db.add_pattern(PatternSignature::new("Test", pattern, offset, Severity::Threat).unwrap());
+        db.build();
+        db.match_patterns(data).first().and_then(|d| d.offset)
+    }
+
+    #[test]
+    fn bundled_set_loads() {
+        let mut db = SignatureDb::new();
+        assert_eq!(db.load_json(BUILTIN_SIGNATURES), Ok((2, 1, 0)));
+    }
+
+    #[test]
+    fn eicar_matches_by_hash() {
+        let db = SignatureDb::builtin();
+        let detections = db.match_hashes(&hashing::digest_reader(EICAR).unwrap(), EICAR.len() as u64);
+        assert_eq!(detections.len(), 1);
+        assert_eq!(detections[0].name, "EICAR-Test-File");
+        assert_eq!(detections[0].source, "hash");
+
+        // The size recorded with the hash must agree
+        assert!(db.match_hashes(&hashing::digest_reader(EICAR).unwrap(), 69).is_empty());
+    }
+
+    #[test]
+    fn eicar_matches_by_pattern() {
+        let db = SignatureDb::builtin();
+        let mut padded = b"\r\n".repeat(20);
+        padded.extend_from_slice(EICAR);
+        padded.extend_from_slice(b"\r\n");
+        let detections = db.match_patterns(&padded);
+        assert_eq!(detections.len(), 1);
+        assert_eq!(detections[0].source, "pattern");
+        assert_eq!(detections[0].offset, Some(40));
+
+        // The signature only allows the string within the first 128 bytes
+        let mut late = vec![b' '; 200];
+        late.extend_from_slice(EICAR);
+        assert!(db.match_patterns(&late).is_empty());
+    }
+
+    #[test]
+    fn scan_reports_each_name_once() {
+        let db = SignatureDb::builtin();
+        let detections = db.scan(EICAR, &hashing::digest_reader(EICAR).unwrap(), EICAR.len() as u64);
+        assert_eq!(detections.len(), 1);
+        assert_eq!(detections[0].source, "hash");
+        assert!(db.scan(b"harmless", &hashing::digest_reader(&b"harmless"[..]).unwrap(), 8).is_empty());
+    }
+
+    #[test]
+    fn wildcards() {
+        assert_eq!(find("4142??44", "*", b"xxABzD"), Some(2));
+        assert_eq!(find("41424?", "*", b"..ABO"), Some(2));
+        assert_eq!(find("41424?", "*", b"..ABP"), None);
+        assert_eq!(find("4142?f", "*", b"ABo"), Some(0));
+        assert_eq!(find("4142?f", "*", b"ABn"), None);
+    }
+
+    #[test]
+    fn gaps() {
+        assert_eq!(find("4142{2}4344", "*", b"AB..CD"), Some(0));
+        assert_eq!(find("4142{2}4344", "*", b"AB.CD"), None);
+        assert_eq!(find("4142{1-3}4344", "*", b"AB...CD"), Some(0));
+        assert_eq!(find("4142{1-3}4344", "*", b"AB....CD"), None);
+        assert_eq!(find("4142{1-3}4344", "*", b"ABCD"), None);
+        assert_eq!(find("4142{-2}4344", "*", b"ABCD"), Some(0));
+        assert_eq!(find("4142{2-}4344", "*", b"AB.........CD"), Some(0));
+        assert_eq!(find("4142{2-}4344", "*", b"AB.CD"), None);
+        assert_eq!(find("4142*4344", "*", b"AB and much later CD"), Some(0));
+        assert_eq!(find("4142*4344", "*", b"CD before AB"), None);
+    }
+
+    #[test]
+    fn gaps_backtrack_to_later_blocks() {
+        // The first CD is too far from EF, the second one isn't
+        assert_eq!(find("4142*4344{1-2}4546", "*", b"AB CD....EF CD.EF"), Some(0));
+        assert_eq!(find("4142*4344{1-2}4546", "*", b"AB CD....EF CD...EF"), None);
+        assert_eq!(find("4142{1}4344*4546", "*", b"AB.CD AB.CD EF"), Some(0));
+        assert_eq!(
+            parse_pattern("41**{2}{1-3}42"),
+            Ok(vec![
+                PatternPart::Bytes(vec![ByteMatcher::Exact(0x41)]),
+                PatternPart::Gap { min: 3, max: None },
+                PatternPart::Bytes(vec![ByteMatcher::Exact(0x42)]),
+            ])
+        );
+    }
+
+    #[test]
+    fn patterns_need_a_literal_anchor() {
+        let anchored = |pattern: &str| PatternSignature::new("Test", pattern, "*", Severity::Threat).is_ok();
+        assert!(anchored("4142"));
+        assert!(anchored("41??4243"));
+        assert!(anchored("41{3}4243*44"));
+        assert!(!anchored("41??42"));
+        assert!(!anchored("41*4243"));
+        assert!(!anchored("4?4?4?"));
+        assert!(PatternSignature::parse("Test", "41*4243", "*", Severity::Threat).is_ok());
+
+        assert_eq!(find("41{3}4243", "*", b"xA...BC"), Some(1));
+        assert_eq!(find("41{3}4243", "2", b"xA...BC"), None);
+    }
+
+    #[test]
+    fn pathological_patterns_stay_linear() {
+        let data = vec![b'A'; 1 << 18];
+        // Every offset is an anchor hit and the gaps reach to the end
+        assert_eq!(find("4141*4243", "*", &data), None);
+        assert_eq!(find("4141{2-}4243*44", "*", &data), None);
+        // Bounded gaps are cut off by the work limit instead
+        assert_eq!(find("4141{0-60000}4243", "*", &data), None);
+
+        let mut tail = data.clone();
+        tail.extend_from_slice(b"BC");
+        assert_eq!(find("4141*4243", "*", &tail), Some(0));
+        let mut db = SignatureDb::new();
+        db.add_pattern(PatternSignature::new("Test", "4141*4243", "*", Severity::Threat).unwrap());
+        db.build();
+        assert_eq!(db.pattern_hits(&tail).get(&0), Some(&(0, MAX_PATTERN_MATCHES)));
+    }
+
+    #[test]
+    fn offset_anchors() {
+        assert_eq!(Offset::parse("*"), Ok(Offset::Any));
+        assert_eq!(Offset::parse("4,8"), Ok(Offset::Absolute { start: 4, range: 8 }));
+        assert_eq!(Offset::parse("EOF-3"), Ok(Offset::FromEnd { distance: 3, range: 0 }));
+        assert!(Offset::parse("EP+4").is_err());
+        assert!(Offset::parse("4,x").is_err());
+
+        assert_eq!(find("4142", "2", b"..AB"), Some(2));
+        assert_eq!(find("4142", "2", b"...AB"), None);
+        assert_eq!(find("4142", "2,3", b"...AB"), Some(3));
+        assert_eq!(find("4142", "2,3", b"......AB"), None);
+        assert_eq!(find("4142", "EOF-2", b"....AB"), Some(4));
+        assert_eq!(find("4142", "EOF-2", b"...AB."), None);
+        assert_eq!(find("4142", "EOF-3,1", b"...AB."), Some(3));
+        assert_eq!(find("4142", "10", b"AB"), None);
+    }
+
+    #[test]
+    fn parse_pattern_errors() {
+        for (pattern, error) in [
+            ("414", "odd number of hex digits"),
+            ("41g2", "invalid hex"),
+            ("g?", "invalid hex"),
+            ("41{2", "unterminated gap"),
+            ("41{x}42", "invalid gap"),
+            ("41{3-1}42", "invalid gap"),
+            ("*", "contains no bytes"),
+            ("{2}4142", "must not start with a gap"),
+        ] {
+            let result = parse_pattern(pattern);
+            assert!(result.as_ref().is_err_and(|e| e.contains(error)), "{}: {:?}", pattern, result);
+        }
+        assert_eq!(
+            parse_pattern("41 ?2{1-}"),
+            Ok(vec![
+                PatternPart::Bytes(vec![ByteMatcher::Exact(0x41), ByteMatcher::LowNibble(2)]),
+                PatternPart::Gap { min: 1, max: None },
+            ])
+        );
+    }
+}
@@ -0,0 +1,194 @@
+//! STIX 2.1 bundle parsing for the IOC store.
+//!
+//! Only what the store can hold is taken from a bundle: indicator objects
+//! whose pattern compares a file hash, domain, IP address or URL for
+//! equality, alone or OR-ed together. The malware an indicator `indicates`
+//! becomes a tag and the identity that created it becomes its source.
+//! Everything else is ignored.
+
+use regex::Regex;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::OnceLock;
+
+use crate::ioc::{IocKind, NewIndicator};
+
+/// Indicators for the supported patterns in `objects`, and one message per
+/// indicator that had to be left out.
+pub fn indicators(objects: &[Value]) -> (Vec<NewIndicator>, Vec<String>) {
+    let str_field = |object: &'_ Value, field: &str| object.get(field).and_then(Value::as_str).map(str::to_string);
+    let of_type = |kind: &'static str| objects.iter().filter(move |o| o.get("type").and_then(Value::as_str) == Some(kind));
+
+    let names = |kind| -> HashMap<String, String> {
+        of_type(kind).filter_map(|o| Some((str_field(o, "id")?, str_field(o, "name")?))).collect()
+    };
+    let identities = names("identity");
+    let malware = names("malware");
+    let mut indicates: HashMap<String, Vec<String>> = HashMap::new();
+    for relationship in of_type("relationship") {
+        if str_field(relationship, "relationship_type").as_deref() != Some("indicates") {
+            continue;
+        }
+        if let (Some(source), Some(name)) = (
+            str_field(relationship, "source_ref"),
+            str_field(relationship, "target_ref").and_then(|t| malware.get(&t).cloned()),
+        ) {
+            indicates.entry(source).or_default().push(name);
+        }
+    }
+
+    let mut indicators = Vec::new();
+    let mut errors = Vec::new();
+    for object in of_type("indicator") {
+        let id = str_field(object, "id").unwrap_or_default();
+        if object.get("revoked").and_then(Value::as_bool) == Some(true) {
+            continue;
+        }
+        let pattern_type = str_field(object, "pattern_type").unwrap_or_else(|| "stix".to_string());
+        if pattern_type != "stix" {
+            errors.push(format!("{}: unsupported pattern type '{}'", id, pattern_type));
+            continue;
+        }
+        let observables = match str_field(object, "pattern").ok_or_else(|| "no pattern".to_string()).and_then(|p| parse_pattern(&p)) {
+            Ok(observables) => observables,
+            Err(e) => {
+                errors.push(format!("{}: {}", id, e));
+                continue;
+            }
+        };
+        let mut tags: Vec<String> = ["labels", "indicator_types"]
+            .iter()
+            .filter_map(|field| object.get(*field).and_then(Value::as_array))
+            .flatten()
+            .filter_map(|t| t.as_str().map(str::to_string))
+            .collect();
+        tags.extend(indicates.get(&id).cloned().unwrap_or_default());
+        for (kind, value) in observables {
+            indicators.push(NewIndicator {
+                kind: Some(kind),
+                value,
+                source: str_field(object, "created_by_ref").and_then(|c| identities.get(&c).cloned()),
+                confidence: object.get("confidence").and_then(Value::as_u64).map(|c| c.min(100) as u8),
+                first_seen: str_field(object, "valid_from").or_else(|| str_field(object, "created")),
+                last_seen: str_field(object, "modified").or_else(|| str_field(object, "created")),
+                valid_until: str_field(object, "valid_until"),
+                tags: tags.clone(),
+                description: str_field(object, "name").or_else(|| str_field(object, "description")),
+                external_id: Some(id.clone()),
+                event: None,
+            });
+        }
+    }
+    (indicators, errors)
+}
+
+/// The observables of a pattern made of `=` comparisons joined by `OR`, such
+/// as `[file:hashes.'SHA-256' = '…'] OR [domain-name:value = '…']`.
+fn parse_pattern(pattern: &str) -> Result<Vec<(IocKind, String)>, String> {
+    static COMPARISON: OnceLock<Regex> = OnceLock::new();
+    let comparison = COMPARISON.get_or_init(|| {
+        Regex::new(r"([a-z0-9-]+):([A-Za-z0-9_.'-]+)\s*=\s*'((?:[^'\\]|\\.)*)'").expect("comparison pattern is valid")
+    });
+    let mut observables = Vec::new();
+    let mut glue = String::new();
+    let mut last = 0;
+    for captures in comparison.captures_iter(pattern) {
+        let whole = captures.get(0).expect("group 0 always matches");
+        glue.push_str(&pattern[last..whole.start()]);
+        last = whole.end();
+        let (object, path) = (&captures[1], captures[2].replace('\'', ""));
+        let kind = match (object, path.as_str()) {
+            ("file", path) if path.starts_with("hashes.") => IocKind::Hash,
+            ("domain-name", "value") => IocKind::Domain,
+            ("ipv4-addr" | "ipv6-addr", "value") => IocKind::Ip,
+            ("url", "value") => IocKind::Url,
+            _ => return Err(format!("unsupported property {}:{}", object, path)),
+        };
+        observables.push((kind, captures[3].replace("\\'", "'").replace("\\\\", "\\")));
+    }
+    glue.push_str(&pattern[last..]);
+    // Anything but brackets and OR between the comparisons (AND, qualifiers,
+    // other operators) narrows the match in ways the store can't represent
+    let glue = glue.replace(['[', ']', '(', ')'], " ");
+    if observables.is_empty() || glue.split_whitespace().any(|word| word != "OR") {
+        return Err(format!("unsupported pattern {}", pattern));
+    }
+    Ok(observables)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn parses_or_joined_comparisons() {
+        let observables = parse_pattern(
+            "[file:hashes.'SHA-256' = 'aa'] OR ([domain-name:value = 'evil.example'] OR [ipv4-addr:value = '198.51.100.0/24'])",
+        )
+        .unwrap();
+        assert_eq!(
+            observables,
+            vec![
+                (IocKind::Hash, "aa".to_string()),
+                (IocKind::Domain, "evil.example".to_string()),
+                (IocKind::Ip, "198.51.100.0/24".to_string()),
+            ]
+        );
+        assert_eq!(
+            parse_pattern("[ipv6-addr:value='2001:db8::1' OR url:value='http://x.example/']").unwrap(),
+            vec![(IocKind::Ip, "2001:db8::1".to_string()), (IocKind::Url, "http://x.example/".to_string())]
+        );
+    }
+
+    #[test]
+    fn unescapes_quoted_values() {
+        assert_eq!(
+            parse_pattern(r"[url:value = 'http://x.example/it\'s\\here']").unwrap(),
+            vec![(IocKind::Url, r"http://x.example/it's\here".to_string())]
+        );
+    }
+
+    #[test]
+    fn rejects_what_the_store_cant_hold() {
+        for (pattern, error) in [
+            ("[file:name = 'evil.exe']", "unsupported property file:name"),
+            ("[email-addr:value = 'a@b.example']", "unsupported property email-addr:value"),
+            ("[domain-name:resolves_to_refs[*].value = '1.2.3.4']", "unsupported pattern"),
+            ("[domain-name:value = 'a.example' AND domain-name:value = 'b.example']", "unsupported pattern"),
+            ("[domain-name:value = 'a.example'] WITHIN 10 SECONDS", "unsupported pattern"),
+            ("[domain-name:value != 'a.example']", "unsupported pattern"),
+            ("", "unsupported pattern"),
+        ] {
+            let result = parse_pattern(pattern);
+            assert!(result.as_ref().is_err_and(|e| e.contains(error)), "{}: {:?}", pattern, result);
+        }
+    }
+
+    #[test]
+    fn indicators_take_source_and_tags_from_the_bundle() {
+        let objects = [
+            json!({"type": "identity", "id": "identity--1", "name": "ACME CERT"}),
+            json!({"type": "malware", "id": "malware--1", "name": "Emotet"}),
+            json!({"type": "relationship", "relationship_type": "indicates",
+                   "source_ref": "indicator--1", "target_ref": "malware--1"}),
+            json!({"type": "indicator", "id": "indicator--1", "created_by_ref": "identity--1",
+                   "pattern": "[domain-name:value = 'evil.example']", "pattern_type": "stix",
+                   "indicator_types": ["malicious-activity"], "confidence": 120,
+                   "valid_from": "2024-01-01T00:00:00Z"}),
+            json!({"type": "indicator", "id": "indicator--2", "revoked": true,
+                   "pattern": "[domain-name:value = 'old.example']", "pattern_type": "stix"}),
+            json!({"type": "indicator", "id": "indicator--3",
+                   "pattern": "alert tcp any any", "pattern_type": "snort"}),
+        ];
+        let (indicators, errors) = indicators(&objects);
+        assert_eq!(indicators.len(), 1);
+        let indicator = &indicators[0];
+        assert_eq!(indicator.value, "evil.example");
+        assert_eq!(indicator.source.as_deref(), Some("ACME CERT"));
+        assert_eq!(indicator.tags, vec!["malicious-activity", "Emotet"]);
+        assert_eq!(indicator.confidence, Some(100));
+        assert_eq!(indicator.external_id.as_deref(), Some("indicator--1"));
+        assert_eq!(errors, vec!["indicator--3: unsupported pattern type 'snort'"]);
+    }
+}
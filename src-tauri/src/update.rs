@@ -0,0 +1,630 @@
+//! Signed, incremental signature database updates.
+//!
+//! An update source, an HTTP(S) URL or a local directory for air-gapped
+//! sites, publishes `manifest.json` with its Ed25519 signature in
+//! `manifest.json.sig`. The manifest names the latest database version and
+//! the deltas leading to it, each signed in turn. A delta moves the database
+//! one step forward by adding, replacing or deleting whole signature files;
+//! the optional `full` entry rebuilds it from nothing for installs too far
+//! behind for the deltas.
+//!
+//! Updated files live in their own directory inside the signature directory,
+//! so they never touch locally imported signatures. Deltas are applied to a
+//! copy of it, which must load cleanly before it is swapped in; the
+//! installed database is left as it was if anything fails.
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use ed25519_dalek::{Signature, VerifyingKey};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use crate::clamav;
+use crate::engine::ScanEngine;
+use crate::models::timestamp;
+use crate::signatures::{LoadSummary, SignatureDb};
+
+/// Directory in the signature directory holding the updated database.
+pub const DATABASE_DIR: &str = "updates";
+/// Version record inside [`DATABASE_DIR`]. Its lack of an extension keeps
+/// the signature loader from reading it.
+const VERSION_FILE: &str = "VERSION";
+const MANIFEST: &str = "manifest.json";
+const MANIFEST_SIGNATURE: &str = "manifest.json.sig";
+const MAX_DOWNLOAD: u64 = 256 * 1024 * 1024;
+const TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Only one update may rewrite the database directory at a time.
+static UPDATING: Mutex<()> = Mutex::new(());
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct UpdateConfig {
+    /// Base URL of the update source, or a local directory.
+    pub source: String,
+    /// Ed25519 public key the manifest and deltas are signed with, as hex or
+    /// base64.
+    pub public_key: String,
+}
+
+impl UpdateConfig {
+    /// The saved config; the default, which has no source, when there is none.
+    pub fn load(path: &Path) -> Result<Self, String> {
+        if !path.exists() {
+            return Ok(UpdateConfig::default());
+        }
+        let text = fs::read_to_string(path).map_err(|e| format!("Cannot read {}: {}", path.display(), e))?;
+        serde_json::from_str(&text).map_err(|e| format!("Invalid update config {}: {}", path.display(), e))
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        let text = serde_json::to_string_pretty(self).map_err(|e| format!("Cannot encode update config: {}", e))?;
+        fs::write(path, text).map_err(|e| format!("Cannot write {}: {}", path.display(), e))
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        if self.source.trim().is_empty() {
+            return Err("No update source configured".to_string());
+        }
+        self.verifying_key().map(|_| ())
+    }
+
+    fn verifying_key(&self) -> Result<VerifyingKey, String> {
+        let key = self.public_key.trim();
+        let bytes = if key.len() == 64 && key.bytes().all(|c| c.is_ascii_hexdigit()) {
+            hex::decode(key).map_err(|e| e.to_string())
+        } else {
+            STANDARD.decode(key).map_err(|e| e.to_string())
+        };
+        let bytes: [u8; 32] = bytes
+            .ok()
+            .and_then(|b| b.try_into().ok())
+            .ok_or_else(|| "Update public key must be 32 bytes of hex or base64".to_string())?;
+        VerifyingKey::from_bytes(&bytes).map_err(|e| format!("Invalid update public key: {}", e))
+    }
+}
+
+/// What the installed database is.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct DatabaseVersion {
+    /// 0 until the first update.
+    pub version: u64,
+    /// When the update source published this version.
+    pub published: Option<String>,
+    /// When it was installed here.
+    pub applied: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DatabaseStatus {
+    pub version: u64,
+    pub published: Option<String>,
+    pub applied: Option<String>,
+    /// Seconds since the installed version was published.
+    pub age_seconds: Option<i64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UpdateCheck {
+    pub installed: u64,
+    pub available: u64,
+    pub published: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UpdateReport {
+    pub from_version: u64,
+    pub to_version: u64,
+    pub deltas_applied: usize,
+    /// Whether the database was rebuilt from the full snapshot.
+    pub rebuilt: bool,
+    /// The reload after the update, or `None` when already up to date.
+    pub signatures: Option<LoadSummary>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Manifest {
+    version: u64,
+    published: String,
+    #[serde(default)]
+    deltas: Vec<DeltaEntry>,
+    #[serde(default)]
+    full: Option<DeltaEntry>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct DeltaEntry {
+    from: u64,
+    to: u64,
+    /// Path of the delta relative to the source.
+    file: String,
+    /// Base64 Ed25519 signature over the delta file.
+    signature: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct Delta {
+    from: u64,
+    to: u64,
+    #[serde(default)]
+    files: Vec<FileChange>,
+}
+
+#[derive(Debug, Deserialize)]
+struct FileChange {
+    name: String,
+    /// Base64 file content; `None` deletes the file.
+    #[serde(default)]
+    content: Option<String>,
+}
+
+pub fn installed(signature_dir: &Path) -> DatabaseVersion {
+    fs::read_to_string(signature_dir.join(DATABASE_DIR).join(VERSION_FILE))
+        .ok()
+        .and_then(|text| serde_json::from_str(&text).ok())
+        .unwrap_or_default()
+}
+
+pub fn status(signature_dir: &Path) -> DatabaseStatus {
+    let installed = installed(signature_dir);
+    let age_seconds = installed
+        .published
+        .as_deref()
+        .and_then(|p| chrono::DateTime::parse_from_rfc3339(p).ok())
+        .map(|published| (chrono::Utc::now() - published.with_timezone(&chrono::Utc)).num_seconds());
+    DatabaseStatus {
+        version: installed.version,
+        published: installed.published,
+        applied: installed.applied,
+        age_seconds,
+    }
+}
+
+/// Fetches and verifies the manifest without applying anything.
+pub fn check(config: &UpdateConfig, signature_dir: &Path) -> Result<UpdateCheck, String> {
+    let manifest = fetch_manifest(config, &agent())?;
+    Ok(UpdateCheck {
+        installed: installed(signature_dir).version,
+        available: manifest.version,
+        published: manifest.published,
+    })
+}
+
+/// Brings the engine's database up to the version the source publishes.
+pub fn update(config: &UpdateConfig, engine: &ScanEngine) -> Result<UpdateReport, String> {
+    let _updating = UPDATING.lock().unwrap_or_else(|e| e.into_inner());
+    let agent = agent();
+    let manifest = fetch_manifest(config, &agent)?;
+    let current = installed(engine.signature_dir()).version;
+    if manifest.version < current {
+        return Err(format!(
+            "Update source offers version {}, older than the installed version {}",
+            manifest.version, current
+        ));
+    }
+    if manifest.version == current {
+        return Ok(UpdateReport {
+            from_version: current,
+            to_version: current,
+            deltas_applied: 0,
+            rebuilt: false,
+            signatures: None,
+        });
+    }
+    let (rebuilt, chain) = plan(&manifest, current)?;
+
+    let live = engine.signature_dir().join(DATABASE_DIR);
+    let staging = engine.signature_dir().join(format!("{}.staging", DATABASE_DIR));
+    let result = stage(config, &agent, &manifest, &chain, rebuilt, &live, &staging).and_then(|_| swap(&live, &staging));
+    if result.is_err() {
+        let _ = fs::remove_dir_all(&staging);
+    }
+    result?;
+    Ok(UpdateReport {
+        from_version: current,
+        to_version: manifest.version,
+        deltas_applied: chain.len(),
+        rebuilt,
+        signatures: Some(engine.reload_signatures()),
+    })
+}
+
+fn agent() -> ureq::Agent {
+    ureq::AgentBuilder::new().timeout(TIMEOUT).build()
+}
+
+fn fetch_manifest(config: &UpdateConfig, agent: &ureq::Agent) -> Result<Manifest, String> {
+    config.validate()?;
+    let key = config.verifying_key()?;
+    let manifest = fetch(agent, &config.source, MANIFEST)?;
+    let signature = fetch(agent, &config.source, MANIFEST_SIGNATURE)?;
+    verify(&key, &manifest, String::from_utf8_lossy(&signature).trim())
+        .map_err(|e| format!("Manifest rejected: {}", e))?;
+    serde_json::from_slice(&manifest).map_err(|e| format!("Invalid update manifest: {}", e))
+}
+
+/// The deltas leading from `current` to the manifest's version, preceded by
+/// the full snapshot when the deltas don't reach back far enough.
+fn plan(manifest: &Manifest, current: u64) -> Result<(bool, Vec<DeltaEntry>), String> {
+    let follow = |mut version: u64| {
+        let mut chain = Vec::new();
+        while let Some(delta) =
+            manifest.deltas.iter().find(|d| d.from == version && d.to > version && d.to <= manifest.version)
+        {
+            chain.push(delta.clone());
+            version = delta.to;
+        }
+        (version, chain)
+    };
+    let (reached, chain) = follow(current);
+    if reached == manifest.version {
+        return Ok((false, chain));
+    }
+    if let Some(full) = manifest.full.as_ref().filter(|f| f.from == 0 && f.to <= manifest.version) {
+        let (reached, rest) = follow(full.to);
+        if reached == manifest.version {
+            return Ok((true, std::iter::once(full.clone()).chain(rest).collect()));
+        }
+    }
+    Err(format!("No update path from version {} to {}", current, manifest.version))
+}
+
+/// Builds the updated database in `staging` and checks that it loads.
+fn stage(
+    config: &UpdateConfig,
+    agent: &ureq::Agent,
+    manifest: &Manifest,
+    chain: &[DeltaEntry],
+    rebuilt: bool,
+    live: &Path,
+    staging: &Path,
+) -> Result<(), String> {
+    let io = |path: &Path, e: std::io::Error| format!("{}: {}", path.display(), e);
+    if staging.exists() {
+        fs::remove_dir_all(staging).map_err(|e| io(staging, e))?;
+    }
+    fs::create_dir_all(staging).map_err(|e| io(staging, e))?;
+    if !rebuilt && live.is_dir() {
+        for entry in fs::read_dir(live).map_err(|e| io(live, e))? {
+            let path = entry.map_err(|e| io(live, e))?.path();
+            if path.is_file() {
+                let target = staging.join(path.file_name().expect("directory entries have names"));
+                fs::copy(&path, &target).map_err(|e| io(&path, e))?;
+            }
+        }
+    }
+
+    let key = config.verifying_key()?;
+    for entry in chain {
+        let bytes = fetch(agent, &config.source, &entry.file)?;
+        verify(&key, &bytes, &entry.signature).map_err(|e| format!("Delta {} rejected: {}", entry.file, e))?;
+        let delta: Delta =
+            serde_json::from_slice(&bytes).map_err(|e| format!("Invalid delta {}: {}", entry.file, e))?;
+        // The signature covers the delta's own version range, so a valid
+        // delta can't be replayed under another manifest entry
+        if delta.from != entry.from || delta.to != entry.to {
+            return Err(format!("Delta {} is for versions {} to {}", entry.file, delta.from, delta.to));
+        }
+        for change in delta.files {
+            let path = staging.join(database_file(&change.name)?);
+            match change.content {
+                Some(content) => {
+                    let data = STANDARD
+                        .decode(content.as_bytes())
+                        .map_err(|e| format!("Invalid content for {} in {}: {}", change.name, entry.file, e))?;
+                    fs::write(&path, data).map_err(|e| io(&path, e))?;
+                }
+                None if path.exists() => fs::remove_file(&path).map_err(|e| io(&path, e))?,
+                None => {}
+            }
+        }
+    }
+
+    let version = DatabaseVersion {
+        version: manifest.version,
+        published: Some(manifest.published.clone()),
+        applied: Some(timestamp()),
+    };
+    let path = staging.join(VERSION_FILE);
+    fs::write(&path, serde_json::to_string_pretty(&version).expect("version serializes")).map_err(|e| io(&path, e))?;
+
+    let summary = SignatureDb::new().load_dir(staging);
+    if let Some(error) = summary.errors.first() {
+        return Err(format!("Updated database failed to load: {}", error));
+    }
+    Ok(())
+}
+
+/// Replaces `live` with `staging`, putting `live` back if that fails.
+fn swap(live: &Path, staging: &Path) -> Result<(), String> {
+    let previous = live.with_extension("previous");
+    if previous.exists() {
+        fs::remove_dir_all(&previous).map_err(|e| format!("{}: {}", previous.display(), e))?;
+    }
+    let had_live = live.exists();
+    if had_live {
+        fs::rename(live, &previous).map_err(|e| format!("Cannot move {} aside: {}", live.display(), e))?;
+    }
+    if let Err(e) = fs::rename(staging, live) {
+        if had_live {
+            let _ = fs::rename(&previous, live);
+        }
+        return Err(format!("Cannot install {}: {}", live.display(), e));
+    }
+    if had_live {
+        let _ = fs::remove_dir_all(&previous);
+    }
+    Ok(())
+}
+
+/// Delta file names must name a signature database directly inside the
+/// database directory.
+fn database_file(name: &str) -> Result<PathBuf, String> {
+    let path = PathBuf::from(name);
+    let plain = path.file_name().is_some_and(|f| f == name) && !name.starts_with('.');
+    let json = path.extension().is_some_and(|e| e.eq_ignore_ascii_case("json"));
+    if !plain || !(json || clamav::is_database(&path)) {
+        return Err(format!("Delta names an invalid database file: {}", name));
+    }
+    Ok(path)
+}
+
+fn verify(key: &VerifyingKey, data: &[u8], signature: &str) -> Result<(), String> {
+    let bytes: [u8; 64] = STANDARD
+        .decode(signature.trim())
+        .ok()
+        .and_then(|b| b.try_into().ok())
+        .ok_or_else(|| "signature must be 64 bytes of base64".to_string())?;
+    key.verify_strict(data, &Signature::from_bytes(&bytes)).map_err(|_| "bad signature".to_string())
+}
+
+/// Reads `name` from the source, an HTTP(S) base URL or a local directory.
+fn fetch(agent: &ureq::Agent, source: &str, name: &str) -> Result<Vec<u8>, String> {
+    if name.split(['/', '\\']).any(|part| part == "..") || name.starts_with('/') {
+        return Err(format!("Invalid update file name: {}", name));
+    }
+    let mut data = Vec::new();
+    if source.starts_with("http://") || source.starts_with("https://") {
+        let url = format!("{}/{}", source.trim_end_matches('/'), name);
+        let response = agent.get(&url).call().map_err(|e| format!("Update request failed: {}", e))?;
+        response
+            .into_reader()
+            .take(MAX_DOWNLOAD + 1)
+            .read_to_end(&mut data)
+            .map_err(|e| format!("Cannot download {}: {}", url, e))?;
+    } else {
+        let path = Path::new(source).join(name);
+        fs::File::open(&path)
+            .and_then(|file| file.take(MAX_DOWNLOAD + 1).read_to_end(&mut data))
+            .map_err(|e| format!("Cannot read {}: {}", path.display(), e))?;
+    }
+    if data.len() as u64 > MAX_DOWNLOAD {
+        return Err(format!("{} is larger than {} bytes", name, MAX_DOWNLOAD));
+    }
+    Ok(data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ed25519_dalek::{Signer, SigningKey};
+    use serde_json::{json, Value};
+
+    const MAIN: &str = r#"{"hashes": [{"name": "Main", "hash": "00112233445566778899aabbccddeeff"}]}"#;
+    const EXTRA: &str = r#"{"hashes": [{"name": "Extra", "hash": "ffeeddccbbaa99887766554433221100"}]}"#;
+
+    /// An update source directory and a signature directory to update.
+    struct Fixture {
+        dir: PathBuf,
+        key: SigningKey,
+        config: UpdateConfig,
+        engine: ScanEngine,
+    }
+
+    impl Fixture {
+        fn new(name: &str) -> Self {
+            let dir = std::env::temp_dir().join(format!("update-test-{}-{}", name, std::process::id()));
+            let _ = fs::remove_dir_all(&dir);
+            fs::create_dir_all(dir.join("source")).unwrap();
+            fs::create_dir_all(dir.join("signatures")).unwrap();
+            let key = SigningKey::from_bytes(&[7; 32]);
+            let config = UpdateConfig {
+                source: dir.join("source").display().to_string(),
+                public_key: hex::encode(key.verifying_key().to_bytes()),
+            };
+            let engine = ScanEngine::new(dir.join("signatures"), dir.join("rules"));
+            Fixture { dir, key, config, engine }
+        }
+
+        fn sign(&self, data: &[u8]) -> String {
+            STANDARD.encode(self.key.sign(data).to_bytes())
+        }
+
+        /// Writes a signed delta between two versions and returns its
+        /// manifest entry.
+        fn delta(&self, from: u64, to: u64, files: Value) -> Value {
+            let file = format!("{}-{}.json", from, to);
+            let body = json!({"from": from, "to": to, "files": files}).to_string();
+            fs::write(self.dir.join("source").join(&file), &body).unwrap();
+            json!({"from": from, "to": to, "file": file, "signature": self.sign(body.as_bytes())})
+        }
+
+        fn publish(&self, version: u64, deltas: Vec<Value>, full: Option<Value>) {
+            let manifest =
+                json!({"version": version, "published": "2024-01-01T00:00:00Z", "deltas": deltas, "full": full})
+                    .to_string();
+            fs::write(self.dir.join("source").join(MANIFEST), &manifest).unwrap();
+            fs::write(self.dir.join("source").join(MANIFEST_SIGNATURE), self.sign(manifest.as_bytes())).unwrap();
+        }
+
+        /// Files of the installed database, with its version record.
+        fn installed_files(&self) -> Vec<String> {
+            let mut files: Vec<String> = fs::read_dir(self.dir.join("signatures").join(DATABASE_DIR))
+                .map(|entries| entries.map(|e| e.unwrap().file_name().to_string_lossy().into_owned()).collect())
+                .unwrap_or_default();
+            files.sort();
+            files
+        }
+
+        /// Installs version 2: the full snapshot holding `main.json`, then a
+        /// delta adding `extra.json`.
+        fn install_version_2(&self) {
+            let full = self.delta(0, 1, json!([{"name": "main.json", "content": STANDARD.encode(MAIN)}]));
+            let delta = self.delta(1, 2, json!([{"name": "extra.json", "content": STANDARD.encode(EXTRA)}]));
+            self.publish(2, vec![delta], Some(full));
+            update(&self.config, &self.engine).unwrap();
+        }
+
+        /// Checks that a failed update left version 2 installed as it was.
+        fn assert_version_2_intact(&self) {
+            assert_eq!(installed(self.engine.signature_dir()).version, 2);
+            assert_eq!(self.installed_files(), ["VERSION", "extra.json", "main.json"]);
+            assert!(!self.dir.join("signatures").join("updates.staging").exists());
+        }
+    }
+
+    impl Drop for Fixture {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.dir);
+        }
+    }
+
+    fn entry(from: u64, to: u64) -> Value {
+        json!({"from": from, "to": to, "file": format!("{}-{}.json", from, to), "signature": ""})
+    }
+
+    fn manifest(version: u64, deltas: Vec<Value>, full: Option<Value>) -> Manifest {
+        serde_json::from_value(json!({"version": version, "published": "", "deltas": deltas, "full": full})).unwrap()
+    }
+
+    fn steps(chain: &[DeltaEntry]) -> Vec<(u64, u64)> {
+        chain.iter().map(|d| (d.from, d.to)).collect()
+    }
+
+    #[test]
+    fn applies_signed_deltas() {
+        let fixture = Fixture::new("apply");
+        let full = fixture.delta(0, 1, json!([{"name": "main.json", "content": STANDARD.encode(MAIN)}]));
+        let add = fixture.delta(1, 2, json!([{"name": "extra.json", "content": STANDARD.encode(EXTRA)}]));
+        fixture.publish(2, vec![add.clone()], Some(full.clone()));
+
+        let report = update(&fixture.config, &fixture.engine).unwrap();
+        assert_eq!((report.from_version, report.to_version, report.deltas_applied), (0, 2, 2));
+        assert!(report.rebuilt);
+        assert_eq!(report.signatures.map(|s| s.hash_signatures), Some(2));
+        fixture.assert_version_2_intact();
+
+        let delete = fixture.delta(2, 3, json!([{"name": "main.json", "content": null}]));
+        fixture.publish(3, vec![add, delete], Some(full));
+        let report = update(&fixture.config, &fixture.engine).unwrap();
+        assert_eq!((report.from_version, report.to_version, report.deltas_applied), (2, 3, 1));
+        assert!(!report.rebuilt);
+        assert_eq!(fixture.installed_files(), ["VERSION", "extra.json"]);
+
+        let status = status(fixture.engine.signature_dir());
+        assert_eq!(status.version, 3);
+        assert_eq!(status.published.as_deref(), Some("2024-01-01T00:00:00Z"));
+        assert!(status.age_seconds.is_some_and(|age| age > 0));
+
+        let report = update(&fixture.config, &fixture.engine).unwrap();
+        assert_eq!(report.deltas_applied, 0);
+        assert!(report.signatures.is_none());
+    }
+
+    #[test]
+    fn rejects_tampered_delta() {
+        let fixture = Fixture::new("tampered");
+        fixture.install_version_2();
+        let mut delta = fixture.delta(2, 3, json!([{"name": "main.json", "content": null}]));
+        delta["signature"] = json!(fixture.sign(b"another delta"));
+        fixture.publish(3, vec![delta], None);
+
+        let error = update(&fixture.config, &fixture.engine).unwrap_err();
+        assert_eq!(error, "Delta 2-3.json rejected: bad signature");
+        fixture.assert_version_2_intact();
+    }
+
+    #[test]
+    fn rejects_tampered_manifest() {
+        let fixture = Fixture::new("manifest");
+        fixture.install_version_2();
+        fixture.publish(3, vec![fixture.delta(2, 3, json!([]))], None);
+        fs::write(fixture.dir.join("source").join(MANIFEST_SIGNATURE), fixture.sign(b"another manifest")).unwrap();
+
+        assert!(check(&fixture.config, fixture.engine.signature_dir()).is_err_and(|e| e.starts_with("Manifest rejected")));
+        assert!(update(&fixture.config, &fixture.engine).is_err());
+        fixture.assert_version_2_intact();
+    }
+
+    #[test]
+    fn rejects_delta_for_other_versions() {
+        let fixture = Fixture::new("replay");
+        fixture.install_version_2();
+        // A genuine delta listed under the wrong version range
+        let mut delta = fixture.delta(1, 2, json!([{"name": "main.json", "content": null}]));
+        delta["from"] = json!(2);
+        delta["to"] = json!(3);
+        fixture.publish(3, vec![delta], None);
+
+        let error = update(&fixture.config, &fixture.engine).unwrap_err();
+        assert_eq!(error, "Delta 1-2.json is for versions 1 to 2");
+        fixture.assert_version_2_intact();
+    }
+
+    #[test]
+    fn rolls_back_when_the_update_does_not_load() {
+        let fixture = Fixture::new("rollback");
+        fixture.install_version_2();
+        let delta = fixture.delta(
+            2,
+            3,
+            json!([
+                {"name": "main.json", "content": null},
+                {"name": "broken.json", "content": STANDARD.encode("{not json")},
+            ]),
+        );
+        fixture.publish(3, vec![delta], None);
+
+        let error = update(&fixture.config, &fixture.engine).unwrap_err();
+        assert!(error.starts_with("Updated database failed to load"), "{}", error);
+        fixture.assert_version_2_intact();
+    }
+
+    #[test]
+    fn rejects_downgrades_and_bad_file_names() {
+        let fixture = Fixture::new("names");
+        fixture.install_version_2();
+        fixture.publish(1, vec![], None);
+        assert!(update(&fixture.config, &fixture.engine).is_err_and(|e| e.contains("older than the installed")));
+
+        let delta = fixture.delta(2, 3, json!([{"name": "../escape.json", "content": STANDARD.encode(EXTRA)}]));
+        fixture.publish(3, vec![delta], None);
+        assert!(update(&fixture.config, &fixture.engine).is_err_and(|e| e.contains("invalid database file")));
+        fixture.assert_version_2_intact();
+        assert!(!fixture.dir.join("signatures").join("escape.json").exists());
+    }
+
+    #[test]
+    fn plans_deltas_then_falls_back_to_the_full_snapshot() {
+        let deltas = vec![entry(3, 4), entry(4, 5), entry(5, 6)];
+        let (rebuilt, chain) = plan(&manifest(6, deltas.clone(), Some(entry(0, 3))), 4).unwrap();
+        assert!(!rebuilt);
+        assert_eq!(steps(&chain), [(4, 5), (5, 6)]);
+
+        // Too far behind for the deltas
+        let (rebuilt, chain) = plan(&manifest(6, deltas.clone(), Some(entry(0, 3))), 1).unwrap();
+        assert!(rebuilt);
+        assert_eq!(steps(&chain), [(0, 3), (3, 4), (4, 5), (5, 6)]);
+
+        let (rebuilt, chain) = plan(&manifest(6, vec![], Some(entry(0, 6))), 0).unwrap();
+        assert!(rebuilt);
+        assert_eq!(steps(&chain), [(0, 6)]);
+
+        assert!(plan(&manifest(6, deltas.clone(), None), 1).is_err());
+        assert!(plan(&manifest(6, deltas, Some(entry(0, 2))), 1).is_err());
+    }
+}
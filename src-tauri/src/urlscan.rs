@@ -0,0 +1,502 @@
+//! Offline URL analysis.
+//!
+//! A URL is normalized the way the IOC store normalizes its URLs, looked up
+//! in the IOC index, and run through heuristics for the tricks phishing
+//! links rely on: raw or encoded IP hosts, internationalized and homoglyph
+//! domains, long subdomain chains, cheap top-level domains, brand
+//! lookalikes, credentials before the host and link shorteners. Each check
+//! is reported as an engine, so the result has the shape the URL scanner
+//! view already shows.
+
+use serde::{Deserialize, Serialize};
+use std::net::{IpAddr, Ipv4Addr};
+
+use crate::engine::ScanEngine;
+use crate::hashing::FileDigests;
+use crate::heuristics::{self, HeuristicScore};
+use crate::ioc::{self, IocHit};
+use crate::models::timestamp;
+use crate::signatures::Detection;
+
+/// More subdomain labels than this in front of the registered domain is
+/// unusual outside of phishing kits and tracking links.
+const MAX_SUBDOMAINS: usize = 3;
+
+/// Top-level domains that are free or near-free to register and are
+/// overrepresented in abuse reports.
+const SUSPICIOUS_TLDS: [&str; 28] = [
+    "bar", "buzz", "cam", "cf", "click", "country", "cyou", "download", "ga", "gdn", "gq", "icu", "kim", "loan",
+    "lol", "men", "ml", "monster", "mov", "racing", "rest", "review", "sbs", "stream", "tk", "top", "work", "zip",
+];
+
+const SHORTENERS: [&str; 18] = [
+    "bit.ly", "bl.ink", "buff.ly", "cutt.ly", "goo.gl", "is.gd", "lnkd.in", "ow.ly", "rb.gy", "rebrand.ly", "s.id",
+    "shorturl.at", "t.co", "t.ly", "tiny.cc", "tinyurl.com", "trib.al", "v.gd",
+];
+
+/// Brands phishing sites imitate, as the label of their registered domain.
+const BRANDS: [&str; 28] = [
+    "adobe", "amazon", "apple", "bankofamerica", "barclays", "binance", "chase", "citibank", "coinbase", "docusign",
+    "dropbox", "facebook", "gmail", "google", "icloud", "instagram", "linkedin", "metamask", "microsoft", "netflix",
+    "office365", "outlook", "paypal", "santander", "steamcommunity", "wellsfargo", "whatsapp", "yahoo",
+];
+
+/// Registered domains of the brands themselves that add words to the brand
+/// name, and would pass for lookalikes otherwise.
+const FIRST_PARTY: [&str; 8] = [
+    "amazon-adsystem.com", "apple-cloudkit.com", "apple-dns.net", "apple-mapkit.com", "google-analytics.com",
+    "icloud-content.com", "paypal-community.com", "paypal-objects.com",
+];
+
+/// Brands shorter than this are one typo away from ordinary words, and
+/// every this many letters allow one more typo.
+const MIN_TYPO_BRAND: usize = 6;
+
+/// Second-level labels under which ccTLDs sell registrations, as in
+/// `example.co.uk`.
+const SECOND_LEVEL: [&str; 10] = ["ac", "co", "com", "edu", "go", "gov", "ne", "net", "or", "org"];
+
+/// One check of a URL scan, shaped like an antivirus engine verdict.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UrlCheck {
+    pub engine: String,
+    pub detected: bool,
+    /// What the check found, or `Clean`.
+    pub result: String,
+}
+
+/// Field names follow the frontend's `UrlScanResult`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UrlScanResult {
+    /// The URL as submitted.
+    pub url: String,
+    pub normalized_url: String,
+    /// The host in Unicode, for internationalized domains.
+    pub display_host: String,
+    pub total_engines: usize,
+    pub detections: usize,
+    pub status: String, // "clean", "suspicious", "malicious"
+    pub scan_date: String,
+    pub engines: Vec<UrlCheck>,
+    pub findings: Vec<Detection>,
+    pub heuristics: HeuristicScore,
+    pub ioc_matches: Vec<IocHit>,
+}
+
+/// The parts of a URL the heuristics look at.
+struct ParsedUrl {
+    normalized: String,
+    /// ASCII host, IPv4 addresses in dotted form.
+    host: String,
+    unicode_host: String,
+    userinfo: Option<String>,
+    /// How the host was written when it was an IPv4 address in decimal,
+    /// hex or octal.
+    encoded_ip: Option<String>,
+}
+
+pub fn scan(engine: &ScanEngine, url: &str) -> Result<UrlScanResult, String> {
+    let parsed = parse(url).ok_or_else(|| format!("Not a valid URL: {}", url))?;
+    let ioc_matches = engine.iocs().matches(&FileDigests::default(), std::slice::from_ref(&parsed.normalized));
+    let ioc_findings = ioc::detections(&ioc_matches);
+
+    let checks: [(&str, Option<Detection>); 8] = [
+        ("IP address host", ip_host(&parsed)),
+        ("Internationalized domain", punycode(&parsed)),
+        ("Homoglyphs", homoglyphs(&parsed)),
+        ("Subdomains", subdomains(&parsed)),
+        ("Top-level domain", suspicious_tld(&parsed)),
+        ("Brand lookalike", lookalike(&parsed)),
+        ("Embedded credentials", credentials(&parsed)),
+        ("URL shortener", shortener(&parsed)),
+    ];
+    let mut engines = vec![UrlCheck {
+        engine: "IOC database".to_string(),
+        detected: !ioc_findings.is_empty(),
+        result: match ioc_findings.as_slice() {
+            [] => "Clean".to_string(),
+            [finding] => finding.name.clone(),
+            findings => format!("{} listings", findings.len()),
+        },
+    }];
+    let mut findings = ioc_findings;
+    for (name, finding) in checks {
+        engines.push(UrlCheck {
+            engine: name.to_string(),
+            detected: finding.is_some(),
+            result: finding
+                .as_ref()
+                .and_then(|f| f.description.clone())
+                .unwrap_or_else(|| "Clean".to_string()),
+        });
+        findings.extend(finding);
+    }
+
+    let config = engine.scoring();
+    let score = heuristics::score(&config, &findings);
+    let status = match config.status(&findings, &score) {
+        "threat" => "malicious",
+        status => status,
+    };
+    Ok(UrlScanResult {
+        url: url.to_string(),
+        normalized_url: parsed.normalized,
+        display_host: parsed.unicode_host,
+        total_engines: engines.len(),
+        detections: engines.iter().filter(|e| e.detected).count(),
+        status: status.to_string(),
+        scan_date: timestamp(),
+        engines,
+        findings,
+        heuristics: score,
+        ioc_matches,
+    })
+}
+
+/// Splits out what [`ioc::normalize_url`] drops or rejects, the user info,
+/// Unicode hosts and encoded IPv4 addresses, before normalizing.
+fn parse(url: &str) -> Option<ParsedUrl> {
+    let url = url.trim();
+    let (scheme, rest) = url.split_once("://").unwrap_or(("http", url));
+    let split = rest.find(['/', '?', '#']).unwrap_or(rest.len());
+    let (authority, path) = rest.split_at(split);
+    let (userinfo, authority) = match authority.rsplit_once('@') {
+        Some((userinfo, authority)) => (Some(userinfo.to_string()), authority),
+        None => (None, authority),
+    };
+    let (host, port) = ioc::split_port(authority);
+    let written = host.trim_end_matches('.');
+    let encoded_ip = encoded_ipv4(written);
+    let host = match encoded_ip {
+        Some(addr) => addr.to_string(),
+        None if written.starts_with('[') => written.to_ascii_lowercase(),
+        None => idna::domain_to_ascii(written).ok()?,
+    };
+    let port = port.map(|p| format!(":{}", p)).unwrap_or_default();
+    let normalized = ioc::normalize_url(&format!("{}://{}{}{}", scheme, host, port, path))?;
+    let host = ioc::url_host(&normalized)?;
+    let (unicode_host, _) = idna::domain_to_unicode(&host);
+    Some(ParsedUrl {
+        normalized,
+        host,
+        unicode_host,
+        userinfo,
+        encoded_ip: encoded_ip.map(|_| written.to_string()),
+    })
+}
+
+/// An IPv4 address written as one to three numbers, or with hex or octal
+/// parts, as browsers still accept: `3232235777`, `0xc0.0xa8.1.1`.
+fn encoded_ipv4(host: &str) -> Option<Ipv4Addr> {
+    if host.parse::<Ipv4Addr>().is_ok() {
+        return None;
+    }
+    let parts: Vec<u64> = host
+        .split('.')
+        .map(|part| {
+            let lower = part.to_ascii_lowercase();
+            match lower.strip_prefix("0x") {
+                Some(hex) => u64::from_str_radix(hex, 16).ok(),
+                None if lower.len() > 1 && lower.starts_with('0') => u64::from_str_radix(&lower[1..], 8).ok(),
+                None => lower.parse().ok(),
+            }
+        })
+        .collect::<Option<_>>()?;
+    let (last, leading) = parts.split_last()?;
+    if parts.len() > 4 || leading.iter().any(|&p| p > 255) || *last >= 1u64 << (8 * (5 - parts.len())) {
+        return None;
+    }
+    let value = leading.iter().enumerate().fold(*last, |value, (i, &part)| value | part << (24 - 8 * i));
+    Some(Ipv4Addr::from(value as u32))
+}
+
+fn finding(name: &str, description: String) -> Option<Detection> {
+    Some(Detection::heuristic(format!("Heuristic.URL.{}", name), "url", description))
+}
+
+fn ip_host(url: &ParsedUrl) -> Option<Detection> {
+    if let Some(written) = &url.encoded_ip {
+        return finding("IpHost", format!("Host {} is the IP address {} in disguise", written, url.host));
+    }
+    if url.host.parse::<IpAddr>().is_err() {
+        return None;
+    }
+    finding("IpHost", format!("Host is the IP address {} instead of a domain name", url.host))
+}
+
+fn punycode(url: &ParsedUrl) -> Option<Detection> {
+    if !url.host.split('.').any(|label| label.starts_with("xn--")) {
+        return None;
+    }
+    finding("Punycode", format!("Internationalized domain {} is shown as {}", url.host, url.unicode_host))
+}
+
+/// Labels written in Cyrillic or Greek letters that pass for Latin ones,
+/// or that mix those scripts with Latin. Accented Latin alone is left to
+/// the lookalike check, it is how many legitimate domains are spelled.
+fn homoglyphs(url: &ParsedUrl) -> Option<Detection> {
+    for label in url.unicode_host.split('.').filter(|l| !l.is_ascii()) {
+        let mut scripts: Vec<Script> = label.chars().filter_map(script).collect();
+        scripts.sort();
+        scripts.dedup();
+        if !scripts.iter().any(|s| *s != Script::Latin) {
+            continue;
+        }
+        let skeleton: String = label.chars().map(skeleton_char).collect();
+        if skeleton.is_ascii() {
+            let imitated = url.unicode_host.replacen(label, &skeleton, 1);
+            let description = format!("{} imitates {} with look-alike characters", url.unicode_host, imitated);
+            return finding("Homoglyph", description);
+        }
+        if scripts.len() > 1 {
+            return finding("Homoglyph", format!("{} mixes Latin with Cyrillic or Greek letters", url.unicode_host));
+        }
+    }
+    None
+}
+
+fn subdomains(url: &ParsedUrl) -> Option<Detection> {
+    let (subdomains, _) = domain_parts(&url.host)?;
+    if subdomains.len() <= MAX_SUBDOMAINS {
+        return None;
+    }
+    finding("ExcessiveSubdomains", format!("{} has {} levels of subdomains", url.host, subdomains.len()))
+}
+
+fn suspicious_tld(url: &ParsedUrl) -> Option<Detection> {
+    let tld = url.host.rsplit('.').next()?;
+    if !SUSPICIOUS_TLDS.contains(&tld) {
+        return None;
+    }
+    finding("SuspiciousTld", format!("Top-level domain .{} is common in abuse", tld))
+}
+
+/// Registered names that spell a brand with substituted characters, one
+/// typo away from it or with words added, and brands used as a subdomain
+/// of someone else's domain. The brands' own domains are left alone.
+fn lookalike(url: &ParsedUrl) -> Option<Detection> {
+    let (subdomains, name) = domain_parts(&url.host)?;
+    let registered = &url.host[subdomains.iter().map(|label| label.len() + 1).sum::<usize>()..];
+    if FIRST_PARTY.contains(&registered) {
+        return None;
+    }
+    let (_, unicode_name) = domain_parts(&url.unicode_host)?;
+    let skeleton: String = unicode_name.chars().map(skeleton_char).collect();
+    let skeleton = skeleton.replace("rn", "m").replace("vv", "w");
+    for brand in BRANDS {
+        if name == brand {
+            continue;
+        }
+        let reason = if skeleton == brand {
+            format!("{} spells {} with substituted characters", url.unicode_host, brand)
+        } else if brand.len() >= MIN_TYPO_BRAND && edit_distance(name, brand) <= brand.len() / MIN_TYPO_BRAND {
+            format!("{} is a misspelling of {}", url.host, brand)
+        } else if name.split('-').any(|word| word == brand) {
+            format!("{} adds words to the brand {}", url.host, brand)
+        } else if subdomains.iter().flat_map(|label| label.split('-')).any(|word| word == brand) {
+            format!("{} puts the brand {} in front of an unrelated domain", url.host, brand)
+        } else {
+            continue;
+        };
+        return finding("Lookalike", reason);
+    }
+    None
+}
+
+fn credentials(url: &ParsedUrl) -> Option<Detection> {
+    let userinfo = url.userinfo.as_ref()?;
+    let description = if userinfo.contains(':') {
+        format!("URL carries a user name and password before the host {}", url.host)
+    } else {
+        format!("URL puts {} before the real host {}", userinfo, url.host)
+    };
+    finding("Credentials", description)
+}
+
+fn shortener(url: &ParsedUrl) -> Option<Detection> {
+    if !SHORTENERS.contains(&url.host.trim_start_matches("www.")) {
+        return None;
+    }
+    finding("Shortener", format!("{} is a link shortener that hides the destination", url.host))
+}
+
+/// The subdomain labels and registered name of a domain; `None` for IP
+/// addresses and single labels.
+fn domain_parts(host: &str) -> Option<(Vec<&str>, &str)> {
+    if host.parse::<IpAddr>().is_ok() {
+        return None;
+    }
+    let labels: Vec<&str> = host.split('.').collect();
+    let n = labels.len();
+    let suffix = if n >= 3 && labels[n - 1].len() == 2 && SECOND_LEVEL.contains(&labels[n - 2]) { 2 } else { 1 };
+    let name = *labels.get(n.checked_sub(suffix + 1)?)?;
+    Some((labels[..n - suffix - 1].to_vec(), name))
+}
+
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, &cb) in b.iter().enumerate() {
+            let substitution = diagonal + usize::from(ca != cb);
+            diagonal = row[j + 1];
+            row[j + 1] = substitution.min(row[j] + 1).min(diagonal + 1);
+        }
+    }
+    row[b.len()]
+}
+
+#[derive(PartialEq, Eq, PartialOrd, Ord)]
+enum Script {
+    Latin,
+    Greek,
+    Cyrillic,
+}
+
+fn script(c: char) -> Option<Script> {
+    match c {
+        'a'..='z' | 'A'..='Z' | '\u{00c0}'..='\u{024f}' => Some(Script::Latin),
+        '\u{0370}'..='\u{03ff}' => Some(Script::Greek),
+        '\u{0400}'..='\u{052f}' => Some(Script::Cyrillic),
+        _ => None,
+    }
+}
+
+/// The ASCII character `c` passes for, or `c` itself.
+fn skeleton_char(c: char) -> char {
+    match c {
+        '0' => 'o',
+        '1' => 'l',
+        '3' => 'e',
+        '5' => 's',
+        'а' | 'à' | 'á' | 'â' | 'ã' | 'ä' | 'å' | 'ɑ' | 'α' => 'a',
+        'Ь' | 'ḃ' => 'b',
+        'с' | 'ç' | 'ϲ' => 'c',
+        'ԁ' | 'ɗ' => 'd',
+        'е' | 'è' | 'é' | 'ê' | 'ë' | 'ё' => 'e',
+        'ɡ' => 'g',
+        'һ' => 'h',
+        'і' | 'ì' | 'í' | 'î' | 'ï' | 'ı' | 'ι' | 'ї' => 'i',
+        'ј' => 'j',
+        'κ' | 'к' => 'k',
+        'ӏ' | 'ł' => 'l',
+        'ո' => 'n',
+        'о' | 'ò' | 'ó' | 'ô' | 'õ' | 'ö' | 'ο' | 'σ' => 'o',
+        'р' | 'ρ' => 'p',
+        'ԛ' => 'q',
+        'ѕ' => 's',
+        'τ' => 't',
+        'υ' | 'ù' | 'ú' | 'û' | 'ü' => 'u',
+        'ν' | 'ѵ' => 'v',
+        'ԝ' | 'ѡ' => 'w',
+        'х' | 'χ' => 'x',
+        'у' | 'ý' | 'ÿ' | 'γ' => 'y',
+        'ᴢ' => 'z',
+        c => c,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn check(check: fn(&ParsedUrl) -> Option<Detection>, url: &str) -> Option<String> {
+        check(&parse(url).unwrap()).map(|d| d.description.unwrap())
+    }
+
+    #[test]
+    fn brands_own_domains_are_not_lookalikes() {
+        for url in [
+            "https://www.paypal.com/signin",
+            "https://www.amazon.co.uk/",
+            "https://aax-eu.amazon-adsystem.com/e/dtb",
+            "https://www.paypal-objects.com/js/app.js",
+            "https://p12-content.icloud-content.com/a",
+            "https://ssl.google-analytics.com/ga.js",
+        ] {
+            assert_eq!(check(lookalike, url), None, "{}", url);
+        }
+    }
+
+    #[test]
+    fn flags_brand_lookalikes() {
+        let cases = [
+            ("http://paypa1.com/", "paypa1.com spells paypal with substituted characters"),
+            ("http://p\u{430}ypal.com/", "p\u{430}ypal.com spells paypal with substituted characters"),
+            ("http://microsofl.com/", "microsofl.com is a misspelling of microsoft"),
+            ("http://paypal-secure-login.com/", "paypal-secure-login.com adds words to the brand paypal"),
+            ("http://evil-amazon-adsystem.com/", "evil-amazon-adsystem.com adds words to the brand amazon"),
+            (
+                "http://paypal.com.evil.example/",
+                "paypal.com.evil.example puts the brand paypal in front of an unrelated domain",
+            ),
+            (
+                "http://amazon-adsystem.com.evil.example/",
+                "amazon-adsystem.com.evil.example puts the brand amazon in front of an unrelated domain",
+            ),
+        ];
+        for (url, description) in cases {
+            assert_eq!(check(lookalike, url).as_deref(), Some(description), "{}", url);
+        }
+        // Short brands are one typo away from ordinary words
+        assert_eq!(check(lookalike, "http://chose.com/"), None);
+    }
+
+    #[test]
+    fn decodes_disguised_ip_hosts() {
+        assert_eq!(encoded_ipv4("3232235777"), Some(Ipv4Addr::new(192, 168, 1, 1)));
+        assert_eq!(encoded_ipv4("0xc0.0xa8.1.1"), Some(Ipv4Addr::new(192, 168, 1, 1)));
+        assert_eq!(encoded_ipv4("0300.0250.1.1"), Some(Ipv4Addr::new(192, 168, 1, 1)));
+        assert_eq!(encoded_ipv4("192.168.1"), Some(Ipv4Addr::new(192, 168, 0, 1)));
+        assert_eq!(encoded_ipv4("192.168.1.1"), None);
+        assert_eq!(encoded_ipv4("1.2.3.4.5"), None);
+        assert_eq!(encoded_ipv4("256.1.1.1"), None);
+        assert_eq!(encoded_ipv4("example.com"), None);
+
+        assert_eq!(
+            check(ip_host, "http://3232235777/login").as_deref(),
+            Some("Host 3232235777 is the IP address 192.168.1.1 in disguise")
+        );
+        assert!(check(ip_host, "http://[2001:db8::1]/").is_some());
+        assert_eq!(check(ip_host, "http://example.com/"), None);
+    }
+
+    #[test]
+    fn flags_the_other_tricks() {
+        assert!(check(homoglyphs, "http://аррӏе.com/").is_some());
+        assert_eq!(check(homoglyphs, "http://münchen.de/"), None);
+        assert!(check(punycode, "http://münchen.de/").is_some());
+        assert!(check(subdomains, "http://a.b.c.d.example.co.uk/").is_some());
+        assert_eq!(check(subdomains, "http://a.b.c.example.co.uk/"), None);
+        assert!(check(suspicious_tld, "http://prize.top/").is_some());
+        assert_eq!(
+            check(credentials, "http://www.paypal.com@evil.example/").as_deref(),
+            Some("URL puts www.paypal.com before the real host evil.example")
+        );
+        assert!(check(shortener, "https://www.bit.ly/abc").is_some());
+        assert!(parse("http://exa mple.com/").is_none());
+    }
+
+    #[test]
+    fn scores_the_checks_together() {
+        let dir = std::env::temp_dir().join(format!("varenizer-urlscan-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let engine = ScanEngine::new(dir.join("signatures"), dir.join("rules"));
+
+        let result = scan(&engine, "https://www.amazon-adsystem.com/widgets").unwrap();
+        assert_eq!((result.status.as_str(), result.detections), ("clean", 0));
+        assert_eq!(result.total_engines, 9);
+
+        let result = scan(&engine, "http://paypal-login.verify.account.secure.example.top/").unwrap();
+        assert_eq!(result.status.as_str(), "malicious");
+        let names: Vec<&str> = result.findings.iter().map(|f| f.name.as_str()).collect();
+        assert_eq!(
+            names,
+            ["Heuristic.URL.ExcessiveSubdomains", "Heuristic.URL.SuspiciousTld", "Heuristic.URL.Lookalike"]
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
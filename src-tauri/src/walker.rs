@@ -0,0 +1,426 @@
+//! Scan target enumeration and the Quick/Full scan profiles.
+//!
+//! A quick scan only looks at locations malware is commonly dropped into or
+//! started from, and only at files that can execute (executables, scripts,
+//! macro documents, installers). A full scan walks everything below its roots
+//! except virtual filesystems.
+
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use walkdir::WalkDir;
+
+use crate::archive::ArchiveLimits;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ScanType {
+    #[default]
+    Quick,
+    Full,
+}
+
+impl ScanType {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ScanType::Quick => "quick",
+            ScanType::Full => "full",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SymlinkPolicy {
+    /// Symlinks are reported as skipped and never followed.
+    #[default]
+    Skip,
+    /// Symlinks are resolved; loops are detected and reported as errors.
+    Follow,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ScanOptions {
+    pub symlinks: SymlinkPolicy,
+    /// Overrides the profile's size limit, `0` disables it.
+    pub max_file_size: Option<u64>,
+    pub exclude: Vec<String>,
+    /// Number of files scanned concurrently, defaults to the number of cores.
+    pub workers: Option<usize>,
+    /// Upper bound on file data held in memory by the workers at once.
+    pub max_in_flight_bytes: Option<u64>,
+    /// Bytes of each file that are matched and unpacked. Larger files are
+    /// still hashed in full. Never more than `max_in_flight_bytes`.
+    pub max_scan_size: Option<u64>,
+    pub archive: ArchiveLimits,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ScanError {
+    pub path: String,
+    pub message: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct ScanProfile {
+    pub scan_type: ScanType,
+    pub roots: Vec<PathBuf>,
+    pub max_depth: Option<usize>,
+    pub max_file_size: Option<u64>,
+    pub executables_only: bool,
+    pub excluded: Vec<PathBuf>,
+}
+
+const QUICK_MAX_DEPTH: usize = 6;
+const QUICK_MAX_FILE_SIZE: u64 = 64 * 1024 * 1024;
+
+/// Extensions of files that can run code directly or via a common host.
+const EXECUTABLE_EXTENSIONS: [&str; 48] = [
+    "exe", "dll", "sys", "scr", "com", "cpl", "ocx", "msi", "msp", "lnk", "bat", "cmd", "ps1", "psm1", "vbs", "vbe", "js",
+    "jse", "wsf", "wsh", "hta", "jar", "apk", "sh", "bash", "zsh", "py", "pl", "rb", "php", "elf", "bin", "run", "so",
+    "dylib", "appimage", "deb", "rpm", "docm", "dotm", "xlsm", "xltm", "xlam", "pptm", "potm", "ppam", "desktop",
+    "service",
+];
+
+fn home_dir() -> Option<PathBuf> {
+    std::env::var_os("HOME")
+        .or_else(|| std::env::var_os("USERPROFILE"))
+        .map(PathBuf::from)
+}
+
+/// Places where droppers and persistence mechanisms typically live.
+fn high_risk_locations() -> Vec<PathBuf> {
+    let mut roots = Vec::new();
+
+    if let Some(temp) = std::env::var_os("TMPDIR").or_else(|| std::env::var_os("TEMP")) {
+        roots.push(PathBuf::from(temp));
+    }
+
+    if let Some(home) = home_dir() {
+        for dir in ["Downloads", "Desktop", "Documents", ".local/bin", ".config/autostart", ".config/systemd/user"] {
+            roots.push(home.join(dir));
+        }
+        if cfg!(target_os = "macos") {
+            roots.push(home.join("Library/LaunchAgents"));
+        }
+        if cfg!(windows) {
+            roots.push(home.join("AppData/Local/Temp"));
+            roots.push(home.join("AppData/Roaming/Microsoft/Windows/Start Menu/Programs/Startup"));
+        }
+    }
+
+    if cfg!(unix) {
+        for dir in [
+            "/tmp",
+            "/var/tmp",
+            "/dev/shm",
+            "/etc/cron.d",
+            "/etc/cron.daily",
+            "/etc/cron.hourly",
+            "/var/spool/cron",
+            "/etc/systemd/system",
+            "/etc/init.d",
+            "/usr/local/bin",
+        ] {
+            roots.push(PathBuf::from(dir));
+        }
+    }
+    if cfg!(target_os = "macos") {
+        roots.push(PathBuf::from("/Library/LaunchAgents"));
+        roots.push(PathBuf::from("/Library/LaunchDaemons"));
+    }
+    if cfg!(windows) {
+        roots.push(PathBuf::from("C:/ProgramData/Microsoft/Windows/Start Menu/Programs/StartUp"));
+        roots.push(PathBuf::from("C:/Windows/Temp"));
+    }
+
+    roots.sort();
+    roots.dedup();
+    roots.retain(|p| p.exists());
+    roots
+}
+
+fn filesystem_roots() -> Vec<PathBuf> {
+    if cfg!(windows) {
+        (b'A'..=b'Z')
+            .map(|d| PathBuf::from(format!("{}:\\", d as char)))
+            .filter(|p| p.exists())
+            .collect()
+    } else {
+        vec![PathBuf::from("/")]
+    }
+}
+
+/// Kernel and runtime pseudo-filesystems that only contain special files.
+fn virtual_filesystems() -> Vec<PathBuf> {
+    if cfg!(unix) {
+        ["/proc", "/sys", "/dev", "/run"].iter().map(PathBuf::from).collect()
+    } else {
+        Vec::new()
+    }
+}
+
+impl ScanProfile {
+    /// Quick profile over `paths`, or over the high-risk locations when no
+    /// paths are given.
+    pub fn quick(paths: &[PathBuf]) -> Self {
+        ScanProfile {
+            scan_type: ScanType::Quick,
+            roots: if paths.is_empty() { high_risk_locations() } else { paths.to_vec() },
+            max_depth: Some(QUICK_MAX_DEPTH),
+            max_file_size: Some(QUICK_MAX_FILE_SIZE),
+            executables_only: true,
+            excluded: virtual_filesystems(),
+        }
+    }
+
+    /// Full profile over `paths`, or over every filesystem root when no paths
+    /// are given.
+    pub fn full(paths: &[PathBuf]) -> Self {
+        ScanProfile {
+            scan_type: ScanType::Full,
+            roots: if paths.is_empty() { filesystem_roots() } else { paths.to_vec() },
+            max_depth: None,
+            max_file_size: None,
+            executables_only: false,
+            excluded: virtual_filesystems(),
+        }
+    }
+
+    pub fn new(scan_type: ScanType, paths: &[PathBuf], options: &ScanOptions) -> Self {
+        let mut profile = match scan_type {
+            ScanType::Quick => ScanProfile::quick(paths),
+            ScanType::Full => ScanProfile::full(paths),
+        };
+        match options.max_file_size {
+            Some(0) => profile.max_file_size = None,
+            Some(limit) => profile.max_file_size = Some(limit),
+            None => {}
+        }
+        profile.excluded.extend(options.exclude.iter().map(PathBuf::from));
+        // An explicitly requested root always wins over the default exclusions
+        let roots = profile.roots.clone();
+        profile.excluded.retain(|ex| !roots.iter().any(|root| root.starts_with(ex)));
+        profile
+    }
+
+    fn is_excluded(&self, path: &Path) -> bool {
+        self.excluded.iter().any(|ex| path.starts_with(ex))
+    }
+}
+
+pub fn is_executable_type(path: &Path, metadata: &fs::Metadata) -> bool {
+    let by_extension = path
+        .extension()
+        .and_then(|e| e.to_str())
+        .is_some_and(|e| EXECUTABLE_EXTENSIONS.contains(&e.to_ascii_lowercase().as_str()));
+    if by_extension {
+        return true;
+    }
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        if metadata.permissions().mode() & 0o111 != 0 {
+            return true;
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = metadata;
+
+    // Extensionless or misnamed binaries and scripts
+    let mut magic = [0u8; 4];
+    let read = File::open(path).and_then(|mut f| f.read(&mut magic)).unwrap_or(0);
+    let magic = &magic[..read];
+    magic.starts_with(b"MZ")
+        || magic.starts_with(b"\x7fELF")
+        || magic.starts_with(b"#!")
+        || magic == [0xcf, 0xfa, 0xed, 0xfe]
+        || magic == [0xce, 0xfa, 0xed, 0xfe]
+}
+
+#[derive(Debug)]
+pub enum WalkEntry {
+    /// A selected file and its size in bytes.
+    File(PathBuf, u64),
+    /// Symlinks (when not followed), special files and files filtered out by
+    /// the profile.
+    Skipped,
+    Error(ScanError),
+}
+
+/// Lazily enumerates the files a profile selects.
+pub fn walk(profile: &ScanProfile, symlinks: SymlinkPolicy) -> impl Iterator<Item = WalkEntry> + '_ {
+    profile.roots.iter().flat_map(move |root| {
+        // Sorted so repeated scans of the same tree report files in the same order
+        let mut walker = WalkDir::new(root)
+            .follow_links(symlinks == SymlinkPolicy::Follow)
+            .sort_by_file_name();
+        if let Some(depth) = profile.max_depth {
+            walker = walker.max_depth(depth);
+        }
+
+        walker
+            .into_iter()
+            .filter_entry(move |e| !profile.is_excluded(e.path()))
+            .filter_map(move |entry| {
+                let entry = match entry {
+                    Ok(entry) => entry,
+                    Err(e) => {
+                        let path = e.path().map(|p| p.to_string_lossy().to_string()).unwrap_or_default();
+                        let message = if e.loop_ancestor().is_some() {
+                            "symlink loop detected".to_string()
+                        } else {
+                            e.io_error().map(|io| io.to_string()).unwrap_or_else(|| e.to_string())
+                        };
+                        return Some(WalkEntry::Error(ScanError { path, message }));
+                    }
+                };
+
+                let file_type = entry.file_type();
+                if file_type.is_dir() {
+                    return None;
+                }
+                let path = entry.path().to_path_buf();
+                if file_type.is_symlink() {
+                    // Only reached with SymlinkPolicy::Skip or for dangling links
+                    return Some(WalkEntry::Skipped);
+                }
+                if !file_type.is_file() {
+                    return Some(WalkEntry::Skipped);
+                }
+
+                let metadata = match entry.metadata() {
+                    Ok(metadata) => metadata,
+                    Err(e) => {
+                        return Some(WalkEntry::Error(ScanError {
+                            path: path.to_string_lossy().to_string(),
+                            message: e.to_string(),
+                        }))
+                    }
+                };
+                if profile.max_file_size.is_some_and(|limit| metadata.len() > limit) {
+                    return Some(WalkEntry::Skipped);
+                }
+                if profile.executables_only && !is_executable_type(&path, &metadata) {
+                    return Some(WalkEntry::Skipped);
+                }
+
+                Some(WalkEntry::File(path, metadata.len()))
+            })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Walked {
+        files: Vec<String>,
+        skipped: usize,
+        errors: Vec<String>,
+    }
+
+    fn run(profile: &ScanProfile, symlinks: SymlinkPolicy) -> Walked {
+        let mut walked = Walked { files: Vec::new(), skipped: 0, errors: Vec::new() };
+        let root = &profile.roots[0];
+        for entry in walk(profile, symlinks) {
+            match entry {
+                WalkEntry::File(path, _) => {
+                    walked.files.push(path.strip_prefix(root).unwrap().to_string_lossy().replace('\\', "/"))
+                }
+                WalkEntry::Skipped => walked.skipped += 1,
+                WalkEntry::Error(e) => walked.errors.push(e.message),
+            }
+        }
+        walked
+    }
+
+    fn profile(scan_type: ScanType, root: &Path, options: &ScanOptions) -> ScanProfile {
+        ScanProfile::new(scan_type, &[root.to_path_buf()], options)
+    }
+
+    fn tree(name: &str) -> PathBuf {
+        let root = std::env::temp_dir().join(format!("varenizer-walker-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        let deep = root.join("1/2/3/4/5");
+        fs::create_dir_all(deep.join("6")).unwrap();
+        fs::create_dir_all(root.join("cache")).unwrap();
+        for (path, data) in [
+            ("setup.exe", &b"MZ"[..]),
+            ("notes.txt", b"plain text"),
+            ("run", b"#!/bin/sh\n"),
+            ("renamed.dat", b"\x7fELF"),
+            ("large.ps1", &[b'#'; 2048][..]),
+            ("cache/cached.exe", b"MZ"),
+            ("1/2/3/4/5/shallow.sh", b"#!/bin/sh\n"),
+            ("1/2/3/4/5/6/deep.sh", b"#!/bin/sh\n"),
+        ] {
+            fs::write(root.join(path), data).unwrap();
+        }
+        root
+    }
+
+    #[test]
+    fn quick_scans_pick_executables_within_limits() {
+        let root = tree("quick");
+        let options = ScanOptions {
+            max_file_size: Some(1024),
+            exclude: vec![root.join("cache").to_string_lossy().to_string()],
+            ..Default::default()
+        };
+        let walked = run(&profile(ScanType::Quick, &root, &options), SymlinkPolicy::Skip);
+        // Sorted by name, magic bytes count as much as extensions
+        assert_eq!(walked.files, ["1/2/3/4/5/shallow.sh", "renamed.dat", "run", "setup.exe"]);
+        // notes.txt isn't executable, large.ps1 is over the size limit
+        assert_eq!(walked.skipped, 2);
+        assert!(walked.errors.is_empty());
+
+        // `0` lifts the profile's size limit
+        let options = ScanOptions { max_file_size: Some(0), ..Default::default() };
+        let walked = run(&profile(ScanType::Quick, &root, &options), SymlinkPolicy::Skip);
+        assert!(walked.files.contains(&"large.ps1".to_string()));
+        assert!(walked.files.contains(&"cache/cached.exe".to_string()));
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn full_scans_take_everything() {
+        let root = tree("full");
+        let walked = run(&profile(ScanType::Full, &root, &ScanOptions::default()), SymlinkPolicy::Skip);
+        assert_eq!(walked.files.len(), 8);
+        assert!(walked.files.contains(&"1/2/3/4/5/6/deep.sh".to_string()));
+        assert_eq!(walked.skipped, 0);
+
+        // Exclusions covering a requested root are dropped
+        let options = ScanOptions { exclude: vec![root.to_string_lossy().to_string()], ..Default::default() };
+        let cache = profile(ScanType::Full, &root.join("cache"), &options);
+        assert!(cache.excluded.iter().all(|ex| *ex != root));
+        assert_eq!(walk(&cache, SymlinkPolicy::Skip).count(), 1);
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symlinks_are_skipped_or_followed() {
+        let root = tree("links");
+        std::os::unix::fs::symlink(root.join("setup.exe"), root.join("link.exe")).unwrap();
+        std::os::unix::fs::symlink(&root, root.join("cache/loop")).unwrap();
+        let full = profile(ScanType::Full, &root, &ScanOptions::default());
+
+        let walked = run(&full, SymlinkPolicy::Skip);
+        assert_eq!(walked.files.len(), 8);
+        assert_eq!(walked.skipped, 2);
+
+        let walked = run(&full, SymlinkPolicy::Follow);
+        assert_eq!(walked.files.len(), 9);
+        assert!(walked.files.contains(&"link.exe".to_string()));
+        assert_eq!(walked.errors, ["symlink loop detected"]);
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,338 @@
+//! Condition evaluation against a scanned buffer.
+
+use regex_automata::hybrid::dfa::{Cache, OverlappingState};
+use regex_automata::{Anchored, Input};
+
+use super::parser::{BinOp, Expr, IterValues, Quantifier, Rule, StringDef};
+
+/// Upper bound on recorded matches per string, YARA's `YR_MAX_STRING_MATCHES`,
+/// so a pattern like `{ 00 }` can't exhaust memory on large files.
+pub const MAX_MATCHES_PER_STRING: usize = 1_000_000;
+
+/// Longest match looked for, YARA's `RE_SCAN_LIMIT`. Keeps the cost of
+/// each match start bounded, so the whole search stays linear.
+const MAX_MATCH_LENGTH: usize = 4096;
+
+/// Data searched for match starts in one go, so a string matching at every
+/// byte doesn't need a list of starts as long as the file.
+const START_WINDOW: usize = 64 * 1024;
+
+/// Upper bound on iterations of a `for ... in (a..b)` loop.
+const MAX_LOOP_ITERATIONS: i64 = 1_000_000;
+
+pub type Matches = Vec<Vec<(usize, usize)>>;
+
+#[derive(Debug, Clone, Copy)]
+enum Value {
+    Int(i64),
+    Bool(bool),
+}
+
+impl Value {
+    fn as_bool(self) -> bool {
+        match self {
+            Value::Bool(b) => b,
+            Value::Int(n) => n != 0,
+        }
+    }
+
+    fn as_int(self) -> i64 {
+        match self {
+            Value::Bool(b) => b as i64,
+            Value::Int(n) => n,
+        }
+    }
+}
+
+/// Returns up to `limit` matches of `def`, as (offset, length) pairs. Like
+/// YARA, every offset a match starts at is reported, so matches overlap.
+pub fn find_matches(def: &StringDef, data: &[u8], limit: usize) -> Vec<(usize, usize)> {
+    let mut matches = Vec::new();
+    let mut cache = def.starts.create_cache();
+    let mut from = 0;
+
+    while from < data.len() && matches.len() < limit {
+        let to = (from + START_WINDOW).min(data.len());
+        for start in match_starts(def, &mut cache, data, from, to) {
+            let input = Input::new(data)
+                .range(start..(start + MAX_MATCH_LENGTH).min(data.len()))
+                .anchored(Anchored::Yes);
+            let Some(m) = def.regex.find(input) else {
+                continue;
+            };
+            if m.is_empty() || (def.fullword && !is_full_word(data, m.start(), m.end())) {
+                continue;
+            }
+            matches.push((m.start(), m.len()));
+            if matches.len() == limit {
+                break;
+            }
+        }
+        from = to;
+    }
+    matches
+}
+
+/// Offsets in `from..to` where a match of `def` may start, in ascending
+/// order, found in one backwards pass.
+fn match_starts(def: &StringDef, cache: &mut Cache, data: &[u8], from: usize, to: usize) -> Vec<usize> {
+    // Far enough past the window to see the end of any match starting in it
+    let input = Input::new(data).range(from..(to + MAX_MATCH_LENGTH).min(data.len()));
+    let mut state = OverlappingState::start();
+    let mut starts = Vec::new();
+    loop {
+        if def.starts.try_search_overlapping_rev(cache, &input, &mut state).is_err() {
+            // Only reachable through quit bytes, which are never set up
+            return (from..to).collect();
+        }
+        match state.get_match() {
+            Some(m) if m.offset() < to => starts.push(m.offset()),
+            Some(_) => {}
+            None => break,
+        }
+    }
+    starts.reverse();
+    starts
+}
+
+/// For each of the rule's strings, whether the condition looks at its single
+/// matches through `#`, `@`, `!`, `at` or `in`. Any other string only has to
+/// be searched for up to its first match.
+pub fn counted_strings(rule: &Rule) -> Vec<bool> {
+    let mut counted = vec![false; rule.strings.len()];
+    mark_counted(&rule.condition, &[], &mut counted);
+    counted
+}
+
+/// `anonymous` holds the strings `$` stands for inside a `for ... of` loop.
+fn mark_counted(expr: &Expr, anonymous: &[usize], counted: &mut [bool]) {
+    let mut mark = |idx: &Option<usize>| match idx {
+        Some(idx) => counted[*idx] = true,
+        None => anonymous.iter().for_each(|&idx| counted[idx] = true),
+    };
+    match expr {
+        Expr::Count(idx) => mark(idx),
+        Expr::MatchedAt(idx, e) | Expr::MatchOffset(idx, e) | Expr::MatchLength(idx, e) => {
+            mark(idx);
+            mark_counted(e, anonymous, counted);
+        }
+        Expr::MatchedIn(idx, lo, hi) => {
+            mark(idx);
+            mark_counted(lo, anonymous, counted);
+            mark_counted(hi, anonymous, counted);
+        }
+        Expr::ReadInt(_, e) | Expr::Not(e) | Expr::Neg(e) | Expr::BitNot(e) => mark_counted(e, anonymous, counted),
+        Expr::And(a, b) | Expr::Or(a, b) | Expr::Binary(_, a, b) => {
+            mark_counted(a, anonymous, counted);
+            mark_counted(b, anonymous, counted);
+        }
+        Expr::Of(quantifier, _) => mark_quantifier(quantifier, anonymous, counted),
+        Expr::ForOf(quantifier, strings, body) => {
+            mark_quantifier(quantifier, anonymous, counted);
+            mark_counted(body, strings, counted);
+        }
+        Expr::ForIn { quantifier, values, body, .. } => {
+            mark_quantifier(quantifier, anonymous, counted);
+            match values {
+                IterValues::Range(lo, hi) => {
+                    mark_counted(lo, anonymous, counted);
+                    mark_counted(hi, anonymous, counted);
+                }
+                IterValues::List(items) => items.iter().for_each(|e| mark_counted(e, anonymous, counted)),
+            }
+            mark_counted(body, anonymous, counted);
+        }
+        Expr::Bool(_) | Expr::Int(_) | Expr::Filesize | Expr::Matched(_) | Expr::Var(_) | Expr::RuleRef(_) => {}
+    }
+}
+
+fn mark_quantifier(quantifier: &Quantifier, anonymous: &[usize], counted: &mut [bool]) {
+    if let Quantifier::Count(e) | Quantifier::Percent(e) = quantifier {
+        mark_counted(e, anonymous, counted);
+    }
+}
+
+fn is_full_word(data: &[u8], start: usize, end: usize) -> bool {
+    let is_word = |b: u8| b.is_ascii_alphanumeric();
+    let before = start.checked_sub(1).map(|i| data[i]);
+    let after = data.get(end).copied();
+    !before.is_some_and(is_word) && !after.is_some_and(is_word)
+}
+
+pub struct Context<'a> {
+    pub data: &'a [u8],
+    pub matches: &'a Matches,
+    pub rule_results: &'a [bool],
+    vars: Vec<(String, i64)>,
+    anonymous: Option<usize>,
+}
+
+impl<'a> Context<'a> {
+    pub fn new(data: &'a [u8], matches: &'a Matches, rule_results: &'a [bool]) -> Self {
+        Context { data, matches, rule_results, vars: Vec::new(), anonymous: None }
+    }
+
+    pub fn eval_condition(&mut self, rule: &Rule) -> bool {
+        self.eval(&rule.condition).is_some_and(Value::as_bool)
+    }
+
+    fn string(&self, idx: Option<usize>) -> Option<&'a [(usize, usize)]> {
+        let idx = idx.or(self.anonymous)?;
+        self.matches.get(idx).map(|m| m.as_slice())
+    }
+
+    fn eval_int(&mut self, expr: &Expr) -> Option<i64> {
+        self.eval(expr).map(Value::as_int)
+    }
+
+    fn eval(&mut self, expr: &Expr) -> Option<Value> {
+        Some(match expr {
+            Expr::Bool(b) => Value::Bool(*b),
+            Expr::Int(n) => Value::Int(*n),
+            Expr::Filesize => Value::Int(self.data.len() as i64),
+            Expr::Matched(idx) => Value::Bool(!self.string(*idx)?.is_empty()),
+            Expr::MatchedAt(idx, at) => {
+                let at = self.eval_int(at)?;
+                Value::Bool(self.string(*idx)?.iter().any(|(o, _)| *o as i64 == at))
+            }
+            Expr::MatchedIn(idx, lo, hi) => {
+                let (lo, hi) = (self.eval_int(lo)?, self.eval_int(hi)?);
+                Value::Bool(self.string(*idx)?.iter().any(|(o, _)| (lo..=hi).contains(&(*o as i64))))
+            }
+            Expr::Count(idx) => Value::Int(self.string(*idx)?.len() as i64),
+            Expr::MatchOffset(idx, n) => {
+                let n = self.eval_int(n)?;
+                let (offset, _) = self.string(*idx)?.get(usize::try_from(n.checked_sub(1)?).ok()?)?;
+                Value::Int(*offset as i64)
+            }
+            Expr::MatchLength(idx, n) => {
+                let n = self.eval_int(n)?;
+                let (_, len) = self.string(*idx)?.get(usize::try_from(n.checked_sub(1)?).ok()?)?;
+                Value::Int(*len as i64)
+            }
+            Expr::ReadInt(f, addr) => {
+                let addr = usize::try_from(self.eval_int(addr)?).ok()?;
+                Value::Int(f.read(self.data, addr)?)
+            }
+            // Undefined operands make boolean operators behave as if the
+            // operand were false, like YARA does.
+            Expr::Not(e) => Value::Bool(!self.eval(e).is_some_and(Value::as_bool)),
+            Expr::And(a, b) => Value::Bool(
+                self.eval(a).is_some_and(Value::as_bool) && self.eval(b).is_some_and(Value::as_bool),
+            ),
+            Expr::Or(a, b) => Value::Bool(
+                self.eval(a).is_some_and(Value::as_bool) || self.eval(b).is_some_and(Value::as_bool),
+            ),
+            Expr::Neg(e) => Value::Int(self.eval_int(e)?.wrapping_neg()),
+            Expr::BitNot(e) => Value::Int(!self.eval_int(e)?),
+            Expr::Binary(op, a, b) => {
+                let (a, b) = (self.eval_int(a)?, self.eval_int(b)?);
+                match op {
+                    BinOp::Add => Value::Int(a.wrapping_add(b)),
+                    BinOp::Sub => Value::Int(a.wrapping_sub(b)),
+                    BinOp::Mul => Value::Int(a.wrapping_mul(b)),
+                    BinOp::Div => Value::Int(a.checked_div(b)?),
+                    BinOp::Mod => Value::Int(a.checked_rem(b)?),
+                    BinOp::BitAnd => Value::Int(a & b),
+                    BinOp::BitOr => Value::Int(a | b),
+                    BinOp::BitXor => Value::Int(a ^ b),
+                    BinOp::Shl => Value::Int(if (0..64).contains(&b) { a << b } else { 0 }),
+                    BinOp::Shr => Value::Int(if (0..64).contains(&b) { a >> b } else { 0 }),
+                    BinOp::Eq => Value::Bool(a == b),
+                    BinOp::Ne => Value::Bool(a != b),
+                    BinOp::Lt => Value::Bool(a < b),
+                    BinOp::Le => Value::Bool(a <= b),
+                    BinOp::Gt => Value::Bool(a > b),
+                    BinOp::Ge => Value::Bool(a >= b),
+                }
+            }
+            Expr::Of(quantifier, set) => {
+                let satisfied = set.iter().filter(|&&i| !self.matches[i].is_empty()).count();
+                Value::Bool(self.quantify(quantifier, satisfied, set.len())?)
+            }
+            Expr::ForOf(quantifier, set, body) => {
+                let saved = self.anonymous;
+                let mut satisfied = 0;
+                for &idx in set {
+                    self.anonymous = Some(idx);
+                    if self.eval(body).is_some_and(Value::as_bool) {
+                        satisfied += 1;
+                    }
+                }
+                self.anonymous = saved;
+                Value::Bool(self.quantify(quantifier, satisfied, set.len())?)
+            }
+            Expr::ForIn { quantifier, var, values, body } => {
+                let values: Vec<i64> = match values {
+                    IterValues::Range(lo, hi) => {
+                        let (lo, hi) = (self.eval_int(lo)?, self.eval_int(hi)?);
+                        if hi.saturating_sub(lo) > MAX_LOOP_ITERATIONS {
+                            return None;
+                        }
+                        (lo..=hi).collect()
+                    }
+                    IterValues::List(list) => list.iter().map(|e| self.eval_int(e)).collect::<Option<_>>()?,
+                };
+                let mut satisfied = 0;
+                for value in &values {
+                    self.vars.push((var.clone(), *value));
+                    if self.eval(body).is_some_and(Value::as_bool) {
+                        satisfied += 1;
+                    }
+                    self.vars.pop();
+                }
+                Value::Bool(self.quantify(quantifier, satisfied, values.len())?)
+            }
+            Expr::Var(name) => Value::Int(self.vars.iter().rev().find(|(n, _)| n == name)?.1),
+            Expr::RuleRef(idx) => Value::Bool(*self.rule_results.get(*idx)?),
+        })
+    }
+
+    fn quantify(&mut self, quantifier: &Quantifier, satisfied: usize, total: usize) -> Option<bool> {
+        Some(match quantifier {
+            Quantifier::All => satisfied == total,
+            Quantifier::Any => satisfied > 0,
+            Quantifier::None => satisfied == 0,
+            Quantifier::Count(n) => satisfied as i64 >= self.eval_int(n)?,
+            Quantifier::Percent(p) => satisfied as i64 * 100 >= self.eval_int(p)? * total as i64,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::yara::lexer::tokenize;
+    use crate::yara::parser::Parser;
+
+    fn string(value: &str) -> StringDef {
+        let source = format!("rule r {{ strings: $a = {} condition: $a }}", value);
+        let mut rules = Parser::new(tokenize(&source).unwrap()).parse_file().unwrap();
+        rules.remove(0).strings.remove(0)
+    }
+
+    #[test]
+    fn every_start_is_a_match() {
+        assert_eq!(find_matches(&string("\"aa\""), b"aaaa", usize::MAX), vec![(0, 2), (1, 2), (2, 2)]);
+        assert_eq!(find_matches(&string("\"aa\""), b"aaaa", 2), vec![(0, 2), (1, 2)]);
+        assert_eq!(find_matches(&string("/a+b/"), b"aaab", usize::MAX), vec![(0, 4), (1, 3), (2, 2)]);
+        assert_eq!(find_matches(&string("\"ab\" fullword"), b"ab cab ab", usize::MAX), vec![(0, 2), (7, 2)]);
+    }
+
+    #[test]
+    fn matches_cross_search_windows() {
+        let mut data = vec![0u8; 3 * START_WINDOW];
+        for &offset in &[START_WINDOW - 2, 2 * START_WINDOW - 1] {
+            data[offset..offset + 4].copy_from_slice(b"MZ\x90\x00");
+        }
+        let matches = find_matches(&string("{ 4D 5A 90 00 }"), &data, usize::MAX);
+        assert_eq!(matches, vec![(START_WINDOW - 2, 4), (2 * START_WINDOW - 1, 4)]);
+
+        // Matches longer than YARA allows are left out rather than searched for
+        let mut data = vec![b'a'; MAX_MATCH_LENGTH + 10];
+        data.push(b'b');
+        let matches = find_matches(&string("/a+b/"), &data, usize::MAX);
+        assert_eq!(matches.first(), Some(&(11, MAX_MATCH_LENGTH)));
+        assert_eq!(matches.len(), MAX_MATCH_LENGTH - 1);
+    }
+}
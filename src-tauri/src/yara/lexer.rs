@@ -0,0 +1,258 @@
+//! Tokenizer for YARA rule files.
+
+use super::RuleError;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Tok {
+    Ident(String),
+    /// `$name`, `$name*` or the anonymous `$`; the `$` is stripped.
+    StringId(String),
+    /// `#name`
+    Count(String),
+    /// `@name`
+    Offset(String),
+    /// `!name`
+    Length(String),
+    Int(i64),
+    Text(Vec<u8>),
+    Regex { pattern: String, flags: String },
+    /// Raw body of a hex string, without the braces.
+    Hex(String),
+    Punct(&'static str),
+    Eof,
+}
+
+#[derive(Debug, Clone)]
+pub struct Token {
+    pub tok: Tok,
+    pub line: usize,
+}
+
+// Longer operators first so `<=` isn't lexed as `<` followed by `=`
+const PUNCTS: [&str; 28] = [
+    "..", ".", "==", "!=", "<=", ">=", "<<", ">>", "{", "}", "(", ")", "[", "]", ":", "=", ",", "<", ">", "+", "-",
+    "*", "\\", "%", "&", "|", "^", "~",
+];
+
+pub fn tokenize(src: &str) -> Result<Vec<Token>, RuleError> {
+    let bytes = src.as_bytes();
+    let mut tokens: Vec<Token> = Vec::new();
+    let mut line = 1;
+    let mut i = 0;
+
+    let err = |line: usize, message: String| RuleError { line, message };
+
+    while i < bytes.len() {
+        let c = bytes[i];
+
+        if c == b'\n' {
+            line += 1;
+            i += 1;
+            continue;
+        }
+        if c.is_ascii_whitespace() {
+            i += 1;
+            continue;
+        }
+
+        // Comments
+        if bytes[i..].starts_with(b"//") {
+            while i < bytes.len() && bytes[i] != b'\n' {
+                i += 1;
+            }
+            continue;
+        }
+        if bytes[i..].starts_with(b"/*") {
+            let start_line = line;
+            i += 2;
+            loop {
+                if i + 1 >= bytes.len() {
+                    return Err(err(start_line, "unterminated comment".to_string()));
+                }
+                if bytes[i] == b'*' && bytes[i + 1] == b'/' {
+                    i += 2;
+                    break;
+                }
+                if bytes[i] == b'\n' {
+                    line += 1;
+                }
+                i += 1;
+            }
+            continue;
+        }
+
+        let prev_is_assign = matches!(tokens.last(), Some(Token { tok: Tok::Punct("="), .. }));
+
+        // Hex strings only appear as the value of a string definition
+        if c == b'{' && prev_is_assign {
+            let start_line = line;
+            let mut j = i + 1;
+            while j < bytes.len() && bytes[j] != b'}' {
+                if bytes[j] == b'\n' {
+                    line += 1;
+                }
+                j += 1;
+            }
+            if j >= bytes.len() {
+                return Err(err(start_line, "unterminated hex string".to_string()));
+            }
+            tokens.push(Token { tok: Tok::Hex(src[i + 1..j].to_string()), line: start_line });
+            i = j + 1;
+            continue;
+        }
+
+        if c == b'"' {
+            let (text, next) = lex_text(bytes, i + 1).map_err(|m| err(line, m))?;
+            tokens.push(Token { tok: Tok::Text(text), line });
+            i = next;
+            continue;
+        }
+
+        if c == b'/' {
+            let mut j = i + 1;
+            let mut pattern = Vec::new();
+            loop {
+                match bytes.get(j) {
+                    None | Some(b'\n') => return Err(err(line, "unterminated regular expression".to_string())),
+                    Some(b'\\') if bytes.get(j + 1) == Some(&b'/') => {
+                        pattern.push(b'/');
+                        j += 2;
+                    }
+                    Some(b'\\') => {
+                        pattern.push(b'\\');
+                        if let Some(&next) = bytes.get(j + 1) {
+                            pattern.push(next);
+                        }
+                        j += 2;
+                    }
+                    Some(b'/') => break,
+                    Some(&other) => {
+                        pattern.push(other);
+                        j += 1;
+                    }
+                }
+            }
+            j += 1;
+            let flags_start = j;
+            while j < bytes.len() && (bytes[j] == b'i' || bytes[j] == b's') {
+                j += 1;
+            }
+            tokens.push(Token {
+                tok: Tok::Regex {
+                    pattern: String::from_utf8_lossy(&pattern).to_string(),
+                    flags: src[flags_start..j].to_string(),
+                },
+                line,
+            });
+            i = j;
+            continue;
+        }
+
+        if matches!(c, b'$' | b'#' | b'@') || (c == b'!' && bytes.get(i + 1).is_some_and(|b| b.is_ascii_alphabetic() || *b == b'_')) {
+            let mut j = i + 1;
+            while j < bytes.len() && (bytes[j].is_ascii_alphanumeric() || bytes[j] == b'_') {
+                j += 1;
+            }
+            if c == b'$' && bytes.get(j) == Some(&b'*') {
+                j += 1;
+            }
+            let name = src[i + 1..j].to_string();
+            let tok = match c {
+                b'$' => Tok::StringId(name),
+                b'#' => Tok::Count(name),
+                b'@' => Tok::Offset(name),
+                _ => Tok::Length(name),
+            };
+            tokens.push(Token { tok, line });
+            i = j;
+            continue;
+        }
+
+        if c.is_ascii_digit() {
+            let (value, next) = lex_int(src, i).map_err(|m| err(line, m))?;
+            tokens.push(Token { tok: Tok::Int(value), line });
+            i = next;
+            continue;
+        }
+
+        if c.is_ascii_alphabetic() || c == b'_' {
+            let mut j = i;
+            while j < bytes.len() && (bytes[j].is_ascii_alphanumeric() || bytes[j] == b'_') {
+                j += 1;
+            }
+            tokens.push(Token { tok: Tok::Ident(src[i..j].to_string()), line });
+            i = j;
+            continue;
+        }
+
+        match PUNCTS.iter().find(|p| bytes[i..].starts_with(p.as_bytes())) {
+            Some(p) => {
+                tokens.push(Token { tok: Tok::Punct(p), line });
+                i += p.len();
+            }
+            None => return Err(err(line, format!("unexpected character '{}'", c as char))),
+        }
+    }
+
+    tokens.push(Token { tok: Tok::Eof, line });
+    Ok(tokens)
+}
+
+fn lex_text(bytes: &[u8], mut i: usize) -> Result<(Vec<u8>, usize), String> {
+    let mut out = Vec::new();
+    loop {
+        match bytes.get(i) {
+            None | Some(b'\n') => return Err("unterminated string".to_string()),
+            Some(b'"') => return Ok((out, i + 1)),
+            Some(b'\\') => {
+                let escaped = bytes.get(i + 1).ok_or("unterminated string")?;
+                match escaped {
+                    b'n' => out.push(b'\n'),
+                    b't' => out.push(b'\t'),
+                    b'r' => out.push(b'\r'),
+                    b'"' => out.push(b'"'),
+                    b'\\' => out.push(b'\\'),
+                    b'x' => {
+                        let hex = bytes.get(i + 2..i + 4).ok_or("invalid \\x escape")?;
+                        let hex = std::str::from_utf8(hex).map_err(|_| "invalid \\x escape")?;
+                        out.push(u8::from_str_radix(hex, 16).map_err(|_| "invalid \\x escape")?);
+                        i += 2;
+                    }
+                    other => return Err(format!("invalid escape sequence '\\{}'", *other as char)),
+                }
+                i += 2;
+            }
+            Some(&b) => {
+                out.push(b);
+                i += 1;
+            }
+        }
+    }
+}
+
+fn lex_int(src: &str, start: usize) -> Result<(i64, usize), String> {
+    let bytes = src.as_bytes();
+    let mut j = start;
+    let (radix, digits_start) = if bytes[start..].starts_with(b"0x") {
+        (16, start + 2)
+    } else if bytes[start..].starts_with(b"0o") {
+        (8, start + 2)
+    } else {
+        (10, start)
+    };
+    j = j.max(digits_start);
+    while j < bytes.len() && (bytes[j] as char).is_digit(radix) {
+        j += 1;
+    }
+    let digits = &src[digits_start..j];
+    let mut value = i64::from_str_radix(digits, radix).map_err(|_| format!("invalid number '{}'", &src[start..j]))?;
+
+    if bytes[j..].starts_with(b"KB") {
+        value = value.saturating_mul(1024);
+        j += 2;
+    } else if bytes[j..].starts_with(b"MB") {
+        value = value.saturating_mul(1024 * 1024);
+        j += 2;
+    }
+    Ok((value, j))
+}
@@ -0,0 +1,281 @@
+//! YARA-compatible rule engine.
+//!
+//! Supports the core of the YARA language: text, hex and regex strings (with
+//! `nocase`, `wide`, `ascii`, `fullword` and `private`), metadata, tags,
+//! `private`/`global` rules, references to earlier rules and conditions built
+//! from string counts/offsets/lengths, `at`/`in`, `filesize`, the
+//! `uint8/16/32` family, `of` sets and `for` loops. Modules (`import "pe"`),
+//! `include`, `entrypoint` and the `xor`/`base64` modifiers are rejected with
+//! a syntax error so unsupported rules never silently evaluate to false.
+//!
+//! Every `.yar`/`.yara` file in the rules directory becomes its own namespace.
+
+mod eval;
+mod lexer;
+mod parser;
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+use parser::{Parser, Rule};
+
+/// Matches listed per string in a [`RuleMatch`]; conditions see all of them.
+const MAX_REPORTED_MATCHES: usize = 1_000;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum MetaValue {
+    Text(String),
+    Int(i64),
+    Bool(bool),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RuleError {
+    pub line: usize,
+    pub message: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RuleFileError {
+    pub file: String,
+    pub line: usize,
+    pub message: String,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct RuleLoadReport {
+    pub directory: String,
+    pub files_loaded: usize,
+    pub rules_loaded: usize,
+    pub errors: Vec<RuleFileError>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StringMatch {
+    pub identifier: String,
+    pub offset: usize,
+    pub length: usize,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RuleMatch {
+    pub rule: String,
+    pub namespace: String,
+    pub tags: Vec<String>,
+    pub meta: BTreeMap<String, MetaValue>,
+    pub strings: Vec<StringMatch>,
+}
+
+struct RuleFile {
+    namespace: String,
+    rules: Vec<Rule>,
+}
+
+#[derive(Default)]
+pub struct RuleSet {
+    files: Vec<RuleFile>,
+}
+
+/// Parses a single rule source into compiled rules.
+fn compile(source: &str) -> Result<Vec<Rule>, RuleError> {
+    let tokens = lexer::tokenize(source)?;
+    Parser::new(tokens).parse_file()
+}
+
+impl RuleSet {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add_source(&mut self, namespace: &str, source: &str) -> Result<usize, RuleError> {
+        let rules = compile(source)?;
+        let count = rules.len();
+        self.files.push(RuleFile { namespace: namespace.to_string(), rules });
+        Ok(count)
+    }
+
+    /// Compiles every rule file in `dir`. Files with syntax errors are left
+    /// out of the set and reported with their line numbers.
+    pub fn load_dir(dir: &Path) -> (RuleSet, RuleLoadReport) {
+        let mut set = RuleSet::new();
+        let mut report = RuleLoadReport {
+            directory: dir.to_string_lossy().to_string(),
+            ..Default::default()
+        };
+
+        let entries = match fs::read_dir(dir) {
+            Ok(entries) => entries,
+            Err(e) => {
+                report.errors.push(RuleFileError {
+                    file: report.directory.clone(),
+                    line: 0,
+                    message: e.to_string(),
+                });
+                return (set, report);
+            }
+        };
+
+        let mut paths: Vec<_> = entries
+            .filter_map(|e| e.ok().map(|e| e.path()))
+            .filter(|p| matches!(p.extension().and_then(|e| e.to_str()), Some("yar" | "yara")))
+            .collect();
+        paths.sort();
+
+        for path in paths {
+            let file = path.to_string_lossy().to_string();
+            let namespace = path.file_stem().and_then(|s| s.to_str()).unwrap_or("default").to_string();
+
+            let source = match fs::read_to_string(&path) {
+                Ok(source) => source,
+                Err(e) => {
+                    report.errors.push(RuleFileError { file, line: 0, message: e.to_string() });
+                    continue;
+                }
+            };
+
+            match set.add_source(&namespace, &source) {
+                Ok(count) => {
+                    report.files_loaded += 1;
+                    report.rules_loaded += count;
+                }
+                Err(e) => report.errors.push(RuleFileError { file, line: e.line, message: e.message }),
+            }
+        }
+
+        (set, report)
+    }
+
+    pub fn scan(&self, data: &[u8]) -> Vec<RuleMatch> {
+        let mut results = Vec::new();
+
+        for file in &self.files {
+            let mut rule_results = Vec::with_capacity(file.rules.len());
+            let mut file_matches = Vec::new();
+            let mut global_failed = false;
+
+            for rule in &file.rules {
+                // Strings the condition only tests for presence stop at their first match
+                let matches: eval::Matches = rule
+                    .strings
+                    .iter()
+                    .zip(eval::counted_strings(rule))
+                    .map(|(s, counted)| {
+                        eval::find_matches(s, data, if counted { eval::MAX_MATCHES_PER_STRING } else { 1 })
+                    })
+                    .collect();
+                let matched = eval::Context::new(data, &matches, &rule_results).eval_condition(rule);
+                rule_results.push(matched);
+
+                if rule.global && !matched {
+                    global_failed = true;
+                }
+                if matched && !rule.private {
+                    file_matches.push(RuleMatch {
+                        rule: rule.name.clone(),
+                        namespace: file.namespace.clone(),
+                        tags: rule.tags.clone(),
+                        meta: rule.meta.clone(),
+                        strings: rule
+                            .strings
+                            .iter()
+                            .zip(&matches)
+                            .filter(|(def, _)| !def.private)
+                            .flat_map(|(def, hits)| {
+                                hits.iter().take(MAX_REPORTED_MATCHES).map(|(offset, length)| StringMatch {
+                                    identifier: format!("${}", def.identifier),
+                                    offset: *offset,
+                                    length: *length,
+                                })
+                            })
+                            .collect(),
+                    });
+                }
+            }
+
+            // A failing global rule suppresses every match in its namespace
+            if !global_failed {
+                results.extend(file_matches);
+            }
+        }
+
+        results
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scan(source: &str, data: &[u8]) -> Vec<RuleMatch> {
+        let mut set = RuleSet::new();
+        set.add_source("test", source).unwrap();
+        set.scan(data)
+    }
+
+    fn error(source: &str) -> RuleError {
+        RuleSet::new().add_source("test", source).unwrap_err()
+    }
+
+    #[test]
+    fn counts_go_past_a_thousand_matches() {
+        let data = b"ab".repeat(5_000);
+        let matches = scan("rule many { strings: $a = \"ab\" condition: #a == 5000 and @a[5000] == 9998 }", &data);
+        assert_eq!(matches.len(), 1);
+        assert_eq!(matches[0].strings.len(), MAX_REPORTED_MATCHES);
+    }
+
+    #[test]
+    fn presence_only_strings_stop_at_the_first_match() {
+        let data = b"ab".repeat(5_000);
+        let matches = scan("rule seen { strings: $a = \"ab\" $b = \"ba\" condition: $a and #b > 10 }", &data);
+        assert_eq!(matches.len(), 1);
+        let count = |id: &str| matches[0].strings.iter().filter(|s| s.identifier == id).count();
+        assert_eq!(count("$a"), 1);
+        assert_eq!(count("$b"), MAX_REPORTED_MATCHES);
+
+        // `$` in a `for ... of` body stands for every string of the set
+        let source = "rule late { strings: $a = \"ab\" condition: for any of ($a) : ($ at 100) }";
+        assert_eq!(scan(source, &data).len(), 1);
+    }
+
+    #[test]
+    fn errors_point_at_their_line() {
+        let e = error("rule a {\n  strings:\n    $a = \"abc\n  condition: $a\n}");
+        assert_eq!((e.line, e.message.as_str()), (3, "unterminated string"));
+        let e = error("rule a {\n  strings:\n\n    $a = { 4D 5A\n");
+        assert_eq!((e.line, e.message.as_str()), (4, "unterminated hex string"));
+        let e = error("rule a {\n  strings:\n    $a = /abc\n  condition: $a\n}");
+        assert_eq!((e.line, e.message.as_str()), (3, "unterminated regular expression"));
+
+        let e = error("rule a { condition: true }\n\nrule a { condition: true }");
+        assert_eq!((e.line, e.message.as_str()), (3, "duplicate rule 'a'"));
+        let e = error("rule a {\n  strings:\n    $a = \"x\"\n    $a = \"y\"\n  condition: $a\n}");
+        assert_eq!((e.line, e.message.as_str()), (4, "duplicate string identifier '$a'"));
+
+        let e = error("rule a {\n  strings:\n    $a = \"x\" xor\n  condition: $a\n}");
+        assert_eq!((e.line, e.message.as_str()), (3, "string modifier 'xor' is not supported"));
+        let e = error("rule a {\n  strings:\n    $a = \"x\"\n    $b = \"y\" base64\n  condition: $a\n}");
+        assert_eq!((e.line, e.message.as_str()), (4, "string modifier 'base64' is not supported"));
+    }
+
+    #[test]
+    fn conditions_on_counts_offsets_and_data() {
+        let data = b"\x7fELF..ab..ab..ab";
+        let matched = |condition: &str| {
+            let source = format!("rule r {{ strings: $a = \"ab\" condition: {} }}", condition);
+            !scan(&source, data).is_empty()
+        };
+        assert!(matched("#a == 3"));
+        assert!(!matched("#a > 3"));
+        assert!(matched("@a[1] == 6 and @a[3] == 14"));
+        assert!(matched("$a at 10 and not $a at 11"));
+        assert!(matched("filesize == 16"));
+        assert!(!matched("filesize > 16"));
+        assert!(matched("uint32(0) == 0x464c457f and uint32be(0) == 0x7f454c46"));
+        // Reads past the end are undefined, which is false
+        assert!(!matched("uint32(14) == 0"));
+    }
+}
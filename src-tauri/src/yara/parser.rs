@@ -0,0 +1,877 @@
+//! Parser turning YARA tokens into rules with compiled string matchers.
+
+use regex_automata::hybrid::dfa::DFA;
+use regex_automata::nfa::thompson;
+use regex_automata::{meta, util::syntax, MatchKind};
+use std::collections::BTreeMap;
+
+use super::lexer::{Tok, Token};
+use super::{MetaValue, RuleError};
+
+/// Compiled size allowed for the automata of a single string.
+const PATTERN_SIZE_LIMIT: usize = 16 * 1024 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BinOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Mod,
+    BitAnd,
+    BitOr,
+    BitXor,
+    Shl,
+    Shr,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IntFn {
+    Int8,
+    Int16,
+    Int32,
+    Uint8,
+    Uint16,
+    Uint32,
+    Int16Be,
+    Int32Be,
+    Uint16Be,
+    Uint32Be,
+}
+
+impl IntFn {
+    fn from_name(name: &str) -> Option<Self> {
+        Some(match name {
+            "int8" | "int8be" => IntFn::Int8,
+            "int16" => IntFn::Int16,
+            "int32" => IntFn::Int32,
+            "uint8" | "uint8be" => IntFn::Uint8,
+            "uint16" => IntFn::Uint16,
+            "uint32" => IntFn::Uint32,
+            "int16be" => IntFn::Int16Be,
+            "int32be" => IntFn::Int32Be,
+            "uint16be" => IntFn::Uint16Be,
+            "uint32be" => IntFn::Uint32Be,
+            _ => return None,
+        })
+    }
+
+    pub fn read(self, data: &[u8], offset: usize) -> Option<i64> {
+        let take = |n: usize| data.get(offset..offset.checked_add(n)?);
+        Some(match self {
+            IntFn::Int8 => take(1)?[0] as i8 as i64,
+            IntFn::Uint8 => take(1)?[0] as i64,
+            IntFn::Int16 => i16::from_le_bytes(take(2)?.try_into().ok()?) as i64,
+            IntFn::Uint16 => u16::from_le_bytes(take(2)?.try_into().ok()?) as i64,
+            IntFn::Int32 => i32::from_le_bytes(take(4)?.try_into().ok()?) as i64,
+            IntFn::Uint32 => u32::from_le_bytes(take(4)?.try_into().ok()?) as i64,
+            IntFn::Int16Be => i16::from_be_bytes(take(2)?.try_into().ok()?) as i64,
+            IntFn::Uint16Be => u16::from_be_bytes(take(2)?.try_into().ok()?) as i64,
+            IntFn::Int32Be => i32::from_be_bytes(take(4)?.try_into().ok()?) as i64,
+            IntFn::Uint32Be => u32::from_be_bytes(take(4)?.try_into().ok()?) as i64,
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum Quantifier {
+    All,
+    Any,
+    None,
+    Count(Box<Expr>),
+    Percent(Box<Expr>),
+}
+
+#[derive(Debug, Clone)]
+pub enum Expr {
+    Bool(bool),
+    Int(i64),
+    Filesize,
+    /// String references hold an index into the rule's strings; `None` is the
+    /// anonymous `$` bound by an enclosing `for ... of` loop.
+    Matched(Option<usize>),
+    MatchedAt(Option<usize>, Box<Expr>),
+    MatchedIn(Option<usize>, Box<Expr>, Box<Expr>),
+    Count(Option<usize>),
+    MatchOffset(Option<usize>, Box<Expr>),
+    MatchLength(Option<usize>, Box<Expr>),
+    ReadInt(IntFn, Box<Expr>),
+    Not(Box<Expr>),
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+    Neg(Box<Expr>),
+    BitNot(Box<Expr>),
+    Binary(BinOp, Box<Expr>, Box<Expr>),
+    Of(Quantifier, Vec<usize>),
+    ForOf(Quantifier, Vec<usize>, Box<Expr>),
+    ForIn {
+        quantifier: Quantifier,
+        var: String,
+        values: IterValues,
+        body: Box<Expr>,
+    },
+    Var(String),
+    RuleRef(usize),
+}
+
+#[derive(Debug, Clone)]
+pub enum IterValues {
+    Range(Box<Expr>, Box<Expr>),
+    List(Vec<Expr>),
+}
+
+#[derive(Debug)]
+pub struct StringDef {
+    pub identifier: String,
+    pub regex: meta::Regex,
+    /// Runs backwards over the data to find every offset a match starts at.
+    pub starts: DFA,
+    pub fullword: bool,
+    pub private: bool,
+}
+
+#[derive(Debug)]
+pub struct Rule {
+    pub name: String,
+    pub tags: Vec<String>,
+    pub meta: BTreeMap<String, MetaValue>,
+    pub strings: Vec<StringDef>,
+    pub condition: Expr,
+    pub private: bool,
+    pub global: bool,
+}
+
+pub struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+    rules: Vec<Rule>,
+    // Per-rule state used while parsing the condition
+    string_ids: Vec<String>,
+    loop_vars: Vec<String>,
+}
+
+type PResult<T> = Result<T, RuleError>;
+
+impl Parser {
+    pub fn new(tokens: Vec<Token>) -> Self {
+        Parser {
+            tokens,
+            pos: 0,
+            rules: Vec::new(),
+            string_ids: Vec::new(),
+            loop_vars: Vec::new(),
+        }
+    }
+
+    pub fn parse_file(mut self) -> PResult<Vec<Rule>> {
+        loop {
+            match self.peek().clone() {
+                Tok::Eof => break,
+                Tok::Ident(kw) if kw == "import" => {
+                    let line = self.line();
+                    self.advance();
+                    match self.advance().tok {
+                        Tok::Text(module) => {
+                            return Err(RuleError {
+                                line,
+                                message: format!("module '{}' is not supported", String::from_utf8_lossy(&module)),
+                            })
+                        }
+                        _ => return Err(self.error_prev("expected module name after 'import'")),
+                    }
+                }
+                Tok::Ident(kw) if kw == "include" => {
+                    return Err(self.error("'include' directives are not supported"));
+                }
+                _ => {
+                    let rule = self.parse_rule()?;
+                    self.rules.push(rule);
+                }
+            }
+        }
+        Ok(self.rules)
+    }
+
+    fn peek(&self) -> &Tok {
+        &self.tokens[self.pos].tok
+    }
+
+    fn peek_at(&self, n: usize) -> &Tok {
+        let idx = (self.pos + n).min(self.tokens.len() - 1);
+        &self.tokens[idx].tok
+    }
+
+    fn line(&self) -> usize {
+        self.tokens[self.pos].line
+    }
+
+    fn advance(&mut self) -> Token {
+        let token = self.tokens[self.pos].clone();
+        if self.pos < self.tokens.len() - 1 {
+            self.pos += 1;
+        }
+        token
+    }
+
+    fn error(&self, message: &str) -> RuleError {
+        RuleError { line: self.line(), message: message.to_string() }
+    }
+
+    fn error_prev(&self, message: &str) -> RuleError {
+        let line = self.tokens[self.pos.saturating_sub(1)].line;
+        RuleError { line, message: message.to_string() }
+    }
+
+    fn is_punct(&self, p: &str) -> bool {
+        matches!(self.peek(), Tok::Punct(q) if *q == p)
+    }
+
+    fn is_keyword(&self, kw: &str) -> bool {
+        matches!(self.peek(), Tok::Ident(k) if k == kw)
+    }
+
+    fn eat_punct(&mut self, p: &str) -> bool {
+        if self.is_punct(p) {
+            self.advance();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn eat_keyword(&mut self, kw: &str) -> bool {
+        if self.is_keyword(kw) {
+            self.advance();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect_punct(&mut self, p: &str) -> PResult<()> {
+        if self.eat_punct(p) {
+            Ok(())
+        } else {
+            Err(self.error(&format!("expected '{}'", p)))
+        }
+    }
+
+    fn expect_ident(&mut self, what: &str) -> PResult<String> {
+        match self.peek().clone() {
+            Tok::Ident(name) => {
+                self.advance();
+                Ok(name)
+            }
+            _ => Err(self.error(&format!("expected {}", what))),
+        }
+    }
+
+    fn parse_rule(&mut self) -> PResult<Rule> {
+        let mut private = false;
+        let mut global = false;
+        loop {
+            if self.eat_keyword("private") {
+                private = true;
+            } else if self.eat_keyword("global") {
+                global = true;
+            } else {
+                break;
+            }
+        }
+
+        if !self.eat_keyword("rule") {
+            return Err(self.error("expected 'rule'"));
+        }
+        let name_line = self.line();
+        let name = self.expect_ident("rule name")?;
+        if self.rules.iter().any(|r| r.name == name) {
+            return Err(RuleError { line: name_line, message: format!("duplicate rule '{}'", name) });
+        }
+
+        let mut tags = Vec::new();
+        if self.eat_punct(":") {
+            while let Tok::Ident(tag) = self.peek().clone() {
+                self.advance();
+                tags.push(tag);
+            }
+        }
+
+        self.expect_punct("{")?;
+
+        let mut meta = BTreeMap::new();
+        let mut strings = Vec::new();
+        self.string_ids.clear();
+
+        if self.is_keyword("meta") && matches!(self.peek_at(1), Tok::Punct(":")) {
+            self.advance();
+            self.advance();
+            while matches!(self.peek(), Tok::Ident(_)) && matches!(self.peek_at(1), Tok::Punct("=")) {
+                let key = self.expect_ident("metadata key")?;
+                self.expect_punct("=")?;
+                let value = match self.advance().tok {
+                    Tok::Text(text) => MetaValue::Text(String::from_utf8_lossy(&text).to_string()),
+                    Tok::Int(n) => MetaValue::Int(n),
+                    Tok::Punct("-") => match self.advance().tok {
+                        Tok::Int(n) => MetaValue::Int(-n),
+                        _ => return Err(self.error_prev("invalid metadata value")),
+                    },
+                    Tok::Ident(b) if b == "true" => MetaValue::Bool(true),
+                    Tok::Ident(b) if b == "false" => MetaValue::Bool(false),
+                    _ => return Err(self.error_prev("invalid metadata value")),
+                };
+                meta.insert(key, value);
+            }
+        }
+
+        if self.is_keyword("strings") && matches!(self.peek_at(1), Tok::Punct(":")) {
+            self.advance();
+            self.advance();
+            while let Tok::StringId(id) = self.peek().clone() {
+                let line = self.line();
+                self.advance();
+                if id.is_empty() || id.ends_with('*') {
+                    return Err(RuleError { line, message: "invalid string identifier".to_string() });
+                }
+                if self.string_ids.contains(&id) {
+                    return Err(RuleError { line, message: format!("duplicate string identifier '${}'", id) });
+                }
+                self.expect_punct("=")?;
+                let def = self.parse_string_def(&id, line)?;
+                self.string_ids.push(id);
+                strings.push(def);
+            }
+        }
+
+        if !(self.is_keyword("condition") && matches!(self.peek_at(1), Tok::Punct(":"))) {
+            return Err(self.error("expected 'condition:' section"));
+        }
+        self.advance();
+        self.advance();
+        let condition = self.parse_expr()?;
+        self.expect_punct("}")?;
+
+        Ok(Rule { name, tags, meta, strings, condition, private, global })
+    }
+
+    fn parse_string_def(&mut self, id: &str, line: usize) -> PResult<StringDef> {
+        let value = self.advance().tok;
+
+        let mut modifiers = Vec::new();
+        while let Tok::Ident(m) = self.peek().clone() {
+            if !matches!(m.as_str(), "nocase" | "wide" | "ascii" | "fullword" | "private" | "xor" | "base64" | "base64wide") {
+                break;
+            }
+            self.advance();
+            modifiers.push(m);
+        }
+        let has = |m: &str| modifiers.iter().any(|x| x == m);
+        if let Some(m) = modifiers.iter().find(|m| matches!(m.as_str(), "xor" | "base64" | "base64wide")) {
+            return Err(RuleError { line, message: format!("string modifier '{}' is not supported", m) });
+        }
+
+        let err = |message: String| RuleError { line, message };
+
+        let pattern = match value {
+            Tok::Text(text) => {
+                let ascii = escape_bytes(&text);
+                let wide = escape_bytes(&text.iter().flat_map(|b| [*b, 0]).collect::<Vec<_>>());
+                let body = match (has("wide"), has("ascii")) {
+                    (true, true) => format!("(?:{}|{})", ascii, wide),
+                    (true, false) => wide,
+                    _ => ascii,
+                };
+                if has("nocase") {
+                    format!("(?i){}", body)
+                } else {
+                    body
+                }
+            }
+            Tok::Hex(hex) => {
+                if !modifiers.is_empty() && modifiers.iter().any(|m| m != "private") {
+                    return Err(err("hex strings only accept the 'private' modifier".to_string()));
+                }
+                hex_to_regex(&hex).map_err(err)?
+            }
+            Tok::Regex { pattern, flags } => {
+                if has("wide") {
+                    return Err(err("'wide' is not supported on regular expressions".to_string()));
+                }
+                let mut prefix = String::new();
+                if flags.contains('i') || has("nocase") {
+                    prefix.push('i');
+                }
+                if flags.contains('s') {
+                    prefix.push('s');
+                }
+                if prefix.is_empty() {
+                    pattern
+                } else {
+                    format!("(?{}){}", prefix, pattern)
+                }
+            }
+            _ => return Err(err(format!("expected string value for '${}'", id))),
+        };
+
+        let invalid = |e: &dyn std::fmt::Display| err(format!("invalid pattern for '${}': {}", id, e));
+        let syntax = syntax::Config::new().unicode(false).utf8(false);
+        let regex = meta::Builder::new()
+            .configure(meta::Config::new().nfa_size_limit(Some(PATTERN_SIZE_LIMIT)))
+            .syntax(syntax)
+            .build(&pattern)
+            .map_err(|e| invalid(&e))?;
+        let starts = DFA::builder()
+            .configure(DFA::config().match_kind(MatchKind::All))
+            .syntax(syntax)
+            .thompson(thompson::Config::new().reverse(true).nfa_size_limit(Some(PATTERN_SIZE_LIMIT)))
+            .build(&pattern)
+            .map_err(|e| invalid(&e))?;
+
+        Ok(StringDef {
+            identifier: id.to_string(),
+            regex,
+            starts,
+            fullword: has("fullword"),
+            private: has("private"),
+        })
+    }
+
+    // Expression grammar, lowest precedence first:
+    // or, and, not, comparison, |, ^, &, shifts, additive, multiplicative, unary
+
+    fn parse_expr(&mut self) -> PResult<Expr> {
+        let mut left = self.parse_and()?;
+        while self.eat_keyword("or") {
+            let right = self.parse_and()?;
+            left = Expr::Or(Box::new(left), Box::new(right));
+        }
+        Ok(left)
+    }
+
+    fn parse_and(&mut self) -> PResult<Expr> {
+        let mut left = self.parse_not()?;
+        while self.eat_keyword("and") {
+            let right = self.parse_not()?;
+            left = Expr::And(Box::new(left), Box::new(right));
+        }
+        Ok(left)
+    }
+
+    fn parse_not(&mut self) -> PResult<Expr> {
+        if self.eat_keyword("not") {
+            return Ok(Expr::Not(Box::new(self.parse_not()?)));
+        }
+        self.parse_comparison()
+    }
+
+    fn parse_comparison(&mut self) -> PResult<Expr> {
+        let left = self.parse_bitor()?;
+        let op = match self.peek() {
+            Tok::Punct("==") => BinOp::Eq,
+            Tok::Punct("!=") => BinOp::Ne,
+            Tok::Punct("<") => BinOp::Lt,
+            Tok::Punct("<=") => BinOp::Le,
+            Tok::Punct(">") => BinOp::Gt,
+            Tok::Punct(">=") => BinOp::Ge,
+            _ => return Ok(left),
+        };
+        self.advance();
+        let right = self.parse_bitor()?;
+        Ok(Expr::Binary(op, Box::new(left), Box::new(right)))
+    }
+
+    fn parse_binary_level(
+        &mut self,
+        ops: &[(&str, BinOp)],
+        next: fn(&mut Self) -> PResult<Expr>,
+    ) -> PResult<Expr> {
+        let mut left = next(self)?;
+        'outer: loop {
+            for (p, op) in ops {
+                if self.eat_punct(p) {
+                    let right = next(self)?;
+                    left = Expr::Binary(*op, Box::new(left), Box::new(right));
+                    continue 'outer;
+                }
+            }
+            return Ok(left);
+        }
+    }
+
+    fn parse_bitor(&mut self) -> PResult<Expr> {
+        self.parse_binary_level(&[("|", BinOp::BitOr)], Self::parse_bitxor)
+    }
+
+    fn parse_bitxor(&mut self) -> PResult<Expr> {
+        self.parse_binary_level(&[("^", BinOp::BitXor)], Self::parse_bitand)
+    }
+
+    fn parse_bitand(&mut self) -> PResult<Expr> {
+        self.parse_binary_level(&[("&", BinOp::BitAnd)], Self::parse_shift)
+    }
+
+    fn parse_shift(&mut self) -> PResult<Expr> {
+        self.parse_binary_level(&[("<<", BinOp::Shl), (">>", BinOp::Shr)], Self::parse_additive)
+    }
+
+    fn parse_additive(&mut self) -> PResult<Expr> {
+        self.parse_binary_level(&[("+", BinOp::Add), ("-", BinOp::Sub)], Self::parse_multiplicative)
+    }
+
+    fn parse_multiplicative(&mut self) -> PResult<Expr> {
+        self.parse_binary_level(
+            &[("*", BinOp::Mul), ("\\", BinOp::Div), ("%", BinOp::Mod)],
+            Self::parse_unary,
+        )
+    }
+
+    fn parse_unary(&mut self) -> PResult<Expr> {
+        if self.eat_punct("-") {
+            return Ok(Expr::Neg(Box::new(self.parse_unary()?)));
+        }
+        if self.eat_punct("~") {
+            return Ok(Expr::BitNot(Box::new(self.parse_unary()?)));
+        }
+        self.parse_primary()
+    }
+
+    fn resolve_string(&self, id: &str) -> PResult<Option<usize>> {
+        if id.is_empty() {
+            return Ok(None);
+        }
+        self.string_ids
+            .iter()
+            .position(|s| s == id)
+            .map(Some)
+            .ok_or_else(|| self.error_prev(&format!("undefined string identifier '${}'", id)))
+    }
+
+    fn parse_primary(&mut self) -> PResult<Expr> {
+        let token = self.advance();
+        match token.tok {
+            Tok::Int(n) => {
+                // `2 of ($a, $b)` / `50% of them`
+                if self.eat_punct("%") {
+                    return self.parse_of(Quantifier::Percent(Box::new(Expr::Int(n))));
+                }
+                if self.is_keyword("of") {
+                    return self.parse_of(Quantifier::Count(Box::new(Expr::Int(n))));
+                }
+                Ok(Expr::Int(n))
+            }
+            Tok::StringId(id) => {
+                if id.ends_with('*') {
+                    return Err(self.error_prev("string wildcards are only allowed in string sets"));
+                }
+                let idx = self.resolve_string(&id)?;
+                if self.eat_keyword("at") {
+                    let at = self.parse_unary()?;
+                    return Ok(Expr::MatchedAt(idx, Box::new(at)));
+                }
+                if self.eat_keyword("in") {
+                    let (lo, hi) = self.parse_range()?;
+                    return Ok(Expr::MatchedIn(idx, Box::new(lo), Box::new(hi)));
+                }
+                Ok(Expr::Matched(idx))
+            }
+            Tok::Count(id) => Ok(Expr::Count(self.resolve_string(&id)?)),
+            Tok::Offset(id) => {
+                let idx = self.resolve_string(&id)?;
+                Ok(Expr::MatchOffset(idx, Box::new(self.parse_index()?)))
+            }
+            Tok::Length(id) => {
+                let idx = self.resolve_string(&id)?;
+                Ok(Expr::MatchLength(idx, Box::new(self.parse_index()?)))
+            }
+            Tok::Punct("(") => {
+                let expr = self.parse_expr()?;
+                self.expect_punct(")")?;
+                Ok(expr)
+            }
+            Tok::Ident(ident) => match ident.as_str() {
+                "true" => Ok(Expr::Bool(true)),
+                "false" => Ok(Expr::Bool(false)),
+                "filesize" => Ok(Expr::Filesize),
+                "all" => self.parse_of(Quantifier::All),
+                "any" => self.parse_of(Quantifier::Any),
+                "none" => self.parse_of(Quantifier::None),
+                "for" => self.parse_for(),
+                "entrypoint" => Err(self.error_prev("'entrypoint' is not supported")),
+                _ => {
+                    if let Some(f) = IntFn::from_name(&ident) {
+                        self.expect_punct("(")?;
+                        let addr = self.parse_expr()?;
+                        self.expect_punct(")")?;
+                        return Ok(Expr::ReadInt(f, Box::new(addr)));
+                    }
+                    if self.loop_vars.contains(&ident) {
+                        return Ok(Expr::Var(ident));
+                    }
+                    if let Some(idx) = self.rules.iter().position(|r| r.name == ident) {
+                        return Ok(Expr::RuleRef(idx));
+                    }
+                    if self.is_punct(".") || self.is_punct("(") {
+                        return Err(self.error_prev(&format!("function or module '{}' is not supported", ident)));
+                    }
+                    Err(self.error_prev(&format!("undefined identifier '{}'", ident)))
+                }
+            },
+            Tok::Eof => Err(self.error_prev("unexpected end of file in condition")),
+            other => Err(RuleError {
+                line: token.line,
+                message: format!("unexpected token {} in condition", describe(&other)),
+            }),
+        }
+    }
+
+    /// Optional `[i]` after `@a` / `!a`; YARA indexes matches from 1.
+    fn parse_index(&mut self) -> PResult<Expr> {
+        if self.eat_punct("[") {
+            let idx = self.parse_expr()?;
+            self.expect_punct("]")?;
+            Ok(idx)
+        } else {
+            Ok(Expr::Int(1))
+        }
+    }
+
+    fn parse_range(&mut self) -> PResult<(Expr, Expr)> {
+        self.expect_punct("(")?;
+        let lo = self.parse_expr()?;
+        self.expect_punct("..")?;
+        let hi = self.parse_expr()?;
+        self.expect_punct(")")?;
+        Ok((lo, hi))
+    }
+
+    fn parse_of(&mut self, quantifier: Quantifier) -> PResult<Expr> {
+        if !self.eat_keyword("of") {
+            return Err(self.error("expected 'of'"));
+        }
+        let set = self.parse_string_set()?;
+        Ok(Expr::Of(quantifier, set))
+    }
+
+    fn parse_string_set(&mut self) -> PResult<Vec<usize>> {
+        if self.eat_keyword("them") {
+            if self.string_ids.is_empty() {
+                return Err(self.error_prev("'them' used in a rule without strings"));
+            }
+            return Ok((0..self.string_ids.len()).collect());
+        }
+
+        self.expect_punct("(")?;
+        let mut set = Vec::new();
+        loop {
+            match self.advance().tok {
+                Tok::StringId(id) => {
+                    let matched: Vec<usize> = match id.strip_suffix('*') {
+                        Some(prefix) => self
+                            .string_ids
+                            .iter()
+                            .enumerate()
+                            .filter(|(_, s)| s.starts_with(prefix))
+                            .map(|(i, _)| i)
+                            .collect(),
+                        None => self.resolve_string(&id)?.into_iter().collect(),
+                    };
+                    if matched.is_empty() {
+                        return Err(self.error_prev(&format!("no strings match '${}'", id)));
+                    }
+                    for idx in matched {
+                        if !set.contains(&idx) {
+                            set.push(idx);
+                        }
+                    }
+                }
+                _ => return Err(self.error_prev("expected string identifier in set")),
+            }
+            if self.eat_punct(")") {
+                break;
+            }
+            self.expect_punct(",")?;
+        }
+        Ok(set)
+    }
+
+    fn parse_quantifier(&mut self) -> PResult<Quantifier> {
+        if self.eat_keyword("all") {
+            return Ok(Quantifier::All);
+        }
+        if self.eat_keyword("any") {
+            return Ok(Quantifier::Any);
+        }
+        if self.eat_keyword("none") {
+            return Ok(Quantifier::None);
+        }
+        let n = self.parse_additive()?;
+        if self.eat_punct("%") {
+            return Ok(Quantifier::Percent(Box::new(n)));
+        }
+        Ok(Quantifier::Count(Box::new(n)))
+    }
+
+    fn parse_for(&mut self) -> PResult<Expr> {
+        let quantifier = self.parse_quantifier()?;
+
+        if self.eat_keyword("of") {
+            let set = self.parse_string_set()?;
+            self.expect_punct(":")?;
+            self.expect_punct("(")?;
+            let body = self.parse_expr()?;
+            self.expect_punct(")")?;
+            return Ok(Expr::ForOf(quantifier, set, Box::new(body)));
+        }
+
+        let var = self.expect_ident("loop variable or 'of'")?;
+        if !self.eat_keyword("in") {
+            return Err(self.error("expected 'in'"));
+        }
+
+        self.expect_punct("(")?;
+        let first = self.parse_expr()?;
+        let values = if self.eat_punct("..") {
+            let hi = self.parse_expr()?;
+            IterValues::Range(Box::new(first), Box::new(hi))
+        } else {
+            let mut list = vec![first];
+            while self.eat_punct(",") {
+                list.push(self.parse_expr()?);
+            }
+            IterValues::List(list)
+        };
+        self.expect_punct(")")?;
+        self.expect_punct(":")?;
+        self.expect_punct("(")?;
+
+        self.loop_vars.push(var.clone());
+        let body = self.parse_expr();
+        self.loop_vars.pop();
+        let body = body?;
+        self.expect_punct(")")?;
+
+        Ok(Expr::ForIn { quantifier, var, values, body: Box::new(body) })
+    }
+}
+
+fn describe(tok: &Tok) -> String {
+    match tok {
+        Tok::Ident(s) => format!("'{}'", s),
+        Tok::StringId(s) => format!("'${}'", s),
+        Tok::Count(s) => format!("'#{}'", s),
+        Tok::Offset(s) => format!("'@{}'", s),
+        Tok::Length(s) => format!("'!{}'", s),
+        Tok::Int(n) => format!("'{}'", n),
+        Tok::Text(_) => "string literal".to_string(),
+        Tok::Regex { .. } => "regular expression".to_string(),
+        Tok::Hex(_) => "hex string".to_string(),
+        Tok::Punct(p) => format!("'{}'", p),
+        Tok::Eof => "end of file".to_string(),
+    }
+}
+
+fn escape_bytes(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("\\x{:02x}", b)).collect()
+}
+
+/// Translates a YARA hex string body (`4D 5A ?? [2-4] (01 | 02) ~00`) into an
+/// equivalent byte regex.
+fn hex_to_regex(hex: &str) -> Result<String, String> {
+    let chars: Vec<char> = hex.chars().filter(|c| !c.is_whitespace()).collect();
+    let mut out = String::from("(?s)");
+    let mut depth = 0usize;
+    let mut i = 0;
+
+    let nibble = |c: char| c.to_digit(16).map(|d| d as u8);
+
+    while i < chars.len() {
+        match chars[i] {
+            '[' => {
+                let close = chars[i..].iter().position(|&c| c == ']').ok_or("unterminated jump in hex string")?;
+                let spec: String = chars[i + 1..i + close].iter().collect();
+                let quant = match spec.split_once('-') {
+                    None => {
+                        let n: usize = spec.parse().map_err(|_| format!("invalid jump '[{}]'", spec))?;
+                        format!("{{{}}}", n)
+                    }
+                    Some((lo, hi)) => {
+                        let lo: usize = if lo.is_empty() { 0 } else { lo.parse().map_err(|_| format!("invalid jump '[{}]'", spec))? };
+                        if hi.is_empty() {
+                            format!("{{{},}}", lo)
+                        } else {
+                            let hi: usize = hi.parse().map_err(|_| format!("invalid jump '[{}]'", spec))?;
+                            if lo > hi {
+                                return Err(format!("invalid jump '[{}]'", spec));
+                            }
+                            format!("{{{},{}}}", lo, hi)
+                        }
+                    }
+                };
+                out.push_str(&format!("(?:.){}?", quant));
+                i += close + 1;
+            }
+            '(' => {
+                depth += 1;
+                out.push_str("(?:");
+                i += 1;
+            }
+            '|' => {
+                if depth == 0 {
+                    return Err("alternative outside of a group in hex string".to_string());
+                }
+                out.push('|');
+                i += 1;
+            }
+            ')' => {
+                depth = depth.checked_sub(1).ok_or("unbalanced ')' in hex string")?;
+                out.push(')');
+                i += 1;
+            }
+            '~' => {
+                let (hi, lo) = (*chars.get(i + 1).ok_or("incomplete byte in hex string")?, *chars.get(i + 2).ok_or("incomplete byte in hex string")?);
+                let b = match (nibble(hi), nibble(lo)) {
+                    (Some(h), Some(l)) => h << 4 | l,
+                    _ => return Err("'~' must be followed by a full byte".to_string()),
+                };
+                out.push_str(&format!("[^\\x{:02x}]", b));
+                i += 3;
+            }
+            hi => {
+                let lo = *chars.get(i + 1).ok_or("incomplete byte in hex string")?;
+                match (hi, lo) {
+                    ('?', '?') => out.push('.'),
+                    ('?', lo) => {
+                        let l = nibble(lo).ok_or_else(|| format!("invalid hex digit '{}'", lo))?;
+                        out.push('[');
+                        for h in 0..16u8 {
+                            out.push_str(&format!("\\x{:02x}", h << 4 | l));
+                        }
+                        out.push(']');
+                    }
+                    (hi, '?') => {
+                        let h = nibble(hi).ok_or_else(|| format!("invalid hex digit '{}'", hi))?;
+                        out.push_str(&format!("[\\x{:02x}-\\x{:02x}]", h << 4, h << 4 | 0x0f));
+                    }
+                    (hi, lo) => match (nibble(hi), nibble(lo)) {
+                        (Some(h), Some(l)) => out.push_str(&format!("\\x{:02x}", h << 4 | l)),
+                        _ => return Err(format!("invalid hex byte '{}{}'", hi, lo)),
+                    },
+                }
+                i += 2;
+            }
+        }
+    }
+
+    if depth != 0 {
+        return Err("unbalanced '(' in hex string".to_string());
+    }
+    if out.len() == 4 {
+        return Err("empty hex string".to_string());
+    }
+    Ok(out)
+}